@@ -0,0 +1,85 @@
+//! Capture automatic gain control: a slowly moving gain multiplier steers the per-chunk RMS toward
+//! a target level, replacing the manual gain while enabled.
+//!
+//! Too loud chunks pull the gain down quickly (`ATTACK`), too quiet ones raise it slowly
+//! (`RELEASE`), bounded by `MIN_GAIN_DB`..`MAX_GAIN_DB`. Chunks under `SILENCE_DB` leave the gain
+//! where it is, so pauses are not pumped up into amplified room noise.
+use std::{sync::{atomic::AtomicBool, Arc}, time::Duration};
+
+use crate::server::AtomicF64;
+
+/// Target slider range (dB RMS).
+pub const TARGET_DB_MIN: f64 = -36.0;
+pub const TARGET_DB_MAX: f64 = -6.0;
+/// Gain bounds (dB).
+pub const MIN_GAIN_DB: f64 = -20.0;
+pub const MAX_GAIN_DB: f64 = 24.0;
+/// Input chunks below this level (dB RMS, before gain) freeze the gain.
+pub const SILENCE_DB: f64 = -55.0;
+/// Smoothing time constants: gain going down / going up.
+const ATTACK: Duration = Duration::from_millis(50);
+const RELEASE: Duration = Duration::from_secs(3);
+
+/// Live AGC settings shared with the capture callback (read every chunk).
+#[derive(Clone)]
+pub struct AgcSettings {
+    pub enabled: Arc<AtomicBool>,
+    pub target_db: Arc<AtomicF64>,
+    /// Gain currently applied by the running AGC (linear, for the UI)
+    pub gain: Arc<AtomicF64>,
+}
+
+impl AgcSettings {
+    pub fn new() -> Self {
+        Self { enabled: Arc::new(AtomicBool::new(false)), target_db: Arc::new(AtomicF64::new(-20.0)), gain: Arc::new(AtomicF64::new(1.0)) }
+    }
+}
+
+impl Default for AgcSettings { fn default() -> Self { Self::new() } }
+
+/// Per-stream AGC state.
+#[derive(Default)]
+pub struct Agc { gain_db: f64 }
+
+impl Agc {
+    pub fn new() -> Self { Self::default() }
+
+    /// Update from one chunk of length `dur` with input (pre-gain) linear `rms`; returns the linear gain to apply.
+    pub fn process(&mut self, rms: f64, target_db: f64, dur: Duration) -> f64 {
+        let db = if rms > 0.0 { 20.0 * rms.log10() } else { f64::NEG_INFINITY };
+        if db >= SILENCE_DB {
+            let want = (target_db - db).clamp(MIN_GAIN_DB, MAX_GAIN_DB);
+            let tau = if want < self.gain_db { ATTACK } else { RELEASE };
+            self.gain_db += (want - self.gain_db) * (1.0 - (-dur.as_secs_f64() / tau.as_secs_f64()).exp());
+        }
+        10f64.powf(self.gain_db / 20.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_drops_fast_rises_slowly_and_ignores_silence() {
+        let lin = |v: f64| 10f64.powf(v / 20.0);
+        let db = |g: f64| 20.0 * g.log10();
+        let chunk = Duration::from_millis(10);
+        let mut agc = Agc::new();
+        // Loud input: most of the way down within a quarter second
+        let mut g = 1.0;
+        for _ in 0..25 { g = agc.process(lin(-8.0), -20.0, chunk); }
+        assert!(db(g) < -11.0 && db(g) >= -12.0, "attack: {:.2} dB", db(g));
+        // Quiet input: a quarter second barely moves it back up
+        for _ in 0..25 { g = agc.process(lin(-40.0), -20.0, chunk); }
+        assert!(db(g) < -8.0, "release is slow: {:.2} dB", db(g));
+        // Given time it settles on the target, capped at the maximum gain
+        for _ in 0..3000 { g = agc.process(lin(-40.0), -20.0, chunk); }
+        assert!((db(g) - 20.0).abs() < 0.1, "settled: {:.2} dB", db(g));
+        for _ in 0..3000 { g = agc.process(lin(-52.0), -20.0, chunk); }
+        assert!((db(g) - MAX_GAIN_DB).abs() < 0.1, "capped: {:.2} dB", db(g));
+        // Silence leaves it alone
+        assert_eq!(agc.process(lin(-70.0), -20.0, chunk), g);
+        assert_eq!(agc.process(0.0, -20.0, chunk), g);
+    }
+}
@@ -0,0 +1,130 @@
+//! Optional local HTTP/JSON control API (cargo feature `control-api`).
+//!
+//! Disabled unless `REMOTEMIC_API_TOKEN` is set; binds `127.0.0.1:7878` by default
+//! (`REMOTEMIC_API_BIND` overrides). Every request must carry `Authorization: Bearer <token>`.
+//! Endpoints and schemas are documented in `docs/control_api.md`.
+use std::{net::SocketAddr, sync::{atomic::Ordering, Arc}, thread, time::Instant};
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::server::{self, ServerState};
+
+const DEFAULT_BIND: &str = "127.0.0.1:7878";
+
+/// The server state the API acts on. The owner replaces it whenever it swaps in a new copy
+/// (`start_server` sets the group and port, enabling encryption replaces `keys`), so requests
+/// never see a snapshot from startup.
+pub type LiveState = Arc<RwLock<ServerState>>;
+
+/// API listener settings.
+pub struct ApiConfig { pub bind: String, pub token: String }
+
+impl ApiConfig {
+    /// Read settings from the environment; `None` when no token is configured (API off).
+    pub fn from_env() -> Option<Self> {
+        let token = std::env::var("REMOTEMIC_API_TOKEN").ok().filter(|t| !t.trim().is_empty())?;
+        let bind = std::env::var("REMOTEMIC_API_BIND").unwrap_or_else(|_| DEFAULT_BIND.into());
+        Some(Self { bind, token })
+    }
+}
+
+#[derive(Serialize)]
+struct StatusBody { running: bool, stage: u8, muted: bool, clients: usize, rms: f64, peak_rms: f64, encrypted: bool, multicast: String, send_errors: u64, oversized_frames: u64, send_kbps: f64 }
+
+#[derive(Serialize)]
+struct ClientBody { addr: String, last_seen_ms: u64, udp_port: Option<u16>, substream: &'static str }
+
+#[derive(Deserialize)]
+struct KickBody { addr: String }
+
+#[derive(Deserialize)]
+struct MuteBody { muted: bool }
+
+#[derive(Serialize)]
+struct OkBody { ok: bool, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String> }
+
+/// Start the API thread when configured via environment (no-op otherwise).
+pub fn start_from_env(state: LiveState) {
+    if let Some(cfg) = ApiConfig::from_env() {
+        if let Err(e) = start(cfg, state) { log::error!("[API] start failed: {e}"); }
+    }
+}
+
+/// Bind the listener and serve requests on a background thread.
+pub fn start(cfg: ApiConfig, state: LiveState) -> Result<()> {
+    let server = Server::http(cfg.bind.as_str()).map_err(|e| anyhow!("bind {}: {e}", cfg.bind))?;
+    log::info!("[API] listening on http://{}", cfg.bind);
+    thread::Builder::new().name("rm-api".into()).spawn(move || {
+        for req in server.incoming_requests() { let current = state.read().clone(); handle(req, &cfg.token, &current); }
+    })?;
+    Ok(())
+}
+
+fn authorized(req: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {token}");
+    req.headers().iter().any(|h| h.field.equiv("Authorization") && constant_time_eq(h.value.as_str().as_bytes(), expected.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn json<T: Serialize>(code: u16, body: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    let data = serde_json::to_vec(body).unwrap_or_default();
+    Response::from_data(data).with_status_code(code).with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error(code: u16, msg: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json(code, &OkBody { ok: false, error: Some(msg.into()) })
+}
+
+fn handle(mut req: Request, token: &str, state: &ServerState) {
+    if !authorized(&req, token) { let _ = req.respond(error(401, "unauthorized")); return; }
+    let path = req.url().split('?').next().unwrap_or("").to_string();
+    let resp = match (req.method(), path.as_str()) {
+        (Method::Get, "/status") => { let m = state.metrics_snapshot(); json(200, &StatusBody {
+            running: state.running.load(Ordering::Relaxed),
+            stage: state.stage.load(Ordering::Relaxed),
+            muted: state.muted.load(Ordering::Relaxed),
+            clients: m.client_count,
+            rms: m.rms,
+            peak_rms: m.peak,
+            encrypted: state.encrypted(),
+            multicast: format!("{}:{}", state.multicast_addr, state.multicast_port),
+            send_errors: state.send_errors.load(Ordering::Relaxed),
+            oversized_frames: state.oversized_frames.load(Ordering::Relaxed),
+            send_kbps: m.kbps,
+        }) }
+        (Method::Get, "/clients") => {
+            let now = Instant::now();
+            let list: Vec<ClientBody> = state.clients.iter().map(|c| ClientBody { addr: c.addr.to_string(), last_seen_ms: now.duration_since(c.last_seen).as_millis() as u64, udp_port: c.udp_port, substream: match c.substream { server::Substream::Multicast => "multicast", server::Substream::LowQuality => "low_quality", server::Substream::Unicast => "unicast", server::Substream::Tcp => "tcp" } }).collect();
+            json(200, &list)
+        }
+        (Method::Post, "/server/stop") => { server::stop_server(state); json(200, &OkBody { ok: true, error: None }) }
+        (Method::Post, "/server/mute") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            match serde_json::from_str::<MuteBody>(&body) {
+                Ok(b) => { state.muted.store(b.muted, Ordering::Relaxed); json(200, &OkBody { ok: true, error: None }) }
+                Err(_) => error(400, "expected {\"muted\":true|false}"),
+            }
+        }
+        (Method::Post, "/server/rekey") => match state.keys.lock().as_mut() {
+            Some(rotation) => { rotation.rotate_now(); json(200, &OkBody { ok: true, error: None }) }
+            None => error(409, "encryption is off"),
+        },
+        (Method::Post, "/clients/kick") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            match serde_json::from_str::<KickBody>(&body).ok().and_then(|b| b.addr.parse::<SocketAddr>().ok()) {
+                Some(addr) if server::kick_client(state, &addr) => json(200, &OkBody { ok: true, error: None }),
+                Some(_) => error(404, "no such client"),
+                None => error(400, "expected {\"addr\":\"ip:port\"}"),
+            }
+        }
+        _ => error(404, "not found"),
+    };
+    let _ = req.respond(resp);
+}
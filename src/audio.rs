@@ -1,16 +1,21 @@
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, SampleFormat, StreamConfig};
+use cpal::{BufferSize, Device, SampleFormat, StreamConfig, SupportedBufferSize, SupportedStreamConfig};
 use crossbeam_channel::Sender;
 use std::sync::{
     atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
+use std::time::{Duration, Instant};
 
 use crate::buffers::AudioBufferPool;
+use crate::agc::{Agc, AgcSettings};
+use crate::gate::{GateSettings, NoiseGate};
+use crate::server::AtomicF64;
+use crate::{convert, types};
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Basic negotiated audio stream parameters.
 pub struct AudioParams {
     pub sample_rate: u32,
@@ -23,100 +28,281 @@ pub struct AudioParams {
 pub struct InputStreamHandle {
     pub stream: cpal::Stream,
     pub params: AudioParams,
+    /// Set by the error callback when the device disappears (unplugged): the stream is dead.
+    pub lost: Arc<AtomicBool>,
 }
 
-/// Enumerate available input and output devices.
-pub fn list_devices() -> Result<(Vec<Device>, Vec<Device>)> {
+/// Hosts where cpal opens an input stream on an output device in loopback mode (WASAPI), i.e.
+/// captures what the device is playing.
+pub const LOOPBACK_SUPPORTED: bool = cfg!(target_os = "windows");
+/// Appended to the name of an output device offered as a capture source (see `capture_name`).
+pub const LOOPBACK_SUFFIX: &str = " (loopback)";
+
+/// Enumerate available input and output devices (system default of each list moved to index 0).
+/// With `loopback` (and host support) the output devices are also appended to the inputs as
+/// loopback capture sources.
+pub fn list_devices(loopback: bool) -> Result<(Vec<Device>, Vec<Device>)> {
     let host = cpal::default_host();
-    let inputs: Vec<_> = host.input_devices().context("input_devices")?.collect();
-    let outputs: Vec<_> = host.output_devices().context("output_devices")?.collect();
+    let mut inputs: Vec<_> = host.input_devices().context("input_devices")?.collect();
+    let mut outputs: Vec<_> = host.output_devices().context("output_devices")?.collect();
+    let (def_in, def_out) = default_device_names();
+    promote_default(&mut inputs, def_in.as_deref());
+    promote_default(&mut outputs, def_out.as_deref());
+    if loopback && LOOPBACK_SUPPORTED { inputs.extend(outputs.iter().cloned()); }
     Ok((inputs, outputs))
 }
 
+/// Names of the host's default input / output devices (None if the host reports none).
+pub fn default_device_names() -> (Option<String>, Option<String>) {
+    let host = cpal::default_host();
+    (host.default_input_device().map(|d| device_name(&d)), host.default_output_device().map(|d| device_name(&d)))
+}
+
+/// The host's current default input device (None while the OS reports no default).
+pub fn default_input_device() -> Option<Device> {
+    cpal::default_host().default_input_device()
+}
+
+/// The host's current default output device (None while the OS reports no default).
+pub fn default_output_device() -> Option<Device> {
+    cpal::default_host().default_output_device()
+}
+
+/// Capture source with exactly this `capture_name` (None if it is gone).
+pub fn input_device_by_name(name: &str) -> Option<Device> {
+    match name.strip_suffix(LOOPBACK_SUFFIX).filter(|_| LOOPBACK_SUPPORTED) {
+        Some(output) => cpal::default_host().output_devices().ok()?.find(|d| device_name(d) == output),
+        None => cpal::default_host().input_devices().ok()?.find(|d| device_name(d) == name),
+    }
+}
+
+/// Output device with exactly this name (None if it is gone).
+pub fn output_device_by_name(name: &str) -> Option<Device> {
+    cpal::default_host().output_devices().ok()?.find(|d| device_name(d) == name)
+}
+
+/// Move the device matching `name` to the front, keeping the relative order of the rest.
+fn promote_default(devs: &mut Vec<Device>, name: Option<&str>) {
+    if let Some(pos) = name.and_then(|n| devs.iter().position(|d| device_name(d) == n)) {
+        let dev = devs.remove(pos);
+        devs.insert(0, dev);
+    }
+}
+
 /// Best-effort device name (fallback to "<unknown>").
 pub fn device_name(dev: &Device) -> String {
     dev.name().unwrap_or_else(|_| "<unknown>".into())
 }
 
+/// Whether capturing from `dev` means loopback (an output device, on a host that supports it).
+pub fn is_loopback(dev: &Device) -> bool {
+    LOOPBACK_SUPPORTED && dev.default_input_config().is_err() && dev.default_output_config().is_ok()
+}
+
+/// Device name as shown in capture source lists (`LOOPBACK_SUFFIX` for loopback sources).
+pub fn capture_name(dev: &Device) -> String {
+    if is_loopback(dev) { format!("{}{LOOPBACK_SUFFIX}", device_name(dev)) } else { device_name(dev) }
+}
+
+/// Default capture config of `dev`: its input config, or its output (mix) format for loopback.
+fn capture_config(dev: &Device) -> Result<SupportedStreamConfig> {
+    match dev.default_input_config() {
+        Ok(cfg) => Ok(cfg),
+        Err(e) if LOOPBACK_SUPPORTED => dev.default_output_config().map_err(|_| e.into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parameters a capture stream on `dev` would negotiate (without opening it).
+pub fn default_input_params(dev: &Device) -> Result<AudioParams> {
+    let cfg = capture_config(dev)?;
+    Ok(AudioParams { sample_rate: cfg.sample_rate().0, channels: cfg.channels(), sample_format: cfg.sample_format() })
+}
+
+/// Stream error callback: logs with `tag`; `DeviceNotAvailable` (hot-unplug) also sets `lost` for
+/// the thread owning the stream to react to (errors arrive on the audio thread).
+pub fn stream_error_callback(tag: &'static str, lost: Arc<AtomicBool>) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    move |e| {
+        log::warn!("{tag} {e}");
+        if matches!(e, cpal::StreamError::DeviceNotAvailable) { lost.store(true, Ordering::Relaxed); }
+    }
+}
+
+/// Frames-per-callback choices offered for capture (0 = device default).
+pub const CAPTURE_FRAME_CHOICES: [u32; 5] = [0, 128, 256, 512, 1024];
+/// Stream sample rates offered for the server (0 = send at the capture rate).
+pub const STREAM_RATE_CHOICES: [u32; 6] = [0, 16_000, 24_000, 32_000, 44_100, 48_000];
+
+/// `frames` clamped to the callback sizes `dev` supports for `cfg`'s channels / format / rate
+/// (`supported_input_configs`, output configs for loopback, else the default config's range;
+/// unknown range = as requested).
+fn supported_input_frames(dev: &Device, cfg: &SupportedStreamConfig, frames: u32, loopback: bool) -> u32 {
+    let rate = cfg.sample_rate();
+    let all: Option<Vec<_>> = if loopback { dev.supported_output_configs().ok().map(Iterator::collect) } else { dev.supported_input_configs().ok().map(Iterator::collect) };
+    let matching = all.and_then(|all| all.into_iter().find(|c| c.channels() == cfg.channels() && c.sample_format() == cfg.sample_format() && c.min_sample_rate() <= rate && rate <= c.max_sample_rate()));
+    match matching.map(|c| *c.buffer_size()).unwrap_or(*cfg.buffer_size()) {
+        SupportedBufferSize::Range { min, max } => frames.clamp(min, max.max(min)),
+        SupportedBufferSize::Unknown => frames,
+    }
+}
+
+/// Check a capture channel selection (0-based, empty = all) against a device with `available`
+/// channels; duplicates are dropped, order is kept.
+pub fn channel_selection(channels: &[u16], available: u16) -> Result<Vec<usize>> {
+    if let Some(&c) = channels.iter().find(|&&c| c >= available) { anyhow::bail!("input channel {} not available (device has {available})", c + 1); }
+    let mut pick: Vec<usize> = Vec::new();
+    for &c in channels { if !pick.contains(&(c as usize)) { pick.push(c as usize); } }
+    Ok(pick)
+}
+
 #[allow(dead_code)]
 /// Build and start a CPAL input stream. Captured chunks are copied into a buffer
 /// from the pool: first 4 bytes store payload length (LE) then raw sample bytes.
+/// `gain` (linear, read every callback) is applied to the copied samples, or the `agc` gain while
+/// that is enabled; `muted` replaces them with silence (buffers are still sent so clients stay in
+/// sync). The noise `gate` then silences chunks whose RMS stays below its threshold.
+/// `buffer_frames` > 0 asks for that many frames per callback (lower latency, more wakeups).
+/// `channels` (0-based device channels, empty = all) keeps only those, in that order: the stream
+/// params then advertise that many channels.
+#[allow(clippy::too_many_arguments)]
 pub fn build_input_stream(
     dev: &Device,
     pool: Arc<AudioBufferPool>,
     send_ready: Sender<usize>,
     running: Arc<AtomicBool>,
+    gain: Arc<AtomicF64>,
+    muted: Arc<AtomicBool>,
+    agc: AgcSettings,
+    gate: GateSettings,
+    buffer_frames: u32,
+    channels: &[u16],
 ) -> Result<InputStreamHandle> {
-    let cfg = dev.default_input_config()?;
+    // Output device on a loopback-capable host: cpal captures what it plays
+    let loopback = is_loopback(dev);
+    let cfg = capture_config(dev)?;
     let sample_format = cfg.sample_format();
-    let config: StreamConfig = cfg.clone().into();
-    let params = AudioParams { sample_rate: config.sample_rate.0, channels: config.channels, sample_format };
+    let mut config: StreamConfig = cfg.clone().into();
+    let pick = channel_selection(channels, config.channels)?;
+    let params = AudioParams { sample_rate: config.sample_rate.0, channels: if pick.is_empty() { config.channels } else { pick.len() as u16 }, sample_format };
+    if !pick.is_empty() { log::info!("[AUDIO] capturing channel(s) {:?} of {}", pick.iter().map(|c| c + 1).collect::<Vec<_>>(), config.channels); }
+    // Size pool buffers for this device's callbacks (largest reported period, capped at 200ms; 20ms if unknown)
+    let mut frames = match cfg.buffer_size() { SupportedBufferSize::Range { max, .. } => (*max).min(params.sample_rate / 5), SupportedBufferSize::Unknown => params.sample_rate / 50 };
+    if buffer_frames > 0 {
+        let n = supported_input_frames(dev, &cfg, buffer_frames, loopback);
+        if n != buffer_frames { log::info!("[AUDIO] {buffer_frames} frames per callback not supported by the device -> {n}"); }
+        config.buffer_size = BufferSize::Fixed(n);
+        frames = n;
+    }
+    if pool.ensure_size(AudioBufferPool::size_for(&params, frames as usize)) { log::info!("[AUDIO] buffer pool grown to {} bytes", pool.buffer_size()); }
     let counter = Arc::new(AtomicU64::new(0));
+    let lost = Arc::new(AtomicBool::new(false));
 
     // Each callback -> one buffer. First 4 bytes length (LE). Remaining bytes = packed raw samples.
-    let make_callback = |_bytes_per_sample: usize| {
-        let pool = pool.clone(); let send_ready = send_ready.clone(); let running = running.clone(); let counter = counter.clone();
+    let make_callback = |fmt: SampleFormat| {
+        let pool = pool.clone(); let send_ready = send_ready.clone(); let running = running.clone(); let counter = counter.clone(); let gain = gain.clone(); let muted = muted.clone(); let agc = agc.clone(); let gate = gate.clone(); let pick = pick.clone();
+        let fmt_code = types::sample_format_code(fmt);
+        let device_channels = config.channels.max(1) as usize;
+        let bytes_per_sec = (params.sample_rate.max(1) as usize * params.channels.max(1) as usize * fmt.sample_size()) as f64;
+        let mut noise_gate = NoiseGate::new();
+        let mut auto_gain = Agc::new();
         move |raw: &[u8]| {
             if !running.load(Ordering::Relaxed) { return; }
-            if let Some(idx) = pool.pop() {
+            if let Some(idx) = pool.acquire() {
                 let mut guard = pool.data[idx].lock();
                 let buf_slice: &mut [u8] = &mut *guard;
                 if buf_slice.len() < 5 { return; }
                 let max_payload = buf_slice.len()-4;
-                let to_copy = raw.len().min(max_payload);
+                let to_copy = if pick.is_empty() {
+                    let to_copy = raw.len().min(max_payload);
+                    if to_copy < raw.len() { pool.note_truncated(raw.len()); }
+                    unsafe { std::ptr::copy_nonoverlapping(raw.as_ptr(), buf_slice[4..].as_mut_ptr(), to_copy); }
+                    to_copy
+                } else {
+                    // Channel selection: only the chosen channels of each frame go into the buffer
+                    let wanted = raw.len() / device_channels * pick.len();
+                    let n = convert::select_channels(raw, &mut buf_slice[4..], device_channels, fmt.sample_size(), &pick);
+                    if n < wanted { pool.note_truncated(wanted); }
+                    n
+                };
                 // write length
                 let len_le = (to_copy as u32).to_le_bytes();
                 buf_slice[0..4].copy_from_slice(&len_le);
-                unsafe { std::ptr::copy_nonoverlapping(raw.as_ptr(), buf_slice[4..].as_mut_ptr(), to_copy); }
+                // Gain 0 = format-correct silence (u16 midpoint, not zero bytes)
+                let g = if muted.load(Ordering::Relaxed) { 0.0 } else if agc.enabled.load(Ordering::Relaxed) {
+                    // AGC replaces the manual gain, steered by the chunk's level before any gain
+                    let dur = Duration::from_secs_f64(to_copy as f64 / bytes_per_sec);
+                    let g = auto_gain.process(convert::rms(&buf_slice[4..4 + to_copy], fmt_code), agc.target_db.load(), dur);
+                    agc.gain.store(g);
+                    g as f32
+                } else { gain.load() as f32 };
+                convert::apply_gain(&mut buf_slice[4..4 + to_copy], fmt_code, g);
+                if gate.enabled.load(Ordering::Relaxed) {
+                    let hold = Duration::from_millis(gate.hold_ms.load(Ordering::Relaxed));
+                    let open = noise_gate.process(convert::rms(&buf_slice[4..4 + to_copy], fmt_code), gate.threshold_db.load(), hold, Instant::now());
+                    if !open { convert::apply_gain(&mut buf_slice[4..4 + to_copy], fmt_code, 0.0); }
+                    gate.open.store(open, Ordering::Relaxed);
+                } else { gate.open.store(true, Ordering::Relaxed); }
                 let _ = send_ready.send(idx);
                 let n = counter.fetch_add(1, Ordering::Relaxed) + 1;
-                if n % 100 == 0 { println!("[AUDIO] {} chunks", n); }
-            } else {
-                // drop if no free buffer
-            }
+                if n % 100 == 0 { log::debug!("[AUDIO] {} chunks", n); }
+            } // no free buffer: chunk dropped (counted by `acquire`)
         }
     };
 
     let stream = match sample_format {
         SampleFormat::F32 => {
-            let cb = make_callback(4);
+            let mut cb = make_callback(SampleFormat::F32);
             dev.build_input_stream(&config, move |data: &[f32], _| {
                 let raw = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*4) };
                 cb(raw);
-            }, move |e| eprintln!("[AUDIO][ERR] {e}"), None)?
+            }, stream_error_callback("[AUDIO][ERR]", lost.clone()), None)?
         }
         SampleFormat::I16 => {
-            let cb = make_callback(2);
+            let mut cb = make_callback(SampleFormat::I16);
             dev.build_input_stream(&config, move |data: &[i16], _| {
                 let raw = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*2) };
                 cb(raw);
-            }, move |e| eprintln!("[AUDIO][ERR] {e}"), None)?
+            }, stream_error_callback("[AUDIO][ERR]", lost.clone()), None)?
         }
         SampleFormat::U16 => {
-            let cb = make_callback(2);
+            let mut cb = make_callback(SampleFormat::U16);
             dev.build_input_stream(&config, move |data: &[u16], _| {
                 let raw = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*2) };
                 cb(raw);
-            }, move |e| eprintln!("[AUDIO][ERR] {e}"), None)?
+            }, stream_error_callback("[AUDIO][ERR]", lost.clone()), None)?
+        }
+        SampleFormat::I32 => {
+            let mut cb = make_callback(SampleFormat::I32);
+            dev.build_input_stream(&config, move |data: &[i32], _| {
+                let raw = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*4) };
+                cb(raw);
+            }, stream_error_callback("[AUDIO][ERR]", lost.clone()), None)?
+        }
+        SampleFormat::F64 => {
+            let mut cb = make_callback(SampleFormat::F64);
+            dev.build_input_stream(&config, move |data: &[f64], _| {
+                let raw = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*8) };
+                cb(raw);
+            }, stream_error_callback("[AUDIO][ERR]", lost.clone()), None)?
         }
         other => {
-            println!(
+            log::info!(
                 "[AUDIO] Unsupported sample format {:?}, falling back via f32 conversion",
                 other
             );
-            let cb = make_callback(4);
+            let mut cb = make_callback(SampleFormat::F32);
             dev.build_input_stream(&config, move |data: &[f32], _| {
                 let raw = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len()*4) };
                 cb(raw);
-            }, move |e| eprintln!("[AUDIO][ERR] {e}"), None)?
+            }, stream_error_callback("[AUDIO][ERR]", lost.clone()), None)?
         }
     };
     stream.play()?;
-    println!(
-        "[AUDIO] Input stream running: {} Hz, {} ch, {:?}",
-        params.sample_rate, params.channels, params.sample_format
+    log::info!(
+        "[AUDIO] Input stream running: {} Hz, {} ch, {:?}{}",
+        params.sample_rate, params.channels, params.sample_format, if loopback { " (loopback)" } else { "" }
     );
-    Ok(InputStreamHandle { stream, params })
+    Ok(InputStreamHandle { stream, params, lost })
 }
 
 #[allow(dead_code)]
@@ -155,7 +341,7 @@ pub fn build_output_stream(
             }
         },
         move |err| {
-            eprintln!("Output stream error: {err}");
+            log::error!("Output stream error: {err}");
         },
         None,
     )?;
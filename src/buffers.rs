@@ -1,9 +1,11 @@
 use crossbeam_channel::{Receiver, Sender};
 use crossbeam_channel as channel;
 use parking_lot::Mutex;
-use std::sync::Arc;
+use std::sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc};
 
-/// Fixed-size reusable audio buffer pool (lock-per-buffer + free index queue).
+use crate::audio::AudioParams;
+
+/// Reusable audio buffer pool (lock-per-buffer + free index queue).
 /// First 4 bytes in each buffer are reserved for payload length (little endian).
 const DEFAULT_BUFFER_SIZE: usize = 4096 * 4; // matches usage in GUI; includes header room
 pub struct AudioBufferPool {
@@ -11,19 +13,59 @@ pub struct AudioBufferPool {
     free_rx: Receiver<usize>,
     /// Underlying raw byte storage guarded by lightweight mutexes.
     pub data: Vec<Mutex<Vec<u8>>>,
+    /// Capture chunks cut short because they did not fit a buffer (samples were dropped).
+    pub truncated: AtomicU64,
+    /// Capture chunks dropped because every buffer was in use (see `acquire`).
+    exhausted: AtomicU64,
+    /// Largest buffer size a truncated chunk needed (applied by `grow_to_wanted`).
+    wanted: AtomicUsize,
 }
 
 impl AudioBufferPool {
     /// Create a new pool with `count` buffers using the default size.
-    pub fn new(count: usize) -> Arc<Self> {
-        let size = DEFAULT_BUFFER_SIZE;
+    pub fn new(count: usize) -> Arc<Self> { Self::with_size(count, DEFAULT_BUFFER_SIZE) }
+
+    /// Create a new pool with `count` buffers of `size` bytes (length prefix included).
+    pub fn with_size(count: usize, size: usize) -> Arc<Self> {
         let (tx, rx) = channel::bounded(count);
         let mut data = Vec::with_capacity(count);
         for i in 0..count {
             data.push(Mutex::new(vec![0u8; size]));
             tx.send(i).unwrap();
         }
-        Arc::new(Self { free_tx: tx, free_rx: rx, data })
+        Arc::new(Self { free_tx: tx, free_rx: rx, data, truncated: AtomicU64::new(0), exhausted: AtomicU64::new(0), wanted: AtomicUsize::new(0) })
+    }
+
+    /// Buffer size for capture callbacks of `frames` frames in `params` format: 2x headroom
+    /// plus the length prefix, never below the default.
+    pub fn size_for(params: &AudioParams, frames: usize) -> usize {
+        (frames * params.channels.max(1) as usize * params.sample_format.sample_size() * 2 + 4).max(DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Current buffer size in bytes (length prefix included).
+    pub fn buffer_size(&self) -> usize { self.data.first().map(|b| b.lock().len()).unwrap_or(0) }
+
+    /// Grow every buffer to at least `size` bytes (never shrinks); true if anything grew.
+    /// Must not be called while holding one of the buffer locks.
+    pub fn ensure_size(&self, size: usize) -> bool {
+        let mut grew = false;
+        for buf in &self.data {
+            let mut b = buf.lock();
+            if b.len() < size { b.resize(size, 0); grew = true; }
+        }
+        grew
+    }
+
+    /// Count a capture chunk of `payload` bytes that did not fit (audio callback: no allocation here).
+    pub fn note_truncated(&self, payload: usize) {
+        self.truncated.fetch_add(1, Ordering::Relaxed);
+        self.wanted.fetch_max(payload + 4, Ordering::Relaxed);
+    }
+
+    /// Grow the pool for chunks that were truncated since the last call (run off the audio thread).
+    pub fn grow_to_wanted(&self) -> bool {
+        let wanted = self.wanted.swap(0, Ordering::Relaxed);
+        wanted > 0 && self.ensure_size(wanted + wanted / 2)
     }
 
     /// Try acquire a free buffer index (non-blocking).
@@ -31,9 +73,37 @@ impl AudioBufferPool {
         self.free_rx.try_recv().ok()
     }
 
+    /// `pop` for the capture callback: an exhausted pool counts the dropped chunk.
+    pub fn acquire(&self) -> Option<usize> {
+        let idx = self.pop();
+        if idx.is_none() { self.exhausted.fetch_add(1, Ordering::Relaxed); }
+        idx
+    }
+
+    /// Capture chunks dropped so far because no buffer was free (server can't keep up).
+    pub fn capture_drops(&self) -> u64 { self.exhausted.load(Ordering::Relaxed) }
+
     /// Return a buffer index to the free queue.
     pub fn push(&self, idx: usize) {
         let _ = self.free_tx.send(idx);
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhaustion_is_counted_until_a_buffer_returns() {
+        let pool = AudioBufferPool::with_size(2, 64);
+        let held: Vec<usize> = (0..2).map(|_| pool.acquire().expect("free buffer")).collect();
+        assert_eq!(pool.capture_drops(), 0);
+        assert!(pool.acquire().is_none());
+        assert!(pool.acquire().is_none());
+        assert_eq!(pool.capture_drops(), 2);
+        pool.push(held[0]);
+        assert_eq!(pool.acquire(), Some(held[0]));
+        assert_eq!(pool.capture_drops(), 2);
+    }
+}
@@ -1,12 +1,21 @@
-//! Client side: TCP control + UDP receive + jitter buffer + playback.
-use std::{net::{TcpStream, SocketAddr, UdpSocket, Ipv4Addr}, thread, time::Duration, sync::{Arc, atomic::{AtomicBool, Ordering}, Mutex}}; use std::io::Write;
-use sha2::{Sha256, Digest};
-use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305};
+//! Client side: TCP control + UDP (or TCP transport) receive + jitter buffer + playback.
+use std::{net::{TcpStream, SocketAddr, UdpSocket, IpAddr, Ipv4Addr, Ipv6Addr}, thread, time::Duration, sync::{Arc, atomic::{AtomicBool, AtomicU8, AtomicU16, AtomicU32, AtomicU64, Ordering}, Mutex}}; use std::io::Write;
 use crate::audio; // bring module into scope
 use anyhow::Result;
 use crossbeam_channel::{unbounded, Sender, Receiver};
 use crate::audio::AudioParams;
-use crate::types;
+use crate::{convert, net, record, types::{self, Transport}};
+use crate::session::{SessionCheck, SessionLimit};
+use crate::meter::{ChannelLevels, PeakMeter, Scope};
+use crate::degrade::{self, Degrader, Optional};
+use crate::control::{ControlMessage, LineBuffer};
+use crate::codec::{Codec, OpusStreamDecoder};
+use crate::fec::FecDecoder;
+use crate::limiter::Limiter;
+use crate::quality::Quality;
+use crate::loudness::{Loudness, LoudnessSettings};
+use crate::secret::PskSource;
+use crate::rekey::{self, KeyRing};
 use cpal::traits::{DeviceTrait, StreamTrait};
 use crossbeam_channel::Sender as CbSender;
 use tokio::sync::mpsc::UnboundedSender as EventSender;
@@ -14,30 +23,70 @@ use tokio::sync::mpsc::UnboundedSender as EventSender;
 /// Aggregated client runtime state shared across helper threads.
 pub struct ClientState {
     pub connected: Arc<AtomicBool>,
+    pub stage: Arc<AtomicU8>, // 0=handshaking,1=joining,2=waiting_audio,3=streaming,4=playing
     pub params: Option<AudioParams>,
+    pub stream_rate: Arc<AtomicU32>, // rate frames are resampled to for playback (handshake, then `PARAMS`); the output stream follows it
     pub key: Option<String>,
     pub server: Option<SocketAddr>,
     pub udp_local: Option<SocketAddr>,
-    pub multicast_addr: Option<(Ipv4Addr, u16)>,
+    pub multicast_addr: Option<(IpAddr, u16)>, // group advertised in the handshake (IPv4 or IPv6)
     pub audio_tx: Option<Sender<Vec<f32>>>,
     pub output_running: Arc<AtomicBool>,
+    pub output_watchdog_ms: Arc<AtomicU64>, // stalled-callback threshold (0 = watchdog off)
+    pub output_restarts: Arc<AtomicU64>,    // watchdog-triggered stream rebuilds
+    pub warmup_log: Arc<AtomicBool>,        // verbose per-callback prebuffer progress logging
     pub udp_thread_alive: Arc<AtomicBool>,
+    pub udp_sock: Option<UdpSocket>,   // receive socket handle (multicast membership changes)
+    pub membership: GroupMembership,   // joined multicast group, left on disconnect / receive thread exit / low-quality switch
+    pub low_quality: Arc<AtomicBool>,  // on the unicast low-quality substream instead of multicast
+    pub unicast_fallback: bool,        // group join failed: server copies multicast frames to us by unicast
+    pub transport: Transport,          // Tcp: frames arrive on the control connection (no UDP socket)
+    pub tcp_frames: Option<Receiver<Vec<u8>>>, // TCP transport frames from the control reader, taken by the receive thread
     pub ctrl: Option<Arc<std::sync::Mutex<TcpStream>>>,
     pub output_stop_tx: Arc<Mutex<Option<CbSender<()>>>>, 
     pub disconnection_reason: Arc<Mutex<Option<String>>>,
-    pub event_sender: Option<EventSender<String>>,
+    pub event_sender: Option<EventSender<ClientEvent>>,
+    pub target: Option<ConnectTarget>, // connect_with_output arguments (auto-reconnect reuses them)
+    pub session: SessionLimit, // optional max connection time (auto disconnect)
     // metrics shared with GUI
     pub avg_latency_ms: Arc<AtomicF64>,
+    pub rtt_ms: Arc<AtomicF64>,        // control channel round trip (smoothed, ms; 0 = not measured), independent of clocks
     pub jitter_ms: Arc<AtomicF64>,
     pub packet_loss: Arc<AtomicF64>, // ratio 0..1
     pub late_drop: Arc<AtomicF64>,   // count (as f64)
+    pub buffer_ms: Arc<AtomicF64>,        // jitter buffer fill (audio waiting in the reorder heap, ms)
+    pub target_buffer_ms: Arc<AtomicF64>, // adaptive fill the buffer releases at (ms)
+    pub max_buffer_ms: Arc<AtomicF64>,    // fill past which frames are released regardless (ms)
+    pub quality: Arc<AtomicU8>,           // `Quality` badge (as_u8) graded on each metrics push
+    pub muted: Arc<AtomicBool>,        // local playback mute (stream, metrics and recording continue)
+    pub output_gain: Arc<AtomicF64>,   // local playback volume (linear, 1.0 = 100%, up to `OUTPUT_GAIN_MAX`), applied in the output callback
+    pub loudness: LoudnessSettings,    // loudness normalization toward a target LUFS (output callback, before the volume)
+    pub output_limiter: Arc<AtomicBool>, // soft limiter after the volume (default on; off = plain clamp to [-1, 1])
+    pub latency_profile: Arc<AtomicU8>, // jitter buffer tuning (`LatencyProfile::as_u8`), read per frame by the UDP thread
+    pub plc_enabled: Arc<AtomicBool>, // 丢包隐藏: 用上一帧淡出填补序号缺口 (而非直接跳过)
+    pub plc_concealed: Arc<AtomicU64>, // concealed (synthesized) frames
+    pub fec_group: u8,                 // parity group size announced in the handshake (`FEC <k>`, 0 = none)
+    pub fec_recovered: Arc<AtomicU64>, // frames rebuilt from parity
+    pub fec_recovery: Arc<AtomicF64>,  // rebuilt / lost (seq gaps), 0..1
+    pub sync_start: Arc<AtomicBool>,   // multi-room: delay first playout to a shared server-timeline boundary
+    pub sync_start_ms: Arc<AtomicU64>, // chosen start position on the server timeline (ms, 0 = not scheduled)
+    pub sync_start_at: Arc<Mutex<Option<std::time::Instant>>>, // local instant of the scheduled start
+    pub frame_channels: Arc<AtomicU16>, // channel count carried by the latest audio frame (handshake value until then)
+    pub force_mono: Arc<AtomicBool>,    // down-mix to mono (copied to every output channel) instead of channel mapping
+    pub stereo_width: Arc<AtomicF64>,   // mid/side width of the front pair (0 = mono, 1 = original, up to `STEREO_WIDTH_MAX`), applied in the output callback
+    pub output_channels: u16,           // channel layout frames are mapped to (output device)
+    pub recording: Arc<Mutex<Option<record::Recording>>>, // played audio -> WAV / MP3 (written by the UDP thread)
     pub current_rms: Arc<AtomicF64>,
     pub peak_rms: Arc<AtomicF64>, // 带衰减的峰值 (RMS)
+    pub channel_rms: Arc<ChannelLevels>, // 各声道 RMS (混音 / 声道映射之前)
+    pub scope: Arc<Scope>,              // 最近 `SCOPE_LEN` 个单声道样本 (波形显示)
+    pub spectrum_shed: Arc<AtomicBool>, // CPU 压力降级舍弃了频谱 (`Optional::Spectrum`): GUI 每 4 次刷新才计算一次 FFT
+    pub dtx_silent: Arc<AtomicBool>,    // 最近收到的是静音标记 (服务器静音抑制中)
     // encryption
     pub enc_enabled: bool,
-    pub enc_salt: Option<[u8;8]>,
-    pub enc_key: Option<[u8;32]>,
-    pub decrypt_fail: Arc<std::sync::atomic::AtomicU64>, // decrypt failures counter
+    pub enc_keys: Arc<Mutex<KeyRing>>, // session keys by epoch (handshake + `REKEY`), read by the UDP thread
+    pub decrypt_fail: Arc<std::sync::atomic::AtomicU64>, // decrypt failures counter (per connection: starts at 0 with each fresh state)
+    pub replay_drop: Arc<AtomicU64>, // authenticated frames dropped as replays (seq repeated / older than the window)
     pub enc_status: Arc<std::sync::atomic::AtomicI32>,   // encryption status: 0=plain 1=ok -1=key error
 }
 
@@ -46,25 +95,148 @@ pub struct ClientState {
 pub struct AtomicF64(std::sync::atomic::AtomicU64);
 impl AtomicF64 { pub fn new(v:f64)->Self { Self(std::sync::atomic::AtomicU64::new(v.to_bits())) } pub fn load(&self)->f64 { f64::from_bits(self.0.load(Ordering::Relaxed)) } pub fn store(&self,v:f64){ self.0.store(v.to_bits(), Ordering::Relaxed); } }
 
-impl ClientState { pub fn new() -> Self { Self { connected: Arc::new(AtomicBool::new(false)), params: None, key: None, server: None, udp_local: None, multicast_addr: None, audio_tx: None, output_running: Arc::new(AtomicBool::new(false)), udp_thread_alive: Arc::new(AtomicBool::new(false)), ctrl: None, output_stop_tx: Arc::new(Mutex::new(None)), disconnection_reason: Arc::new(Mutex::new(None)), event_sender: None, avg_latency_ms: Arc::new(AtomicF64::new(0.0)), jitter_ms: Arc::new(AtomicF64::new(0.0)), packet_loss: Arc::new(AtomicF64::new(0.0)), late_drop: Arc::new(AtomicF64::new(0.0)), current_rms: Arc::new(AtomicF64::new(0.0)), peak_rms: Arc::new(AtomicF64::new(0.0)), enc_enabled: false, enc_salt: None, enc_key: None, decrypt_fail: Arc::new(std::sync::atomic::AtomicU64::new(0)), enc_status: Arc::new(std::sync::atomic::AtomicI32::new(0)) } } 
+impl ClientState { pub fn new() -> Self { Self { connected: Arc::new(AtomicBool::new(false)), stage: Arc::new(AtomicU8::new(0)), params: None, stream_rate: Arc::new(AtomicU32::new(0)), key: None, server: None, udp_local: None, multicast_addr: None, audio_tx: None, output_running: Arc::new(AtomicBool::new(false)), output_watchdog_ms: Arc::new(AtomicU64::new(OUTPUT_WATCHDOG_DEFAULT_MS)), output_restarts: Arc::new(AtomicU64::new(0)), warmup_log: Arc::new(AtomicBool::new(false)), udp_thread_alive: Arc::new(AtomicBool::new(false)), udp_sock: None, membership: GroupMembership::default(), low_quality: Arc::new(AtomicBool::new(false)), unicast_fallback: false, transport: Transport::Udp, tcp_frames: None, ctrl: None, output_stop_tx: Arc::new(Mutex::new(None)), disconnection_reason: Arc::new(Mutex::new(None)), event_sender: None, target: None, session: SessionLimit::new(), avg_latency_ms: Arc::new(AtomicF64::new(0.0)), rtt_ms: Arc::new(AtomicF64::new(0.0)), jitter_ms: Arc::new(AtomicF64::new(0.0)), packet_loss: Arc::new(AtomicF64::new(0.0)), late_drop: Arc::new(AtomicF64::new(0.0)), buffer_ms: Arc::new(AtomicF64::new(0.0)), target_buffer_ms: Arc::new(AtomicF64::new(0.0)), max_buffer_ms: Arc::new(AtomicF64::new(0.0)), quality: Arc::new(AtomicU8::new(Quality::Unknown.as_u8())), muted: Arc::new(AtomicBool::new(false)), output_gain: Arc::new(AtomicF64::new(1.0)), loudness: LoudnessSettings::new(), output_limiter: Arc::new(AtomicBool::new(true)), latency_profile: Arc::new(AtomicU8::new(LatencyProfile::Balanced.as_u8())), plc_enabled: Arc::new(AtomicBool::new(true)), plc_concealed: Arc::new(AtomicU64::new(0)), fec_group: 0, fec_recovered: Arc::new(AtomicU64::new(0)), fec_recovery: Arc::new(AtomicF64::new(0.0)), sync_start: Arc::new(AtomicBool::new(false)), sync_start_ms: Arc::new(AtomicU64::new(0)), sync_start_at: Arc::new(Mutex::new(None)), frame_channels: Arc::new(AtomicU16::new(0)), force_mono: Arc::new(AtomicBool::new(false)), stereo_width: Arc::new(AtomicF64::new(1.0)), output_channels: 0, recording: Arc::new(Mutex::new(None)), current_rms: Arc::new(AtomicF64::new(0.0)), peak_rms: Arc::new(AtomicF64::new(0.0)), channel_rms: Arc::new(ChannelLevels::new()), scope: Arc::new(Scope::new()), spectrum_shed: Arc::new(AtomicBool::new(false)), dtx_silent: Arc::new(AtomicBool::new(false)), enc_enabled: false, enc_keys: Arc::new(Mutex::new(KeyRing::default())), decrypt_fail: Arc::new(std::sync::atomic::AtomicU64::new(0)), replay_drop: Arc::new(AtomicU64::new(0)), enc_status: Arc::new(std::sync::atomic::AtomicI32::new(0)) } } 
     pub fn update_enc_status(&self, new: i32) { if self.enc_status.load(Ordering::Relaxed) != new { self.enc_status.store(new, Ordering::Relaxed); } }
+    /// Read the live metrics once (headless status line, tests); the GUI still reads the atomics it draws.
+    pub fn metrics_snapshot(&self) -> ClientMetrics {
+        ClientMetrics { latency_ms: self.avg_latency_ms.load(), jitter_ms: self.jitter_ms.load(), loss: self.packet_loss.load(), late_drop: self.late_drop.load() as u64, buffer_ms: self.buffer_ms.load(), target_buffer_ms: self.target_buffer_ms.load(), max_buffer_ms: self.max_buffer_ms.load(), quality: Quality::from_u8(self.quality.load(Ordering::Relaxed)), rms: self.current_rms.load(), peak: self.peak_rms.load(), decrypt_fail: self.decrypt_fail.load(Ordering::Relaxed), enc_status: self.enc_status.load(Ordering::Relaxed) }
+    }
+}
+
+/// Plain copy of the client metrics (`ClientState::metrics_snapshot`).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClientMetrics {
+    pub latency_ms: f64,
+    pub jitter_ms: f64,
+    pub loss: f64,      // ratio 0..1
+    pub late_drop: u64,
+    pub buffer_ms: f64,        // jitter buffer fill
+    pub target_buffer_ms: f64,
+    pub max_buffer_ms: f64,
+    pub quality: Quality,
+    pub rms: f64,
+    pub peak: f64,      // decaying peak RMS
+    pub decrypt_fail: u64,
+    pub enc_status: i32, // 0=plain 1=ok -1=key error
+}
+
+/// Everything needed to repeat a `connect_with_output` call.
+#[derive(Clone, Debug)]
+pub struct ConnectTarget { pub server_ip: String, pub port: u16, pub output: Option<String>, pub psk: Option<PskSource>, pub handshake_timeout: Duration, pub transport: Transport }
+
+/// Output device by name (`None` = system default), looked up when playback starts so a reordered
+/// device list cannot pick the wrong one. A vanished device falls back to the default with an
+/// `OutputLost` event, as if it had been unplugged.
+fn output_device(name: Option<&str>, event_sender: Option<&EventSender<ClientEvent>>) -> Option<cpal::Device> {
+    let Some(name) = name else { return audio::default_output_device() };
+    if let Some(dev) = audio::output_device_by_name(name) { return Some(dev); }
+    let fallback = audio::default_output_device();
+    let new = fallback.as_ref().map(audio::device_name);
+    log::warn!("[CLIENT][WARN] output device {name} not found -> {}", new.as_deref().unwrap_or("no output"));
+    if let Some(tx) = event_sender { let _ = tx.send(ClientEvent::OutputLost { old: name.to_string(), new }); }
+    fallback
+}
+
+/// Connect progress step (`ClientEvent::Connecting`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectPhase {
+    Tcp,
+    Handshake,
+    /// Server answered `NO_PARAMS` (capture not started): waiting for its `PARAMS`
+    WaitingForAudio,
 }
 
-fn hex_to_array8(s: &str) -> Result<[u8;8], ()> {
-    if s.len()!=16 { return Err(()); }
-    let mut out=[0u8;8];
-    for i in 0..8 { let byte = u8::from_str_radix(&s[i*2..i*2+2], 16).map_err(|_| ())?; out[i]=byte; }
-    Ok(out)
+/// Notifications from the client threads to the GUI / headless loop (`ClientState.event_sender`).
+#[derive(Clone, Debug)]
+pub enum ClientEvent {
+    /// Connect progress: TCP connect, then waiting for the handshake line
+    Connecting(ConnectPhase),
+    /// Handshake accepted (control connection up; audio may still be starting)
+    Connected,
+    /// Connection over for good (closed, kicked, server stopped, session expired, reconnect gave up)
+    Disconnected(String),
+    /// Heartbeat timeout: the connection is gone but may be retried (`reconnect`)
+    TimedOut(String),
+    /// Reconnect attempt (1-based) waiting out its backoff
+    Reconnecting(u32),
+    /// This server cannot be used (protocol version too new); the connect call fails as well
+    Error(String),
+    /// Server banner (may contain newlines)
+    Motd(String),
+    /// Server switched its input device mid-session
+    Params(AudioParams),
+    /// Session time limit reached in this many seconds
+    SessionWarning(u64),
+    /// Output device unplugged; playback moved to `new` (`None` = stopped)
+    OutputLost { old: String, new: Option<String> },
+    /// Output watchdog rebuilt the stream (restart count)
+    OutputRestart(u64),
+    /// Jitter prebuffer filled
+    BufferReady { frames: usize, warmup_ms: f64 },
+    /// Channel count carried by the frames changed
+    ChannelsChanged { old: u16, new: u16 },
+}
+
+/// Default wait for the TCP connection and, separately, for the server's handshake line.
+pub const HANDSHAKE_TIMEOUT_DEFAULT: Duration = Duration::from_secs(3);
+/// Heartbeat period on the control connection (the server drops clients silent for 5s).
+const HEART_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a `NO_PARAMS` server may take to start capturing before connect gives up.
+const AUDIO_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Auto-reconnect attempts after a heartbeat timeout before giving up.
+pub const RECONNECT_MAX_ATTEMPTS: u32 = 5;
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Wait before reconnect attempt `attempt` (1-based): 1s, 2s, 4s ... capped at `RECONNECT_MAX_DELAY`.
+pub fn reconnect_delay(attempt: u32) -> Duration { RECONNECT_BASE_DELAY.saturating_mul(1 << attempt.clamp(1, 16).saturating_sub(1)).min(RECONNECT_MAX_DELAY) }
+
+/// Retry `connect_with_output` with exponential backoff (blocking). Sends `Reconnecting(attempt)`
+/// before each wait; returns `None` when `cancel` is set (manual disconnect) or all attempts failed
+/// (then `Disconnected` is sent).
+pub fn reconnect(target: &ConnectTarget, event_sender: Option<EventSender<ClientEvent>>, cancel: &AtomicBool) -> Option<ClientState> {
+    for attempt in 1..=RECONNECT_MAX_ATTEMPTS {
+        if let Some(ref tx) = event_sender { let _ = tx.send(ClientEvent::Reconnecting(attempt)); }
+        let wake = std::time::Instant::now() + reconnect_delay(attempt);
+        while std::time::Instant::now() < wake { if cancel.load(Ordering::Relaxed) { return None; } thread::sleep(Duration::from_millis(100)); }
+        log::info!("[CLIENT][RECONNECT] attempt {attempt}/{RECONNECT_MAX_ATTEMPTS} -> {}:{}", target.server_ip, target.port);
+        match connect_with_output(target.server_ip.clone(), target.port, target.output.clone(), target.psk.clone(), event_sender.clone(), target.handshake_timeout, target.transport) {
+            Ok(state) if state.connected.load(Ordering::Relaxed) => {
+                // User gave up while the handshake was in flight: drop the fresh connection
+                if cancel.load(Ordering::Relaxed) { disconnect(&state); return None; }
+                return Some(state);
+            }
+            Ok(_) => log::warn!("[CLIENT][RECONNECT] handshake rejected"),
+            Err(e) => log::warn!("[CLIENT][RECONNECT] attempt {attempt} failed: {e}"),
+        }
+    }
+    if let Some(ref tx) = event_sender { let _ = tx.send(ClientEvent::Disconnected(format!("重连失败 ({RECONNECT_MAX_ATTEMPTS} 次)"))); }
+    None
 }
 
 /// Connect to server (TCP handshake + start heartbeat). No audio output.
-pub fn connect(server_ip: String, port: u16, psk: Option<String>, event_sender: Option<EventSender<String>>) -> Result<ClientState> {
-    use std::io::{Read, ErrorKind};
-    let mut stream = TcpStream::connect((server_ip.as_str(), port))?; // 初始连接
+/// Progress is reported as `Connecting(Tcp)` then `Connecting(Handshake)`; both steps may take up to
+/// `handshake_timeout`. Errors say whether nothing listens (refused), the host never answered, or
+/// the server accepted but stayed silent.
+pub fn connect(server_ip: String, port: u16, psk: Option<PskSource>, event_sender: Option<EventSender<ClientEvent>>, handshake_timeout: Duration, transport: Transport) -> Result<ClientState> {
+    use std::io::{Read, Write, ErrorKind};
+    let psk = match psk { Some(src) => src.resolve()?, None => None };
+    let progress = |phase| if let Some(ref tx) = event_sender { let _ = tx.send(ClientEvent::Connecting(phase)); };
+    progress(ConnectPhase::Tcp);
+    let secs = handshake_timeout.as_secs_f32();
+    let addr = net::resolve_host(&server_ip, port)?;
+    let mut stream = match TcpStream::connect_timeout(&addr, handshake_timeout) { // 初始连接
+        Ok(s) => s,
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Err(anyhow::anyhow!("connection refused: no server listening on {addr}")),
+        Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => return Err(anyhow::anyhow!("no answer from {addr} within {secs:.0}s (host unreachable or port filtered)")),
+        Err(e) => return Err(anyhow::anyhow!("connect {addr}: {e}")),
+    };
+    progress(ConnectPhase::Handshake);
     // Make stream non-blocking and poll handshake bytes
     stream.set_nonblocking(true)?;
     let start = std::time::Instant::now();
-    let deadline = start + Duration::from_secs(3);
+    let deadline = start + handshake_timeout;
     let mut header_bytes: Vec<u8> = Vec::with_capacity(256);
     loop {
         let mut tmp = [0u8; 128];
@@ -79,7 +251,7 @@ pub fn connect(server_ip: String, port: u16, psk: Option<String>, event_sender:
             }
             Err(ref e) if e.kind()==ErrorKind::WouldBlock => {
                 if std::time::Instant::now() > deadline {
-                    return Err(anyhow::anyhow!("handshake timeout (waited >3s)"));
+                    return Err(anyhow::anyhow!("handshake timeout: {addr} accepted the connection but sent nothing for {secs:.0}s"));
                 }
                 std::thread::sleep(Duration::from_millis(15));
                 continue;
@@ -87,50 +259,118 @@ pub fn connect(server_ip: String, port: u16, psk: Option<String>, event_sender:
             Err(e) => return Err(e.into()),
         }
     }
+    // Only the first line is the handshake; anything after it (e.g. MOTD) goes through the control parser
+    let mut lines = LineBuffer::new();
+    let early = match header_bytes.iter().position(|&b| b == b'\n') { Some(i) => { let msgs = lines.push(&header_bytes[i + 1..]); header_bytes.truncate(i + 1); msgs } None => Vec::new() };
     let header = String::from_utf8_lossy(&header_bytes).to_string();
-    println!("[CLIENT] handshake raw: {:?}", header_bytes);
-    println!("[CLIENT] handshake header: {}", header.trim());
+    log::debug!("[CLIENT] handshake raw: {:?}", header_bytes);
+    log::info!("[CLIENT] handshake header: {}", header.trim());
+    // Server up but not capturing yet: wait for its `PARAMS`, then handshake again for the full header
+    if let ["NO_PARAMS", key, ..] = header.split_whitespace().collect::<Vec<_>>()[..] {
+        progress(ConnectPhase::WaitingForAudio);
+        wait_for_params(&mut stream, key, lines, early)?;
+        drop(stream);
+        return connect(server_ip, port, psk.map(PskSource::Inline), event_sender, handshake_timeout, transport);
+    }
     let mut state = ClientState::new(); state.event_sender = event_sender;
-    let parts: Vec<_> = header.split_whitespace().collect();
+    for msg in &early { if let ControlMessage::Motd { text } = msg { notify_motd(text, state.event_sender.as_ref()); } }
+    let mut parts: Vec<_> = header.split_whitespace().collect();
+    if parts.first() == Some(&"FULL") { return Err(anyhow::anyhow!("server is full (client limit reached)")); }
+    if parts.first() == Some(&"REVOKED") { return Err(anyhow::anyhow!("access revoked by the server")); }
+    // Protocol version token right after OK; refuse headers newer than we can parse instead of misreading fields
+    let server_version = match parts.get(1).and_then(|t| types::parse_version_token(t)) { Some(v) if parts[0] == "OK" => { parts.remove(1); v } _ => 1 };
+    if server_version > types::PROTOCOL_VERSION {
+        let msg = format!("服务器协议版本 V{server_version} 高于本客户端支持的 V{}，请升级客户端", types::PROTOCOL_VERSION);
+        log::warn!("[CLIENT] protocol version mismatch: server V{server_version} > client V{}", types::PROTOCOL_VERSION);
+        if let Some(ref tx) = state.event_sender { let _ = tx.send(ClientEvent::Error(msg.clone())); }
+        return Err(anyhow::anyhow!(msg));
+    }
     if parts.len()>=2 && parts[0]=="OK" {
         let key = parts[1].to_string();
         state.key = Some(key.clone());
         if parts.len()>=5 { if let (Ok(sr), Ok(ch), Ok(fmt_code)) = (parts[2].parse::<u32>(), parts[3].parse::<u16>(), parts[4].parse::<u8>()) { let sf = types::code_to_sample_format(fmt_code); state.params = Some(AudioParams { sample_rate: sr, channels: ch, sample_format: sf }); } }
-        if parts.len()>=7 { if let (Ok(ipv4), Ok(mport)) = (parts[5].parse::<Ipv4Addr>(), parts[6].parse::<u16>()) { state.multicast_addr = Some((ipv4, mport)); } }
-    // Encryption tokens: either ENC <salthex> or NOENC
-        if let Some(idx_enc) = parts.iter().position(|p| *p=="ENC" || p.starts_with("ENC")) {
-            // Accept: ENC <salthex> or ENC<salthex>
+        if parts.len()>=7 { if let (Ok(group), Ok(mport)) = (parts[5].parse::<IpAddr>(), parts[6].parse::<u16>()) { state.multicast_addr = Some((group, mport)); } }
+    // Encryption tokens: KX <pubhex> (per-client keys), ENC <salthex> [epoch] or NOENC
+        let server_kx = parts.iter().position(|p| *p == "KX").and_then(|i| parts.get(i + 1)).and_then(|h| rekey::parse_public(h));
+        if let Some(server_pub) = server_kx {
+            // Per-client keys: answer with our x25519 key, the session key comes back wrapped for us
+            state.enc_enabled = true;
+            let (secret, public) = rekey::kx_keypair();
+            let mut ring = KeyRing::with_kek(rekey::kx_finish(secret, server_pub));
+            stream.write_all(ControlMessage::Kx { public }.to_line().as_bytes())?;
+            if await_wrapped_key(&mut stream, &mut lines, &mut ring, state.event_sender.as_ref()) { log::info!("[CLIENT] encryption enabled (per-client key)"); state.update_enc_status(1); }
+            else { log::warn!("[CLIENT][WARN] key exchange: no session key received yet"); }
+            state.enc_keys = Arc::new(Mutex::new(ring));
+        } else if let Some(idx_enc) = parts.iter().position(|p| *p=="ENC" || p.starts_with("ENC")) {
+            // Accept: ENC <salthex> <epoch> or ENC<salthex> (epoch 0)
             let salt_hex = if parts[idx_enc]=="ENC" { parts.get(idx_enc+1).map(|s| *s).unwrap_or("") } else { &parts[idx_enc][3..] };
+            let epoch = if parts[idx_enc]=="ENC" { parts.get(idx_enc+2).and_then(|s| s.parse::<u8>().ok()).unwrap_or(0) } else { 0 };
             if salt_hex.len()==16 { // 8 bytes hex
-                if let Ok(salt_bytes) = hex_to_array8(salt_hex) {
-                    state.enc_enabled = true; state.enc_salt = Some(salt_bytes);
-                    if let (Some(psk_str), Some(_)) = (psk.as_ref(), state.enc_salt) {
-                        let mut hasher: Sha256 = Default::default();
-                        hasher.update(psk_str.as_bytes());
-                        hasher.update(&salt_bytes);
-                        let digest = hasher.finalize();
-                        let mut key=[0u8;32]; key.copy_from_slice(&digest[..32]);
-                        state.enc_key = Some(key);
-                        println!("[CLIENT] encryption enabled (salt={}, key_derived)", salt_hex);
+                if let Some(salt_bytes) = rekey::parse_salt(salt_hex) {
+                    state.enc_enabled = true;
+                    let mut ring = KeyRing::new(psk.clone()); ring.install(epoch, salt_bytes);
+                    // Key announced before our heartbeat thread started (rotation in progress)
+                    for msg in &early { if let ControlMessage::Rekey { epoch, salt } = msg { ring.install(*epoch, *salt); } }
+                    let derived = ring.has_secret();
+                    state.enc_keys = Arc::new(Mutex::new(ring));
+                    if derived {
+                        log::info!("[CLIENT] encryption enabled (salt={}, epoch={}, key_derived)", salt_hex, epoch);
                         state.update_enc_status(1);
-                    } else { println!("[CLIENT][WARN] server encryption enabled but no PSK provided"); }
-                } else { println!("[CLIENT][WARN] invalid salt hex len"); }
-            } else { println!("[CLIENT][WARN] ENC token but salt malformed"); }
+                    } else { log::warn!("[CLIENT][WARN] server encryption enabled but no PSK provided"); }
+                } else { log::warn!("[CLIENT][WARN] invalid salt hex len"); }
+            } else { log::warn!("[CLIENT][WARN] ENC token but salt malformed"); }
         } else {
             // Plain (no encryption) path
             state.update_enc_status(0);
         }
+        // Codec negotiation: answer the server's CODECS token with what we can decode
+        if let Some(idx) = parts.iter().position(|p| *p == "CODECS") {
+            let offered: Vec<Codec> = parts[idx + 1..].iter().filter_map(|p| Codec::parse(p)).collect();
+            if offered.contains(&Codec::Opus) { let _ = stream.write_all(ControlMessage::Codecs { list: Codec::ALL.to_vec() }.to_line().as_bytes()); }
+        }
+        // FEC parity group size (frames still carry their own k; this only primes the receive thread)
+        state.fec_group = parts.iter().position(|p| *p == "FEC").and_then(|i| parts.get(i + 1)).and_then(|k| k.parse().ok()).unwrap_or(0);
+        // TCP transport: only if the server offers it (older servers: stay on UDP)
+        if transport == Transport::Tcp {
+            let offered = parts.iter().position(|p| *p == "TRANSPORT").is_some_and(|i| parts[i + 1..].iter().any(|p| Transport::parse(p) == Some(Transport::Tcp)));
+            if offered {
+                // Blocking socket from here on: the reader thread waits on it, frames must never be cut by WouldBlock
+                stream.set_nonblocking(false)?; stream.set_read_timeout(Some(Duration::from_millis(200)))?;
+                stream.write_all(ControlMessage::Transport { kind: Transport::Tcp }.to_line().as_bytes())?;
+                state.transport = Transport::Tcp;
+                log::info!("[CLIENT] tcp transport: frames arrive on the control connection");
+            } else { log::warn!("[CLIENT][WARN] server does not offer the tcp transport -> udp"); }
+        }
         state.server = Some(SocketAddr::new(stream.peer_addr()?.ip(), port));
         state.connected.store(true, Ordering::SeqCst);
+        if let Some(ref tx) = state.event_sender { let _ = tx.send(ClientEvent::Connected); }
+        // Params acknowledged -> next stage is multicast join; without params the handshake is incomplete
+        if state.params.is_some() { state.stage.store(1, Ordering::SeqCst); }
     let ctrl_arc = Arc::new(std::sync::Mutex::new(stream));
     let hb_connected = state.connected.clone();
     let hb_output_running = state.output_running.clone();
     let hb_udp_alive = state.udp_thread_alive.clone();
     let hb_stop_tx_arc = state.output_stop_tx.clone();
     let key_copy = state.key.clone(); let reason_clone = state.disconnection_reason.clone();
+    let hb_keys = state.enc_keys.clone();
+    let hb_rate = state.stream_rate.clone();
+    // Round-trip timing only if the server echoes heartbeat stamps (older servers reject stamped HEART lines)
+    let hb_rtt = parts.contains(&"RTT").then(|| state.rtt_ms.clone());
     state.ctrl = Some(ctrl_arc.clone());
     let ev_clone = state.event_sender.clone();
-    thread::spawn(move || heartbeat_loop(
+    state.session.start();
+    let session = state.session.clone();
+    // TCP transport: a reader thread owns all reads and splits frames from control messages
+    let input = if state.transport == Transport::Tcp {
+        let reader = ctrl_arc.lock().map_err(|_| anyhow::anyhow!("control stream poisoned"))?.try_clone()?;
+        let (frame_tx, frame_rx) = crossbeam_channel::bounded(TCP_FRAME_QUEUE);
+        let (msg_tx, msg_rx) = unbounded();
+        let reader_connected = state.connected.clone();
+        thread::Builder::new().name("rm-tcp-recv".into()).spawn(move || tcp_reader_loop(reader, lines, reader_connected, frame_tx, msg_tx))?;
+        state.tcp_frames = Some(frame_rx);
+        ControlInput::Reader(msg_rx)
+    } else { ControlInput::Socket(lines) };
+    thread::Builder::new().name("rm-heartbeat".into()).spawn(move || heartbeat_loop(
         ctrl_arc.clone(),
         key_copy.unwrap(),
         hb_connected,
@@ -139,57 +379,123 @@ pub fn connect(server_ip: String, port: u16, psk: Option<String>, event_sender:
         hb_stop_tx_arc,
         reason_clone,
         ev_clone,
-    ));
+        session,
+        input,
+        hb_keys,
+        hb_rate,
+        hb_rtt,
+    ))?;
         // UDP thread TODO: handshake actual port; for now reuse same port local ephemeral.
     }
     Ok(state)
 }
 
+/// Keep a `NO_PARAMS` connection alive (heartbeats) until the server announces its capture format with
+/// `PARAMS`. Fails when the server stops, drops us, or stays silent for `AUDIO_WAIT_TIMEOUT`.
+fn wait_for_params(stream: &mut TcpStream, key: &str, mut lines: LineBuffer, early: Vec<ControlMessage>) -> Result<()> {
+    use std::io::{ErrorKind, Read};
+    log::info!("[CLIENT] server has no audio input yet; waiting for PARAMS");
+    let deadline = std::time::Instant::now() + AUDIO_WAIT_TIMEOUT;
+    let mut next_heart = std::time::Instant::now();
+    let mut pending = early;
+    loop {
+        for msg in pending.drain(..) {
+            match msg {
+                ControlMessage::Params { sample_rate, channels, .. } => { log::info!("[CLIENT] server audio ready ({sample_rate} Hz, {channels} ch)"); return Ok(()); }
+                ControlMessage::ServerStop | ControlMessage::Kicked => return Err(anyhow::anyhow!("server stopped before its audio started")),
+                _ => {}
+            }
+        }
+        let now = std::time::Instant::now();
+        if now > deadline { return Err(anyhow::anyhow!("server has no audio input running (waited {}s)", AUDIO_WAIT_TIMEOUT.as_secs())); }
+        if now >= next_heart {
+            stream.write_all(ControlMessage::Heart { key: key.to_string(), stamp: None }.to_line().as_bytes())?;
+            next_heart = now + HEART_INTERVAL;
+        }
+        let mut buf = [0u8; 256];
+        match stream.read(&mut buf) {
+            Ok(0) => return Err(anyhow::anyhow!("server closed the connection while waiting for audio")),
+            Ok(n) => pending = lines.push(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
 /// Connect plus configure UDP + output playback thread.
-pub fn connect_with_output(server_ip: String, port: u16, output_index: usize, psk: Option<String>, event_sender: Option<EventSender<String>>) -> Result<ClientState> {
-    let mut state = connect(server_ip.clone(), port, psk, event_sender)?;
+pub fn connect_with_output(server_ip: String, port: u16, output: Option<String>, psk: Option<PskSource>, event_sender: Option<EventSender<ClientEvent>>, handshake_timeout: Duration, transport: Transport) -> Result<ClientState> {
+    let target = ConnectTarget { server_ip: server_ip.clone(), port, output: output.clone(), psk: psk.clone(), handshake_timeout, transport };
+    let mut state = connect(server_ip.clone(), port, psk, event_sender, handshake_timeout, transport)?;
+    state.target = Some(target);
     if !state.connected.load(Ordering::Relaxed) { return Ok(state); }
-    // Setup UDP multicast receiving socket
-    let (m_ip, m_port) = if let Some(t) = state.multicast_addr { t } else { (Ipv4Addr::new(239,255,0,222), port) }; // fallback default
-    let bind_addr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED), m_port);
-    let udp = UdpSocket::bind(bind_addr)?; 
-    let _ = udp.set_nonblocking(true); // reuse_address not exposed in stable std; OS default usually fine
-    if let Err(e) = udp.join_multicast_v4(&m_ip, &Ipv4Addr::UNSPECIFIED) { eprintln!("[CLIENT][MCAST] join group {m_ip}:{m_port} failed: {e}"); }
-    let local_addr = udp.local_addr().ok(); state.udp_local = local_addr.clone();
-    println!("[CLIENT] Joined multicast {m_ip}:{m_port} local={:?}", local_addr);
+    let source = if let Some(frames) = state.tcp_frames.take() {
+        // TCP transport: no UDP socket, the control reader hands frames over
+        if state.params.is_some() { state.stage.store(2, Ordering::SeqCst); }
+        FrameSource::Tcp(frames)
+    } else {
+        FrameSource::Udp(open_udp_source(&mut state, port)?.0)
+    };
+    let ordered = matches!(source, FrameSource::Tcp(_));
     if let Some(params) = &state.params {
-        let outputs = audio::list_devices().map(|(_i,o)| o).unwrap_or(vec![]);
-        let out_dev = outputs.get(output_index).or_else(|| outputs.get(0));
-        if let Some(dev) = out_dev { println!("[CLIENT] Selected output device: {}", audio::device_name(dev));
+        state.stream_rate.store(params.sample_rate, Ordering::Relaxed);
+        let out_dev = output_device(output.as_deref(), state.event_sender.as_ref());
+        let out_dev = out_dev.as_ref();
+        if let Some(dev) = out_dev { log::info!("[CLIENT] Selected output device: {}", audio::device_name(dev));
+            // Tell the server what this device plays natively; a different stream format is converted on playback
+            if let Ok(cfg) = dev.default_output_config() {
+                let _ = send_control(&state, &ControlMessage::Want { sample_rate: cfg.sample_rate().0, channels: cfg.channels() });
+                if cfg.sample_rate().0 != params.sample_rate { log::info!("[CLIENT] stream {} Hz, output {} Hz -> resampled on playback", params.sample_rate, cfg.sample_rate().0); }
+            }
             let (tx, rx) = unbounded::<Vec<f32>>();
         state.audio_tx = Some(tx.clone());
             state.output_running.store(true, Ordering::SeqCst);
-            if let Some(dev_clone) = out_dev.cloned() { let stop_tx = spawn_output_thread(dev_clone, rx, state.output_running.clone(), params.clone()); if let Ok(mut guard)=state.output_stop_tx.lock() { *guard = Some(stop_tx); } }
-            // UDP receive -> channel
-            let udp_clone = udp.try_clone()?;
+            if let Some(dev_clone) = out_dev.cloned() { let stop_tx = spawn_output_thread(dev_clone, rx, state.output_running.clone(), params.clone(), OutputHooks { watchdog_ms: state.output_watchdog_ms.clone(), restarts: state.output_restarts.clone(), stage: state.stage.clone(), warmup_log: state.warmup_log.clone(), muted: state.muted.clone(), gain: state.output_gain.clone(), width: state.stereo_width.clone(), loudness: state.loudness.clone(), limiter: state.output_limiter.clone(), event_sender: state.event_sender.clone(), stream_rate: state.stream_rate.clone() }); if let Ok(mut guard)=state.output_stop_tx.lock() { *guard = Some(stop_tx); } }
+            // UDP / TCP-transport receive -> channel
         let alive = state.udp_thread_alive.clone(); alive.store(true, Ordering::SeqCst);
             // Capture metrics handles
             let metrics_latency = state.avg_latency_ms.clone();
             let metrics_jitter = state.jitter_ms.clone();
             let metrics_loss = state.packet_loss.clone();
             let metrics_late = state.late_drop.clone();
+            let (metrics_buffer, metrics_target, metrics_max) = (state.buffer_ms.clone(), state.target_buffer_ms.clone(), state.max_buffer_ms.clone());
+            let metrics_quality = state.quality.clone();
             let metrics_rms = state.current_rms.clone();
             let metrics_peak = state.peak_rms.clone();
+            let metrics_channels = state.channel_rms.clone();
+            let metrics_scope = state.scope.clone();
+            let spectrum_shed = state.spectrum_shed.clone();
+            let dtx_silent = state.dtx_silent.clone();
             // Clone encryption fields & decrypt fail counter for UDP thread so we don't move full state
             let enc_enabled = state.enc_enabled;
-            let enc_salt = state.enc_salt;
-            let enc_key = state.enc_key;
+            let enc_keys = state.enc_keys.clone();
             let decrypt_fail = state.decrypt_fail.clone();
+            let replay_drop = state.replay_drop.clone();
             let enc_status = state.enc_status.clone();
-            thread::spawn(move || {
+            let stage = state.stage.clone();
+            let plc_enabled = state.plc_enabled.clone();
+            let latency_profile = state.latency_profile.clone();
+            let plc_concealed = state.plc_concealed.clone();
+            let (fec_recovered, fec_recovery) = (state.fec_recovered.clone(), state.fec_recovery.clone());
+            let mut fec_group = state.fec_group; let mut fec_active = fec_group > 0;
+            let frame_channels = state.frame_channels.clone(); frame_channels.store(params.channels, Ordering::Relaxed);
+            let udp_events = state.event_sender.clone();
+            let membership = state.membership.clone();
+            let mut channels = FrameChannels::new(params.channels);
+            let stream_rate = state.stream_rate.clone();
+            let mut out_rate = params.sample_rate;
+            // Frames are mapped to the output device layout here; the output callback copies them verbatim
+            let out_channels = dev.default_output_config().map(|c| c.channels()).unwrap_or(2).max(1);
+            state.output_channels = out_channels;
+            let force_mono = state.force_mono.clone();
+            let recording = state.recording.clone();
+            let (sync_start, sync_start_ms, sync_start_at) = (state.sync_start.clone(), state.sync_start_ms.clone(), state.sync_start_at.clone());
+            thread::Builder::new().name("rm-udp-recv".into()).spawn(move || {
                 use std::cmp::Reverse; use std::collections::BinaryHeap;
                 let mut buf = vec![0u8; 65536];
                 let mut last_stats_report = std::time::Instant::now();
                 let mut latency_acc: f64 = 0.0; let mut latency_samples: u64 = 0;
                 // Clock alignment & jitter state
-                let mut base_server_ts: Option<u64> = None;            // first server ts_ns
-                let mut base_client_instant: Option<std::time::Instant> = None; // first local arrival Instant
-                let mut offset_ns: i128 = 0; // arrival_rel - server_rel
+                let mut clock = ArrivalClock::default();
                 let mut jitter_ewma_ns: f64 = 0.0;  // RFC3550 style EWMA of transit deltas (ns)
                 let mut prev_transit: Option<i128> = None; // previous transit for jitter
                 // Adaptive buffering
@@ -210,88 +516,89 @@ pub fn connect_with_output(server_ip: String, port: u16, output_index: usize, ps
                 let mut frame_pool: Vec<Vec<f32>> = (0..POOL_CAPACITY).map(|_| Vec::with_capacity(2048)).collect();
                 let _pool_recycled: u64 = 0; // 保留占位用于后续调试统计
                 let mut late_drop_count: u64 = 0;
-                let mut recv_seq: u64 = 0; let mut expected_seq: u64 = 0; let mut loss_acc: f64 = 0.0;
+                let mut replay_windows = [ReplayWindow::new(), ReplayWindow::new(), ReplayWindow::new()];
+                let mut recv_seq: u64 = 0; let mut loss = LossCounter::new();
                 let mut last_metrics_push = std::time::Instant::now();
-                // Compute dynamic reorder delay (5ms base up to 40ms)
-                fn compute_reorder_delay(jitter_ns: f64) -> u64 { let base=5_000_000f64; let scaled = (jitter_ns*2.5).max(base); scaled.min(40_000_000f64) as u64 }
-                // Compute adaptive targets based on jitter
-                fn adjust_targets(jitter_ns: f64) -> (u64,u64) {
-                    // Map jitter to extra buffer: jitter 0..8ms -> add 0..20ms (cap 40ms total target)
-                    let jitter_ms = jitter_ns/1_000_000.0;
-                    let base_ms = 15.0; // slightly lower base to allow growth
-                    let extra = (jitter_ms*2.5).clamp(0.0, 25.0); // up to +25ms
-                    let target = (base_ms + extra).clamp(10.0, 40.0); // 10..40ms
-                    let max = (target*2.0).clamp(30.0, 100.0); // max 100ms
-                    ((target*1_000_000.0) as u64, (max*1_000_000.0) as u64)
-                }
+                let mut peak_meter = PeakMeter::new();
+                // CPU pressure: shed spectrum / metering cadence / concealment when per-frame work nears the frame duration
+                let mut degrader = Degrader::from_env("CLIENT", &degrade::CLIENT_ORDER); let mut meter_tick: u32 = 0;
+                let mut opus_dec: Option<OpusStreamDecoder> = None; // rebuilt when the Opus header rate/channels change
+                // Packet-loss concealment: end timestamp + copy of the last released frame
+                let mut plc_prev_end: Option<u64> = None; let mut plc_prev: Vec<f32> = Vec::with_capacity(2048);
+                // Synchronized start gate: (start_ts, local release instant); latched once playout began
+                let mut sync_gate: Option<(u64, std::time::Instant)> = None; let mut sync_done = false;
+                // FEC: recent packets for parity recovery; a rebuilt packet is processed on the next iteration
+                let mut fec = FecDecoder::new(); let mut fec_rebuilt: Option<Vec<u8>> = None; let mut last_dur_ns: u64 = 0;
                 while alive.load(Ordering::Relaxed) {
-                    match udp_clone.recv_from(&mut buf) {
-                        Ok((n,_src)) => {
-                            if n < 22 { continue; }
-                            if &buf[0..2] != &types::FRAME_MAGIC { continue; }
-                            let seq = u32::from_be_bytes([buf[2],buf[3],buf[4],buf[5]]) as u64;
-                            let fmt = buf[6]; let ch = buf[7] as u16; let sr = u32::from_be_bytes([buf[8],buf[9],buf[10],buf[11]]);
-                            let payload_len = u16::from_be_bytes([buf[12],buf[13]]) as usize; // ciphertext length if encrypted
-                            let ts_ns = u64::from_be_bytes([buf[14],buf[15],buf[16],buf[17],buf[18],buf[19],buf[20],buf[21]]);
-                            if 22+payload_len > n { continue; }
+                    let next = match fec_rebuilt.take() { Some(p) => { let n = p.len().min(buf.len()); buf[..n].copy_from_slice(&p[..n]); Ok(n) } None => source.recv(&mut buf) };
+                    match next {
+                        Ok(n) => {
+                            let busy_start = std::time::Instant::now();
+                            const HDR: usize = types::FRAME_HEADER_LEN;
+                            // Parity frame: rebuild the group's single missing frame (if any)
+                            if n >= 2 && buf[0..2] == types::FRAME_MAGIC_FEC {
+                                fec_active = true;
+                                if let Some(p) = fec.recover(&buf[..n]) { fec_recovered.fetch_add(1, Ordering::Relaxed); fec_rebuilt = Some(p); }
+                                fec_group = fec.group();
+                                continue;
+                            }
+                            let Some(header) = types::parse_frame_header(&buf[..n]) else { continue };
+                            let substream = if header.magic == types::FRAME_MAGIC { types::SUBSTREAM_MULTICAST } else if header.magic == types::FRAME_MAGIC_LQ { types::SUBSTREAM_LOW_QUALITY } else if header.magic == types::FRAME_MAGIC_SILENCE { types::SUBSTREAM_SILENCE } else { continue };
+                            let seq = header.seq as u64;
+                            // Keep multicast packets for recovery; drops the late original of a rebuilt frame
+                            if fec_active && substream == types::SUBSTREAM_MULTICAST && !fec.store(seq as u32, &buf[..n]) { continue; }
+                            let (fmt, ch, sr, ts_ns, epoch) = (header.fmt, header.ch as u16, header.rate, header.ts_ns, header.key_epoch);
+                            let payload_len = header.payload_len as usize; // ciphertext length if encrypted
+                            if HDR+payload_len > n { continue; }
                             let mut _payload_plain_owned: Option<Vec<u8>> = None; // decrypted buffer holder
                             let payload: &[u8] = if enc_enabled {
-                                let ct = &buf[22..22+payload_len];
-                                if let (Some(salt), Some(key)) = (enc_salt, enc_key) {
-                                    let cipher = XChaCha20Poly1305::new(&key.into());
-                                    let mut nonce = [0u8;24];
-                                    nonce[..8].copy_from_slice(&salt);
-                                    nonce[8..12].copy_from_slice(&(seq as u32).to_be_bytes());
-                                    nonce[12..20].copy_from_slice(&ts_ns.to_be_bytes());
-                    // AAD = first 22 bytes header (payload_len already ciphertext length on sender)
-                    let aad = &buf[0..22];
-                                    match cipher.decrypt(&nonce.into(), Payload { msg: ct, aad }) {
+                                let ct = &buf[HDR..HDR+payload_len];
+                                let (epoch_key, has_secret) = enc_keys.lock().map(|r| (r.get(epoch), r.has_secret())).unwrap_or((None, false));
+                                if let Some(k) = epoch_key {
+                                    // AAD = full header as received (ciphertext payload_len, key epoch)
+                                    match types::open_payload(&k.key, &k.salt, &header, substream, ct) {
                                         Ok(pt) => { // 确认已加密状态 (仅一次)
                                             if enc_status.load(Ordering::Relaxed) != 1 { enc_status.store(1, Ordering::Relaxed); }
                                             _payload_plain_owned = Some(pt); _payload_plain_owned.as_ref().unwrap() }
-                                        Err(e) => { decrypt_fail.fetch_add(1, Ordering::Relaxed); if enc_status.load(Ordering::Relaxed) != -1 { enc_status.store(-1, Ordering::Relaxed); eprintln!("[CLIENT][DEC] decrypt fail seq={seq}: {e}"); } continue; }
+                                        Err(e) => { decrypt_fail.fetch_add(1, Ordering::Relaxed); if enc_status.load(Ordering::Relaxed) != -1 { enc_status.store(-1, Ordering::Relaxed); log::warn!("[CLIENT][DEC] decrypt fail seq={seq}: {e}"); } continue; }
                                     }
+                                } else if has_secret { // Epoch we never received a key for
+                                    decrypt_fail.fetch_add(1, Ordering::Relaxed); if enc_status.load(Ordering::Relaxed) != -1 { enc_status.store(-1, Ordering::Relaxed); log::warn!("[CLIENT][DEC] no key for epoch {epoch} seq={seq}"); }
+                                    continue;
                                 } else { // No key yet derived
                                     if enc_status.load(Ordering::Relaxed) != 0 { enc_status.store(0, Ordering::Relaxed); }
                                     continue;
                                 }
-                            } else { &buf[22..22+payload_len] };
+                            } else { &buf[HDR..HDR+payload_len] };
+                            // Anti-replay (authenticated frames only, one window per substream seq counter)
+                            if enc_enabled && !replay_windows[substream as usize].accept(seq as u32) {
+                                if replay_drop.fetch_add(1, Ordering::Relaxed) == 0 { log::warn!("[CLIENT][DEC] replayed / stale frame dropped seq={seq}"); }
+                                continue;
+                            }
+                            // Silence marker (server DTX): goes through as an empty frame, which releases what is
+                            // buffered and stops concealment across the pause; not part of the audio seq
+                            let silence = substream == types::SUBSTREAM_SILENCE;
+                            dtx_silent.store(silence, Ordering::Relaxed);
                             let now_inst = std::time::Instant::now();
                             // --- Clock alignment & latency ---
-                            if base_server_ts.is_none() { base_server_ts = Some(ts_ns); base_client_instant = Some(now_inst); offset_ns = 0; }
-                            let arrival_rel_ns: u64 = if let Some(base) = base_client_instant { now_inst.duration_since(base).as_nanos() as u64 } else { 0 };
-                            let server_rel_ns: u64 = if let Some(base_s) = base_server_ts { ts_ns - base_s } else { 0 };
-                            // (Optional) adjust offset on first few frames if negative delay appears
-                            // expected arrival = server_rel + offset
-                            let expected_arrival_rel = (server_rel_ns as i128) + offset_ns;
-                            let mut delay_ns_i = (arrival_rel_ns as i128) - expected_arrival_rel;
-                            if delay_ns_i < 0 { // clamp & gently pull offset toward eliminating negatives
-                                offset_ns += delay_ns_i / 8; // small correction to reduce future negatives
-                                delay_ns_i = 0;
-                            }
+                            let (delay_ns_i, transit) = clock.observe(ts_ns, now_inst);
                             let delay_ms = (delay_ns_i as f64)/1_000_000.0;
                             latency_acc += delay_ms; latency_samples += 1;
-                            // transit for jitter (same basis: arrival_rel - server_rel - offset)
-                            let transit = (arrival_rel_ns as i128) - (server_rel_ns as i128) - offset_ns;
                             if let Some(prev_t) = prev_transit { let mut d = transit - prev_t; if d < 0 { d = -d; } // |d|
                                 if jitter_ewma_ns == 0.0 { jitter_ewma_ns = d as f64; } else { jitter_ewma_ns += (d as f64 - jitter_ewma_ns)/16.0; }
                             }
                             prev_transit = Some(transit);
                             // seq / loss update
-                            if expected_seq==0 { expected_seq=seq; }
-                            if seq>=expected_seq { let gap = seq - expected_seq; if gap>0 { // lost frames
-                                    loss_acc += gap as f64;
-                                }
-                                expected_seq = seq + 1;
-                            } else {
-                                // out-of-order older frame (already handled by reorder), ignore for loss calc
-                            }
-                            recv_seq += 1;
+                            if !silence { loss.observe(seq as u32); recv_seq += 1; }
                             // adaptive target buffer & caps
-                            let (tgt, max_cap) = adjust_targets(jitter_ewma_ns);
+                            let tuning = LatencyProfile::from_u8(latency_profile.load(Ordering::Relaxed)).tuning();
+                            let (tgt, max_cap) = adjust_targets(jitter_ewma_ns, &tuning);
                             target_buffer_ns = tgt; max_buffer_ns = max_cap;
                             // dynamic reorder delay
-                            let reorder_delay = compute_reorder_delay(jitter_ewma_ns);
+                            // TCP transport delivers in order: release without waiting for stragglers
+                            let mut reorder_delay = if ordered { 0 } else { compute_reorder_delay(jitter_ewma_ns, &tuning) };
+                            // FEC: wait out one parity group so a rebuilt frame still makes it into the buffer
+                            if fec_group > 0 && !ordered { reorder_delay = reorder_delay.max(fec_group as u64 * last_dur_ns); }
                             // late frame drop policy (severely late > 2*reorder_delay behind newest)
                             if newest_ts!=0 && ts_ns + 2*reorder_delay < newest_ts { late_drop_count += 1; continue; }
                             if ts_ns > newest_ts { newest_ts = ts_ns; }
@@ -299,30 +606,84 @@ pub fn connect_with_output(server_ip: String, port: u16, output_index: usize, ps
                             let mut frames: Vec<f32> = if let Some(mut reused)=frame_pool.pop(){ reused.clear(); reused } else { Vec::with_capacity(2048) };
                             match fmt {
                                 types::FMT_F32 => { let cnt=payload_len/4; frames.reserve(cnt); for chunk in payload.chunks_exact(4).take(cnt){ let mut a=[0u8;4]; a.copy_from_slice(chunk); frames.push(f32::from_ne_bytes(a)); } },
-                                types::FMT_I16 => { let cnt=payload_len/2; frames.reserve(cnt); for chunk in payload.chunks_exact(2).take(cnt){ let v=i16::from_le_bytes([chunk[0],chunk[1]]); frames.push(v as f32/32768.0); } },
-                                types::FMT_U16 => { let cnt=payload_len/2; frames.reserve(cnt); for chunk in payload.chunks_exact(2).take(cnt){ let v=u16::from_le_bytes([chunk[0],chunk[1]]); frames.push((v as f32 - 32768.0)/32768.0); } },
+                                types::FMT_I16 => { let cnt=payload_len/2; frames.reserve(cnt); for chunk in payload.chunks_exact(2).take(cnt){ frames.push(convert::i16_to_f32(i16::from_le_bytes([chunk[0],chunk[1]]))); } },
+                                types::FMT_OPUS => {
+                                    if !matches!(&opus_dec, Some(d) if d.matches(sr, ch)) { opus_dec = OpusStreamDecoder::new(sr, ch).map_err(|e| log::warn!("[CLIENT][OPUS] {e:#}")).ok(); }
+                                    match opus_dec.as_mut().map(|d| d.decode(payload, &mut frames)) {
+                                        Some(Ok(())) => {},
+                                        Some(Err(e)) => { log::warn!("[CLIENT][OPUS] seq={seq}: {e:#}"); if frame_pool.len()<POOL_CAPACITY { frame_pool.push(frames); } continue }
+                                        None => { if frame_pool.len()<POOL_CAPACITY { frame_pool.push(frames); } continue }
+                                    }
+                                },
+                                types::FMT_U16 => { let cnt=payload_len/2; frames.reserve(cnt); for chunk in payload.chunks_exact(2).take(cnt){ frames.push(convert::u16_to_f32(u16::from_le_bytes([chunk[0],chunk[1]]))); } },
+                                types::FMT_I32 => { let cnt=payload_len/4; frames.reserve(cnt); for chunk in payload.chunks_exact(4).take(cnt){ frames.push(convert::i32_to_f32(i32::from_le_bytes([chunk[0],chunk[1],chunk[2],chunk[3]]))); } },
+                                types::FMT_F64 => { let cnt=payload_len/8; frames.reserve(cnt); for chunk in payload.chunks_exact(8).take(cnt){ let mut a=[0u8;8]; a.copy_from_slice(chunk); frames.push(f64::from_ne_bytes(a) as f32); } },
                                 _ => { if frame_pool.len()<POOL_CAPACITY { frame_pool.push(frames); } continue }
                             }
-                            // Down-mix to mono if multi-channel
-                            let effective = if ch>1 { let mut mono = if let Some(mut reused)=frame_pool.pop(){ reused.clear(); reused } else { Vec::with_capacity(frames.len()/ch as usize) }; for chunk in frames.chunks_exact(ch as usize){ let s: f32 = chunk.iter().copied().sum(); mono.push(s / ch as f32); } if frame_pool.len()<POOL_CAPACITY { frame_pool.push(frames); } mono } else { frames };
-                            // RMS & peak (with decay)
-                            if !effective.is_empty() { let mut acc=0f64; for &smp in &effective { acc += (smp as f64)*(smp as f64); } let rms=(acc/(effective.len() as f64)).sqrt(); metrics_rms.store(rms); // peak update
-                                let prev_peak = metrics_peak.load();
-                                let new_peak = if rms > prev_peak { rms } else { // 100ms metrics push cadence -> approximate 1% decay per 100ms
-                                    prev_peak * 0.99
-                                }; if (new_peak - prev_peak).abs() > 1e-12 { metrics_peak.store(new_peak); } }
-                            let dur_ns = if sr>0 { ((effective.len() as u128)*1_000_000_000u128 / sr as u128) as u64 } else {0};
-                            buffered_total_ns = buffered_total_ns.saturating_add(dur_ns);
+                            // Channel layout comes from this frame's header (may differ from the handshake)
+                            if let Some((old, new)) = (!silence).then(|| channels.observe(ch)).flatten() {
+                                log::info!("[CLIENT] frame channel count changed {old} -> {new} (seq={seq})");
+                                frame_channels.store(new, Ordering::Relaxed);
+                                if let Some(ref ev) = udp_events { let _ = ev.send(ClientEvent::ChannelsChanged { old, new }); }
+                            }
+                            // RMS & peak on the source samples (hold 1.5s, then fall at a fixed dB/s)
+                            meter_tick = meter_tick.wrapping_add(1);
+                            if !frames.is_empty() && (!degrader.is_shed(Optional::Metering) || meter_tick & 3 == 0) { let mut acc=0f64; for &smp in &frames { acc += (smp as f64)*(smp as f64); } let rms=(acc/(frames.len() as f64)).sqrt(); metrics_rms.store(rms);
+                                metrics_peak.store(peak_meter.update(rms, now_inst));
+                                metrics_channels.update(&frames, ch as usize);
+                                metrics_scope.push(&frames, ch as usize); }
+                            // Server switched input (`PARAMS`): the output is rebuilt at the new rate; a recording cannot change rate mid-file
+                            let rate_now = stream_rate.load(Ordering::Relaxed);
+                            if rate_now != out_rate { out_rate = rate_now; end_recording(&recording, "server sample rate changed"); }
+                            // Low-quality substream / Opus (or any rate mismatch) -> back to the rate the output was opened with
+                            let (frames, sr) = if sr>0 && sr != out_rate { let mut up = if let Some(mut reused)=frame_pool.pop(){ reused.clear(); reused } else { Vec::with_capacity(frames.len() * (out_rate / sr.max(1)).max(1) as usize) }; convert::resample_interleaved(&frames, ch, sr, out_rate, &mut up); if frame_pool.len()<POOL_CAPACITY { frame_pool.push(frames); } (up, out_rate) } else { (frames, sr) };
+                            // Map to the output layout (or forced mono)
+                            let mut effective = if let Some(mut reused)=frame_pool.pop(){ reused.clear(); reused } else { Vec::with_capacity(frames.len()) };
+                            map_channels(&frames, ch, out_channels, force_mono.load(Ordering::Relaxed), &mut effective);
+                            if frame_pool.len()<POOL_CAPACITY { frame_pool.push(frames); }
+                            let dur_ns = if sr>0 { ((effective.len() / out_channels as usize) as u128 * 1_000_000_000u128 / sr as u128) as u64 } else {0};
+                            buffered_total_ns = buffered_total_ns.saturating_add(dur_ns); last_dur_ns = dur_ns;
                             heap.push(Reverse(BufFrame { ts_ns, dur_ns, data: effective }));
+                            // First decodable frame -> streaming (only from waiting_audio, i.e. params + join confirmed)
+                            if !silence && stage.load(Ordering::Relaxed) == 2 { stage.store(3, Ordering::Relaxed); log::info!("[CLIENT] streaming (first decodable frame seq={seq})"); }
+                            // Synchronized start: hold the first release until the shared grid instant (all clients pick the same server-timeline boundary)
+                            let mut hold = false; let mut force_release = silence;
+                            if !sync_done {
+                                if sync_gate.is_none() {
+                                    if sync_start.load(Ordering::Relaxed) {
+                                        let start_ts = sync_start_ts(newest_ts);
+                                        let at = clock.local_instant(start_ts, now_inst) + SYNC_PLAYOUT_DELAY;
+                                        log::info!("[CLIENT][SYNC] start at stream {:.3}s (in {}ms)", start_ts as f64 / 1e9, at.saturating_duration_since(now_inst).as_millis());
+                                        sync_gate = Some((start_ts, at)); sync_start_ms.store(start_ts / 1_000_000, Ordering::Relaxed);
+                                        if let Ok(mut g) = sync_start_at.lock() { *g = Some(at); }
+                                    } else { sync_done = true; }
+                                }
+                                if let Some((start_ts, at)) = sync_gate {
+                                    // Drop frames before the boundary; if we open late by `x`, start `x` further into the schedule so every room plays the same position
+                                    let skip_before = start_ts + now_inst.saturating_duration_since(at).as_nanos() as u64;
+                                    while heap.peek().is_some_and(|Reverse(f)| f.ts_ns < skip_before) {
+                                        if let Some(Reverse(f)) = heap.pop() { buffered_total_ns = buffered_total_ns.saturating_sub(f.dur_ns); if frame_pool.len()<POOL_CAPACITY { frame_pool.push(f.data); } }
+                                    }
+                                    if now_inst >= at { sync_done = true; force_release = true; log::info!("[CLIENT][SYNC] started"); } else { hold = true; }
+                                }
+                            }
                             // Release frames while latency condition or overflow
                             let mut released = 0usize;
                             while let Some(Reverse(ref peek)) = heap.peek() {
-                                let can_release = (peek.ts_ns + reorder_delay <= newest_ts && buffered_total_ns >= target_buffer_ns && heap.len()>2) || buffered_total_ns > max_buffer_ns;
+                                if hold { break; }
+                                let can_release = force_release || (peek.ts_ns + reorder_delay <= newest_ts && buffered_total_ns >= target_buffer_ns && heap.len()>2) || buffered_total_ns > max_buffer_ns;
                                 if can_release {
                                     if let Some(Reverse(f)) = heap.pop() {
                                         buffered_total_ns = buffered_total_ns.saturating_sub(f.dur_ns);
+                                        // Gap before this frame -> fill with faded repeats of the previous frame
+                                        if let Some(prev_end) = plc_prev_end { if plc_enabled.load(Ordering::Relaxed) && !degrader.is_shed(Optional::Concealment) && f.dur_ns > 0 && !plc_prev.is_empty() {
+                                            let missing = (f.ts_ns.saturating_sub(prev_end) + f.dur_ns / 2) / f.dur_ns;
+                                            for fill in conceal_frames(&plc_prev, out_channels, missing as usize) { record_samples(&recording, &fill); if tx.send(fill).is_err() { break; } plc_concealed.fetch_add(1, Ordering::Relaxed); }
+                                        } }
+                                        plc_prev_end = Some(f.ts_ns + f.dur_ns); plc_prev.clear(); plc_prev.extend_from_slice(&f.data);
                                         let mut out_vec = if let Some(mut reused)=frame_pool.pop(){ reused.clear(); reused } else { Vec::with_capacity(f.data.len()) };
                                         out_vec.extend_from_slice(&f.data);
+                                        record_samples(&recording, &out_vec);
                                         if tx.send(out_vec).is_err() { break; }
                                         if frame_pool.len()<POOL_CAPACITY { frame_pool.push(f.data); }
                                         released +=1;
@@ -330,148 +691,918 @@ pub fn connect_with_output(server_ip: String, port: u16, output_index: usize, ps
                                 } else { break; }
                             }
                             // Periodic stats (5s)
-                            if last_stats_report.elapsed().as_secs() >= 5 { let avg_lat = if latency_samples>0 { latency_acc/(latency_samples as f64) } else {0.0}; println!("[CLIENT] stats: avg_lat={:.2}ms jitter={:.2}ms tgt={:.1}ms buf={:.1}ms max={:.1}ms heap={} rel={} late_drop={} rdelay={:.1}ms", avg_lat, jitter_ewma_ns/1_000_000.0, target_buffer_ns as f64/1_000_000.0, buffered_total_ns as f64/1_000_000.0, max_buffer_ns as f64/1_000_000.0, heap.len(), released, late_drop_count, reorder_delay as f64/1_000_000.0); latency_acc=0.0; latency_samples=0; last_stats_report=std::time::Instant::now(); if recv_seq==1 { println!("[CLIENT] first multicast frame seq={seq}"); } }
+                            if last_stats_report.elapsed().as_secs() >= 5 { let avg_lat = if latency_samples>0 { latency_acc/(latency_samples as f64) } else {0.0}; log::debug!("[CLIENT] stats: avg_lat={:.2}ms jitter={:.2}ms tgt={:.1}ms buf={:.1}ms max={:.1}ms heap={} rel={} late_drop={} rdelay={:.1}ms", avg_lat, jitter_ewma_ns/1_000_000.0, target_buffer_ns as f64/1_000_000.0, buffered_total_ns as f64/1_000_000.0, max_buffer_ns as f64/1_000_000.0, heap.len(), released, late_drop_count, reorder_delay as f64/1_000_000.0); latency_acc=0.0; latency_samples=0; last_stats_report=std::time::Instant::now(); if recv_seq==1 { log::debug!("[CLIENT] first multicast frame seq={seq}"); } }
                             // Metrics update every 100ms
                             if last_metrics_push.elapsed().as_millis() >= 100 {
                                 let avg_lat = if latency_samples>0 { latency_acc/(latency_samples as f64) } else { metrics_latency.load() };
                                 metrics_latency.store(avg_lat);
                                 metrics_jitter.store(jitter_ewma_ns/1_000_000.0);
                                 // packet loss ratio = lost / (received + lost)
-                                let lost = loss_acc; let total = (recv_seq as f64) + lost; if total>0.0 { metrics_loss.store(lost/total); }
+                                let lost = loss.lost as f64; let total = (recv_seq as f64) + lost; if total>0.0 { metrics_loss.store(lost/total); }
+                                if lost > 0.0 { fec_recovery.store((fec_recovered.load(Ordering::Relaxed) as f64 / lost).min(1.0)); }
                                 metrics_late.store(late_drop_count as f64);
+                                metrics_buffer.store(buffered_total_ns as f64 / 1_000_000.0); metrics_target.store(target_buffer_ns as f64 / 1_000_000.0); metrics_max.store(max_buffer_ns as f64 / 1_000_000.0);
+                                if total > 0.0 { metrics_quality.store(Quality::assess(avg_lat, jitter_ewma_ns / 1_000_000.0, lost / total, late_drop_count as f64 / total).as_u8(), Ordering::Relaxed); }
                                 last_metrics_push = std::time::Instant::now();
                             }
-                        }, Err(ref e) if e.kind()==std::io::ErrorKind::WouldBlock => { thread::sleep(Duration::from_millis(10)); }, Err(e) => { eprintln!("[CLIENT][UDP][ERR] recv: {e}"); break } }
+                            degrader.observe(busy_start.elapsed(), Duration::from_nanos(dur_ns));
+                            spectrum_shed.store(degrader.is_shed(Optional::Spectrum), Ordering::Relaxed);
+                        }, Err(ref e) if e.kind()==std::io::ErrorKind::WouldBlock => { thread::sleep(Duration::from_millis(10)); }, Err(e) => { log::error!("[CLIENT][UDP][ERR] recv: {e}"); break } }
                 }
                 // Drain remaining frames
                 while let Some(Reverse(f)) = heap.pop() {
                     let out = f.data; if tx.send(out.clone()).is_err() { break; }
                     if frame_pool.len()<POOL_CAPACITY { frame_pool.push(out); }
                 }
-                eprintln!("[CLIENT][UDP] thread exit"); alive.store(false, Ordering::SeqCst);
-            });
+                membership.leave();
+                log::warn!("[CLIENT][UDP] thread exit"); alive.store(false, Ordering::SeqCst);
+            })?;
         }
-    } else { println!("[CLIENT] No audio params yet; output not started"); }
+    } else { log::info!("[CLIENT] No audio params yet; output not started"); }
     Ok(state)
 }
 
-/// Spawn audio output thread (f32 only).
-fn spawn_output_thread(dev: cpal::Device, rx: Receiver<Vec<f32>>, running: Arc<AtomicBool>, params: AudioParams) -> CbSender<()> {
+/// Jitter buffer latency / stability trade-off chosen in the client panel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LatencyProfile { UltraLow, #[default] Balanced, Stable }
+
+/// Buffer bounds for one profile (ms): target = base + jitter-driven extra, clamped to `target`;
+/// hard cap = 2 x target clamped to `cap`; reorder wait = 2.5 x jitter clamped to `reorder`.
+pub struct JitterTuning { pub base_ms: f64, pub target: (f64, f64), pub cap: (f64, f64), pub reorder: (f64, f64) }
+
+impl LatencyProfile {
+    pub const ALL: [LatencyProfile; 3] = [LatencyProfile::UltraLow, LatencyProfile::Balanced, LatencyProfile::Stable];
+
+    /// Compact form for atomics (`ClientState::latency_profile`).
+    pub fn as_u8(self) -> u8 { self as u8 }
+    pub fn from_u8(v: u8) -> Self { Self::ALL.get(v as usize).copied().unwrap_or_default() }
+
+    /// Language key of the selector label.
+    pub fn lang_key(self) -> &'static str { match self { LatencyProfile::UltraLow => "client.latency.ultra_low", LatencyProfile::Balanced => "client.latency.balanced", LatencyProfile::Stable => "client.latency.stable" } }
+
+    pub fn tuning(self) -> JitterTuning {
+        match self {
+            LatencyProfile::UltraLow => JitterTuning { base_ms: 5.0, target: (3.0, 15.0), cap: (10.0, 25.0), reorder: (2.0, 12.0) },
+            LatencyProfile::Balanced => JitterTuning { base_ms: 15.0, target: (10.0, 40.0), cap: (30.0, 100.0), reorder: (5.0, 40.0) },
+            LatencyProfile::Stable => JitterTuning { base_ms: 30.0, target: (25.0, 70.0), cap: (60.0, 120.0), reorder: (10.0, 60.0) },
+        }
+    }
+}
+
+/// Reorder wait before releasing the oldest frame (ns): scales with jitter inside the profile's bounds.
+fn compute_reorder_delay(jitter_ns: f64, t: &JitterTuning) -> u64 { ((jitter_ns * 2.5).max(t.reorder.0 * 1e6).min(t.reorder.1 * 1e6)) as u64 }
+
+/// Adaptive (target, max) buffer depth in ns: jitter adds up to +25ms over the profile's base.
+fn adjust_targets(jitter_ns: f64, t: &JitterTuning) -> (u64, u64) {
+    let extra = (jitter_ns / 1_000_000.0 * 2.5).clamp(0.0, 25.0);
+    let target = (t.base_ms + extra).clamp(t.target.0, t.target.1);
+    let max = (target * 2.0).clamp(t.cap.0, t.cap.1);
+    ((target * 1_000_000.0) as u64, (max * 1_000_000.0) as u64)
+}
+
+/// Server timeline -> local arrival time. The first frame sets the base; later frames measure their
+/// delay against it, and one arriving "early" pulls the offset down (the first frame was late).
+#[derive(Default)]
+struct ArrivalClock { base: Option<(u64, std::time::Instant)>, offset_ns: i128 }
+
+impl ArrivalClock {
+    /// Delay over the fastest path (ns, >= 0) and transit (for jitter) of a frame stamped `ts_ns`
+    /// arriving at `now`. Signed throughout: an FEC rebuild of a packet sent before the first one
+    /// received is older than the base and simply counts as late.
+    fn observe(&mut self, ts_ns: u64, now: std::time::Instant) -> (i128, i128) {
+        let (base_ts, base_at) = *self.base.get_or_insert((ts_ns, now));
+        let arrival_rel = now.saturating_duration_since(base_at).as_nanos() as i128;
+        let server_rel = ts_ns as i128 - base_ts as i128;
+        let mut delay = arrival_rel - (server_rel + self.offset_ns);
+        if delay < 0 { self.offset_ns += delay / 8; delay = 0; } // gently pull the offset toward no negatives
+        (delay, arrival_rel - server_rel - self.offset_ns)
+    }
+
+    /// Expected local arrival of the frame stamped `ts_ns` (`now` before the first frame).
+    fn local_instant(&self, ts_ns: u64, now: std::time::Instant) -> std::time::Instant {
+        let Some((base_ts, base_at)) = self.base else { return now };
+        let local_ns = ts_ns as i128 - base_ts as i128 + self.offset_ns;
+        if local_ns >= 0 { base_at + Duration::from_nanos(local_ns as u64) } else { base_at.checked_sub(Duration::from_nanos(local_ns.unsigned_abs() as u64)).unwrap_or(base_at) }
+    }
+}
+
+/// Synchronized start grid on the server timeline: every client starts on a multiple of this.
+const SYNC_GRID_NS: u64 = 500_000_000;
+/// Minimum lead before the chosen boundary (room for buffering on every client).
+const SYNC_MARGIN_NS: u64 = 200_000_000;
+/// Fixed playout delay after the boundary frame's expected arrival, identical on all clients.
+const SYNC_PLAYOUT_DELAY: Duration = Duration::from_millis(60);
+
+/// First grid boundary at least `SYNC_MARGIN_NS` after `newest_ts`; late joiners land on the same grid.
+fn sync_start_ts(newest_ts: u64) -> u64 { ((newest_ts + SYNC_MARGIN_NS) / SYNC_GRID_NS + 1) * SYNC_GRID_NS }
+
+/// Tracks the channel count carried in frame headers so a mid-session change (server switched
+/// input device) is noticed instead of silently down-mixing with a stale layout.
+struct FrameChannels { current: u16 }
+
+impl FrameChannels {
+    fn new(initial: u16) -> Self { Self { current: initial.max(1) } }
+
+    /// Record the channel count of an incoming frame; returns `(old, new)` when it changed.
+    fn observe(&mut self, ch: u16) -> Option<(u16, u16)> {
+        let ch = ch.max(1);
+        if ch == self.current { return None; }
+        let old = std::mem::replace(&mut self.current, ch);
+        Some((old, ch))
+    }
+}
+
+/// Anti-replay window (frames): repeats inside it, and anything older, are rejected.
+const REPLAY_WINDOW: u32 = 256;
+
+/// Sliding-window replay filter over the wrapping u32 frame `seq` (bit `k` = `highest - k` seen).
+struct ReplayWindow { highest: Option<u32>, seen: [u64; 4] }
+
+impl ReplayWindow {
+    fn new() -> Self { Self { highest: None, seen: [0; 4] } }
+
+    /// Accept each `seq` once; false for replays and frames more than `REPLAY_WINDOW` behind.
+    fn accept(&mut self, seq: u32) -> bool {
+        let Some(highest) = self.highest else { self.highest = Some(seq); self.seen = [1, 0, 0, 0]; return true };
+        // Forward distance below 2^31 = newer (wraps past u32::MAX)
+        let ahead = seq.wrapping_sub(highest);
+        if ahead != 0 && ahead < 1 << 31 {
+            self.shift(ahead);
+            self.highest = Some(seq);
+            self.seen[0] |= 1;
+            return true;
+        }
+        let behind = highest.wrapping_sub(seq);
+        if behind >= REPLAY_WINDOW { return false; }
+        let (word, bit) = ((behind / 64) as usize, behind % 64);
+        if self.seen[word] & (1 << bit) != 0 { return false; }
+        self.seen[word] |= 1 << bit;
+        true
+    }
+
+    /// Age every seen bit by `n` frames (a newer `highest`).
+    fn shift(&mut self, n: u32) {
+        if n >= REPLAY_WINDOW { self.seen = [0; 4]; return; }
+        let (words, bits) = ((n / 64) as usize, n % 64);
+        for i in (0..self.seen.len()).rev() {
+            let lo = i.checked_sub(words).map_or(0, |s| self.seen[s]);
+            let carry = match (bits, i.checked_sub(words + 1)) { (0, _) | (_, None) => 0, (_, Some(s)) => self.seen[s] >> (64 - bits) };
+            self.seen[i] = (lo << bits) | carry;
+        }
+    }
+}
+
+/// Frames missing from the wrapping u32 `seq` sequence (gaps ahead of the next expected seq).
+struct LossCounter { expected: Option<u32>, lost: u64 }
+
+impl LossCounter {
+    fn new() -> Self { Self { expected: None, lost: 0 } }
+
+    /// Count the gap before `seq`; frames behind the expected seq (reordered) are not counted.
+    fn observe(&mut self, seq: u32) {
+        let Some(expected) = self.expected else { self.expected = Some(seq.wrapping_add(1)); return };
+        // Signed distance modulo 2^32: negative = older frame, also across u32::MAX -> 0
+        let ahead = seq.wrapping_sub(expected) as i32;
+        if ahead < 0 { return; }
+        self.lost += ahead as u64;
+        self.expected = Some(seq.wrapping_add(1));
+    }
+}
+
+/// Average interleaved `ch`-channel samples into `out` (trailing partial frame dropped).
+fn downmix_mono(samples: &[f32], ch: u16, out: &mut Vec<f32>) {
+    let ch = ch.max(1) as usize;
+    out.extend(samples.chunks_exact(ch).map(|f| f.iter().sum::<f32>() / ch as f32));
+}
+
+/// Map interleaved `in_ch` audio onto `out_ch` output channels (appended to `out`).
+/// - mono source or `force_mono`: down-mix, same sample on every output channel
+/// - equal counts: copied; fewer outputs: stereo gets the usual 5.1 fold-down (C at -3 dB to both
+///   sides, LFE dropped, surrounds to their side), mono output gets the average
+/// - more outputs: source channels land on the first outputs, the rest stay silent
+pub fn map_channels(samples: &[f32], in_ch: u16, out_ch: u16, force_mono: bool, out: &mut Vec<f32>) {
+    let (ic, oc) = (in_ch.max(1) as usize, out_ch.max(1) as usize);
+    if oc == 1 {
+        downmix_mono(samples, in_ch, out);
+    } else if ic == 1 || force_mono {
+        out.reserve(samples.len() / ic * oc);
+        for f in samples.chunks_exact(ic) { let m = f.iter().sum::<f32>() / ic as f32; out.resize(out.len() + oc, m); }
+    } else if ic == oc {
+        out.extend_from_slice(&samples[..samples.len() / ic * ic]);
+    } else if ic < oc {
+        for f in samples.chunks_exact(ic) { out.extend_from_slice(f); out.resize(out.len() + oc - ic, 0.0); }
+    } else {
+        // Fold extra channels into front L/R: 2 = C (both, -3 dB), 3 = LFE (dropped), then even -> L, odd -> R
+        const C_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+        let mut gains = vec![[0f32; 2]; ic];
+        for (i, g) in gains.iter_mut().enumerate() { *g = match i { 0 => [1.0, 0.0], 1 => [0.0, 1.0], 2 => [C_GAIN, C_GAIN], 3 => [0.0, 0.0], _ if i % 2 == 0 => [C_GAIN, 0.0], _ => [0.0, C_GAIN] }; }
+        let norm = [gains.iter().map(|g| g[0]).sum::<f32>().max(1.0), gains.iter().map(|g| g[1]).sum::<f32>().max(1.0)];
+        for f in samples.chunks_exact(ic) {
+            let (l, r) = f.iter().zip(&gains).fold((0f32, 0f32), |(l, r), (s, g)| (l + s * g[0], r + s * g[1]));
+            out.push(l / norm[0]); out.push(r / norm[1]);
+            // outputs beyond stereo (e.g. 5.1 device fed by a 7.1 source) copy the matching source channel
+            out.extend((2..oc).map(|c| f[c]));
+        }
+    }
+}
+
+/// Append played samples to the active recording (if any); a failed write ends the recording.
+fn record_samples(recording: &Mutex<Option<record::Recording>>, samples: &[f32]) {
+    let Ok(mut guard) = recording.lock() else { return };
+    let Some(writer) = guard.as_mut() else { return };
+    if let Err(e) = writer.write(samples) {
+        log::warn!("[CLIENT][RECORD] write failed: {e} -> stopping");
+        if let Some(w) = guard.take() { let _ = w.finalize(); }
+    }
+}
+
+/// Finalize the active recording (if any), logging why.
+fn end_recording(recording: &Mutex<Option<record::Recording>>, why: &str) {
+    let Some(w) = recording.lock().ok().and_then(|mut g| g.take()) else { return };
+    log::warn!("[CLIENT][RECORD] {why} -> stopping");
+    let _ = w.finalize();
+}
+
+/// Max consecutive frames synthesized for one gap; longer gaps fade to silence (output zero-fills).
+const PLC_MAX_FRAMES: usize = 3;
+
+/// Build up to `PLC_MAX_FRAMES` replacements for `missing` lost frames by repeating `prev`
+/// (interleaved, `ch` channels) under a linear fade-out that reaches zero at the end of the last
+/// concealed frame. All channels of one sample frame share the same gain.
+fn conceal_frames(prev: &[f32], ch: u16, missing: usize) -> Vec<Vec<f32>> {
+    let ch = ch.max(1) as usize;
+    let count = missing.min(PLC_MAX_FRAMES);
+    let per = prev.len() / ch;
+    let total = (count * per).max(1) as f32;
+    (0..count).map(|k| prev.iter().enumerate().map(|(i, &s)| s * (1.0 - (k * per + i / ch + 1) as f32 / total)).collect()).collect()
+}
+
+/// Playback volume ceiling (linear; 2.0 = 200%, +6 dB).
+pub const OUTPUT_GAIN_MAX: f64 = 2.0;
+
+/// Widest stereo image (side signal doubled).
+pub const STEREO_WIDTH_MAX: f64 = 2.0;
+
+/// Mid/side width on the front pair of interleaved `ch`-channel audio: `S *= width`, so 0 gives
+/// exactly the `force_mono` down-mix of a stereo source and 1 leaves it untouched.
+fn apply_stereo_width(samples: &mut [f32], ch: u16, width: f32) {
+    if ch < 2 || width == 1.0 { return; }
+    for f in samples.chunks_exact_mut(ch as usize) {
+        let (mid, side) = ((f[0] + f[1]) / 2.0, (f[0] - f[1]) / 2.0 * width);
+        f[0] = mid + side; f[1] = mid - side;
+    }
+}
+
+/// Default output watchdog threshold: rebuild the stream if no callback arrives for this long (0 disables).
+pub const OUTPUT_WATCHDOG_DEFAULT_MS: u64 = 1500;
+
+/// Shared handles the output thread reports into (watchdog, stage, warmup events).
+#[derive(Clone)]
+struct OutputHooks {
+    watchdog_ms: Arc<AtomicU64>,
+    restarts: Arc<AtomicU64>,
+    stage: Arc<AtomicU8>,
+    warmup_log: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    gain: Arc<AtomicF64>,
+    width: Arc<AtomicF64>,
+    loudness: LoudnessSettings,
+    limiter: Arc<AtomicBool>,
+    event_sender: Option<EventSender<ClientEvent>>,
+    stream_rate: Arc<AtomicU32>,
+}
+
+/// Spawn audio output thread (f32 only). A watchdog rebuilds the stream on the same device if callbacks stall;
+/// it is also rebuilt when the server's sample rate changes (`PARAMS`). If the device is unplugged the
+/// stream moves to the system default output (`OutputLost` event; `new: None` = playback stopped).
+fn spawn_output_thread(dev: cpal::Device, rx: Receiver<Vec<f32>>, running: Arc<AtomicBool>, mut params: AudioParams, hooks: OutputHooks) -> CbSender<()> {
     let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(1);
-    thread::spawn(move || {
+    let OutputHooks { watchdog_ms, restarts, stream_rate, .. } = hooks.clone();
+    let spawned = thread::Builder::new().name("rm-output".into()).spawn(move || {
     let running_outer = running.clone();
-    if let Ok(cfg) = dev.default_output_config() {
+    let mut dev = dev;
+    while let Ok(cfg) = dev.default_output_config() {
         let sample_format = cfg.sample_format();
         let config: cpal::StreamConfig = cfg.clone().into();
+        let mut device_lost = false;
         match sample_format {
             cpal::SampleFormat::F32 => {
-                let mut leftover: Vec<f32> = Vec::new();
-                let out_channels = config.channels.max(1);
-                let rx_clone = rx.clone();
-                let in_channels = params.channels.max(1);
-                // Jitter prebuffer: fill ~20ms before start
-                let prebuffer_frames: usize = (params.sample_rate as f32 * 0.02) as usize; // 20ms
-                let mut started = false;
-                let mut underruns: u64 = 0; let mut last_report = std::time::Instant::now();
-                let build_res = dev.build_output_stream(&config, move |out: &mut [f32], _| {
-                    if !running.load(Ordering::Relaxed) { return; }
-                    let needed_frames = out.len() / out_channels as usize;
-                    if !started {
-                        // Prebuffer phase: accumulate until threshold
-                        while leftover.len() < prebuffer_frames {
-                            match rx_clone.try_recv() { Ok(mut frames) => { leftover.append(&mut frames); }, Err(_) => break }
-                        }
-                        if leftover.len() >= prebuffer_frames {
-                            started = true;
-                            println!("[CLIENT] jitter buffer filled: {} frames (target {})", leftover.len(), prebuffer_frames);
-                        } else {
-                            // Not enough yet: keep filling, output silence
-                            while leftover.len() < needed_frames {
-                                match rx_clone.try_recv() { Ok(mut frames) => { leftover.append(&mut frames); }, Err(_) => break }
-                            }
-                            for s in out.iter_mut() { *s = 0.0; }
-                            return;
-                        }
-                    } else {
-                        // Steady state: ensure one callback worth of frames
-                        while leftover.len() < needed_frames {
-                            match rx_clone.try_recv() { Ok(mut frames) => { leftover.append(&mut frames); }, Err(_) => break }
-                        }
-                    }
-                    let mut produced = 0usize;
-                    for frame_index in 0..needed_frames {
-                        if frame_index < leftover.len() { let sample_mono = leftover[frame_index];
-                            // Upmix / downmix (currently mono already)
-                            for ch in 0..out_channels { out[produced + ch as usize] = if in_channels==1 { sample_mono } else { sample_mono }; }
-                            produced += out_channels as usize;
-                        } else { // zero fill remainder
-                            for ch in 0..out_channels { out[produced + ch as usize] = 0.0; }
-                            produced += out_channels as usize;
-                            underruns += 1;
-                        }
-                    }
-                    // Consume frames
-                    if needed_frames <= leftover.len() { leftover.drain(0..needed_frames); } else { leftover.clear(); }
-                    if last_report.elapsed().as_secs_f32() > 5.0 { println!("[CLIENT] playback stats: leftover={} underruns={}", leftover.len(), underruns); last_report = std::time::Instant::now(); }
-                }, move |e| eprintln!("[CLIENT][OUTPUT][ERR] {e}"), None);
-                if let Ok(stream) = build_res { if let Err(e) = stream.play() { eprintln!("[CLIENT][OUTPUT][ERR] play: {e}"); } else { println!("[CLIENT][OUTPUT] stream started"); }
-                    // Wait for stop
+                // Callback heartbeat: ms since `epoch` of the most recent output callback
+                let epoch = std::time::Instant::now();
+                let last_cb = Arc::new(AtomicU64::new(0));
+                loop {
+                    let lost = Arc::new(AtomicBool::new(false));
+                    let stream = match build_f32_output_stream(&dev, &config, rx.clone(), running.clone(), &params, last_cb.clone(), epoch, hooks.clone(), lost.clone()) {
+                        Ok(stream) => stream,
+                        Err(e) => { log::error!("[CLIENT][OUTPUT][ERR] build: {e}"); break; }
+                    };
+                    if let Err(e) = stream.play() { log::error!("[CLIENT][OUTPUT][ERR] play: {e}"); } else { log::info!("[CLIENT][OUTPUT] stream started"); }
+                    // Grace period starts now so a slow first callback is not treated as a stall
+                    last_cb.store(epoch.elapsed().as_millis() as u64, Ordering::Relaxed);
+                    // Wait for stop (or a stalled stream / new server rate / unplugged device)
+                    let (mut stalled, mut new_rate) = (false, None);
                     loop {
                         if !running_outer.load(Ordering::Relaxed) { break; }
                         if stop_rx.recv_timeout(Duration::from_millis(200)).is_ok() { break; }
+                        if lost.load(Ordering::Relaxed) { device_lost = true; break; }
+                        let rate = stream_rate.load(Ordering::Relaxed);
+                        if rate > 0 && rate != params.sample_rate { new_rate = Some(rate); break; }
+                        let limit = watchdog_ms.load(Ordering::Relaxed);
+                        let since_cb = (epoch.elapsed().as_millis() as u64).saturating_sub(last_cb.load(Ordering::Relaxed));
+                        if limit > 0 && since_cb > limit { stalled = true; break; }
                     }
-                    if let Err(e) = stream.pause() { eprintln!("[CLIENT][OUTPUT] pause err: {e}"); } else { println!("[CLIENT][OUTPUT] stream paused"); }
+                    if let Err(e) = stream.pause() { log::warn!("[CLIENT][OUTPUT] pause err: {e}"); } else { log::info!("[CLIENT][OUTPUT] stream paused"); }
+                    drop(stream);
+                    if let Some(rate) = new_rate {
+                        log::info!("[CLIENT][OUTPUT] server rate {} Hz -> {rate} Hz, rebuilding stream", params.sample_rate);
+                        params.sample_rate = rate;
+                        continue;
+                    }
+                    if !stalled { break; }
+                    let n = restarts.fetch_add(1, Ordering::Relaxed) + 1;
+                    log::warn!("[CLIENT][OUTPUT] no callback for >{}ms -> rebuilding stream (restart #{n})", watchdog_ms.load(Ordering::Relaxed));
+                    if let Some(ref tx) = hooks.event_sender { let _ = tx.send(ClientEvent::OutputRestart(n)); }
                 }
             }
-            _ => { println!("[CLIENT] Unsupported output sample format: {:?}", sample_format); }
+            _ => { log::error!("[CLIENT] Unsupported output sample format: {:?}", sample_format); }
         }
+        if !device_lost { break; }
+        // Unplugged: continue on the system default output (unless that is the device that just went away)
+        let old_name = audio::device_name(&dev);
+        let fallback = audio::default_output_device().filter(|d| audio::device_name(d) != old_name);
+        let new_name = fallback.as_ref().map(audio::device_name);
+        log::warn!("[CLIENT][OUTPUT] output device {old_name} disconnected -> {}", new_name.as_deref().unwrap_or("playback stopped"));
+        if let Some(ref tx) = hooks.event_sender { let _ = tx.send(ClientEvent::OutputLost { old: old_name, new: new_name }); }
+        match fallback { Some(d) => dev = d, None => break }
     }
-    println!("[CLIENT][OUTPUT] thread exit");
+    log::info!("[CLIENT][OUTPUT] thread exit");
     });
+    if let Err(e) = spawned { log::error!("[CLIENT][OUTPUT][ERR] spawn: {e}"); }
     stop_tx
 }
 
+/// Build (not start) the f32 output stream: prebuffer, resampling and underrun accounting live in the callback.
+/// Frames arrive already mapped to the device channel layout (see `map_channels`).
+#[allow(clippy::too_many_arguments)]
+fn build_f32_output_stream(dev: &cpal::Device, config: &cpal::StreamConfig, rx: Receiver<Vec<f32>>, running: Arc<AtomicBool>, params: &AudioParams, last_cb: Arc<AtomicU64>, epoch: std::time::Instant, hooks: OutputHooks, lost: Arc<AtomicBool>) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    let mut leftover: Vec<f32> = Vec::new(); // interleaved, device layout, at the device rate
+    let out_channels = config.channels.max(1) as usize;
+    // Stream rate -> device rate (fresh per build, so a rebuilt stream starts without stale phase)
+    let mut resampler = convert::StreamResampler::new(params.sample_rate, config.sample_rate.0, config.channels);
+    if params.sample_rate != config.sample_rate.0 { log::info!("[CLIENT][OUTPUT] resampling {} Hz -> {} Hz", params.sample_rate, config.sample_rate.0); }
+    // Jitter prebuffer: fill ~20ms before start
+    let prebuffer_frames: usize = (config.sample_rate.0 as f32 * 0.02) as usize; // 20ms
+    let prebuffer_samples = prebuffer_frames * out_channels;
+    let mut started = false;
+    let mut warmup_start: Option<std::time::Instant> = None; // first callback of this stream
+    let mut underruns: u64 = 0; let mut last_report = std::time::Instant::now();
+    let mut normalizer = Loudness::new(config.sample_rate.0, out_channels);
+    let mut limiter = Limiter::new(config.sample_rate.0, out_channels);
+    dev.build_output_stream(config, move |out: &mut [f32], _| {
+        last_cb.store(epoch.elapsed().as_millis() as u64, Ordering::Relaxed);
+        if !running.load(Ordering::Relaxed) { return; }
+        if !started {
+            let warmup_began = *warmup_start.get_or_insert_with(std::time::Instant::now);
+            // Prebuffer phase: accumulate until threshold
+            while leftover.len() < prebuffer_samples {
+                match rx.try_recv() { Ok(frames) => { resampler.process(&frames, &mut leftover); }, Err(_) => break }
+            }
+            if leftover.len() >= prebuffer_samples {
+                started = true;
+                let warmup_ms = warmup_began.elapsed().as_secs_f64() * 1000.0;
+                log::info!("[CLIENT] jitter buffer filled: {} frames (target {}) in {:.1}ms", leftover.len() / out_channels, prebuffer_frames, warmup_ms);
+                if hooks.stage.load(Ordering::Relaxed) == 3 { hooks.stage.store(4, Ordering::Relaxed); }
+                if let Some(ref tx) = hooks.event_sender { let _ = tx.send(ClientEvent::BufferReady { frames: leftover.len() / out_channels, warmup_ms }); }
+            } else {
+                if hooks.warmup_log.load(Ordering::Relaxed) { log::debug!("[CLIENT] warmup: {}/{} frames", leftover.len() / out_channels, prebuffer_frames); }
+                // Not enough yet: keep filling, output silence
+                while leftover.len() < out.len() {
+                    match rx.try_recv() { Ok(frames) => { resampler.process(&frames, &mut leftover); }, Err(_) => break }
+                }
+                for s in out.iter_mut() { *s = 0.0; }
+                return;
+            }
+        } else {
+            // Steady state: ensure one callback worth of frames
+            while leftover.len() < out.len() {
+                match rx.try_recv() { Ok(frames) => { resampler.process(&frames, &mut leftover); }, Err(_) => break }
+            }
+        }
+        // Copy whole frames, zero fill the remainder (counted per missing frame)
+        let avail = leftover.len().min(out.len()) / out_channels * out_channels;
+        out[..avail].copy_from_slice(&leftover[..avail]);
+        for s in out[avail..].iter_mut() { *s = 0.0; }
+        underruns += ((out.len() - avail) / out_channels) as u64;
+        leftover.drain(..avail);
+        apply_stereo_width(out, out_channels as u16, hooks.width.load() as f32);
+        // Loudness normalization (slow gain + limiter), then the user's volume on top
+        if hooks.loudness.enabled.load(Ordering::Relaxed) {
+            normalizer.process(out, hooks.loudness.target_lufs.load());
+            hooks.loudness.gain.store(normalizer.gain());
+            hooks.loudness.measured.store(normalizer.measured().unwrap_or(f64::NEG_INFINITY));
+        }
+        // Local volume / mute (= gain 0): the channel is still drained above, only the device output changes.
+        // Then the soft limiter, or just one multiply + clamp per sample (no branch in the loop)
+        let gain = hooks.gain.load() as f32 * (!hooks.muted.load(Ordering::Relaxed)) as u8 as f32;
+        if hooks.limiter.load(Ordering::Relaxed) { limiter.process(out, gain); }
+        else { for s in out.iter_mut() { *s = (*s * gain).clamp(-1.0, 1.0); } }
+        if last_report.elapsed().as_secs_f32() > 5.0 { log::debug!("[CLIENT] playback stats: leftover={} underruns={}", leftover.len() / out_channels, underruns); last_report = std::time::Instant::now(); }
+    }, audio::stream_error_callback("[CLIENT][OUTPUT][ERR]", lost), None)
+}
+
+/// TCP transport frames buffered between the control reader and the receive thread (~5 s of 10 ms chunks).
+const TCP_FRAME_QUEUE: usize = 500;
+
+/// Where the receive thread reads sealed frames from.
+enum FrameSource { Udp(UdpSocket), Tcp(Receiver<Vec<u8>>) }
+
+impl FrameSource {
+    /// Next frame copied into `buf`; `WouldBlock` when none is waiting.
+    fn recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            FrameSource::Udp(sock) => sock.recv_from(buf).map(|(n, _)| n),
+            FrameSource::Tcp(rx) => match rx.try_recv() {
+                Ok(frame) => { let n = frame.len().min(buf.len()); buf[..n].copy_from_slice(&frame[..n]); Ok(n) }
+                Err(crossbeam_channel::TryRecvError::Empty) => Err(std::io::ErrorKind::WouldBlock.into()),
+                Err(crossbeam_channel::TryRecvError::Disconnected) => Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "control connection closed")),
+            },
+        }
+    }
+}
+
+/// Where the heartbeat loop gets control messages from: its own reads, or the TCP transport reader.
+enum ControlInput { Socket(LineBuffer), Reader(Receiver<ControlMessage>) }
+
+/// TCP transport: sole reader of the control connection. Frames go to the receive thread (dropped
+/// when it falls `TCP_FRAME_QUEUE` behind), everything else to the heartbeat loop; both channels
+/// close when the connection does.
+fn tcp_reader_loop(mut stream: TcpStream, mut lines: LineBuffer, connected: Arc<AtomicBool>, frames: CbSender<Vec<u8>>, control: CbSender<ControlMessage>) {
+    use std::io::{ErrorKind, Read};
+    let mut buf = vec![0u8; 65536];
+    while connected.load(Ordering::Relaxed) {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => for msg in lines.push(&buf[..n]) {
+                match msg {
+                    ControlMessage::Frame { data } => { let _ = frames.try_send(data); }
+                    other => if control.send(other).is_err() { return; },
+                }
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => { log::warn!("[CLIENT][TCP] read err: {e}"); break; }
+        }
+    }
+    log::info!("[CLIENT][TCP] reader exit");
+}
+
 /// Periodic heartbeat + timeout detection + coordinated shutdown.
-fn heartbeat_loop(stream_arc: Arc<std::sync::Mutex<TcpStream>>, key: String, connected: Arc<AtomicBool>, output_running: Arc<AtomicBool>, udp_alive: Arc<AtomicBool>, output_stop_tx: Arc<Mutex<Option<CbSender<()>>>>, reason: Arc<Mutex<Option<String>>>, event_sender: Option<EventSender<String>>) {
+#[allow(clippy::too_many_arguments)]
+fn heartbeat_loop(stream_arc: Arc<std::sync::Mutex<TcpStream>>, key: String, connected: Arc<AtomicBool>, output_running: Arc<AtomicBool>, udp_alive: Arc<AtomicBool>, output_stop_tx: Arc<Mutex<Option<CbSender<()>>>>, reason: Arc<Mutex<Option<String>>>, event_sender: Option<EventSender<ClientEvent>>, session: SessionLimit, mut input: ControlInput, keys: Arc<Mutex<KeyRing>>, stream_rate: Arc<AtomicU32>, rtt: Option<Arc<AtomicF64>>) {
     use std::io::{Write, Read};
     let mut buf = [0u8; 256];
     let mut last_ok = std::time::Instant::now();
-    const HEART_INTERVAL: Duration = Duration::from_secs(1);
+    let clock = std::time::Instant::now(); // heartbeat stamps: µs since this instant (only ever compared locally)
     const HEART_TIMEOUT: Duration = Duration::from_secs(5); // 超过 5 秒未收到 OK 认为超时
-    while connected.load(Ordering::Relaxed) {
-        if let Ok(mut stream) = stream_arc.lock() {
-            let _ = stream.write_all(format!("HEART {key}\n").as_bytes());
-            match stream.read(&mut buf) {
-                Ok(0) => { println!("[CLIENT][HEART] server closed"); if let Ok(mut r)=reason.lock(){ let msg: String = "服务器连接关闭".into(); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(format!("DISCONNECT:{msg}")); } } connected.store(false, Ordering::SeqCst); break; },
-                Ok(n) => {
-                    let s = String::from_utf8_lossy(&buf[..n]);
-                    if s.contains("SERVER_STOP") { println!("[CLIENT] server stop detected"); if let Ok(mut r)=reason.lock(){ let msg: String = "服务器已停止".into(); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(format!("DISCONNECT:{msg}")); } } connected.store(false, Ordering::SeqCst); break; }
-                    if s.contains("OK") { last_ok = std::time::Instant::now(); }
+    const RTT_POLL: Duration = Duration::from_millis(5); // poll step while a stamped OK is outstanding
+    'beat: while connected.load(Ordering::Relaxed) {
+        let next_beat = std::time::Instant::now() + HEART_INTERVAL;
+        let mut pending = rtt.as_ref().map(|_| clock.elapsed().as_micros() as u64);
+        if let Ok(mut stream) = stream_arc.lock() { let _ = stream.write_all(ControlMessage::Heart { key: key.clone(), stamp: pending }.to_line().as_bytes()); }
+        // Poll once; with a stamp outstanding keep polling in short steps so the echo is timed when it arrives
+        loop {
+            // Ok(None) = connection closed
+            let polled = match stream_arc.lock() {
+                Ok(mut stream) => match &mut input {
+                    ControlInput::Socket(lines) => stream.read(&mut buf).map(|n| (n > 0).then(|| lines.push(&buf[..n]))),
+                    ControlInput::Reader(rx) => {
+                        let mut msgs = Vec::new();
+                        loop { match rx.try_recv() { Ok(m) => msgs.push(m), Err(crossbeam_channel::TryRecvError::Empty) => break Ok(Some(msgs)), Err(crossbeam_channel::TryRecvError::Disconnected) => break Ok(None) } }
+                    }
+                },
+                Err(_) => Ok(Some(Vec::new())),
+            };
+            match polled {
+                Ok(None) => { log::info!("[CLIENT][HEART] server closed"); if let Ok(mut r)=reason.lock(){ let msg: String = "服务器连接关闭".into(); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::Disconnected(msg)); } } connected.store(false, Ordering::SeqCst); break 'beat; },
+                Ok(Some(msgs)) => {
+                    if msgs.contains(&ControlMessage::Kicked) { log::info!("[CLIENT] kicked by the server"); if let Ok(mut r)=reason.lock(){ let msg: String = "已被服务器移出".into(); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::Disconnected(msg)); } } connected.store(false, Ordering::SeqCst); break 'beat; }
+                    if msgs.contains(&ControlMessage::ServerStop) { log::info!("[CLIENT] server stop detected"); if let Ok(mut r)=reason.lock(){ let msg: String = "服务器已停止".into(); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::Disconnected(msg)); } } connected.store(false, Ordering::SeqCst); break 'beat; }
+                    if msgs.contains(&ControlMessage::Ok) { last_ok = std::time::Instant::now(); }
+                    for msg in &msgs {
+                        match msg {
+                            ControlMessage::Echo { stamp } => {
+                                last_ok = std::time::Instant::now();
+                                // Only the current beat's stamp: a late echo was not timed on arrival
+                                if let (Some(rtt), true) = (rtt.as_ref(), pending == Some(*stamp)) {
+                                    pending = None;
+                                    let sample = clock.elapsed().as_micros().saturating_sub(*stamp as u128) as f64 / 1000.0;
+                                    let prev = rtt.load();
+                                    rtt.store(if prev > 0.0 { prev + (sample - prev) * 0.25 } else { sample });
+                                }
+                            }
+                            ControlMessage::Motd { text } => notify_motd(text, event_sender.as_ref()),
+                            ControlMessage::Rekey { epoch, salt } => { if let Ok(mut ring) = keys.lock() { ring.install(*epoch, *salt); } log::info!("[CLIENT] session key epoch {epoch} received"); }
+                            ControlMessage::Key { epoch, salt, wrapped } => { let ok = keys.lock().map(|mut ring| ring.install_wrapped(*epoch, *salt, wrapped)).unwrap_or(false); log::info!("[CLIENT] wrapped session key epoch {epoch} {}", if ok { "received" } else { "rejected" }); }
+                            // Server switched its input device: output follows the new rate, GUI updates its params
+                            ControlMessage::Params { sample_rate, channels, fmt } => {
+                                log::info!("[CLIENT] server params changed: {sample_rate} Hz, {channels} ch, fmt {fmt}");
+                                stream_rate.store(*sample_rate, Ordering::Relaxed);
+                                if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::Params(AudioParams { sample_rate: *sample_rate, channels: *channels, sample_format: types::code_to_sample_format(*fmt) })); }
+                            }
+                            _ => {}
+                        }
+                    }
                 },
                 Err(e) if e.kind()==std::io::ErrorKind::WouldBlock => { /* no data this round */ },
-                Err(e) => { eprintln!("[CLIENT][HEART] read err: {e}"); }
+                Err(e) => { log::warn!("[CLIENT][HEART] read err: {e}"); }
             }
+            if pending.is_none() || std::time::Instant::now() + RTT_POLL >= next_beat { break; }
+            std::thread::sleep(RTT_POLL);
+        }
+        match session.check() {
+            SessionCheck::Warn(left) => { log::info!("[CLIENT][SESSION] time limit reached in {}s", left.as_secs()); if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::SessionWarning(left.as_secs())); } }
+            SessionCheck::Expired => { log::info!("[CLIENT][SESSION] time limit reached -> disconnect"); if let Ok(mut r)=reason.lock(){ let msg: String = "会话时长已到".into(); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::Disconnected(msg)); } } connected.store(false, Ordering::SeqCst); break; }
+            SessionCheck::Ok => {}
         }
         if last_ok.elapsed() > HEART_TIMEOUT {
-            println!("[CLIENT][HEART] timeout > {}s -> disconnect", HEART_TIMEOUT.as_secs()); if let Ok(mut r)=reason.lock(){ let msg=format!("心跳超时{}s", HEART_TIMEOUT.as_secs()); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(format!("DISCONNECT:{msg}")); } }
+            log::info!("[CLIENT][HEART] timeout > {}s -> disconnect", HEART_TIMEOUT.as_secs()); if let Ok(mut r)=reason.lock(){ let msg=format!("心跳超时{}s", HEART_TIMEOUT.as_secs()); *r=Some(msg.clone()); if let Some(ref tx)=event_sender { let _=tx.send(ClientEvent::TimedOut(msg)); } }
             connected.store(false, Ordering::SeqCst);
             break;
         }
-        std::thread::sleep(HEART_INTERVAL);
+        std::thread::sleep(next_beat.saturating_duration_since(std::time::Instant::now()));
     }
     // trigger full stop for output & udp
     output_running.store(false, Ordering::SeqCst);
     udp_alive.store(false, Ordering::SeqCst);
     if let Ok(mut guard) = output_stop_tx.lock() { if let Some(tx)=guard.take() { let _ = tx.send(()); } }
-    if let Ok(mut stream) = stream_arc.lock() { let _ = stream.write_all(b"DISCONNECT\n"); }
+    if let Ok(mut stream) = stream_arc.lock() { let _ = stream.write_all(ControlMessage::Disconnect.to_line().as_bytes()); }
+}
+
+/// Per-client key mode: read control lines until a wrapped session key opens (3 s budget; later
+/// keys are picked up by the heartbeat loop).
+fn await_wrapped_key(stream: &mut TcpStream, lines: &mut LineBuffer, ring: &mut KeyRing, event_sender: Option<&EventSender<ClientEvent>>) -> bool {
+    use std::io::{Read, ErrorKind};
+    let deadline = std::time::Instant::now() + Duration::from_secs(3);
+    let mut buf = [0u8; 512];
+    while !ring.ready() && std::time::Instant::now() < deadline {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => for msg in lines.push(&buf[..n]) {
+                match msg {
+                    ControlMessage::Key { epoch, salt, wrapped } if !ring.install_wrapped(epoch, salt, &wrapped) => log::warn!("[CLIENT] wrapped key epoch {epoch} rejected"),
+                    ControlMessage::Motd { text } => notify_motd(&text, event_sender),
+                    _ => {}
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(15)),
+            Err(_) => break,
+        }
+    }
+    ring.ready()
+}
+
+/// Forward a server banner to the GUI (`ClientEvent::Motd`).
+fn notify_motd(text: &str, event_sender: Option<&EventSender<ClientEvent>>) {
+    log::info!("[CLIENT] MOTD: {text}");
+    if let Some(tx) = event_sender { let _ = tx.send(ClientEvent::Motd(text.to_string())); }
+}
+
+/// Write one control line to the server.
+fn send_control(state: &ClientState, msg: &ControlMessage) -> Result<()> {
+    let ctrl = state.ctrl.as_ref().ok_or_else(|| anyhow::anyhow!("not connected"))?;
+    ctrl.lock().map_err(|_| anyhow::anyhow!("control stream poisoned"))?.write_all(msg.to_line().as_bytes())?;
+    Ok(())
+}
+
+/// Bind the frame socket on the handshake's multicast port (control `port` if none was given), announce
+/// it to the server and join the group; a failed join asks for unicast copies instead (`unicast_fallback`).
+/// Returns the socket and the join error, if any.
+fn open_udp_source(state: &mut ClientState, port: u16) -> Result<(UdpSocket, Option<std::io::Error>)> {
+    let (m_ip, m_port) = if let Some(t) = state.multicast_addr { t } else { (IpAddr::V4(Ipv4Addr::new(239,255,0,222)), port) }; // fallback default
+    let any = if m_ip.is_ipv6() { IpAddr::V6(Ipv6Addr::UNSPECIFIED) } else { IpAddr::V4(Ipv4Addr::UNSPECIFIED) };
+    let bind_addr = SocketAddr::new(any, m_port);
+    // Port already bound (another connection of this process, e.g. a second server on the same
+    // multicast port): own ephemeral port + unicast copies, so streams never mix on one socket
+    let (udp, port_taken) = match UdpSocket::bind(bind_addr) {
+        Ok(s) => (s, false),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => (UdpSocket::bind(SocketAddr::new(any, 0))?, true),
+        Err(e) => return Err(e.into()),
+    };
+    let _ = udp.set_nonblocking(true); // reuse_address not exposed in stable std; OS default usually fine
+    let local_addr = udp.local_addr().ok(); state.udp_local = local_addr;
+    if let Some(local) = local_addr { if let Err(e) = send_control(state, &ControlMessage::Udp { port: local.port() }) { log::warn!("[CLIENT] announce udp port failed: {e}"); } }
+    let join_err = match if port_taken { Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, format!("port {m_port} in use"))) } else { state.membership.join(&udp, m_ip) } {
+        Ok(()) => { if state.params.is_some() { state.stage.store(2, Ordering::SeqCst); } log::info!("[CLIENT] Joined multicast {m_ip}:{m_port} local={:?}", local_addr); None }
+        Err(e) => {
+            log::warn!("[CLIENT][MCAST] join group {m_ip}:{m_port} failed: {e} -> requesting unicast");
+            // Multicast blocked (corporate / hotel wifi): ask the server to send the same frames to our socket
+            match send_control(state, &ControlMessage::WantUnicast { port: local_addr.map(|a| a.port()).unwrap_or(m_port) }) {
+                Ok(()) => { state.unicast_fallback = true; if state.params.is_some() { state.stage.store(2, Ordering::SeqCst); } }
+                Err(e) => log::warn!("[CLIENT] unicast request failed: {e}"),
+            }
+            Some(e)
+        }
+    };
+    state.multicast_addr = Some((m_ip, m_port)); state.udp_sock = udp.try_clone().ok();
+    Ok((udp, join_err))
 }
 
+/// Diagnostics: open the frame socket as `connect_with_output` does (no playback) and wait up to `wait`
+/// for the first RemoteMic packet. Returns the group join outcome (time taken, or the error: the
+/// server was asked for unicast copies then) and the time until that packet arrived.
+pub fn probe_frames(state: &mut ClientState, port: u16, wait: Duration) -> Result<(std::result::Result<Duration, std::io::Error>, Option<Duration>)> {
+    let start = std::time::Instant::now();
+    let (udp, join_err) = open_udp_source(state, port)?;
+    let joined = match join_err { None => Ok(start.elapsed()), Some(e) => Err(e) };
+    let start = std::time::Instant::now();
+    let mut buf = vec![0u8; 65536];
+    let magics = [types::FRAME_MAGIC, types::FRAME_MAGIC_LQ, types::FRAME_MAGIC_SILENCE, types::FRAME_MAGIC_FEC];
+    while start.elapsed() < wait {
+        match udp.recv(&mut buf) {
+            Ok(n) if n >= 2 && magics.contains(&[buf[0], buf[1]]) => return Ok((joined, Some(start.elapsed()))),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((joined, None))
+}
+
+/// Join `group` on the default interface (IPv6 interface 0 = the system's default multicast interface).
+fn join_group(sock: &UdpSocket, group: IpAddr) -> std::io::Result<()> {
+    match group { IpAddr::V4(g) => sock.join_multicast_v4(&g, &Ipv4Addr::UNSPECIFIED), IpAddr::V6(g) => sock.join_multicast_v6(&g, 0) }
+}
+
+fn leave_group(sock: &UdpSocket, group: IpAddr) -> std::io::Result<()> {
+    match group { IpAddr::V4(g) => sock.leave_multicast_v4(&g, &Ipv4Addr::UNSPECIFIED), IpAddr::V6(g) => sock.leave_multicast_v6(&g, 0) }
+}
+
+/// Multicast group a connection has joined, with its own handle on the receive socket so the
+/// membership can be dropped explicitly (the OS only drops it when the last socket clone closes).
+/// `leave` is idempotent: disconnect and the receive thread both call it, whichever comes first wins.
+#[derive(Clone, Default)]
+pub struct GroupMembership(Arc<Mutex<Option<(IpAddr, UdpSocket)>>>);
+
+impl GroupMembership {
+    fn join(&self, sock: &UdpSocket, group: IpAddr) -> std::io::Result<()> {
+        let handle = sock.try_clone()?;
+        join_group(sock, group)?;
+        if let Ok(mut g) = self.0.lock() { *g = Some((group, handle)); }
+        Ok(())
+    }
+
+    /// Leave the joined group (no-op when not joined).
+    pub fn leave(&self) {
+        let Some((group, sock)) = self.0.lock().ok().and_then(|mut g| g.take()) else { return };
+        match leave_group(&sock, group) { Ok(()) => log::info!("[CLIENT] Left multicast {group}"), Err(e) => log::warn!("[CLIENT][MCAST] leave group {group} failed: {e}") }
+    }
+}
+
+/// Switch between the multicast stream and the server's unicast low-quality substream
+/// (mono i16 @ `LQ_SAMPLE_RATE`). Multicast membership is dropped while on the substream so
+/// frames are not received twice.
+pub fn set_low_quality(state: &ClientState, on: bool) -> Result<()> {
+    if state.transport == Transport::Tcp { return Err(anyhow::anyhow!("low-quality substream needs the udp transport")); }
+    let (Some(sock), Some((m_ip, _)), Some(local)) = (&state.udp_sock, state.multicast_addr, state.udp_local) else { return Err(anyhow::anyhow!("not connected")); };
+    if on == state.low_quality.load(Ordering::Relaxed) { return Ok(()); }
+    // Unicast fallback clients never joined the group: leaving the substream means unicast again
+    let msg = match (on, state.unicast_fallback) { (true, _) => ControlMessage::Substream { low_quality_port: Some(local.port()) }, (false, true) => ControlMessage::WantUnicast { port: local.port() }, (false, false) => ControlMessage::Substream { low_quality_port: None } };
+    if !on && !state.unicast_fallback { state.membership.join(sock, m_ip)?; }
+    send_control(state, &msg)?;
+    if on { state.membership.leave(); }
+    state.low_quality.store(on, Ordering::Relaxed);
+    log::info!("[CLIENT] substream -> {}", if on { "low quality (unicast)" } else { "multicast" });
+    Ok(())
+}
+
+/// Start writing the played stream (post jitter buffer, output layout) to `path` in `format`.
+pub fn start_recording(state: &ClientState, path: &std::path::Path, format: record::RecordFormat) -> Result<()> {
+    let (Some(params), true) = (&state.params, state.output_channels > 0) else { return Err(anyhow::anyhow!("not connected")); };
+    // Samples are written at the current stream rate, which a `PARAMS` switch may have moved off the handshake rate
+    let rate = match state.stream_rate.load(Ordering::Relaxed) { 0 => params.sample_rate, r => r };
+    let writer = record::create(path, format, rate, state.output_channels)?;
+    *state.recording.lock().map_err(|_| anyhow::anyhow!("recording lock poisoned"))? = Some(writer);
+    Ok(())
+}
+
+/// Stop and finalize the current recording (no-op when not recording).
+pub fn stop_recording(state: &ClientState) -> Result<()> {
+    let writer = state.recording.lock().map_err(|_| anyhow::anyhow!("recording lock poisoned"))?.take();
+    if let Some(w) = writer { w.finalize()?; log::info!("[CLIENT][RECORD] finalized"); }
+    Ok(())
+}
+
+pub fn is_recording(state: &ClientState) -> bool { state.recording.lock().map(|g| g.is_some()).unwrap_or(false) }
+
 /// Manual disconnect sequence.
 pub fn disconnect(state: &ClientState) {
+    if let Err(e) = stop_recording(state) { log::warn!("[CLIENT][RECORD] finalize: {e}"); }
     state.connected.store(false, Ordering::SeqCst);
     state.output_running.store(false, Ordering::SeqCst);
     state.udp_thread_alive.store(false, Ordering::SeqCst);
+    state.membership.leave();
     if let Ok(mut guard)=state.output_stop_tx.lock() { if let Some(tx)=guard.take() { let _ = tx.send(()); } }
     if let Ok(mut r)=state.disconnection_reason.lock() { if r.is_none() { *r=Some("手动断开".into()); } }
-    if let Some(ctrl) = &state.ctrl { if let Ok(mut s)=ctrl.lock() { let _ = s.write_all(b"DISCONNECT\n"); } }
+    if let Some(ctrl) = &state.ctrl { if let Ok(mut s)=ctrl.lock() { let _ = s.write_all(ControlMessage::Disconnect.to_line().as_bytes()); } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fec_rebuild_older_than_the_first_frame_counts_as_late() {
+        // Joined mid-group: packet 0 was sent before we listened, parity rebuilds it after packets 1-3
+        let frame = |seq: u32| { let h = types::FrameHeader { magic: types::FRAME_MAGIC, seq, fmt: types::FMT_I16, ch: 1, rate: 48_000, payload_len: 4, ts_ns: 1_000_000_000 + seq as u64 * 10_000_000, key_epoch: 0 }; let mut f = types::build_frame_header(&h).to_vec(); f.extend([0u8; 4]); f };
+        let sent: Vec<_> = (0..4).map(frame).collect();
+        let mut enc = crate::fec::FecEncoder::new();
+        let parity = sent.iter().filter_map(|f| enc.push(4, f)).last().unwrap();
+        let (mut dec, mut clock) = (FecDecoder::new(), ArrivalClock::default());
+        let t0 = std::time::Instant::now();
+        for (i, f) in sent.iter().enumerate().skip(1) {
+            dec.store(i as u32, f);
+            let (delay, _) = clock.observe(types::parse_frame_header(f).unwrap().ts_ns, t0 + Duration::from_millis(10 * (i as u64 - 1)));
+            assert_eq!(delay, 0);
+        }
+        let rebuilt = dec.recover(&parity).unwrap();
+        let ts = types::parse_frame_header(&rebuilt).unwrap().ts_ns;
+        // 10ms before the base, arriving 25ms after it: 35ms late, no wrap
+        let (delay, transit) = clock.observe(ts, t0 + Duration::from_millis(25));
+        assert_eq!((delay, transit), (35_000_000, 35_000_000));
+        assert_eq!(clock.observe(1_040_000_000, t0 + Duration::from_millis(30)).0, 0);
+        assert_eq!(clock.local_instant(1_010_000_000, t0), t0);
+    }
+
+    #[test]
+    fn no_params_wait_ends_on_params_and_fails_on_server_stop() {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut hearts = Vec::new();
+            for reply in [ControlMessage::Params { sample_rate: 48000, channels: 1, fmt: 2 }, ControlMessage::ServerStop] {
+                let (mut sock, _) = listener.accept().unwrap();
+                // Capture starts while the client is waiting: its heartbeat kept the entry alive
+                let mut line = String::new();
+                BufReader::new(sock.try_clone().unwrap()).read_line(&mut line).unwrap();
+                hearts.push(line);
+                sock.write_all(reply.to_line().as_bytes()).unwrap();
+            }
+            (hearts, listener)
+        });
+        let dial = || { let s = TcpStream::connect(addr).unwrap(); s.set_nonblocking(true).unwrap(); s };
+        assert!(wait_for_params(&mut dial(), "k1", LineBuffer::new(), Vec::new()).is_ok());
+        assert!(wait_for_params(&mut dial(), "k2", LineBuffer::new(), Vec::new()).is_err());
+        let (hearts, _listener) = server.join().unwrap();
+        assert_eq!(hearts, vec!["HEART k1\n".to_string(), "HEART k2\n".to_string()]);
+        // PARAMS that arrived with the handshake line needs no wait at all
+        assert!(wait_for_params(&mut dial(), "k3", LineBuffer::new(), vec![ControlMessage::Params { sample_rate: 44100, channels: 2, fmt: 2 }]).is_ok());
+    }
+
+    #[test]
+    fn channel_switch_mid_stream_remaps_onto_the_stereo_output() {
+        // The UDP thread's per-frame steps: note the frame's channel count, then map onto the output opened at connect
+        let mut tracker = FrameChannels::new(2);
+        let mut play = |ch: u16, frames: &[f32]| { let change = tracker.observe(ch); let mut out = Vec::new(); map_channels(frames, ch, 2, false, &mut out); (out, change) };
+        assert_eq!(play(2, &[0.5, 0.1, -0.2, -0.4]), (vec![0.5, 0.1, -0.2, -0.4], None));
+        // Server switched to a mono device: every sample is now its own frame, copied to both sides
+        assert_eq!(play(1, &[0.2, 0.4, 0.6]), (vec![0.2, 0.2, 0.4, 0.4, 0.6, 0.6], Some((2, 1))));
+        assert_eq!(play(1, &[0.0]), (vec![0.0, 0.0], None));
+        // ... and back to stereo: L/R pass through again
+        assert_eq!(play(2, &[1.0, 0.0]), (vec![1.0, 0.0], Some((1, 2))));
+    }
+
+    #[test]
+    fn channels_map_onto_the_output_layout() {
+        let map = |samples: &[f32], ic, oc, mono| { let mut out = Vec::new(); map_channels(samples, ic, oc, mono, &mut out); out };
+        // stereo stays stereo (L/R not swapped or averaged)
+        assert_eq!(map(&[0.5, -0.5, 0.1, 0.2], 2, 2, false), vec![0.5, -0.5, 0.1, 0.2]);
+        // forced mono / mono source: same sample on every output channel
+        assert_eq!(map(&[0.5, -0.1], 2, 2, true), vec![0.2, 0.2]);
+        assert_eq!(map(&[0.3], 1, 2, false), vec![0.3, 0.3]);
+        // mono output: average
+        assert_eq!(map(&[1.0, 0.0], 2, 1, false), vec![0.5]);
+        // stereo source on a 4ch device: front pair only
+        assert_eq!(map(&[0.1, 0.2], 2, 4, false), vec![0.1, 0.2, 0.0, 0.0]);
+        // 5.1 -> stereo: centre on both sides, LFE dropped, surrounds to their side
+        let folded = map(&[1.0, 0.0, 0.0, 1.0, 0.0, 0.0], 6, 2, false);
+        assert!(folded[0] > 0.0 && folded[1] == 0.0, "{folded:?}");
+        let folded = map(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0], 6, 2, false);
+        assert!((folded[0] - folded[1]).abs() < 1e-6 && folded[0] > 0.0, "{folded:?}");
+        assert_eq!(map(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0], 6, 2, false), vec![0.0, 0.0]);
+        let folded = map(&[1.0; 6], 6, 2, false);
+        assert!(folded.iter().all(|&s| s <= 1.0 + 1e-6), "fold-down must not clip: {folded:?}");
+    }
+
+    #[test]
+    fn zero_stereo_width_matches_the_mono_downmix() {
+        let stereo = [0.7, -0.3, 0.1, 0.9, -1.0, 1.0, 0.33, 0.17];
+        let mut mono = Vec::new();
+        map_channels(&stereo, 2, 2, true, &mut mono);
+        let mut narrowed = stereo;
+        apply_stereo_width(&mut narrowed, 2, 0.0);
+        assert_eq!(narrowed.to_vec(), mono);
+        // 1 = untouched, 2 = side doubled (L/R pushed apart around the same mid)
+        let mut same = stereo;
+        apply_stereo_width(&mut same, 2, 1.0);
+        assert_eq!(same, stereo);
+        let mut wide = [0.6, 0.2];
+        apply_stereo_width(&mut wide, 2, 2.0);
+        assert!((wide[0] - 0.8).abs() < 1e-6 && (wide[1] - 0.0).abs() < 1e-6, "{wide:?}");
+    }
+
+    #[test]
+    fn zero_channel_header_is_treated_as_mono() {
+        let mut tracker = FrameChannels::new(1);
+        assert_eq!(tracker.observe(0), None);
+        let mut out = Vec::new();
+        downmix_mono(&[0.25, 0.5], 0, &mut out);
+        assert_eq!(out, vec![0.25, 0.5]);
+    }
+
+    #[test]
+    fn reconnect_backoff_doubles_up_to_the_cap() {
+        let secs: Vec<u64> = (1..=7).map(|a| reconnect_delay(a).as_secs()).collect();
+        assert_eq!(secs, vec![1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
+    }
+
+    #[test]
+    fn replay_window_rejects_repeats_and_stale_frames_across_wrap() {
+        let mut w = ReplayWindow::new();
+        let start = u32::MAX - 2;
+        for i in 0..6 { assert!(w.accept(start.wrapping_add(i)), "in-order frame {i}"); }
+        assert!(!w.accept(u32::MAX), "replay before the wrap");
+        assert!(!w.accept(1), "replay after the wrap");
+        // Reordered (late but unseen) frames inside the window are still accepted once
+        assert!(w.accept(300));
+        assert!(w.accept(100) && !w.accept(100));
+        assert!(!w.accept(300 - REPLAY_WINDOW), "older than the window");
+        assert!(!w.accept(start), "pre-wrap frame far behind");
+    }
+
+    #[test]
+    fn loss_counter_crosses_the_u32_wrap() {
+        let mut loss = LossCounter::new();
+        let start = u32::MAX - 3;
+        for i in 0..8 { loss.observe(start.wrapping_add(i)); }
+        assert_eq!(loss.lost, 0, "in-order across the wrap");
+        // One frame lost right after the wrap, one reordered straggler from before it
+        loss.observe(5); loss.observe(u32::MAX);
+        assert_eq!(loss.lost, 1);
+        loss.observe(6);
+        assert_eq!(loss.lost, 1);
+    }
+
+    #[test]
+    fn latency_profiles_order_buffer_depth() {
+        let jitter_ns = 4_000_000.0;
+        let [ultra, balanced, stable] = LatencyProfile::ALL.map(|p| { let t = p.tuning(); (adjust_targets(jitter_ns, &t), compute_reorder_delay(jitter_ns, &t)) });
+        assert!(ultra.0 .0 < balanced.0 .0 && balanced.0 .0 < stable.0 .0, "targets");
+        assert!(ultra.0 .1 <= 25_000_000 && stable.0 .1 <= 120_000_000, "caps");
+        // Balanced keeps the previous fixed tuning: 15ms + 2.5 x 4ms, reorder 2.5 x 4ms
+        assert_eq!(balanced, ((25_000_000, 50_000_000), 10_000_000));
+        assert_eq!(LatencyProfile::from_u8(LatencyProfile::Stable.as_u8()), LatencyProfile::Stable);
+    }
+
+    #[test]
+    fn multicast_group_can_be_rejoined_after_leaving() {
+        let group = IpAddr::V4(Ipv4Addr::new(239, 255, 0, 223));
+        let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let membership = GroupMembership::default();
+        // No multicast-capable interface on this machine: nothing to verify
+        if membership.join(&sock, group).is_err() { return; }
+        membership.leave();
+        assert!(membership.0.lock().unwrap().is_none());
+        membership.leave(); // second leave (disconnect after thread exit) is a no-op
+        // Joining a group the socket is still in fails (EADDRINUSE), so this rejoin shows the OS dropped it
+        membership.join(&sock, group).expect("rejoin after leave");
+        // Reconnect: a fresh socket joins the same group
+        membership.leave();
+        let again = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        GroupMembership::default().join(&again, group).expect("join from a new socket");
+    }
+
+    #[test]
+    fn recording_after_a_rate_switch_uses_the_stream_rate() {
+        let mut cs = ClientState::new();
+        cs.params = Some(AudioParams { sample_rate: 48_000, channels: 2, sample_format: cpal::SampleFormat::F32 });
+        cs.output_channels = 2;
+        cs.stream_rate.store(44_100, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("rm-client-rec-{}.wav", std::process::id()));
+        start_recording(&cs, &path, record::RecordFormat::Wav).unwrap();
+        stop_recording(&cs).unwrap();
+        let spec = hound::WavReader::open(&path).unwrap().spec();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!((spec.sample_rate, spec.channels), (44_100, 2));
+    }
+
+    #[test]
+    fn metrics_snapshot_copies_the_live_counters() {
+        let cs = ClientState::new();
+        assert_eq!(cs.metrics_snapshot(), ClientMetrics::default());
+        cs.avg_latency_ms.store(12.5); cs.packet_loss.store(0.02); cs.late_drop.store(3.0);
+        cs.decrypt_fail.fetch_add(2, Ordering::Relaxed); cs.update_enc_status(-1); cs.quality.store(Quality::Fair.as_u8(), Ordering::Relaxed);
+        let m = cs.metrics_snapshot();
+        assert_eq!((m.latency_ms, m.loss, m.late_drop, m.decrypt_fail, m.enc_status, m.quality), (12.5, 0.02, 3, 2, -1, Quality::Fair));
+    }
 }
@@ -0,0 +1,106 @@
+//! Multicast stream codecs: raw PCM (default, any client) or Opus (`FMT_OPUS`, far less
+//! bandwidth on wifi).
+//!
+//! Opus only accepts fixed frame sizes at 8/12/16/24/48 kHz with 1–2 channels, so the encoder
+//! buffers captured samples and emits `OPUS_FRAME_MS` packets: other capture rates are
+//! linearly resampled to 48 kHz, more than two channels are down-mixed to mono.
+use anyhow::{Context, Result};
+use audiopus::coder::{Decoder, Encoder};
+use audiopus::{packet::Packet, Application, Bitrate, Channels, MutSignals, SampleRate};
+
+use crate::convert;
+
+/// Codec used on the multicast group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Codec { #[default] Pcm, Opus }
+
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::Pcm, Codec::Opus];
+
+    /// Wire / config name (`CODECS` control line, handshake token).
+    pub fn as_str(self) -> &'static str { match self { Codec::Pcm => "PCM", Codec::Opus => "OPUS" } }
+
+    pub fn parse(s: &str) -> Option<Self> { Self::ALL.into_iter().find(|c| c.as_str().eq_ignore_ascii_case(s.trim())) }
+
+    /// Compact form for atomics (`ServerState::codec`).
+    pub fn as_u8(self) -> u8 { self as u8 }
+    pub fn from_u8(v: u8) -> Self { if v == Codec::Opus as u8 { Codec::Opus } else { Codec::Pcm } }
+}
+
+/// Opus packet duration.
+pub const OPUS_FRAME_MS: u32 = 20;
+/// Target bitrate per encoded channel.
+const OPUS_BITRATE_PER_CH: i32 = 64_000;
+/// Largest packet we produce (libopus recommends 4000 bytes as a safe upper bound).
+const OPUS_MAX_PACKET: usize = 4000;
+/// Longest frame a decoder may return (120 ms at 48 kHz).
+const OPUS_MAX_FRAME_SAMPLES: usize = 5760;
+
+fn opus_rate(rate: u32) -> Option<SampleRate> { SampleRate::try_from(rate as i32).ok() }
+
+fn opus_channels(ch: u16) -> Option<Channels> { match ch { 1 => Some(Channels::Mono), 2 => Some(Channels::Stereo), _ => None } }
+
+/// Streaming encoder: arbitrary capture chunks in, fixed-size Opus packets out.
+pub struct OpusStreamEncoder { enc: Encoder, in_rate: u32, in_ch: u16, rate: u32, channels: u16, resampler: convert::StreamResampler, pending: Vec<f32>, packet: Vec<u8> }
+
+impl OpusStreamEncoder {
+    /// Encoder for a capture stream of `in_rate` Hz / `in_ch` channels.
+    pub fn new(in_rate: u32, in_ch: u16) -> Result<Self> {
+        let (rate, sr) = match opus_rate(in_rate) { Some(sr) => (in_rate, sr), None => (48_000, SampleRate::Hz48000) };
+        let (channels, chs) = if in_ch == 2 { (2, Channels::Stereo) } else { (1, Channels::Mono) };
+        let mut enc = Encoder::new(sr, chs, Application::Audio).context("create opus encoder")?;
+        enc.set_bitrate(Bitrate::BitsPerSecond(OPUS_BITRATE_PER_CH * channels as i32)).context("set opus bitrate")?;
+        Ok(Self { enc, in_rate, in_ch, rate, channels, resampler: convert::StreamResampler::new(in_rate, rate, channels), pending: Vec::new(), packet: vec![0u8; OPUS_MAX_PACKET] })
+    }
+
+    /// Whether this encoder was built for the given capture params (rebuild otherwise).
+    pub fn matches(&self, in_rate: u32, in_ch: u16) -> bool { self.in_rate == in_rate && self.in_ch == in_ch }
+
+    /// Rate / channel count written into the frame header (what the decoder must use).
+    pub fn rate(&self) -> u32 { self.rate }
+    pub fn channels(&self) -> u16 { self.channels }
+
+    fn frame_samples(&self) -> usize { (self.rate * OPUS_FRAME_MS / 1000) as usize * self.channels as usize }
+
+    /// Append interleaved capture samples; returns each finished packet with how long before the
+    /// end of `samples` it starts (ns), so the caller can timestamp it on the capture timeline.
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<(Vec<u8>, u64)>> {
+        let in_ch = self.in_ch.max(1) as usize;
+        let shaped: Vec<f32> = if in_ch <= 2 { samples.to_vec() } else { samples.chunks_exact(in_ch).map(|f| f.iter().sum::<f32>() / in_ch as f32).collect() };
+        // Stateful across pushes: no phase reset / edge click at every capture chunk (pass-through at equal rates)
+        self.resampler.process(&shaped, &mut self.pending);
+        let frame = self.frame_samples();
+        let ns_per_sample = 1e9 / (self.rate as f64 * self.channels as f64);
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= frame {
+            let n = self.enc.encode_float(&self.pending[offset..offset + frame], &mut self.packet).context("opus encode")?;
+            let lag_ns = ((self.pending.len() - offset) as f64 * ns_per_sample) as u64;
+            packets.push((self.packet[..n].to_vec(), lag_ns));
+            offset += frame;
+        }
+        self.pending.drain(..offset);
+        Ok(packets)
+    }
+}
+
+/// Decoder for one (rate, channels) stream; recreate when the frame header changes.
+pub struct OpusStreamDecoder { dec: Decoder, rate: u32, channels: u16, out: Vec<f32> }
+
+impl OpusStreamDecoder {
+    pub fn new(rate: u32, channels: u16) -> Result<Self> {
+        let (Some(sr), Some(chs)) = (opus_rate(rate), opus_channels(channels)) else { anyhow::bail!("unsupported opus stream {rate} Hz / {channels} ch") };
+        Ok(Self { dec: Decoder::new(sr, chs).context("create opus decoder")?, rate, channels, out: vec![0f32; OPUS_MAX_FRAME_SAMPLES * channels as usize] })
+    }
+
+    pub fn matches(&self, rate: u32, channels: u16) -> bool { self.rate == rate && self.channels == channels }
+
+    /// Decode one packet, appending interleaved f32 samples to `frames`.
+    pub fn decode(&mut self, packet: &[u8], frames: &mut Vec<f32>) -> Result<()> {
+        let packet = Packet::try_from(packet).context("opus packet")?;
+        let signals = MutSignals::try_from(&mut self.out).context("opus output")?;
+        let per_ch = self.dec.decode_float(Some(packet), signals, false).context("opus decode")?;
+        frames.extend_from_slice(&self.out[..per_ch * self.channels as usize]);
+        Ok(())
+    }
+}
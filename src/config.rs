@@ -0,0 +1,77 @@
+//! Persisted user settings: JSON in the OS config dir (e.g. `~/.config/remote-mic/settings.json`,
+//! `%APPDATA%\remote-mic\config\settings.json`).
+//!
+//! Devices are stored by name, not list index, so a selection survives devices being reordered or
+//! hot-plugged. Saves are debounced on a background thread: rapid edits (typing an IP) produce a
+//! single write once input settles for `SAVE_DEBOUNCE`.
+use std::{path::PathBuf, time::Duration};
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Sender};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Quiet period before pending settings are written.
+const SAVE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Everything restored on the next launch (missing fields fall back to defaults).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub input_device: Option<String>,
+    pub output_device: Option<String>,
+    pub lang: Option<String>,
+    /// Language consulted for keys the chosen one lacks (hand-edited; default `lang::DEFAULT_FALLBACK_LANG`).
+    pub lang_fallback: Option<String>,
+    pub server_port: Option<u16>,
+    /// Multicast room name (empty = random group each launch)
+    pub server_room: String,
+    pub client_ip: String,
+    pub client_port: String,
+    pub handshake_timeout_secs: Option<u64>,
+    pub transport: Option<String>,
+    /// `log` level name (`error` .. `trace`; default `logging::DEFAULT_LEVEL`)
+    pub log_level: Option<String>,
+    /// Recording format (`wav` / `mp3`; default WAV, MP3 only in builds with the `mp3` feature)
+    pub record_format: Option<String>,
+}
+
+/// Per-user config directory (also searched for custom `lang/<code>.json` files).
+pub fn config_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "remote-mic").map(|d| d.config_dir().to_path_buf())
+}
+
+fn settings_path() -> Option<PathBuf> { config_dir().map(|d| d.join("settings.json")) }
+
+/// Load saved settings; a missing or unreadable file yields defaults (never fatal). Problems go to
+/// stderr: this runs before `logging::init`, whose level comes from these settings.
+pub fn load() -> Settings {
+    let Some(path) = settings_path() else { return Settings::default() };
+    match std::fs::read_to_string(&path) {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| { eprintln!("[CONFIG] ignoring {}: {e}", path.display()); Settings::default() }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Settings::default(),
+        Err(e) => { eprintln!("[CONFIG] read {}: {e}", path.display()); Settings::default() }
+    }
+}
+
+fn write(settings: &Settings) -> Result<()> {
+    let path = settings_path().context("no config directory")?;
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?; }
+    std::fs::write(&path, serde_json::to_vec_pretty(settings)?).with_context(|| format!("write {}", path.display()))
+}
+
+/// Debounced writer: keeps only the newest settings until input is quiet, then writes once.
+static SAVER: Lazy<Option<Sender<Settings>>> = Lazy::new(|| {
+    let (tx, rx) = unbounded::<Settings>();
+    let spawned = std::thread::Builder::new().name("rm-settings".into()).spawn(move || {
+        while let Ok(mut latest) = rx.recv() {
+            while let Ok(newer) = rx.recv_timeout(SAVE_DEBOUNCE) { latest = newer; }
+            if let Err(e) = write(&latest) { log::warn!("[CONFIG] save failed: {e:#}"); }
+        }
+    });
+    spawned.map_err(|e| log::warn!("[CONFIG] spawn saver: {e}")).ok().map(|_| tx)
+});
+
+/// Queue `settings` for saving (debounced, non-blocking).
+pub fn save(settings: Settings) {
+    if let Some(tx) = SAVER.as_ref() { let _ = tx.send(settings); }
+}
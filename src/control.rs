@@ -0,0 +1,310 @@
+//! Control channel (TCP) line messages shared by server and client.
+//!
+//! Every message is one `\n` terminated line of space separated tokens; the first token is the
+//! keyword and must match exactly (no substring matching). The handshake reply (`OK <key> ...`)
+//! is parsed separately in `client::connect`.
+//!
+//! TCP transport: after `TRANSPORT TCP` the server also streams audio frames on this connection as
+//! `FRAME <len>\n` followed by `len` raw bytes; `LineBuffer` hands these out as `ControlMessage::Frame`.
+use std::fmt;
+
+use crate::{codec::Codec, rekey, types::Transport};
+
+/// Longest accepted line; anything longer is dropped (protects the partial-line buffer).
+pub const MAX_LINE: usize = 1024;
+/// Largest `FRAME` body accepted (header + u16 payload + AEAD tag fits well below).
+pub const MAX_FRAME: usize = 70_000;
+
+/// One control line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// client -> server: `HEART <session_key> [<stamp>]`; the stamp (client clock, ms) is echoed in the reply
+    Heart { key: String, stamp: Option<u64> },
+    /// server -> client: heartbeat accepted
+    Ok,
+    /// server -> client: `OK <stamp>`, heartbeat accepted, echoing the `HEART` stamp (round-trip timing)
+    Echo { stamp: u64 },
+    /// client -> server: leaving
+    Disconnect,
+    /// server -> client: reply to `DISCONNECT`
+    Bye,
+    /// server -> client: server stopped / client removed
+    ServerStop,
+    /// server -> client: this client was kicked by the host (do not reconnect on your own)
+    Kicked,
+    /// client -> server: `SUBSTREAM LQ <udp_port>` (Some) | `SUBSTREAM MC` (None)
+    Substream { low_quality_port: Option<u16> },
+    /// server -> client (after the handshake): `MOTD <escaped text>`; text may span lines
+    Motd { text: String },
+    /// client -> server: codecs this client can decode, e.g. `CODECS PCM OPUS`
+    Codecs { list: Vec<Codec> },
+    /// client -> server: local UDP receive port (`UDP <port>`), sent once the socket is bound
+    Udp { port: u16 },
+    /// client -> server: multicast join failed, send the full stream to `<control IP>:port` instead
+    WantUnicast { port: u16 },
+    /// server -> client: `REKEY <epoch> <salthex>`, next session key (frames switch to it shortly after)
+    Rekey { epoch: u8, salt: [u8;8] },
+    /// client -> server: `KX <pubhex>`, x25519 public key answering the handshake `KX` token
+    Kx { public: [u8;32] },
+    /// server -> client: `KEY <epoch> <salthex> <wrappedhex>`, session key wrapped for this client (per-client key mode)
+    Key { epoch: u8, salt: [u8;8], wrapped: Vec<u8> },
+    /// server -> client: `PARAMS <sr> <ch> <fmt>`, capture format changed (input device switched); fmt as in the handshake
+    Params { sample_rate: u32, channels: u16, fmt: u8 },
+    /// client -> server: `WANT <sr> <ch>`, format the client's output device plays natively (after the handshake)
+    Want { sample_rate: u32, channels: u16 },
+    /// client -> server: `TRANSPORT TCP|UDP`, how frames should reach this client (default UDP)
+    Transport { kind: Transport },
+    /// server -> client (TCP transport): one sealed frame; wire form `FRAME <len>` + raw bytes, see `frame_bytes`
+    Frame { data: Vec<u8> },
+}
+
+/// Parse one line (surrounding whitespace ignored). Unknown keywords / bad arity -> None.
+pub fn parse_line(line: &str) -> Option<ControlMessage> {
+    // Free text: everything after the keyword, escapes undone (inner spaces kept)
+    if let Some(rest) = line.trim().strip_prefix("MOTD ") { return Some(ControlMessage::Motd { text: unescape(rest) }); }
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+        ["HEART", key] => Some(ControlMessage::Heart { key: key.to_string(), stamp: None }),
+        ["HEART", key, stamp] => Some(ControlMessage::Heart { key: key.to_string(), stamp: Some(stamp.parse().ok()?) }),
+        ["OK"] => Some(ControlMessage::Ok),
+        ["OK", stamp] => Some(ControlMessage::Echo { stamp: stamp.parse().ok()? }),
+        ["DISCONNECT"] => Some(ControlMessage::Disconnect),
+        ["BYE"] => Some(ControlMessage::Bye),
+        ["SERVER_STOP"] => Some(ControlMessage::ServerStop),
+        ["KICKED"] => Some(ControlMessage::Kicked),
+        ["SUBSTREAM", "LQ", port] => port.parse().ok().filter(|&p: &u16| p != 0).map(|p| ControlMessage::Substream { low_quality_port: Some(p) }),
+        ["SUBSTREAM", "MC"] => Some(ControlMessage::Substream { low_quality_port: None }),
+        ["UDP", port] => port.parse().ok().filter(|&p: &u16| p != 0).map(|port| ControlMessage::Udp { port }),
+        ["WANT_UNICAST", port] => port.parse().ok().filter(|&p: &u16| p != 0).map(|port| ControlMessage::WantUnicast { port }),
+        ["REKEY", epoch, salt] => Some(ControlMessage::Rekey { epoch: epoch.parse().ok()?, salt: rekey::parse_salt(salt)? }),
+        ["KX", public] => Some(ControlMessage::Kx { public: rekey::parse_public(public)? }),
+        ["KEY", epoch, salt, wrapped] => Some(ControlMessage::Key { epoch: epoch.parse().ok()?, salt: rekey::parse_salt(salt)?, wrapped: rekey::from_hex(wrapped)? }),
+        ["TRANSPORT", kind] => Some(ControlMessage::Transport { kind: Transport::parse(kind)? }),
+        ["WANT", sr, ch] => Some(ControlMessage::Want { sample_rate: sr.parse().ok().filter(|&r: &u32| r > 0)?, channels: ch.parse().ok().filter(|&c: &u16| c > 0)? }),
+        ["PARAMS", sr, ch, fmt] => Some(ControlMessage::Params { sample_rate: sr.parse().ok()?, channels: ch.parse().ok()?, fmt: fmt.parse().ok()? }),
+        ["CODECS", names @ ..] => Some(ControlMessage::Codecs { list: names.iter().filter_map(|n| Codec::parse(n)).collect() }),
+        _ => None,
+    }
+}
+
+impl ControlMessage {
+    /// Wire form including the trailing newline.
+    pub fn to_line(&self) -> String { format!("{self}\n") }
+
+    /// Wire form of a `FRAME` (TCP transport): header line followed by the raw frame.
+    pub fn frame_bytes(frame: &[u8]) -> Vec<u8> {
+        let mut out = format!("FRAME {}\n", frame.len()).into_bytes();
+        out.extend_from_slice(frame);
+        out
+    }
+
+    /// MOTD from host input: trimmed, `None` if empty, cut at a char boundary so the escaped line fits `MAX_LINE`.
+    pub fn motd(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.is_empty() { return None; }
+        let mut len = "MOTD ".len();
+        let fitted: String = text.chars().take_while(|&c| { len += escape(&c.to_string()).len(); len < MAX_LINE }).collect();
+        Some(ControlMessage::Motd { text: fitted })
+    }
+}
+
+/// Escape free text for one control line: `\\`, `\n`, `\r`.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' { out.push(c); continue; }
+        match chars.next() { Some('n') => out.push('\n'), Some('r') => out.push('\r'), Some(other) => out.push(other), None => out.push('\\') }
+    }
+    out
+}
+
+impl fmt::Display for ControlMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlMessage::Heart { key, stamp: None } => write!(f, "HEART {key}"),
+            ControlMessage::Heart { key, stamp: Some(t) } => write!(f, "HEART {key} {t}"),
+            ControlMessage::Ok => f.write_str("OK"),
+            ControlMessage::Echo { stamp } => write!(f, "OK {stamp}"),
+            ControlMessage::Disconnect => f.write_str("DISCONNECT"),
+            ControlMessage::Bye => f.write_str("BYE"),
+            ControlMessage::ServerStop => f.write_str("SERVER_STOP"),
+            ControlMessage::Kicked => f.write_str("KICKED"),
+            ControlMessage::Substream { low_quality_port: Some(p) } => write!(f, "SUBSTREAM LQ {p}"),
+            ControlMessage::Substream { low_quality_port: None } => f.write_str("SUBSTREAM MC"),
+            ControlMessage::Motd { text } => write!(f, "MOTD {}", escape(text)),
+            ControlMessage::Codecs { list } => { f.write_str("CODECS")?; for c in list { write!(f, " {}", c.as_str())?; } Ok(()) }
+            ControlMessage::Udp { port } => write!(f, "UDP {port}"),
+            ControlMessage::WantUnicast { port } => write!(f, "WANT_UNICAST {port}"),
+            ControlMessage::Rekey { epoch, salt } => write!(f, "REKEY {epoch} {}", rekey::to_hex(salt)),
+            ControlMessage::Kx { public } => write!(f, "KX {}", rekey::to_hex(public)),
+            ControlMessage::Key { epoch, salt, wrapped } => write!(f, "KEY {epoch} {} {}", rekey::to_hex(salt), rekey::to_hex(wrapped)),
+            ControlMessage::Params { sample_rate, channels, fmt } => write!(f, "PARAMS {sample_rate} {channels} {fmt}"),
+            ControlMessage::Want { sample_rate, channels } => write!(f, "WANT {sample_rate} {channels}"),
+            ControlMessage::Transport { kind } => write!(f, "TRANSPORT {}", kind.as_str()),
+            ControlMessage::Frame { data } => write!(f, "FRAME {}", data.len()),
+        }
+    }
+}
+
+/// Reassembles lines across TCP reads: a line split over two `read` calls is only returned
+/// once its `\n` arrives. A `FRAME <len>` line switches to collecting `len` raw bytes.
+#[derive(Default)]
+pub struct LineBuffer { partial: Vec<u8>, overflow: bool, frame: Option<(usize, Vec<u8>)> }
+
+impl LineBuffer {
+    pub fn new() -> Self { Self::default() }
+
+    /// Feed raw bytes; returns the parsed complete lines (unknown / overlong lines skipped).
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<ControlMessage> {
+        let mut out = Vec::new();
+        let mut rest = bytes;
+        while let Some((&b, tail)) = rest.split_first() {
+            if let Some((len, data)) = self.frame.as_mut() {
+                let take = (*len - data.len()).min(rest.len());
+                data.extend_from_slice(&rest[..take]); rest = &rest[take..];
+                if data.len() == *len { if let Some((_, data)) = self.frame.take() { out.push(ControlMessage::Frame { data }); } }
+                continue;
+            }
+            rest = tail;
+            if b == b'\n' {
+                if !self.overflow {
+                    let line = String::from_utf8_lossy(&self.partial);
+                    match line.trim().strip_prefix("FRAME ").map(|n| n.parse::<usize>()) {
+                        Some(Ok(len)) if len <= MAX_FRAME => self.frame = Some((len, Vec::with_capacity(len))).filter(|_| len > 0),
+                        Some(_) => {} // bad length: nothing sensible to resync on but the next line
+                        None => if let Some(msg) = parse_line(&line) { out.push(msg); },
+                    }
+                }
+                self.partial.clear(); self.overflow = false;
+            } else if self.partial.len() < MAX_LINE {
+                self.partial.push(b);
+            } else {
+                self.overflow = true;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_message_and_round_trips() {
+        let all = [
+            ControlMessage::Heart { key: "abcDEF0123456789".into(), stamp: None },
+            ControlMessage::Heart { key: "abcDEF0123456789".into(), stamp: Some(123_456) },
+            ControlMessage::Ok,
+            ControlMessage::Echo { stamp: 0 },
+            ControlMessage::Disconnect,
+            ControlMessage::Bye,
+            ControlMessage::ServerStop,
+            ControlMessage::Kicked,
+            ControlMessage::Substream { low_quality_port: Some(50123) },
+            ControlMessage::Substream { low_quality_port: None },
+            ControlMessage::Motd { text: "Weekly jam session — be nice".into() },
+            ControlMessage::Codecs { list: vec![Codec::Pcm, Codec::Opus] },
+            ControlMessage::Udp { port: 50001 },
+            ControlMessage::WantUnicast { port: 50001 },
+            ControlMessage::Rekey { epoch: 255, salt: [0, 1, 0x2a, 0x7f, 0x80, 0xab, 0xcd, 0xff] },
+            ControlMessage::Kx { public: [0xa5; 32] },
+            ControlMessage::Key { epoch: 3, salt: [9; 8], wrapped: (0..72).collect() },
+            ControlMessage::Params { sample_rate: 44100, channels: 1, fmt: 2 },
+            ControlMessage::Want { sample_rate: 48000, channels: 2 },
+            ControlMessage::Transport { kind: Transport::Tcp },
+        ];
+        for msg in all { assert_eq!(parse_line(&msg.to_line()), Some(msg)); }
+    }
+
+    #[test]
+    fn keys_containing_keywords_do_not_false_match() {
+        assert_eq!(parse_line("HEART OKSERVER_STOP"), Some(ControlMessage::Heart { key: "OKSERVER_STOP".into(), stamp: None }));
+        assert_eq!(parse_line("OKAY"), None);
+        assert_eq!(parse_line("NOT_OK"), None);
+        assert_eq!(parse_line("XSERVER_STOP"), None);
+        assert_eq!(parse_line("ok"), None);
+        // handshake reply is not a heartbeat OK
+        assert_eq!(parse_line("OK key 48000 2 1 239.1.2.3 50000 NOENC"), None);
+    }
+
+    #[test]
+    fn trailing_whitespace_and_bad_arity() {
+        assert_eq!(parse_line("  OK \r"), Some(ControlMessage::Ok));
+        assert_eq!(parse_line("HEART key \t"), Some(ControlMessage::Heart { key: "key".into(), stamp: None }));
+        assert_eq!(parse_line("HEART"), None);
+        assert_eq!(parse_line("HEART a b"), None);
+        assert_eq!(parse_line("HEART a 1 2"), None);
+        assert_eq!(parse_line("OK -1"), None);
+        assert_eq!(parse_line("SUBSTREAM LQ"), None);
+        assert_eq!(parse_line("SUBSTREAM LQ 0"), None);
+        assert_eq!(parse_line("SUBSTREAM LQ 70000"), None);
+        assert_eq!(parse_line("SUBSTREAM MC extra"), None);
+        assert_eq!(parse_line("WANT_UNICAST 0"), None);
+        assert_eq!(parse_line("WANT_UNICAST"), None);
+        assert_eq!(parse_line("REKEY 1 0011"), None);
+        assert_eq!(parse_line("REKEY 256 0011223344556677"), None);
+        assert_eq!(parse_line(""), None);
+    }
+
+    #[test]
+    fn split_lines_are_reassembled() {
+        let mut lb = LineBuffer::new();
+        assert!(lb.push(b"HEART ab").is_empty());
+        assert_eq!(lb.push(b"cd\nO"), vec![ControlMessage::Heart { key: "abcd".into(), stamp: None }]);
+        assert!(lb.push(b"K").is_empty());
+        assert_eq!(lb.push(b"\r\nSERVER_STOP\nGARBAGE\nBYE\n"), vec![ControlMessage::Ok, ControlMessage::ServerStop, ControlMessage::Bye]);
+    }
+
+    #[test]
+    fn frames_are_split_from_lines_across_reads() {
+        let frame: Vec<u8> = (0..=255u8).chain([b'\n'; 3]).collect();
+        let mut wire = ControlMessage::frame_bytes(&frame);
+        wire.extend_from_slice(b"OK\n");
+        wire.extend(ControlMessage::frame_bytes(b"RM\n"));
+        let mut lb = LineBuffer::new();
+        let mut got = Vec::new();
+        for chunk in wire.chunks(7) { got.extend(lb.push(chunk)); }
+        assert_eq!(got, vec![ControlMessage::Frame { data: frame }, ControlMessage::Ok, ControlMessage::Frame { data: b"RM\n".to_vec() }]);
+        // Oversized length is ignored, following lines still parse
+        assert_eq!(lb.push(format!("FRAME {}\nBYE\n", MAX_FRAME + 1).as_bytes()), vec![ControlMessage::Bye]);
+    }
+
+    #[test]
+    fn motd_survives_newlines_and_escapes() {
+        let text = "line one\nline two\r\n  indented \\n literal\\";
+        let Some(msg) = ControlMessage::motd(text) else { panic!("motd rejected") };
+        let wire = msg.to_line();
+        assert_eq!(wire.matches('\n').count(), 1, "MOTD must stay a single line: {wire:?}");
+        let mut lb = LineBuffer::new();
+        let (head, tail) = wire.as_bytes().split_at(7);
+        assert!(lb.push(head).is_empty());
+        assert_eq!(lb.push(tail), vec![ControlMessage::Motd { text: text.into() }]);
+        // keywords inside the text are not messages
+        assert_eq!(parse_line(&ControlMessage::motd("SERVER_STOP").unwrap().to_line()), Some(ControlMessage::Motd { text: "SERVER_STOP".into() }));
+    }
+
+    #[test]
+    fn motd_is_trimmed_and_fits_one_line() {
+        assert_eq!(ControlMessage::motd("  \n "), None);
+        let Some(ControlMessage::Motd { text }) = ControlMessage::motd(&"界\n".repeat(1000)) else { panic!("motd rejected") };
+        let wire = ControlMessage::Motd { text: text.clone() }.to_line();
+        assert!(wire.len() <= MAX_LINE, "{} bytes", wire.len());
+        assert!(!text.is_empty() && text.starts_with('界'));
+        assert_eq!(LineBuffer::new().push(wire.as_bytes()), vec![ControlMessage::Motd { text }]);
+    }
+
+    #[test]
+    fn overlong_line_is_dropped() {
+        let mut lb = LineBuffer::new();
+        let mut long = b"HEART ".to_vec(); long.resize(MAX_LINE * 2, b'A');
+        assert!(lb.push(&long).is_empty());
+        assert!(lb.push(b"\n").is_empty());
+        assert_eq!(lb.push(b"OK\n"), vec![ControlMessage::Ok]);
+    }
+}
@@ -0,0 +1,298 @@
+//! Sample format conversions shared by the receive (decode) and replay (encode) paths.
+//!
+//! All integer formats use the same scale (2^15): `i16 v -> v / 32768`, and `u16` is the same
+//! code offset by 0x8000, so `u16 32768 == i16 0 == 0.0` and both decode paths are bit-identical.
+//! Encoding clamps to `[-32768, 32767]` and rounds to nearest, making `f32 -> int -> f32`
+//! round-trip within half an LSB for every in-range sample. `i32` uses 2^31 the same way.
+
+use crate::types;
+
+const SCALE: f32 = 32768.0;
+const SCALE_I32: f64 = 2147483648.0;
+
+/// i16 -> f32 in [-1, 1).
+#[inline] pub fn i16_to_f32(v: i16) -> f32 { v as f32 / SCALE }
+
+/// u16 (offset binary) -> f32 in [-1, 1), identical to the i16 path for the same code.
+#[inline] pub fn u16_to_f32(v: u16) -> f32 { i16_to_f32((v ^ 0x8000) as i16) }
+
+/// f32 -> i16 (round to nearest, clamped).
+#[inline] pub fn f32_to_i16(s: f32) -> i16 { (s * SCALE).round().clamp(-32768.0, 32767.0) as i16 }
+
+/// f32 -> u16 offset binary (same code as the i16 path with the sign bit flipped).
+#[inline] pub fn f32_to_u16(s: f32) -> u16 { (f32_to_i16(s) as u16) ^ 0x8000 }
+
+/// i32 -> f32 in [-1, 1).
+#[inline] pub fn i32_to_f32(v: i32) -> f32 { (v as f64 / SCALE_I32) as f32 }
+
+/// f32 -> i32 (round to nearest, clamped).
+#[inline] pub fn f32_to_i32(s: f32) -> i32 { (s as f64 * SCALE_I32).round().clamp(-SCALE_I32, SCALE_I32 - 1.0) as i32 }
+
+/// Captured / received PCM bytes of `fmt_code` -> interleaved f32 (integer formats little endian,
+/// float formats native, as produced by the capture callback on the sending host).
+pub fn decode_pcm(data: &[u8], fmt_code: u8) -> Vec<f32> {
+    match fmt_code {
+        types::FMT_I16 => data.chunks_exact(2).map(|c| i16_to_f32(i16::from_le_bytes([c[0], c[1]]))).collect(),
+        types::FMT_U16 => data.chunks_exact(2).map(|c| u16_to_f32(u16::from_le_bytes([c[0], c[1]]))).collect(),
+        types::FMT_I32 => data.chunks_exact(4).map(|c| i32_to_f32(i32::from_le_bytes([c[0], c[1], c[2], c[3]]))).collect(),
+        types::FMT_F64 => data.chunks_exact(8).map(|c| f64::from_ne_bytes(c.try_into().unwrap()) as f32).collect(),
+        _ => data.chunks_exact(4).map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]])).collect(),
+    }
+}
+
+/// Inverse of `decode_pcm`: interleaved f32 -> PCM bytes of `fmt_code` (same byte orders), for
+/// audio re-encoded in the capture format after processing (server stream-rate conversion).
+pub fn encode_pcm(samples: &[f32], fmt_code: u8) -> Vec<u8> {
+    match fmt_code {
+        types::FMT_I16 => samples.iter().flat_map(|&s| f32_to_i16(s).to_le_bytes()).collect(),
+        types::FMT_U16 => samples.iter().flat_map(|&s| f32_to_u16(s).to_le_bytes()).collect(),
+        types::FMT_I32 => samples.iter().flat_map(|&s| f32_to_i32(s).to_le_bytes()).collect(),
+        types::FMT_F64 => samples.iter().flat_map(|&s| (s as f64).to_ne_bytes()).collect(),
+        _ => samples.iter().flat_map(|&s| s.to_ne_bytes()).collect(),
+    }
+}
+
+/// Capture gain applied in place to one raw buffer of `fmt_code` samples (native byte order, as
+/// copied from the input callback). Results are clamped to full scale, so integer formats saturate
+/// instead of wrapping around.
+pub fn apply_gain(raw: &mut [u8], fmt_code: u8, gain: f32) {
+    if gain == 1.0 { return; }
+    match fmt_code {
+        types::FMT_I16 => for c in raw.chunks_exact_mut(2) { let v = f32_to_i16(i16_to_f32(i16::from_ne_bytes([c[0], c[1]])) * gain); c.copy_from_slice(&v.to_ne_bytes()); },
+        types::FMT_U16 => for c in raw.chunks_exact_mut(2) { let v = f32_to_u16(u16_to_f32(u16::from_ne_bytes([c[0], c[1]])) * gain); c.copy_from_slice(&v.to_ne_bytes()); },
+        types::FMT_I32 => for c in raw.chunks_exact_mut(4) { let v = f32_to_i32(i32_to_f32(i32::from_ne_bytes([c[0], c[1], c[2], c[3]])) * gain); c.copy_from_slice(&v.to_ne_bytes()); },
+        types::FMT_F64 => for c in raw.chunks_exact_mut(8) { let v = (f64::from_ne_bytes(c.try_into().unwrap()) * gain as f64).clamp(-1.0, 1.0); c.copy_from_slice(&v.to_ne_bytes()); },
+        _ => for c in raw.chunks_exact_mut(4) { let v = (f32::from_ne_bytes([c[0], c[1], c[2], c[3]]) * gain).clamp(-1.0, 1.0); c.copy_from_slice(&v.to_ne_bytes()); },
+    }
+}
+
+/// Copy the `pick` channels (0-based, in that order) of raw interleaved `channels`-channel audio with
+/// `sample_size`-byte samples into `out`, whole frames only, as many as fit. Returns bytes written.
+pub fn select_channels(raw: &[u8], out: &mut [u8], channels: usize, sample_size: usize, pick: &[usize]) -> usize {
+    let (in_frame, out_frame) = (channels * sample_size, pick.len() * sample_size);
+    if in_frame == 0 || out_frame == 0 { return 0; }
+    let mut written = 0;
+    for (frame, dst) in raw.chunks_exact(in_frame).zip(out.chunks_exact_mut(out_frame)) {
+        for (&c, d) in pick.iter().zip(dst.chunks_exact_mut(sample_size)) { d.copy_from_slice(&frame[c * sample_size..(c + 1) * sample_size]); }
+        written += out_frame;
+    }
+    written
+}
+
+/// RMS (linear, 0..1) of one raw buffer of `fmt_code` samples (native byte order, as in `apply_gain`).
+pub fn rms(raw: &[u8], fmt_code: u8) -> f64 {
+    let (acc, n) = match fmt_code {
+        types::FMT_I16 => raw.chunks_exact(2).fold((0f64, 0usize), |(a, n), c| { let v = i16_to_f32(i16::from_ne_bytes([c[0], c[1]])) as f64; (a + v * v, n + 1) }),
+        types::FMT_U16 => raw.chunks_exact(2).fold((0f64, 0usize), |(a, n), c| { let v = u16_to_f32(u16::from_ne_bytes([c[0], c[1]])) as f64; (a + v * v, n + 1) }),
+        types::FMT_I32 => raw.chunks_exact(4).fold((0f64, 0usize), |(a, n), c| { let v = i32::from_ne_bytes([c[0], c[1], c[2], c[3]]) as f64 / SCALE_I32; (a + v * v, n + 1) }),
+        types::FMT_F64 => raw.chunks_exact(8).fold((0f64, 0usize), |(a, n), c| { let v = f64::from_ne_bytes(c.try_into().unwrap()); (a + v * v, n + 1) }),
+        _ => raw.chunks_exact(4).fold((0f64, 0usize), |(a, n), c| { let v = f32::from_ne_bytes([c[0], c[1], c[2], c[3]]) as f64; (a + v * v, n + 1) }),
+    };
+    if n > 0 { (acc / n as f64).sqrt() } else { 0.0 }
+}
+
+/// Optional gain compensation before down-conversion: scale `samples` so the absolute peak
+/// reaches `target` (e.g. 0.98). Silent buffers and gains above `max_gain` are left capped.
+/// Returns the applied gain.
+pub fn normalize_peak(samples: &mut [f32], target: f32, max_gain: f32) -> f32 {
+    let peak = samples.iter().fold(0f32, |m, s| m.max(s.abs()));
+    if peak <= f32::EPSILON { return 1.0; }
+    let gain = (target / peak).min(max_gain);
+    for s in samples.iter_mut() { *s *= gain; }
+    gain
+}
+
+/// Linear-interpolation resample of a mono block from `from` Hz to `to` Hz, appended to `out`.
+/// Stateless per block (good enough for the low-quality substream, not for hi-fi paths).
+pub fn resample_linear(input: &[f32], from: u32, to: u32, out: &mut Vec<f32>) {
+    if input.is_empty() || from == 0 || to == 0 { return; }
+    if from == to { out.extend_from_slice(input); return; }
+    let n_out = ((input.len() as u64 * to as u64) / from as u64).max(1) as usize;
+    let step = from as f64 / to as f64;
+    let last = input.len() - 1;
+    out.extend((0..n_out).map(|n| { let t = n as f64 * step; let i = (t as usize).min(last); let j = (i + 1).min(last); let frac = (t - i as f64) as f32; input[i] + (input[j] - input[i]) * frac }));
+}
+
+/// `resample_linear` for interleaved `ch`-channel audio (each channel resampled on its own).
+pub fn resample_interleaved(input: &[f32], ch: u16, from: u32, to: u32, out: &mut Vec<f32>) {
+    let ch = ch.max(1) as usize;
+    if ch == 1 { resample_linear(input, from, to, out); return; }
+    let planes: Vec<Vec<f32>> = (0..ch).map(|c| { let plane: Vec<f32> = input.iter().skip(c).step_by(ch).copied().collect(); let mut p = Vec::new(); resample_linear(&plane, from, to, &mut p); p }).collect();
+    let len = planes.iter().map(Vec::len).min().unwrap_or(0);
+    out.extend((0..len).flat_map(|i| planes.iter().map(move |p| p[i])));
+}
+
+/// Streaming linear resampler for a continuous interleaved signal (playback path): unlike
+/// `resample_linear`, the fractional read position and the last input frame carry over between
+/// blocks, so block boundaries add no clicks and the long-run output/input ratio is exactly `to / from`.
+pub struct StreamResampler { from: u32, to: u32, ch: usize, pos: f64, prev: Vec<f32> }
+
+impl StreamResampler {
+    pub fn new(from: u32, to: u32, channels: u16) -> Self { Self { from, to, ch: channels.max(1) as usize, pos: 0.0, prev: Vec::new() } }
+
+    /// Resample interleaved `input` (appended to `out`); pass-through when the rates match.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        if self.from == self.to || self.from == 0 || self.to == 0 { out.extend_from_slice(input); return; }
+        let ch = self.ch;
+        let frames = input.len() / ch;
+        if frames == 0 { return; }
+        // Virtual frame sequence: [prev] + input (frame 0 = last frame of the previous block)
+        let head = (!self.prev.is_empty()) as usize;
+        let len = frames + head;
+        let prev = &self.prev;
+        let at = |i: usize, c: usize| if i < head { prev[c] } else { input[(i - head) * ch + c] };
+        let step = self.from as f64 / self.to as f64;
+        let mut pos = self.pos;
+        while pos + 1.0 < len as f64 {
+            let i = pos as usize; let frac = (pos - i as f64) as f32;
+            out.extend((0..ch).map(|c| at(i, c) + (at(i + 1, c) - at(i, c)) * frac));
+            pos += step;
+        }
+        self.pos = pos - (len - 1) as f64;
+        self.prev.clear(); self.prev.extend_from_slice(&input[(frames - 1) * ch..frames * ch]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f32, n: usize) -> Vec<f32> {
+        (0..n).map(|i| amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 48_000.0).sin()).collect()
+    }
+
+    fn peak(v: &[f32]) -> f32 { v.iter().fold(0f32, |m, s| m.max(s.abs())) }
+
+    fn rms_error(a: &[f32], b: &[f32]) -> f32 {
+        (a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>() / a.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn zero_codes_have_no_dc_offset() {
+        assert_eq!(i16_to_f32(0), 0.0);
+        assert_eq!(u16_to_f32(32768), 0.0);
+        assert_eq!(f32_to_i16(0.0), 0);
+        assert_eq!(f32_to_u16(0.0), 32768);
+    }
+
+    #[test]
+    fn i16_and_u16_paths_are_symmetric() {
+        for v in [i16::MIN, -12345, -1, 0, 1, 12345, i16::MAX] {
+            let u = (v as u16) ^ 0x8000;
+            assert_eq!(i16_to_f32(v), u16_to_f32(u));
+        }
+        for s in [-1.0f32, -0.5, -1e-4, 0.0, 1e-4, 0.5, 0.999] {
+            assert_eq!(f32_to_u16(s), (f32_to_i16(s) as u16) ^ 0x8000);
+        }
+    }
+
+    #[test]
+    fn sine_amplitude_survives_each_path() {
+        let src = sine(0.8, 4800);
+        let via_i16: Vec<f32> = src.iter().map(|&s| i16_to_f32(f32_to_i16(s))).collect();
+        let via_u16: Vec<f32> = src.iter().map(|&s| u16_to_f32(f32_to_u16(s))).collect();
+        assert_eq!(via_i16, via_u16);
+        assert!((peak(&via_i16) - peak(&src)).abs() <= 0.5 / SCALE + 1e-7);
+        assert!(rms_error(&src, &via_i16) < 1.0 / SCALE);
+        let mean: f32 = via_u16.iter().sum::<f32>() / via_u16.len() as f32;
+        assert!(mean.abs() < 1e-4, "DC offset {mean}");
+    }
+
+    #[test]
+    fn full_scale_clamps_instead_of_wrapping() {
+        assert_eq!(f32_to_i16(1.0), i16::MAX);
+        assert_eq!(f32_to_i16(-1.5), i16::MIN);
+        assert_eq!(f32_to_u16(1.0), u16::MAX);
+        assert_eq!(f32_to_u16(-1.0), 0);
+    }
+
+    #[test]
+    fn i32_buffer_round_trips_with_2_pow_31_scaling() {
+        let codes = [i32::MIN, -(1 << 30), -1, 0, 1, 1 << 30, i32::MAX];
+        let raw: Vec<u8> = codes.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let decoded = decode_pcm(&raw, types::FMT_I32);
+        assert_eq!(decoded, codes.iter().map(|&v| v as f32 / 2147483648.0).collect::<Vec<_>>());
+        assert_eq!(&decoded[..2], &[-1.0, -0.5]);
+        // Back to i32 (these codes are exact in f32); i32::MAX rounds up to 1.0 and clamps instead of wrapping
+        assert_eq!(decoded[6], 1.0);
+        assert_eq!(decoded.iter().map(|&s| f32_to_i32(s)).collect::<Vec<_>>(), codes);
+        let f64_raw: Vec<u8> = [0.25f64, -0.75].iter().flat_map(|v| v.to_ne_bytes()).collect();
+        assert_eq!(decode_pcm(&f64_raw, types::FMT_F64), vec![0.25, -0.75]);
+    }
+
+    #[test]
+    fn encode_pcm_inverts_decode_pcm_for_every_format() {
+        let samples = [-1.0f32, -0.5, 0.0, 0.25, 0.5];
+        for fmt in [types::FMT_F32, types::FMT_I16, types::FMT_U16, types::FMT_I32, types::FMT_F64] {
+            let raw = encode_pcm(&samples, fmt);
+            assert_eq!(raw.len(), samples.len() * types::bytes_per_sample(fmt));
+            assert_eq!(decode_pcm(&raw, fmt), samples, "fmt {fmt}");
+        }
+    }
+
+    #[test]
+    fn capture_gain_saturates_integer_formats() {
+        let mut raw: Vec<u8> = [20_000i16, -20_000, 100].iter().flat_map(|v| v.to_ne_bytes()).collect();
+        apply_gain(&mut raw, types::FMT_I16, 4.0);
+        let out: Vec<i16> = raw.chunks_exact(2).map(|c| i16::from_ne_bytes([c[0], c[1]])).collect();
+        assert_eq!(out, vec![i16::MAX, i16::MIN, 400]);
+        let mut raw: Vec<u8> = [0xF000u16, 0x1000].iter().flat_map(|v| v.to_ne_bytes()).collect();
+        apply_gain(&mut raw, types::FMT_U16, 4.0);
+        assert_eq!(raw.chunks_exact(2).map(|c| u16::from_ne_bytes([c[0], c[1]])).collect::<Vec<_>>(), vec![u16::MAX, 0]);
+        let mut raw: Vec<u8> = [0.5f32, -0.1].iter().flat_map(|v| v.to_ne_bytes()).collect();
+        apply_gain(&mut raw, types::FMT_F32, 0.5);
+        assert_eq!(raw.chunks_exact(4).map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]])).collect::<Vec<_>>(), vec![0.25, -0.05]);
+    }
+
+    #[test]
+    fn normalization_improves_quiet_signal_resolution() {
+        let quiet = sine(0.001, 4800);
+        let plain_err = rms_error(&quiet, &quiet.iter().map(|&s| i16_to_f32(f32_to_i16(s))).collect::<Vec<_>>()) / peak(&quiet);
+        let mut boosted = quiet.clone();
+        let gain = normalize_peak(&mut boosted, 0.98, 1000.0);
+        assert!((peak(&boosted) - 0.98).abs() < 1e-3);
+        let norm_err = rms_error(&boosted, &boosted.iter().map(|&s| i16_to_f32(f32_to_i16(s))).collect::<Vec<_>>()) / peak(&boosted);
+        assert!(gain > 900.0 && norm_err * 100.0 < plain_err, "plain={plain_err} normalized={norm_err}");
+        let mut silent = vec![0.0f32; 16];
+        assert_eq!(normalize_peak(&mut silent, 0.98, 1000.0), 1.0);
+    }
+
+    #[test]
+    fn stream_resampler_44100_to_48000_keeps_rate_and_pitch() {
+        let src: Vec<f32> = (0..44_100).map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44_100.0).sin()).collect();
+        let mut rs = StreamResampler::new(44_100, 48_000, 1);
+        let mut out = Vec::new();
+        // Irregular block sizes, as delivered by the jitter buffer
+        for block in src.chunks(441).flat_map(|b| { let (a, c) = b.split_at(b.len() / 3); [a, c] }) { rs.process(block, &mut out); }
+        let ratio = out.len() as f64 / src.len() as f64;
+        assert!((ratio - 48_000.0 / 44_100.0).abs() < 1e-3, "ratio {ratio} ({} samples)", out.len());
+        // Still 440 Hz: ~880 sign changes per second of 48 kHz output, and no block-edge jumps
+        let crossings = out.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+        assert!((878..=882).contains(&crossings), "crossings {crossings}");
+        let max_step = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0f32, f32::max);
+        assert!(max_step < 2.0 * std::f32::consts::PI * 440.0 / 48_000.0 * 1.05, "discontinuity {max_step}");
+    }
+
+    #[test]
+    fn stream_resampler_passes_through_equal_rates() {
+        let mut rs = StreamResampler::new(48_000, 48_000, 2);
+        let mut out = Vec::new();
+        rs.process(&[0.1, 0.2, 0.3], &mut out);
+        assert_eq!(out, vec![0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn select_channels_extracts_whole_frames_in_pick_order() {
+        // 3 frames of 4-channel i16: sample value = 10 * frame + channel
+        let raw: Vec<u8> = (0..3).flat_map(|f| (0..4).map(move |c| 10 * f + c)).flat_map(|v: i16| v.to_ne_bytes()).collect();
+        let samples = |b: &[u8]| b.chunks_exact(2).map(|c| i16::from_ne_bytes([c[0], c[1]])).collect::<Vec<_>>();
+        let mut out = vec![0u8; 64];
+        let n = select_channels(&raw, &mut out, 4, 2, &[2]);
+        assert_eq!(samples(&out[..n]), vec![2, 12, 22]);
+        let n = select_channels(&raw, &mut out, 4, 2, &[3, 0]);
+        assert_eq!(samples(&out[..n]), vec![3, 0, 13, 10, 23, 20]);
+        // Output too small: only the frames that fit
+        let n = select_channels(&raw, &mut out[..5], 4, 2, &[3, 0]);
+        assert_eq!(samples(&out[..n]), vec![3, 0]);
+    }
+}
@@ -0,0 +1,119 @@
+//! CPU-pressure aware degradation: shed optional per-frame work before real-time breaks.
+//!
+//! Each hot loop measures its busy time per frame against the frame's audio duration. When the
+//! smoothed load stays above `SHED_ABOVE` the next item in the configured order is shed; when it
+//! stays below `RESTORE_BELOW` the most recently shed item comes back. Each side has its own
+//! default order (`CLIENT_ORDER`, `SERVER_ORDER`); `REMOTEMIC_DEGRADE_ORDER` (comma separated, e.g.
+//! `spectrum,metering,concealment`) overrides it, minus items that do not run on that side, so no
+//! shed step is a no-op that burns a hold period.
+//!
+//! There is no EQ stage in the pipeline, so the "spectrum, metering, EQ" order of the original plan
+//! ends with packet-loss concealment instead: the next most optional per-frame work on the client.
+use std::time::{Duration, Instant};
+
+/// Load (busy / frame duration) above which work is shed.
+const SHED_ABOVE: f64 = 0.7;
+/// Load below which shed work is restored.
+const RESTORE_BELOW: f64 = 0.3;
+/// Minimum time between two changes (hysteresis; restore waits longer).
+const SHED_HOLD: Duration = Duration::from_secs(2);
+const RESTORE_HOLD: Duration = Duration::from_secs(10);
+
+/// Optional work that may be shed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Optional {
+    /// Client spectrum display: the GUI FFT drops to every 4th refresh (client only).
+    Spectrum,
+    /// RMS / peak metering: drop to every 4th frame.
+    Metering,
+    /// Packet-loss concealment (client only).
+    Concealment,
+}
+
+impl Optional {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() { "spectrum" => Some(Self::Spectrum), "metering" => Some(Self::Metering), "concealment" | "plc" => Some(Self::Concealment), _ => None }
+    }
+}
+
+/// Client shedding order (first = shed first).
+pub const CLIENT_ORDER: [Optional; 3] = [Optional::Spectrum, Optional::Metering, Optional::Concealment];
+/// Server shedding order: no spectrum display or concealment there.
+pub const SERVER_ORDER: [Optional; 1] = [Optional::Metering];
+
+pub struct Degrader { name: &'static str, order: Vec<Optional>, shed: usize, load: f64, last_change: Instant }
+
+impl Degrader {
+    pub fn new(name: &'static str, order: Vec<Optional>) -> Self { Self { name, order, shed: 0, load: 0.0, last_change: Instant::now() } }
+
+    /// Order from `REMOTEMIC_DEGRADE_ORDER`, keeping only items in `side` (this side's default
+    /// order; unknown names ignored, empty/unset = `side`).
+    pub fn from_env(name: &'static str, side: &[Optional]) -> Self {
+        Self::new(name, order_for(std::env::var("REMOTEMIC_DEGRADE_ORDER").ok().as_deref(), side))
+    }
+
+    pub fn is_shed(&self, item: Optional) -> bool { self.order[..self.shed].contains(&item) }
+
+    /// Record one frame's busy time against its audio duration; sheds / restores with hysteresis.
+    pub fn observe(&mut self, busy: Duration, frame: Duration) { self.observe_at(busy, frame, Instant::now()); }
+
+    fn observe_at(&mut self, busy: Duration, frame: Duration, now: Instant) {
+        if frame.is_zero() { return; }
+        let sample = busy.as_secs_f64() / frame.as_secs_f64();
+        self.load += (sample - self.load) / 32.0;
+        let since = now.saturating_duration_since(self.last_change);
+        if self.load > SHED_ABOVE && self.shed < self.order.len() && since >= SHED_HOLD {
+            log::warn!("[{}][DEGRADE] load {:.0}% -> shedding {:?}", self.name, self.load * 100.0, self.order[self.shed]);
+            self.shed += 1; self.last_change = now;
+        } else if self.load < RESTORE_BELOW && self.shed > 0 && since >= RESTORE_HOLD {
+            self.shed -= 1; self.last_change = now;
+            log::info!("[{}][DEGRADE] load {:.0}% -> restoring {:?}", self.name, self.load * 100.0, self.order[self.shed]);
+        }
+    }
+}
+
+
+/// Configured order (`spec` = `REMOTEMIC_DEGRADE_ORDER`) restricted to `side`, or `side` itself.
+fn order_for(spec: Option<&str>, side: &[Optional]) -> Vec<Optional> {
+    let order: Vec<Optional> = spec.map(|v| v.split(',').filter_map(Optional::parse).filter(|o| side.contains(o)).collect()).unwrap_or_default();
+    if order.is_empty() { side.to_vec() } else { order }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_order_keeps_only_this_sides_items() {
+        assert_eq!(order_for(Some("metering, PLC"), &CLIENT_ORDER), vec![Optional::Metering, Optional::Concealment]);
+        // Spectrum / concealment do not exist on the server: never a no-op first step there
+        assert_eq!(order_for(Some("spectrum,concealment,metering"), &SERVER_ORDER), vec![Optional::Metering]);
+        assert_eq!(order_for(Some("fft,eq"), &CLIENT_ORDER), CLIENT_ORDER.to_vec());
+        assert_eq!(order_for(None, &SERVER_ORDER), SERVER_ORDER.to_vec());
+    }
+
+    #[test]
+    fn sheds_and_restores_one_item_per_hold_period() {
+        let mut d = Degrader::new("TEST", CLIENT_ORDER.to_vec());
+        let t0 = d.last_change;
+        let frame = Duration::from_millis(10);
+        let run = |d: &mut Degrader, busy: u64, at: Duration, n: usize| for _ in 0..n { d.observe_at(Duration::from_millis(busy), frame, t0 + at); };
+        // Overloaded, but the first shed still waits for SHED_HOLD
+        run(&mut d, 10, Duration::from_secs(1), 100);
+        assert!(!d.is_shed(Optional::Spectrum));
+        run(&mut d, 10, Duration::from_millis(2_100), 1);
+        assert!(d.is_shed(Optional::Spectrum) && !d.is_shed(Optional::Metering));
+        // One step per hold period, in order
+        run(&mut d, 10, Duration::from_secs(3), 10);
+        assert!(!d.is_shed(Optional::Metering));
+        run(&mut d, 10, Duration::from_millis(4_200), 1);
+        assert!(d.is_shed(Optional::Metering) && !d.is_shed(Optional::Concealment));
+        // Idle again: nothing returns before RESTORE_HOLD, then the last shed item comes back first
+        run(&mut d, 0, Duration::from_secs(5), 200);
+        assert!(d.is_shed(Optional::Metering));
+        run(&mut d, 0, Duration::from_millis(14_300), 1);
+        assert!(!d.is_shed(Optional::Metering) && d.is_shed(Optional::Spectrum));
+        run(&mut d, 0, Duration::from_millis(24_400), 1);
+        assert!(!d.is_shed(Optional::Spectrum));
+    }
+}
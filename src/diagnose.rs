@@ -0,0 +1,109 @@
+//! Network diagnostics (dry run): the client connect and server bind paths run step by step, and
+//! each step is reported with its latency, so a failure points at the stage that broke (control port
+//! filtered, multicast blocked, server not sending...) instead of a single connect error.
+use std::{thread, time::{Duration, Instant}};
+use crossbeam_channel::RecvTimeoutError;
+
+use crate::{client::{self, ClientEvent, ConnectPhase}, lang, secret::PskSource, server::{self, ServerState}, types::Transport};
+
+/// How long the client probe waits for the first frame after joining.
+pub const FIRST_FRAME_WAIT: Duration = Duration::from_secs(3);
+/// Connect progress poll interval (resolution of the TCP / handshake split).
+const POLL: Duration = Duration::from_millis(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome { Passed, Failed, Skipped }
+
+/// One checklist row: `key` is the lang key of the step.
+#[derive(Clone, Debug)]
+pub struct Check { pub key: &'static str, pub outcome: Outcome, pub elapsed: Option<Duration>, pub detail: String }
+
+impl Check {
+    fn passed(key: &'static str, elapsed: Duration, detail: impl Into<String>) -> Self { Self { key, outcome: Outcome::Passed, elapsed: Some(elapsed), detail: detail.into() } }
+    fn failed(key: &'static str, detail: impl Into<String>) -> Self { Self { key, outcome: Outcome::Failed, elapsed: None, detail: detail.into() } }
+    fn skipped(key: &'static str) -> Self { Self { key, outcome: Outcome::Skipped, elapsed: None, detail: String::new() } }
+}
+
+const TCP: &str = "diag.client.tcp";
+const HANDSHAKE: &str = "diag.client.handshake";
+const MULTICAST: &str = "diag.client.multicast";
+const FIRST_FRAME: &str = "diag.client.first_frame";
+
+/// Client steps against `host:port`: TCP connect, handshake, multicast join, first frame (UDP
+/// transport, nothing is played). The probe connection is closed before returning.
+pub fn client(host: String, port: u16, psk: Option<PskSource>, timeout: Duration) -> Vec<Check> {
+    let (ev_tx, mut ev_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    let start = Instant::now();
+    // `connect` waits for a server without audio; the probe stops waiting, and a late success is closed here
+    let spawned = thread::Builder::new().name("rm-diagnose".into()).spawn(move || {
+        if let Err(crossbeam_channel::SendError(Ok(cs))) = tx.send(client::connect(host, port, psk, Some(ev_tx), timeout, Transport::Udp)) { client::disconnect(&cs); }
+    });
+    if let Err(e) = spawned { return vec![Check::failed(TCP, e.to_string()), Check::skipped(HANDSHAKE), Check::skipped(MULTICAST), Check::skipped(FIRST_FRAME)]; }
+    let mut tcp_done = None;
+    let res = loop {
+        let res = rx.recv_timeout(POLL);
+        // Phase events arrive before the result: the TCP step ends when the handshake phase starts
+        while let Ok(ev) = ev_rx.try_recv() {
+            match ev {
+                ClientEvent::Connecting(ConnectPhase::Handshake) => { tcp_done.get_or_insert(start.elapsed()); }
+                ClientEvent::Connecting(ConnectPhase::WaitingForAudio) => {
+                    let tcp = tcp_done.unwrap_or_default();
+                    return vec![Check::passed(TCP, tcp, ""), Check::passed(HANDSHAKE, start.elapsed() - tcp, lang::tr("diag.detail.no_audio")), Check::skipped(MULTICAST), Check::skipped(FIRST_FRAME)];
+                }
+                _ => {}
+            }
+        }
+        match res {
+            Ok(res) => break res,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break Err(anyhow::anyhow!("connect thread exited")),
+        }
+    };
+    let (mut cs, tcp) = match (res, tcp_done) {
+        (Ok(cs), tcp) => (cs, tcp.unwrap_or_default()),
+        (Err(e), Some(tcp)) => return vec![Check::passed(TCP, tcp, ""), Check::failed(HANDSHAKE, format!("{e:#}")), Check::skipped(MULTICAST), Check::skipped(FIRST_FRAME)],
+        (Err(e), None) => return vec![Check::failed(TCP, format!("{e:#}")), Check::skipped(HANDSHAKE), Check::skipped(MULTICAST), Check::skipped(FIRST_FRAME)],
+    };
+    let mut checks = vec![Check::passed(TCP, tcp, ""), Check::passed(HANDSHAKE, start.elapsed() - tcp, "")];
+    let group = cs.multicast_addr.map(|(ip, p)| format!("{ip}:{p}")).unwrap_or_default();
+    match client::probe_frames(&mut cs, port, FIRST_FRAME_WAIT) {
+        Ok((joined, first)) => {
+            checks.push(match joined { Ok(d) => Check::passed(MULTICAST, d, group), Err(e) => Check::failed(MULTICAST, format!("{group}: {e} — {}", lang::tr("diag.detail.unicast"))) });
+            let wait = FIRST_FRAME_WAIT.as_secs().to_string();
+            checks.push(match first { Some(d) => Check::passed(FIRST_FRAME, d, ""), None => Check::failed(FIRST_FRAME, lang::tr_args("diag.detail.no_frame", &[("secs", &wait)])) });
+        }
+        Err(e) => checks.extend([Check::failed(MULTICAST, format!("{e:#}")), Check::skipped(FIRST_FRAME)]),
+    }
+    client::disconnect(&cs);
+    checks
+}
+
+/// Server steps for `bind_ip:port`: control listener, multicast send socket, one probe datagram to
+/// the group (shorter than a frame header: clients drop it). A running server reports its live state.
+pub fn server(state: &ServerState, bind_ip: &str, port: u16) -> Vec<Check> {
+    const LISTENER: &str = "diag.server.listener";
+    const SOCKET: &str = "diag.server.multicast_socket";
+    const SEND: &str = "diag.server.multicast_send";
+    if state.running.load(std::sync::atomic::Ordering::Relaxed) {
+        let running = lang::tr("diag.detail.running");
+        let send = match state.send_error.lock().clone() { Some(e) => Check::failed(SEND, e), None => Check::passed(SEND, Duration::ZERO, format!("{:.1} kbps", state.send_kbps.load())) };
+        return vec![Check::passed(LISTENER, Duration::ZERO, format!("{running} ({bind_ip}:{port})")), Check::passed(SOCKET, Duration::ZERO, running), send];
+    }
+    let mut checks = Vec::new();
+    let start = Instant::now();
+    match server::bind_control(bind_ip, port) {
+        Ok(listener) => { checks.push(Check::passed(LISTENER, start.elapsed(), format!("{bind_ip}:{port}"))); drop(listener); }
+        Err(e) => checks.push(Check::failed(LISTENER, format!("{e:#}"))),
+    }
+    let start = Instant::now();
+    let (udp, send_ip) = match server::bind_multicast_sender(state, bind_ip) {
+        Ok(bound) => bound,
+        Err(e) => { checks.extend([Check::failed(SOCKET, format!("{e:#}")), Check::skipped(SEND)]); return checks; }
+    };
+    checks.push(Check::passed(SOCKET, start.elapsed(), send_ip.to_string()));
+    let dest = std::net::SocketAddr::new(state.multicast_addr, server::multicast_port_for(state, port).unwrap_or(port));
+    let start = Instant::now();
+    checks.push(match udp.send_to(&[0], dest) { Ok(_) => Check::passed(SEND, start.elapsed(), dest.to_string()), Err(e) => Check::failed(SEND, format!("{dest}: {e}")) });
+    checks
+}
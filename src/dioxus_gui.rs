@@ -1,12 +1,12 @@
 //! Dioxus desktop GUI.
-use crate::{audio, buffers::AudioBufferPool, client, lang, server};
+use crate::{agc, audio, buffers::AudioBufferPool, client::{self, ClientEvent, ConnectPhase, LatencyProfile}, codec::Codec, config, diagnose, discovery, dtx, gate, lang, logging, loudness, net, packetize, ptt, quality::Quality, record, replay, secret, selftest, server, spectrum, types::Transport};
 use anyhow::Result;
 use cpal::traits::{DeviceTrait, StreamTrait};
 use crossbeam_channel::unbounded;
 use dioxus::prelude::*;
-use std::sync::{atomic::Ordering, Arc};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 // 全局深色扁平主题 CSS (设计令牌 + 扁平化，无大阴影)
 const GLOBAL_DARK_CSS: &str = r#":root {
@@ -74,107 +74,264 @@ pub fn run() -> anyhow::Result<()> {
     );
 }
 
+/// Additional client connection (several servers monitored at once), next to the main `client_state`.
+struct ExtraClient { id: u64, label: String, state: client::ClientState }
+
 /// Top-level application state mirrored into the UI.
 struct AppState {
     current_lang: String,
+    /// Language dropdown entries (code, display name): embedded + external files, scanned once at startup
+    lang_options: Vec<(String, String)>,
+    log_level: log::LevelFilter, // 日志级别 (本程序; 依赖库只记录警告/错误), 运行中可改
     input_devices: Vec<String>,
     output_devices: Vec<String>,
-    sel_input: usize,
-    sel_output: usize,
+    default_input: Option<String>,  // 系统默认输入设备名 (列表中置顶并标注)
+    default_output: Option<String>, // 系统默认输出设备名
+    sel_input: String,  // 选中的设备按名称保存: 启动采集 / 播放时重新枚举匹配 (列表顺序可能已变, 设备不在则回退默认)
+    sel_output: String,
     server_ip_list: Vec<String>,
     sel_server_ip: usize,
+    /// Egress interface for the audio send socket: 0 = same as the bind address, i + 1 = `server_ip_list[i]`
+    sel_server_egress: usize,
+    /// Multicast UDP port text (empty = auto, see `ServerState::multicast_port`)
+    server_mcast_port: String,
+    server_room: String,       // 房间名: 非空时组播组由其派生 (每次启动相同), 空 = 随机
+    server_ttl: u32,           // IPv4 组播 TTL (1 = 仅本子网)
+    server_max_clients: usize, // 客户端数量上限 (0 = 不限)
+    server_capture_frames: u32, // 采集每回调帧数 (0 = 设备默认)
+    server_packet_frames: u32, // 每个 UDP 包的 PCM 帧数 (0 = 每个采集缓冲一包)
+    server_mtu: u16, // 重新分包时的数据报上限 (字节)
+    server_stream_rate: u32, // 发送采样率 (0 = 采集采样率)
+    server_input_channels: String, // 采集的输入声道 (1 起, 逗号分隔; 空 = 全部)
+    server_ipv6: bool,         // 列出 IPv6 地址并使用 IPv6 组播组 (ff02::/16)
     server_port: u16,
+    server_port_text: String,  // 端口输入框原文 (校验通过才写入 server_port)
     server_running: bool,
     server_state: server::ServerState,
+    #[cfg(feature = "control-api")]
+    api_state: crate::api::LiveState, // control API 使用的服务器状态, 每次启动服务器时替换
+    ptt_hotkey: Option<ptt::PttHotkey>, // 按键通话全局热键 (注册期间持有; drop 即注销)
+    server_qr_visible: bool,   // 显示连接地址二维码
     buffer_pool: Arc<AudioBufferPool>,
     client_state: Option<client::ClientState>,
     client_server_ip: String,
     client_server_port: String,
     error_message: Option<String>,
-    event_rx: Option<UnboundedReceiver<String>>, // 客户端事件接收
+    toast: Option<(String, Instant)>, // 短暂提示 (右下角, TOAST_DURATION 后自动消失)
+    event_rx: Option<UnboundedReceiver<ClientEvent>>, // 客户端事件接收
     metrics_tick: Instant,
+    client_spectrum: Vec<f32>, // 客户端频谱柱高 (0..1), 由 100ms 刷新任务计算
     mic_test_done: bool,
     mic_available: bool,
     net_test_done: bool,
     net_available: bool,
     server_psk: String,        // 服务器预共享密钥输入
     client_psk: String,        // 客户端预共享密钥输入
+    client_psk_required: bool, // 粘贴的 remotemic:// 地址带 enc=1 (服务器需要 PSK)
+    server_psk_file: Option<std::path::PathBuf>, // 从文件读取 PSK (优先级低于手动输入)
+    client_psk_file: Option<std::path::PathBuf>,
+    server_psk_remember: bool, // 成功启动后把输入的 PSK 存入系统钥匙串
+    client_psk_remember: bool,
+    server_psk_saved: bool,    // 钥匙串中已有 PSK (输入为空时使用)
+    server_per_client_keys: bool, // 每客户端密钥 (x25519 交换，取代 PSK，可单独吊销)
+    client_psk_saved: bool,
+    client_require_ack: bool,  // 仅在参数/组播/首帧均确认后显示已连接
+    client_plc: bool,          // 丢包隐藏 (淡出重复上一帧)
+    client_force_mono: bool,   // 强制单声道 (立体声源也混为单声道)
+    client_width: u32,         // 立体声宽度 % (0 = 单声道, 100 = 原始, 最大 200; M/S, 输出回调中处理)
+    client_sync_start: bool,   // 多房间同步起播
+    client_low_quality: bool,  // 请求低质量单播子流 (mono i16 16kHz)
+    client_muted: bool,        // 本地播放静音 (仍接收/缓冲)
+    client_volume: u32,        // 本地播放音量 % (0-200, 与系统音量无关)
+    client_loudness: bool,     // 响度归一化 (播放端, 慢速增益 + 限幅)
+    client_limiter: bool,      // 输出软限幅 (默认开启)
+    client_loudness_target: f64, // 响度目标 (LUFS)
+    client_latency: LatencyProfile, // 抖动缓冲档位 (超低延迟 / 均衡 / 稳定)
+    client_auto_reconnect: bool, // 心跳超时后自动重连 (指数退避)
+    reconnect_cancel: Option<Arc<AtomicBool>>, // 重连进行中 (置位 = 用户断开, 停止重试)
+    reconnect_attempt: Option<u32>, // 当前重连次数 (RECONNECTING 事件)
+    client_connecting: Option<Option<ConnectPhase>>, // 连接进行中: 当前阶段 (Connecting 事件, None = 刚开始)
+    client_handshake_timeout: u64, // 连接 / 握手超时 (秒, 保存在设置中)
+    client_transport: Transport,   // UDP (组播) 或 TCP (经控制连接, 可靠但延迟更高)
+    server_session_min: String, // 服务器最长运行时间 (分钟, 空/0 = 不限)
+    client_session_min: String, // 客户端最长连接时间 (分钟, 空/0 = 不限)
+    client_motd: Option<String>, // 服务器欢迎横幅 (可关闭)
+    extra_clients: Vec<ExtraClient>, // 额外连接: 同时收听其他服务器 (各自的输出线程与 UDP 端口, 不自动重连)
+    extra_ip: String,          // 额外连接表单: 服务器 IP / 端口 / 输出设备 / PSK
+    extra_port: String,
+    extra_output: String,
+    extra_psk: String,
+    extra_connecting: bool,
+    next_extra_id: u64,
+    discovered: Vec<discovery::DiscoveredServer>, // 局域网扫描结果 (mDNS)
+    scanning: bool,
+    self_testing: bool, // 本机回环自检进行中
+    diagnostics: Option<(String, Vec<diagnose::Check>)>, // 网络诊断结果 (标题, 检查清单)
+    diagnosing: bool, // 客户端网络诊断进行中
+    codec: Codec,              // 组播编码 (默认 PCM 兼容旧客户端)
+    record_format: record::RecordFormat, // 录音格式 (默认 WAV 无损; MP3 需 `mp3` feature, 保存在设置中)
 }
 
 impl AppState {
     /// Collect initial devices, network interfaces and allocate buffer pool.
     fn new() -> Self {
-        let (inputs, outputs) = audio::list_devices()
+        // Loopback sources (host permitting) come after the real inputs, marked by their name
+        let (inputs, outputs) = audio::list_devices(audio::LOOPBACK_SUPPORTED)
             .map(|(i, o)| {
                 (
-                    i.into_iter().map(|d| audio::device_name(&d)).collect(),
+                    i.into_iter().map(|d| audio::capture_name(&d)).collect(),
                     o.into_iter().map(|d| audio::device_name(&d)).collect(),
                 )
             })
             .unwrap_or((vec![], vec![]));
-        let mut ips: Vec<String> = get_if_addrs::get_if_addrs()
-            .map(|ifs| {
-                let mut v: Vec<String> = ifs
-                    .into_iter()
-                    .filter_map(|i| {
-                        let ip = i.ip();
-                        if ip.is_ipv4() {
-                            Some(ip.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-                v.sort();
-                v.dedup();
-                v
-            })
-            .unwrap_or_else(|_| vec![]);
-        if !ips.iter().any(|s| s == "0.0.0.0") {
-            ips.insert(0, "0.0.0.0".into());
-        }
-        // 选择第一个既不是 0.0.0.0 也不是 127.0.0.1 的地址作为默认
-        let default_sel = ips
-            .iter()
-            .enumerate()
-            .find_map(|(i, ip)| {
-                if ip != "0.0.0.0" && ip != "127.0.0.1" {
-                    Some(i)
-                } else {
-                    None
-                }
-            })
-            .unwrap_or(0);
-        let port = crate::net::pick_free_port().unwrap_or(50000);
+        let (default_input, default_output) = audio::default_device_names();
+        let (ips, default_sel) = interface_ips(false);
+        // Saved settings: devices matched by name, saved port reused only while it is still free
+        let saved = config::load();
+        if let Some(code) = &saved.lang_fallback { lang::set_fallback_lang(code); }
+        if let Some(code) = &saved.lang { lang::reload_lang(code); }
+        let find = |list: &Vec<String>, name: &Option<String>| name.as_ref().filter(|n| list.contains(n)).or(list.first()).cloned().unwrap_or_default();
+        let (sel_input, sel_output) = (find(&inputs, &saved.input_device), find(&outputs, &saved.output_device));
+        let port = saved.server_port.filter(|&p| p >= SERVER_PORT_MIN && crate::net::tcp_port_free(p))
+            .unwrap_or_else(|| crate::net::pick_free_port_in(crate::net::DEFAULT_PORT_RANGE).unwrap_or(50000));
     let pool = AudioBufferPool::new(64);
+        let server_state = server::ServerState::new();
+        #[cfg(feature = "control-api")]
+        let api_state = std::sync::Arc::new(parking_lot::RwLock::new(server_state.clone()));
+        #[cfg(feature = "control-api")]
+        crate::api::start_from_env(api_state.clone());
         let (_tx, _rx) = unbounded::<usize>();
         Self {
-            current_lang: "zh".into(),
+            current_lang: saved.lang.clone().unwrap_or_else(|| "zh".into()),
+            lang_options: lang::available_langs().into_iter().map(|c| { let label = lang::lang_display(&c); (c, label) }).collect(),
+            log_level: log::max_level(),
             input_devices: inputs,
             output_devices: outputs,
-            sel_input: 0,
-            sel_output: 0,
+            default_input,
+            default_output,
+            sel_input,
+            sel_output: sel_output.clone(),
             server_ip_list: ips,
             sel_server_ip: default_sel,
+            sel_server_egress: 0,
+            server_mcast_port: String::new(),
+            server_room: saved.server_room,
+            server_ttl: 1,
+            server_max_clients: 0,
+            server_capture_frames: 0,
+            server_packet_frames: 0,
+            server_mtu: packetize::DEFAULT_MTU,
+            server_stream_rate: 0,
+            server_input_channels: String::new(),
+            server_ipv6: false,
             server_port: port,
+            server_port_text: port.to_string(),
             server_running: false,
-            server_state: server::ServerState::new(),
+            server_state,
+            #[cfg(feature = "control-api")]
+            api_state,
+            ptt_hotkey: None,
+            server_qr_visible: false,
             buffer_pool: pool,
             // previously used audio buffer notification channels (now managed server-side)
             client_state: None,
-            client_server_ip: String::new(),
-            client_server_port: String::new(),
+            client_server_ip: saved.client_ip,
+            client_server_port: saved.client_port,
             error_message: None,
+            toast: None,
             event_rx: None,
             metrics_tick: Instant::now(),
+            client_spectrum: Vec::new(),
+            extra_clients: Vec::new(),
+            extra_ip: String::new(),
+            extra_port: String::new(),
+            extra_output: sel_output,
+            extra_psk: String::new(),
+            extra_connecting: false,
+            next_extra_id: 0,
             mic_test_done: false,
             mic_available: false,
             net_test_done: false,
             net_available: false,
             server_psk: String::new(),
             client_psk: String::new(),
+            client_psk_required: false,
+            server_psk_file: None,
+            client_psk_file: None,
+            server_psk_remember: secret::has_keyring(PSK_ACCOUNT_SERVER),
+            server_per_client_keys: false,
+            client_psk_remember: secret::has_keyring(PSK_ACCOUNT_CLIENT),
+            server_psk_saved: secret::has_keyring(PSK_ACCOUNT_SERVER),
+            client_psk_saved: secret::has_keyring(PSK_ACCOUNT_CLIENT),
+            client_require_ack: true,
+            client_plc: true,
+            client_force_mono: false,
+            client_width: 100,
+            client_sync_start: false,
+            client_low_quality: false,
+            client_muted: false,
+            client_volume: 100,
+            client_loudness: false,
+            client_limiter: true,
+            client_loudness_target: -18.0,
+            client_latency: LatencyProfile::Balanced,
+            client_auto_reconnect: false,
+            reconnect_cancel: None,
+            reconnect_attempt: None,
+            client_connecting: None,
+            client_transport: saved.transport.as_deref().and_then(Transport::parse).unwrap_or_default(),
+            client_handshake_timeout: saved.handshake_timeout_secs.unwrap_or(client::HANDSHAKE_TIMEOUT_DEFAULT.as_secs()),
+            server_session_min: String::new(),
+            client_session_min: String::new(),
+            client_motd: None,
+            discovered: Vec::new(),
+            scanning: false,
+            self_testing: false,
+            diagnostics: None,
+            diagnosing: false,
+            codec: Codec::Pcm,
+            record_format: saved.record_format.as_deref().and_then(record::RecordFormat::parse).filter(|f| f.available()).unwrap_or_default(),
         }
     }
+
+    /// Push the per-connection client options onto a fresh connection (manual connect or reconnect).
+    fn apply_client_options(&mut self, cs: &client::ClientState) {
+        cs.plc_enabled.store(self.client_plc, Ordering::Relaxed);
+        cs.latency_profile.store(self.client_latency.as_u8(), Ordering::Relaxed);
+        cs.muted.store(self.client_muted, Ordering::Relaxed);
+        cs.output_gain.store(self.client_volume as f64 / 100.0);
+        cs.loudness.enabled.store(self.client_loudness, Ordering::Relaxed);
+        cs.output_limiter.store(self.client_limiter, Ordering::Relaxed);
+        cs.loudness.target_lufs.store(self.client_loudness_target);
+        cs.force_mono.store(self.client_force_mono, Ordering::Relaxed);
+        cs.stereo_width.store(self.client_width as f64 / 100.0);
+        cs.sync_start.store(self.client_sync_start, Ordering::Relaxed);
+        if self.client_low_quality { if let Err(e) = client::set_low_quality(cs, true) { log::warn!("[GUI] low-quality request failed: {e}"); } }
+        cs.session.limit_secs.store(session_limit_secs(&self.client_session_min), Ordering::Relaxed);
+    }
+
+    /// Main connection first, then the additional ones (options toggled while connected apply to all).
+    fn all_clients(&self) -> impl Iterator<Item = &client::ClientState> {
+        self.client_state.iter().chain(self.extra_clients.iter().map(|x| &x.state))
+    }
+
+    /// Queue the persisted subset of the state for saving (debounced in `config`).
+    fn save_settings(&self) {
+        config::save(config::Settings {
+            input_device: device_choice(&self.sel_input),
+            output_device: device_choice(&self.sel_output),
+            lang: Some(self.current_lang.clone()),
+            lang_fallback: Some(lang::fallback_lang()),
+            server_port: Some(self.server_port),
+            server_room: self.server_room.clone(),
+            client_ip: self.client_server_ip.clone(),
+            client_port: self.client_server_port.clone(),
+            handshake_timeout_secs: Some(self.client_handshake_timeout),
+            transport: Some(self.client_transport.as_str().to_string()),
+            log_level: Some(self.log_level.as_str().to_ascii_lowercase()),
+            record_format: Some(self.record_format.as_str().to_string()),
+        });
+    }
 }
 
 /// Root component constructing the full interface and spawning async refresh tasks.
@@ -188,7 +345,7 @@ fn app() -> Element {
         use_future(move || async move {
             let _ = trig_val; // silence unused
             // Microphone check: enumerate and open default input config
-            let mic_ok = match audio::list_devices() {
+            let mic_ok = match audio::list_devices(false) {
                 Ok((inputs, _)) => {
                     if let Some(dev) = inputs.into_iter().next() { dev.default_input_config().is_ok() } else { false }
                 }
@@ -237,18 +394,27 @@ fn app() -> Element {
                 // 尝试取出一个接收器（只取一次）
                 let rx_opt = { st_events.write().event_rx.take() };
                 if let Some(mut rx) = rx_opt {
-                    while let Some(msg) = rx.recv().await {
-                        if let Some(rest) = msg.strip_prefix("DISCONNECT:") {
-                            {
-                                let mut w = st_events.write();
-                                if w.error_message.is_none() {
-                                    w.error_message = Some(format!(
-                                        "{}{rest}",
-                                        lang::tr("client.disconnected.prefix")
-                                    ));
-                                }
-                                w.client_state = None; // 清理状态
+                    while let Some(ev) = rx.recv().await {
+                        match ev {
+                            ClientEvent::Motd(text) => st_events.write().client_motd = Some(text),
+                            ClientEvent::Disconnected(reason) => client_lost(st_events, &reason),
+                            ClientEvent::TimedOut(reason) => {
+                                // 心跳超时: 开启自动重连时复用原连接参数重试，否则按断开处理
+                                let retry = { let r = st_events.read(); if r.client_auto_reconnect { r.client_state.as_ref().and_then(|cs| cs.target.clone().map(|t| (t, cs.event_sender.clone()))) } else { None } };
+                                match retry { Some((target, events)) => start_reconnect(st_events, target, events), None => client_lost(st_events, &reason) }
+                            }
+                            // 服务器不可用 (协议版本过新): 连接调用本身也会失败, 这里只补充说明
+                            ClientEvent::Error(msg) => { let mut w = st_events.write(); if w.error_message.is_none() { w.error_message = Some(msg); } }
+                            // 输出设备被拔出: 已切到系统默认输出 (或无可用设备, 播放停止)
+                            ClientEvent::OutputLost { old, new } => st_events.write().error_message = Some(output_lost_text(&old, new.as_deref())),
+                            ClientEvent::Reconnecting(n) => st_events.write().reconnect_attempt = Some(n),
+                            ClientEvent::Connecting(phase) => {
+                                // 仅在手动连接仍在进行时更新 (事件可能晚于连接结果到达)
+                                let mut w = st_events.write(); if w.client_connecting.is_some() { w.client_connecting = Some(Some(phase)); }
                             }
+                            // 服务器运行中切换了输入设备: 更新显示的音频参数
+                            ClientEvent::Params(params) => if let Some(cs) = st_events.write().client_state.as_mut() { cs.params = Some(params); },
+                            ClientEvent::Connected | ClientEvent::SessionWarning(_) | ClientEvent::OutputRestart(_) | ClientEvent::BufferReady { .. } | ClientEvent::ChannelsChanged { .. } => {}
                         }
                     }
                 } else {
@@ -274,15 +440,38 @@ fn app() -> Element {
         .as_ref()
         .map(|c| c.connected.load(Ordering::Relaxed))
         .unwrap_or(false);
+    let reconnecting = st.read().reconnect_cancel.is_some();
+    let connecting = st.read().client_connecting.is_some();
     let mut st_clone = st.clone();
     // metrics 100ms refresh loop
     {
         let mut st_metrics = st.clone();
         use_future(move || async move {
+            let mut spectrum = spectrum::Spectrum::new(); let mut refresh: u32 = 0;
             loop {
                 tokio::time::sleep(Duration::from_millis(100)).await;
                 // Just trigger rerender
-                st_metrics.write().metrics_tick = Instant::now();
+                let mut w = st_metrics.write();
+                w.metrics_tick = Instant::now();
+                if w.toast.as_ref().is_some_and(|(_, at)| at.elapsed() >= TOAST_DURATION) { w.toast = None; }
+                // Client spectrum: FFT here on the UI task, the receive thread only fills the scope ring.
+                // Shed under CPU pressure (`Optional::Spectrum`): recompute every 4th refresh, bars hold in between
+                refresh = refresh.wrapping_add(1);
+                let shed = w.client_state.as_ref().is_some_and(|cs| cs.spectrum_shed.load(Ordering::Relaxed));
+                if !shed || refresh & 3 == 0 {
+                    let bars = w.client_state.as_ref().map(|cs| {
+                        let rate = cs.stream_rate.load(Ordering::Relaxed);
+                        let rate = if rate > 0 { rate } else { cs.params.as_ref().map_or(48_000, |p| p.sample_rate) };
+                        spectrum.update(&cs.scope.snapshot(), rate).to_vec()
+                    });
+                    if bars.is_none() { spectrum.reset(); }
+                    w.client_spectrum = bars.unwrap_or_default();
+                }
+                // Server may be stopped externally (control API) -> keep buttons in sync
+                if w.server_running && !w.server_state.running.load(Ordering::Relaxed) { w.server_running = false; }
+                // Input device unplugged while capturing (the capture thread keeps trying the default input)
+                let lost_input = w.server_state.input_lost.lock().take();
+                if let Some(name) = lost_input { w.error_message = Some(lang::tr_args("server.input_lost", &[("name", &name)])); }
             }
         });
     }
@@ -311,6 +500,28 @@ fn app() -> Element {
                         }
                     }
                 )) }
+                { st.read().diagnostics.as_ref().map(|(title, checks)| { let checks = checks.clone(); rsx!(
+                    div { style: "position:fixed;inset:0;display:flex;align-items:center;justify-content:center;background:rgba(0,0,0,0.55);z-index:999;",
+                        div { style: "background:#1e1e1e;padding:16px 20px;border-radius:8px;min-width:360px;max-width:520px;box-shadow:0 4px 18px rgba(0,0,0,0.6);display:flex;flex-direction:column;gap:8px;color:#ddd;",
+                            h3 { style: "margin:0 0 4px 0;font-size:16px;", "{title}" }
+                            { checks.into_iter().enumerate().map(|(i, c)| {
+                                let (mark, color) = match c.outcome { diagnose::Outcome::Passed => ("✔", "#5cb85c"), diagnose::Outcome::Failed => ("✘", "#ff5555"), diagnose::Outcome::Skipped => ("–", "#777") };
+                                let ms = c.elapsed.map(|d| format!("{:.1} ms", d.as_secs_f64() * 1000.0)).unwrap_or_default();
+                                rsx!( div { key: "diag{i}", style: "display:grid;grid-template-columns:18px 1fr auto;column-gap:8px;font-size:12px;",
+                                    span { style: "color:{color};", "{mark}" }
+                                    div { span { {tr(c.key)} } if !c.detail.is_empty() { div { style: "color:#888;font-size:11px;white-space:pre-wrap;", "{c.detail}" } } }
+                                    span { style: "color:#aaa;", "{ms}" }
+                                } )
+                            }) }
+                            div { style: "display:flex;justify-content:flex-end;gap:8px;margin-top:4px;",
+                                button { style:"background:#333;color:#eee;border:1px solid #555;padding:6px 14px;border-radius:4px;cursor:pointer;", onclick: move |_| { st.write().diagnostics=None; }, "OK" }
+                            }
+                        }
+                    }
+                ) }) }
+                { st.read().toast.as_ref().map(|(msg, _)| rsx!(
+                    div { style: "position:fixed;right:16px;bottom:16px;max-width:360px;background:#1e1e1e;border:1px solid #555;border-radius:6px;padding:8px 12px;font-size:12px;color:#ddd;box-shadow:0 4px 18px rgba(0,0,0,0.6);z-index:998;", "{msg}" }
+                )) }
                 // Settings panel
                 div { class: "panel", style: panel_style(),
                     // floating title
@@ -337,14 +548,36 @@ fn app() -> Element {
                         div { style: "display:flex;flex-direction:column;gap:10px;",
                             div { style: "display:flex;align-items:center;gap:8px;", 
                                 span { style: "font-size:12px;color:#bbb;display:inline-block;width:90px;", {tr("audio.input_device")} }
-                                select { value: st.read().sel_input.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v)=e.value().parse::<usize>() { st.write().sel_input=v; } },
-                                    { st.read().input_devices.iter().enumerate().map(|(i,name)| rsx!( option { key: "in{i}", value: i.to_string(), "{name}" } )) }
+                                select { value: st.read().sel_input.clone(), disabled: st.read().server_state.follow_default_input.load(Ordering::Relaxed), oninput: move |e| {
+                                        let name = e.value();
+                                        let mut w = st.write(); w.sel_input = name.clone(); w.save_settings();
+                                        // 运行中: 不重启服务器, 由采集线程切换设备
+                                        if w.server_running { if let Err(e) = server::switch_input_device(&w.server_state, name) { log::warn!("[SERVER][INPUT] switch failed: {e:#}"); } }
+                                    },
+                                    { let def = st.read().default_input.clone(); st.read().input_devices.iter().enumerate().map(|(i, name)| { let label = device_label(name, def.as_deref()); rsx!( option { key: "in{i}", value: "{name}", "{label}" } ) }).collect::<Vec<_>>().into_iter() }
                                 }
                             }
+                            div { style: "display:flex;align-items:center;gap:6px;padding-left:98px;",
+                                { let follow = st.read().server_state.follow_default_input.clone(); let checked = follow.load(Ordering::Relaxed);
+                                  rsx!( input { r#type: "checkbox", checked: checked, oninput: move |e| { follow.store(e.checked(), Ordering::Relaxed); } } ) }
+                                span { style: "font-size:12px;color:#bbb;", { tr("audio.follow_default_input") } }
+                            }
+                            // Capture gain (dB), applies live while capturing
+                            div { style: "display:flex;align-items:center;gap:8px;",
+                                span { style: "font-size:12px;color:#bbb;display:inline-block;width:90px;", {tr("audio.input_gain")} }
+                                { let gain = st.read().server_state.gain.clone(); let db = 20.0 * gain.load().log10(); let agc_on = st.read().server_state.agc.enabled.load(Ordering::Relaxed);
+                                  rsx!(
+                                    input { r#type: "range", min: "{GAIN_DB_MIN}", max: "{GAIN_DB_MAX}", step: "0.5", value: "{db}", disabled: agc_on, title: if agc_on { tr("server.agc.overrides") } else { String::new() }, style: "flex:1;", oninput: move |e| { if let Ok(v)=e.value().parse::<f64>() { gain.store(10f64.powf(v.clamp(GAIN_DB_MIN, GAIN_DB_MAX) / 20.0)); } } }
+                                    span { style: "font-size:11px;color:#aaa;width:56px;text-align:right;", "{db:+.1} dB" }
+                                  ) }
+                                { // capture mute: frames keep flowing (silent payload) so clients stay in sync
+                                  let muted = st.read().server_state.muted.clone(); let on = muted.load(Ordering::Relaxed);
+                                  rsx!( button { style: if on { "background:#8a2a2a;" } else { "" }, onclick: move |_| { muted.fetch_xor(true, Ordering::Relaxed); }, { if on { tr("audio.unmute") } else { tr("audio.mute") } } } ) }
+                            }
                             div { style: "display:flex;align-items:center;gap:8px;", 
                                 span { style: "font-size:12px;color:#bbb;display:inline-block;width:90px;", {tr("audio.output_device")} }
-                                select { value: st.read().sel_output.to_string(), disabled: connected, oninput: move |e| { if let Ok(v)=e.value().parse::<usize>() { st.write().sel_output=v; } },
-                                    { st.read().output_devices.iter().enumerate().map(|(i,name)| rsx!( option { key: "out{i}", value: i.to_string(), "{name}" } )) }
+                                select { value: st.read().sel_output.clone(), disabled: connected, oninput: move |e| { let mut w = st.write(); w.sel_output = e.value(); w.save_settings(); },
+                                    { let def = st.read().default_output.clone(); st.read().output_devices.iter().enumerate().map(|(i, name)| { let label = device_label(name, def.as_deref()); rsx!( option { key: "out{i}", value: "{name}", "{label}" } ) }).collect::<Vec<_>>().into_iter() }
                                 }
                             }
                         }
@@ -352,7 +585,7 @@ fn app() -> Element {
                         div { style: "display:flex;flex-direction:column;gap:10px;",
                             button { style: "width:100%;", onclick: move |_| {
                                 let msg = tr("dialog.virtual_mic");
-                                std::thread::spawn(move || {
+                                let _ = std::thread::Builder::new().name("rm-dialog".into()).spawn(move || {
                                     let _ = rfd::MessageDialog::new()
                                         .set_title("Info")
                                         .set_description(msg)
@@ -361,21 +594,80 @@ fn app() -> Element {
                                         .show();
                                 });
                             }, { tr("audio.install_virtual_mic") } }
+                            // Loopback self test: local server + client streaming the test tone (blocks ~2s: off the UI thread)
+                            button { style: "width:100%;", title: tr("selftest.hint"), disabled: st.read().self_testing, onclick: move |_| {
+                                    st.write().self_testing = true;
+                                    let output = device_choice(&st.read().sel_output);
+                                    let (tx, rx) = tokio::sync::oneshot::channel();
+                                    let _ = std::thread::Builder::new().name("rm-selftest".into()).spawn(move || { let _ = tx.send(selftest::run(output)); });
+                                    spawn(async move {
+                                        let res = rx.await.unwrap_or_else(|_| Err(anyhow::anyhow!("self test thread exited")));
+                                        st.write().self_testing = false;
+                                        let (msg, level) = self_test_message(res);
+                                        let _ = std::thread::Builder::new().name("rm-dialog".into()).spawn(move || {
+                                            let _ = rfd::MessageDialog::new().set_title(tr("selftest.title")).set_description(msg).set_level(level).set_buttons(rfd::MessageButtons::Ok).show();
+                                        });
+                                    });
+                                }, { if st.read().self_testing { tr("selftest.running") } else { tr("selftest.button") } } }
                             div { style: "display:flex;align-items:center;gap:8px;", 
                                 span { style: "font-size:12px;color:#bbb;", {tr("lang.current")} }
                                 select { value: st.read().current_lang.clone(), oninput: move |e| {
                                         let new = e.value().to_string();
                                         if new != st.read().current_lang {
                                             lang::reload_lang(&new);
-                                            st.write().current_lang = new;
+                                            let mut w = st.write(); w.current_lang = new; w.save_settings(); drop(w);
                                             let title = lang::tr("app.title");
                                             window.set_title(&title);
                                         }
                                     },
-                                    { let list = lang::available_langs(); rsx!( { list.into_iter().map(|c| {
-                                            let label = lang::lang_display(&c);
-                                            rsx!( option { value: "{c}", "{label}" } )
-                                        }) } ) }
+                                    { st.read().lang_options.iter().map(|(c, label)| rsx!( option { key: "{c}", value: "{c}", "{label}" } )) }
+                                }
+                            }
+                            // Log level (live) + log file path for bug reports
+                            div { style: "display:flex;align-items:center;gap:8px;",
+                                span { style: "font-size:12px;color:#bbb;", title: tr("log.level.hint"), {tr("log.level")} }
+                                select { value: st.read().log_level.as_str().to_ascii_lowercase(), oninput: move |e| {
+                                        if let Some(level) = logging::parse_level(&e.value()) { logging::set_level(level); let mut w = st.write(); w.log_level = level; w.save_settings(); }
+                                    },
+                                    { logging::LEVELS.iter().map(|l| { let name = l.as_str().to_ascii_lowercase(); rsx!( option { key: "{name}", value: "{name}", "{name}" } ) }) }
+                                }
+                                if let Some(path) = logging::log_path() {
+                                    button { title: path.display().to_string(), onclick: move |_| {
+                                            match arboard::Clipboard::new().and_then(|mut c| c.set_text(path.display().to_string())) {
+                                                Ok(()) => st.write().toast = Some((tr("log.path_copied"), Instant::now())),
+                                                Err(e) => st.write().error_message = Some(format!("{}: {e}", tr("server.share.copy_failed"))),
+                                            }
+                                        }, { tr("log.copy_path") } }
+                                }
+                            }
+                            // Recording format (server and client record buttons); only shown when MP3 is compiled in
+                            if record::RecordFormat::Mp3.available() {
+                                div { style: "display:flex;align-items:center;gap:8px;",
+                                    span { style: "font-size:12px;color:#bbb;", title: tr("record.format.hint"), {tr("record.format")} }
+                                    select { value: st.read().record_format.as_str(), oninput: move |e| {
+                                            if let Some(f) = record::RecordFormat::parse(&e.value()) { let mut w = st.write(); w.record_format = f; w.save_settings(); }
+                                        },
+                                        { record::RecordFormat::ALL.iter().filter(|f| f.available()).map(|f| rsx!( option { key: "{f.as_str()}", value: f.as_str(), {f.label()} } )) }
+                                    }
+                                }
+                            }
+                            // Multicast codec (switchable live; Opus falls back to PCM while any client lacks support)
+                            div { style: "display:flex;align-items:center;gap:8px;",
+                                span { style: "font-size:12px;color:#bbb;", {tr("audio.codec")} }
+                                select { value: st.read().codec.as_str(), oninput: move |e| {
+                                        if let Some(c) = Codec::parse(&e.value()) { let mut w = st.write(); w.codec = c; w.server_state.codec.store(c.as_u8(), Ordering::Relaxed); }
+                                    },
+                                    option { value: Codec::Pcm.as_str(), {tr("audio.codec.pcm")} }
+                                    option { value: Codec::Opus.as_str(), {tr("audio.codec.opus")} }
+                                }
+                                // XOR parity every k frames (live; clients read k from each parity frame)
+                                span { style: "font-size:12px;color:#bbb;", title: tr("audio.fec.hint"), {tr("audio.fec")} }
+                                select { value: st.read().server_state.fec_k.load(Ordering::Relaxed).to_string(), oninput: move |e| {
+                                        if let Ok(k) = e.value().parse::<u8>() { st.read().server_state.fec_k.store(k, Ordering::Relaxed); }
+                                    },
+                                    option { value: "0", {tr("audio.fec.off")} }
+                                    option { value: "4", "K=4" }
+                                    option { value: "8", "K=8" }
                                 }
                             }
                         }
@@ -401,15 +693,130 @@ fn app() -> Element {
                                 if st.read().server_running {
                                     button { onclick: move |_| { let srv_state = st.read().server_state.clone(); server::stop_server(&srv_state); st.write().server_running=false; }, {tr("server.stop")} }
                                 }
+                                // Dry run of the bind steps (quick: runs inline)
+                                button { title: tr("diag.server.hint"), onclick: move |_| {
+                                        let res = diagnose_server(&st.read());
+                                        let mut w = st.write();
+                                        match res { Ok(checks) => w.diagnostics = Some((tr("diag.server.title"), checks)), Err(e) => w.error_message = Some(format!("{e}")) }
+                                    }, {tr("diag.button")} }
                             }
                             // Row 2: Port
                             span { style: "font-size:12px;color:#bbb;", {tr("server.port")} }
-                            input { style: "width:60px;", readonly: true, value: st.read().server_port.to_string(), oninput: move |e| { if let Ok(v)=e.value().parse() { st.write().server_port=v; } } }
-                            div {} // 占位: 让下一行从新行开始
+                            { let valid = parse_server_port(&st.read().server_port_text).is_some();
+                              rsx!(input { style: if valid { "width:60px;" } else { "width:60px;border-color:#d9534f;" }, maxlength: "5", title: tr("server.port.hint"), disabled: st.read().server_running, value: st.read().server_port_text.clone(), oninput: move |e| {
+                                      let text: String = e.value().chars().filter(|c| c.is_ascii_digit()).take(5).collect();
+                                      let mut w = st.write();
+                                      if let Some(p) = parse_server_port(&text) { w.server_port = p; w.save_settings(); }
+                                      w.server_port_text = text;
+                                  } }) }
+                            // IPv6: re-list interface addresses and use an IPv6 multicast group (fixed while running)
+                            div { style: "display:flex;align-items:center;gap:6px;font-size:12px;color:#bbb;",
+                                input { r#type: "checkbox", checked: st.read().server_ipv6, disabled: st.read().server_running, oninput: move |e| {
+                                        let (ips, sel) = interface_ips(e.checked());
+                                        let mut w = st.write(); w.server_ipv6 = e.checked(); w.server_ip_list = ips; w.sel_server_ip = sel; w.sel_server_egress = 0;
+                                    } }
+                                span { { tr("server.ipv6") } }
+                            }
                             // Row 3: PSK (3 cells -> label, input, placeholder)
                             span { style: "font-size:12px;color:#bbb;", { tr("server.psk") } }
-                            input { style: "width:130px;", r#type: "password", placeholder: "(可选)", value: st.read().server_psk.clone(), disabled: st.read().server_running, oninput: move |e| { st.write().server_psk = e.value().to_string(); } }
+                            input { style: "width:130px;", r#type: "password", placeholder: psk_placeholder(&st.read().server_psk_file, st.read().server_psk_saved), value: st.read().server_psk.clone(), disabled: st.read().server_running || st.read().server_psk_file.is_some() || st.read().server_per_client_keys, oninput: move |e| { st.write().server_psk = e.value().to_string(); } }
+                            div { style: "display:flex;align-items:center;gap:6px;font-size:12px;color:#bbb;",
+                                if st.read().server_psk_file.is_none() {
+                                    button { title: tr("psk.from_file"), disabled: st.read().server_running, onclick: move |_| { if let Some(p) = rfd::FileDialog::new().pick_file() { st.write().server_psk_file = Some(p); } }, "📄" }
+                                } else {
+                                    button { disabled: st.read().server_running, onclick: move |_| { st.write().server_psk_file = None; }, "×" }
+                                }
+                                input { r#type: "checkbox", checked: st.read().server_psk_remember, disabled: st.read().server_running, oninput: move |e| { let mut w = st.write(); w.server_psk_remember = e.checked(); if !e.checked() { forget_psk(&mut w.server_psk_saved, PSK_ACCOUNT_SERVER); } } }
+                                span { { tr("psk.remember") } }
+                                input { r#type: "checkbox", checked: st.read().server_per_client_keys, disabled: st.read().server_running, oninput: move |e| { st.write().server_per_client_keys = e.checked(); } }
+                                span { title: tr("server.per_client_keys.hint"), { tr("server.per_client_keys") } }
+                            }
+                            // Row 4: max session duration (minutes)
+                            span { style: "font-size:12px;color:#bbb;", { tr("session.limit_min") } }
+                            input { style: "width:60px;", placeholder: "0", maxlength: "4", value: st.read().server_session_min.clone(), disabled: st.read().server_running, oninput: move |e| { st.write().server_session_min = e.value().chars().filter(|c| c.is_ascii_digit()).collect(); } }
                             div {}
+                            // Row 5: banner shown to clients after connecting (editable while running; applies to new connections)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.motd") } }
+                            { let motd = st.read().server_state.motd.clone(); let text = motd.lock().clone();
+                              rsx!( textarea { style: "width:160px;height:36px;resize:vertical;font-size:12px;", placeholder: tr("server.motd.placeholder"), value: "{text}", oninput: move |e| { *motd.lock() = e.value(); } } ) }
+                            div {}
+                            // Row 6: test signal instead of the input device (setup checks without talking)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.signal") } }
+                            { let signal = st.read().server_state.signal.clone(); let cur = signal.load(Ordering::Relaxed);
+                              rsx!( select { style: "width:130px;", value: cur.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v) = e.value().parse::<u8>() { signal.store(replay::SignalSource::from_u8(v).as_u8(), Ordering::Relaxed); } },
+                                  { replay::SignalSource::ALL.iter().map(|s| rsx!( option { key: "sig{s.as_u8()}", value: s.as_u8().to_string(), { tr(s.label_key()) } } )) }
+                              } ) }
+                            span { style: "font-size:11px;color:#888;", { tr("server.signal.hint") } }
+                            // Row 7: diagnostic replay (WAV instead of input device)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.replay") } }
+                            { let srv = st.read().server_state.clone(); let running = st.read().server_running;
+                              let file_lbl = srv.replay_path.lock().as_ref().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| tr("server.replay.pick"));
+                              let has_file = srv.replay_path.lock().is_some(); let looping = srv.replay_loop.load(Ordering::Relaxed);
+                              let normalize = srv.replay_normalize.load(Ordering::Relaxed);
+                              let (srv_pick, srv_clear, srv_loop, srv_norm) = (srv.clone(), srv.clone(), srv.clone(), srv.clone());
+                              rsx!(
+                                button { style: "max-width:130px;overflow:hidden;text-overflow:ellipsis;white-space:nowrap;", disabled: running, onclick: move |_| {
+                                        if let Some(p) = rfd::FileDialog::new().add_filter("WAV", &["wav"]).pick_file() { *srv_pick.replay_path.lock() = Some(p); }
+                                    }, "{file_lbl}" }
+                                div { style: "display:flex;align-items:center;gap:6px;font-size:12px;color:#bbb;",
+                                    if has_file {
+                                        input { r#type: "checkbox", checked: looping, disabled: running, oninput: move |e| { srv_loop.replay_loop.store(e.checked(), Ordering::Relaxed); } }
+                                        span { { tr("server.replay.loop") } }
+                                        input { r#type: "checkbox", checked: normalize, disabled: running, oninput: move |e| { srv_norm.replay_normalize.store(e.checked(), Ordering::Relaxed); } }
+                                        span { { tr("server.replay.normalize") } }
+                                        button { disabled: running, onclick: move |_| { *srv_clear.replay_path.lock() = None; }, "×" }
+                                    }
+                                }
+                              ) }
+                            // Row 8: multicast TTL (IPv4 hops; 1 = local subnet only)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.ttl") } }
+                            input { style: "width:60px;", r#type: "number", min: "1", max: "255", value: st.read().server_ttl.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v) = e.value().trim().parse::<u32>() { st.write().server_ttl = v.clamp(1, 255); } } }
+                            span { style: "font-size:11px;color:#888;", { tr("server.ttl.hint") } }
+                            // Row 9: client limit (0 = unlimited)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.max_clients") } }
+                            input { style: "width:60px;", r#type: "number", min: "0", max: "999", value: st.read().server_max_clients.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v) = e.value().trim().parse::<usize>() { st.write().server_max_clients = v.min(999); } } }
+                            span { style: "font-size:11px;color:#888;", { tr("server.max_clients.hint") } }
+                            // Row 10: egress interface for audio (multihomed hosts; control still listens on the bind address)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.egress") } }
+                            select { style: "width:130px;", value: st.read().sel_server_egress.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v)=e.value().parse::<usize>() { st.write().sel_server_egress=v; } },
+                                option { value: "0", { tr("server.egress.same") } }
+                                { st.read().server_ip_list.iter().enumerate().map(|(i,ip)| rsx!( option { key: "eg{i}", value: (i + 1).to_string(), "{ip}" } )) }
+                            }
+                            span { style: "font-size:11px;color:#888;", { tr("server.egress.hint") } }
+                            // Row 11: multicast UDP port (empty = control port, or a free one if its UDP side is taken)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.mcast_port") } }
+                            input { style: "width:60px;", placeholder: tr("server.mcast_port.auto"), maxlength: "5", value: st.read().server_mcast_port.clone(), disabled: st.read().server_running, oninput: move |e| { st.write().server_mcast_port = e.value().chars().filter(|c| c.is_ascii_digit()).collect(); } }
+                            span { style: "font-size:11px;color:#888;", { tr("server.mcast_port.hint") } }
+                            // Row 11b: room name (same room = same multicast group every launch; empty = random group)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.room") } }
+                            input { style: "width:130px;", placeholder: tr("server.room.random"), maxlength: "64", value: st.read().server_room.clone(), disabled: st.read().server_running, oninput: move |e| { let mut w = st.write(); w.server_room = e.value(); w.save_settings(); } }
+                            span { style: "font-size:11px;color:#888;", { tr("server.room.hint") } }
+                            // Row 12: capture frames per callback (smaller = less latency, more CPU)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.buffer_frames") } }
+                            select { style: "width:130px;", value: st.read().server_capture_frames.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v)=e.value().parse::<u32>() { st.write().server_capture_frames=v; } },
+                                { audio::CAPTURE_FRAME_CHOICES.iter().map(|&n| rsx!( option { key: "bf{n}", value: n.to_string(), { if n == 0 { tr("server.buffer_frames.default") } else { n.to_string() } } } )) }
+                            }
+                            span { style: "font-size:11px;color:#888;", { tr("server.buffer_frames.hint") } }
+                            // Row 12b: input channels to capture (e.g. "3" = mono from channel 3 of a multi-input interface)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.input_channels") } }
+                            input { style: "width:130px;", placeholder: tr("server.input_channels.all"), maxlength: "32", value: st.read().server_input_channels.clone(), disabled: st.read().server_running, oninput: move |e| { st.write().server_input_channels = e.value().chars().filter(|c| c.is_ascii_digit() || *c == ',' || *c == ' ').collect(); } }
+                            span { style: "font-size:11px;color:#888;", { tr("server.input_channels.hint") } }
+                            // Row 12b2: stream sample rate (capture resampled before sending; clients all receive this rate)
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.stream_rate") } }
+                            select { style: "width:130px;", value: st.read().server_stream_rate.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v)=e.value().parse::<u32>() { st.write().server_stream_rate=v; } },
+                                { audio::STREAM_RATE_CHOICES.iter().map(|&r| rsx!( option { key: "sr{r}", value: r.to_string(), { if r == 0 { tr("server.stream_rate.capture") } else { format!("{r} Hz") } } } )) }
+                            }
+                            span { style: "font-size:11px;color:#888;", { tr("server.stream_rate.hint") } }
+                            // Row 12c: PCM frames per packet (regrouped from capture buffers) and the datagram ceiling they must fit
+                            span { style: "font-size:12px;color:#bbb;", { tr("server.packet_frames") } }
+                            div { style: "display:flex;align-items:center;gap:6px;",
+                                select { style: "width:80px;", value: st.read().server_packet_frames.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v)=e.value().parse::<u32>() { st.write().server_packet_frames=v; } },
+                                    { packetize::PACKET_FRAME_CHOICES.iter().map(|&n| rsx!( option { key: "pf{n}", value: n.to_string(), { if n == 0 { tr("server.packet_frames.capture") } else { n.to_string() } } } )) }
+                                }
+                                span { style: "font-size:12px;color:#bbb;", "MTU" }
+                                input { style: "width:50px;", r#type: "number", min: packetize::MIN_MTU.to_string(), max: "9000", value: st.read().server_mtu.to_string(), disabled: st.read().server_running, oninput: move |e| { if let Ok(v) = e.value().trim().parse::<u16>() { st.write().server_mtu = v.clamp(packetize::MIN_MTU, 9000); } } }
+                            }
+                            span { style: "font-size:11px;color:#888;", { tr("server.packet_frames.hint") } }
                         }
                         // Server metrics panel (audio params + volume + clients)
                         { let server_running = st.read().server_running; let srv_state = st.read().server_state.clone();
@@ -418,12 +825,32 @@ fn app() -> Element {
                               let rms = srv_state.current_rms.load();
                               let db = if rms>0.0 { 20.0 * rms.log10() } else { -60.0 }; let norm = (rms.sqrt()).min(1.0);
                               let now = Instant::now();
-                              let clients: Vec<(String, Option<u16>, u64)> = srv_state.clients.iter().map(|c| { let age = now.duration_since(c.last_seen).as_secs(); (c.addr.to_string(), c.udp_port, age) }).collect();
+                              let clients: Vec<_> = srv_state.clients.iter().map(|c| { let age = now.duration_since(c.last_seen).as_secs(); (c.addr, c.udp_port, c.substream, c.want, age) }).collect();
+                              let stream_fmt = srv_state.stream_params().map(|p| (p.sample_rate, p.channels));
                               rsx!(div { style: "margin-top:8px;padding:8px;border:1px solid #2e2e2e;border-radius:6px;display:flex;flex-direction:column;gap:6px;background:#181818;",
                                   div { style: "font-size:12px;font-weight:600;color:#bbb;", { tr("server.metrics.title") } }
-                                  { if let Some(p)=params_opt { let fmt_str = match p.sample_format { cpal::SampleFormat::F32=>"f32", cpal::SampleFormat::I16=>"i16", cpal::SampleFormat::U16=>"u16", _=>"f32"}; let enc_active = st.read().server_state.key_bytes.is_some(); let enc_lbl = if enc_active { tr("enc.enabled") } else { tr("enc.disabled") }; rsx!(div { style: "font-size:11px;color:#aaa;display:flex;flex-wrap:wrap;gap:12px;align-items:center;",
-                                      span { { format!("SR:{}", p.sample_rate) } }
-                                      span { { format!("CH:{}", p.channels) } }
+                                  { // share: copy the connection address (`?enc=1` when a PSK is needed) or show it as a QR code
+                                    let psk = srv_state.psk.is_some();
+                                    let url = share_ip(&st.read().server_ip_list, st.read().sel_server_ip).map(|ip| net::connection_url(ip, st.read().server_port, psk));
+                                    let qr = st.read().server_qr_visible;
+                                    url.map(|url| { let svg = if qr { qr_svg(&url) } else { None }; let copy_url = url.clone();
+                                      rsx!(div { style: "display:flex;flex-direction:column;gap:6px;font-size:11px;color:#aaa;",
+                                        div { style: "display:flex;align-items:center;gap:8px;",
+                                          span { style: "font-family:monospace;color:#ccc;user-select:text;", "{url}" }
+                                          if psk { span { style: "padding:2px 6px;border-radius:4px;background:#555;color:#fff;font-size:10px;", { tr("server.share.psk") } } }
+                                          button { title: tr("server.share.copy.hint"), onclick: move |_| {
+                                                  match arboard::Clipboard::new().and_then(|mut c| c.set_text(copy_url.clone())) {
+                                                      Ok(()) => st.write().toast = Some((tr("server.share.copied"), Instant::now())),
+                                                      Err(e) => st.write().error_message = Some(format!("{}: {e}", tr("server.share.copy_failed"))),
+                                                  }
+                                              }, { tr("server.share.copy") } }
+                                          button { onclick: move |_| { let v = !qr; st.write().server_qr_visible = v; }, { tr(if qr { "server.share.qr.hide" } else { "server.share.qr" }) } }
+                                        }
+                                        { svg.map(|svg| rsx!(div { style: "align-self:flex-start;padding:6px;background:#fff;border-radius:4px;line-height:0;", dangerous_inner_html: svg })) }
+                                      }) }) }
+                                  { if let Some(p)=params_opt { let fmt_str = match p.sample_format { cpal::SampleFormat::F32=>"f32", cpal::SampleFormat::I16=>"i16", cpal::SampleFormat::U16=>"u16", cpal::SampleFormat::I32=>"i32", cpal::SampleFormat::F64=>"f64", _=>"f32"}; let enc_active = st.read().server_state.encrypted(); let enc_lbl = if enc_active { tr("enc.enabled") } else { tr("enc.disabled") }; rsx!(div { style: "font-size:11px;color:#aaa;display:flex;flex-wrap:wrap;gap:12px;align-items:center;",
+                                      span { { if srv_state.output_rate > 0 && srv_state.output_rate != p.sample_rate { format!("SR:{}→{}", p.sample_rate, srv_state.output_rate) } else { format!("SR:{}", p.sample_rate) } } }
+                                      span { { format!("CH:{} ({})", p.channels, channel_layout_label(p.channels)) } }
                                       span { { format!("FMT:{}", fmt_str) } }
                                       span { style: format!("padding:2px 6px;border-radius:4px;background:{};color:#fff;font-size:10px;letter-spacing:.5px;", if enc_active { "#216e39" } else { "#555" }), "{enc_lbl}" }
                                   }) } else { rsx!(div { style: "font-size:11px;color:#666;", { tr(status_key) } }) } }
@@ -436,11 +863,122 @@ fn app() -> Element {
                                       span { style: "font-size:11px;width:70px;text-align:right;color:#ccc;", { format!("{:.3} RMS", rms) } }
                                       span { style: "font-size:11px;width:60px;text-align:right;color:#ccc;", { format!("{:.1} dB", db) } }
                                   }) }
+                                  { channel_meter_rows(srv_state.channel_rms.levels(), 70) }
+                                  { scope_view(srv_state.scope.snapshot()) }
+                                  // Network send rate (bytes actually written: ciphertext, FEC parity and unicast / TCP copies included)
+                                  div { style: "font-size:11px;color:#aaa;", title: tr("server.metrics.bitrate.hint"), { format!("{}: {:.1} kbps ({:.0} {})", tr("server.metrics.bitrate"), srv_state.send_kbps.load(), srv_state.send_pps.load(), tr("server.metrics.pps")) } }
+                                  { // noise gate: capture chunks below the threshold are sent as silence (hold avoids chatter)
+                                    let g = srv_state.gate.clone(); let on = g.enabled.load(Ordering::Relaxed); let thr = g.threshold_db.load(); let hold = g.hold_ms.load(Ordering::Relaxed); let closed = on && !g.open.load(Ordering::Relaxed);
+                                    let (g_on, g_thr, g_hold) = (g.clone(), g.clone(), g);
+                                    rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                      span { style: "font-size:12px;min-width:70px;color:#bbb;display:flex;align-items:center;gap:4px;", title: tr("server.gate.hint"),
+                                          input { r#type: "checkbox", checked: on, oninput: move |e| { g_on.enabled.store(e.checked(), Ordering::Relaxed); } }
+                                          { tr("server.gate") } }
+                                      input { r#type: "range", min: "{gate::THRESHOLD_DB_MIN}", max: "{gate::THRESHOLD_DB_MAX}", step: "1", value: "{thr}", disabled: !on, style: "flex:1;", oninput: move |e| { if let Ok(v)=e.value().parse::<f64>() { g_thr.threshold_db.store(v.clamp(gate::THRESHOLD_DB_MIN, gate::THRESHOLD_DB_MAX)); } } }
+                                      span { style: "width:56px;text-align:right;", "{thr:.0} dB" }
+                                      span { { tr("server.gate.hold") } }
+                                      input { r#type: "number", min: "{gate::HOLD_MS_MIN}", max: "{gate::HOLD_MS_MAX}", step: "50", value: "{hold}", disabled: !on, style: "width:64px;", oninput: move |e| { if let Ok(v)=e.value().trim().parse::<u64>() { g_hold.hold_ms.store(v.clamp(gate::HOLD_MS_MIN, gate::HOLD_MS_MAX), Ordering::Relaxed); } } }
+                                      span { "ms" }
+                                      if closed { span { style: "padding:2px 6px;border-radius:4px;background:#555;color:#fff;font-size:10px;", { tr("server.gate.closed") } } }
+                                    }) }
+                                  { // silence suppression (DTX): quiet stretches go out as occasional silence markers instead of frames
+                                    let d = srv_state.dtx.clone(); let on = d.enabled.load(Ordering::Relaxed); let thr = d.threshold_db.load(); let quiet = on && d.suppressing.load(Ordering::Relaxed);
+                                    let (d_on, d_thr) = (d.clone(), d);
+                                    rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                      span { style: "font-size:12px;min-width:70px;color:#bbb;display:flex;align-items:center;gap:4px;", title: tr("server.dtx.hint"),
+                                          input { r#type: "checkbox", checked: on, oninput: move |e| { d_on.enabled.store(e.checked(), Ordering::Relaxed); } }
+                                          { tr("server.dtx") } }
+                                      input { r#type: "range", min: "{dtx::THRESHOLD_DB_MIN}", max: "{dtx::THRESHOLD_DB_MAX}", step: "1", value: "{thr}", disabled: !on, style: "flex:1;", oninput: move |e| { if let Ok(v)=e.value().parse::<f64>() { d_thr.threshold_db.store(v.clamp(dtx::THRESHOLD_DB_MIN, dtx::THRESHOLD_DB_MAX)); } } }
+                                      span { style: "width:56px;text-align:right;", "{thr:.0} dB" }
+                                      if quiet { span { style: "padding:2px 6px;border-radius:4px;background:#555;color:#fff;font-size:10px;", { tr("server.dtx.suppressing") } } }
+                                    }) }
+                                  { // push-to-talk: frames only while the global hotkey is held
+                                    let p = srv_state.ptt.clone(); let on = p.enabled.load(Ordering::Relaxed); let transmitting = p.held.load(Ordering::Relaxed); let key = p.key.lock().clone();
+                                    let (p_on, p_key) = (p.clone(), p);
+                                    rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                      span { style: "font-size:12px;min-width:70px;color:#bbb;display:flex;align-items:center;gap:4px;", title: tr("server.ptt.hint"),
+                                          input { r#type: "checkbox", checked: on, oninput: move |e| {
+                                                  if !e.checked() { p_on.enabled.store(false, Ordering::Relaxed); st.write().ptt_hotkey = None; return; }
+                                                  match ptt::PttHotkey::register(&p_on) {
+                                                      Ok(h) => { st.write().ptt_hotkey = Some(h); p_on.enabled.store(true, Ordering::Relaxed); }
+                                                      Err(e) => st.write().error_message = Some(format!("{}: {e:#}", tr("server.ptt.failed"))),
+                                                  }
+                                              } }
+                                          { tr("server.ptt") } }
+                                      span { { tr("server.ptt.key") } }
+                                      input { r#type: "text", value: "{key}", disabled: on, style: "width:120px;", title: tr("server.ptt.key.hint"), oninput: move |e| { *p_key.key.lock() = e.value().trim().to_string(); } }
+                                      if on && transmitting { span { style: "padding:2px 6px;border-radius:4px;background:#d9534f;color:#fff;font-size:10px;font-weight:bold;", { format!("● {}", tr("server.ptt.transmitting")) } } }
+                                      if on && !transmitting { span { style: "padding:2px 6px;border-radius:4px;background:#555;color:#fff;font-size:10px;", { tr("server.ptt.standby") } } }
+                                    }) }
+                                  { // AGC: steers the capture level toward the target, replacing the manual gain while on
+                                    let a = srv_state.agc.clone(); let on = a.enabled.load(Ordering::Relaxed); let target = a.target_db.load(); let applied = 20.0 * a.gain.load().log10();
+                                    let (a_on, a_target) = (a.clone(), a);
+                                    rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                      span { style: "font-size:12px;min-width:70px;color:#bbb;display:flex;align-items:center;gap:4px;", title: tr("server.agc.hint"),
+                                          input { r#type: "checkbox", checked: on, oninput: move |e| { a_on.enabled.store(e.checked(), Ordering::Relaxed); } }
+                                          { tr("server.agc") } }
+                                      input { r#type: "range", min: "{agc::TARGET_DB_MIN}", max: "{agc::TARGET_DB_MAX}", step: "1", value: "{target}", disabled: !on, style: "flex:1;", oninput: move |e| { if let Ok(v)=e.value().parse::<f64>() { a_target.target_db.store(v.clamp(agc::TARGET_DB_MIN, agc::TARGET_DB_MAX)); } } }
+                                      span { style: "width:56px;text-align:right;", "{target:.0} dB" }
+                                      if on { span { style: "width:90px;text-align:right;", { format!("{} {applied:+.1} dB", tr("server.agc.gain")) } } }
+                                    }) }
+                                  { session_remaining_row(&srv_state.session) }
+                                  { let send_errors = srv_state.send_errors.load(Ordering::Relaxed); let send_error = srv_state.send_error.lock().clone(); let oversized = srv_state.oversized_frames.load(Ordering::Relaxed);
+                                    rsx!(
+                                      if let Some(msg) = send_error { div { style: "font-size:11px;color:#ff7676;background:#2a1212;border:1px solid #5c2323;padding:6px 8px;border-radius:6px;", "{msg}" } }
+                                      if send_errors > 0 { div { style: "font-size:11px;color:#aaa;", { format!("{}: {}", tr("server.metrics.send_errors"), send_errors) } } }
+                                      if oversized > 0 { div { style: "font-size:11px;color:#e0a040;", title: tr("server.metrics.oversized.hint"), { lang::tr_args("server.metrics.oversized", &[("count", &oversized.to_string()), ("mtu", &srv_state.mtu.to_string())]) } } }
+                                    ) }
+                                  { // capture-side losses: chunks that did not fit a pool buffer (the pool grows) / no free buffer at all
+                                    let pool = st.read().buffer_pool.clone(); let truncated = pool.truncated.load(Ordering::Relaxed); let drops = pool.capture_drops();
+                                    rsx!(
+                                      if drops > 0 { div { style: "font-size:11px;color:#aaa;", title: tr("server.metrics.capture_drops.hint"), { format!("{}: {}", tr("server.metrics.capture_drops"), drops) } } }
+                                      if truncated > 0 { div { style: "font-size:11px;color:#aaa;", title: tr("server.metrics.truncated.hint"), { format!("{}: {}", tr("server.metrics.truncated"), truncated) } } }
+                                    ) }
+                                  { // record captured audio (WAV / MP3)
+                                    let recording = srv_state.recording.lock().is_some(); let srv_rec = srv_state.clone();
+                                    rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:12px;",
+                                        button { disabled: !server_running, onclick: move |_| {
+                                                let res = if srv_rec.recording.lock().is_some() { server::stop_recording(&srv_rec) } else {
+                                                    let format = st.read().record_format;
+                                                    match rfd::FileDialog::new().add_filter(format.label(), &[format.as_str()]).set_file_name(record::default_file_name("server", format).to_string_lossy()).save_file() { Some(path) => server::start_recording(&srv_rec, &path, format), None => Ok(()) }
+                                                };
+                                                if let Err(e) = res { st.write().error_message = Some(format!("{}: {e}", tr("record.failed"))); }
+                                            }, { if recording { tr("record.stop") } else { tr("record.start") } } }
+                                        if recording { span { style: "color:#d9534f;", { format!("● {}", tr("record.active")) } } }
+                                    })
+                                  }
+                                  { // local monitor: captured audio on this machine's default output (feeds back through speakers)
+                                    let level = srv_state.monitor.lock().as_ref().map(|m| m.level()); let srv_mon = srv_state.clone();
+                                    rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                      span { style: "font-size:12px;min-width:70px;color:#bbb;display:flex;align-items:center;gap:4px;", title: tr("server.monitor.hint"),
+                                          input { r#type: "checkbox", checked: level.is_some(), oninput: move |e| {
+                                                  let res = if e.checked() { server::start_monitor(&srv_mon) } else { server::stop_monitor(&srv_mon); Ok(()) };
+                                                  if let Err(e) = res { st.write().error_message = Some(format!("{}: {e:#}", tr("server.monitor.failed"))); }
+                                              } }
+                                          { tr("server.monitor") } }
+                                      if let Some(rms) = level {
+                                          div { style: "width:120px;height:6px;background:#2d2d2d;border-radius:3px;overflow:hidden;",
+                                              div { style: format!("height:100%;width:{:.2}%;background:linear-gradient(90deg,#2e8b57,#f0ad4e,#d9534f);", rms.sqrt().min(1.0) * 100.0) }
+                                          }
+                                          span { style: "color:#d9a84e;", { tr("server.monitor.warning") } }
+                                      }
+                                    })
+                                  }
                                   { if !clients.is_empty() { let total = clients.len(); rsx!(div { style: "display:flex;flex-direction:column;gap:4px;",
                                           div { style: "font-size:12px;color:#bbb;font-weight:600;", { format!("{} ({total})", tr("server.connected_clients")) } }
                                           div { style: "max-height:120px;overflow-y:auto;display:flex;flex-direction:column;gap:4px;",
-                                              { clients.into_iter().enumerate().map(|(i,(addr,_udp,_age))| rsx!(div { key: "cli{i}", style: "font-size:12px;padding:4px 6px;border:1px solid #333;border-radius:4px;background:#222;display:flex;gap:12px;align-items:center;",
+                                              { clients.into_iter().enumerate().map(|(i,(addr,udp_port,substream,want,_age))| rsx!(div { key: "cli{i}", style: "font-size:12px;padding:4px 6px;border:1px solid #333;border-radius:4px;background:#222;display:flex;gap:12px;align-items:center;",
                                                   span { style: "min-width:150px;color:#ddd;", "{addr}" }
+                                                  span { style: "min-width:70px;color:#888;font-size:11px;", { udp_port.map(|p| format!("UDP {p}")).unwrap_or_else(|| "UDP —".into()) } }
+                                                  // Output format the client announced (`WANT`); orange when it converts the stream on playback
+                                                  if let Some((rate, ch)) = want { span { style: if stream_fmt.is_some_and(|f| f != (rate, ch)) { "color:#e0a040;font-size:11px;" } else { "color:#888;font-size:11px;" }, title: tr("server.client.want.hint"), { format!("{rate} Hz ×{ch}") } } }
+                                                  if substream == server::Substream::LowQuality { span { style: "padding:1px 5px;border-radius:4px;background:#8a6d1f;color:#fff;font-size:10px;", { tr("server.client.low_quality") } } }
+                                                  if substream == server::Substream::Unicast { span { style: "padding:1px 5px;border-radius:4px;background:#2d5f8a;color:#fff;font-size:10px;", { tr("server.client.unicast") } } }
+                                                  if substream == server::Substream::Tcp { span { style: "padding:1px 5px;border-radius:4px;background:#5a3d8a;color:#fff;font-size:10px;", { tr("server.client.tcp") } } }
+                                                  // Disconnect just this client (it is told it was kicked; it may connect again)
+                                                  button { style: "margin-left:auto;font-size:11px;", title: tr("server.client.kick.hint"), onclick: { let srv = srv_state.clone(); move |_| { server::kick_client(&srv, &addr); } }, { tr("server.client.kick") } }
+                                                  // Per-client keys: drop the client and rotate the session key past it
+                                                  if srv_state.per_client_keys { button { style: "font-size:11px;", title: tr("server.client.revoke.hint"), onclick: { let srv = srv_state.clone(); move |_| { server::revoke_client(&srv, &addr); } }, { tr("server.client.revoke") } } }
                                               }) ) }
                                           }
                                       }) } else { rsx!(div { style: "font-size:12px;color:#555;", { tr("server.no_clients") } }) } }
@@ -456,56 +994,230 @@ fn app() -> Element {
                     div { class: "panel", style: format!("{}flex:1;", panel_style()),
                         div { style: panel_title_style(), {tr("group.client")} }
                         div { style: "display:grid;grid-template-columns:auto auto 1fr;column-gap:12px;row-gap:8px;align-items:center;",
+                            // Row 0: LAN discovery (mDNS) -> fills IP / port
+                            span { style: "font-size:12px;color:#bbb;", {tr("client.discover")} }
+                            select { style: "width:130px;", disabled: connected || st.read().discovered.is_empty(), value: "", oninput: move |e| {
+                                    let picked = e.value().parse::<usize>().ok().and_then(|i| st.read().discovered.get(i).cloned());
+                                    if let Some(s) = picked { let mut w = st.write(); w.client_server_ip = s.ip.to_string(); w.client_server_port = s.port.to_string(); w.save_settings(); }
+                                },
+                                option { value: "", { if st.read().discovered.is_empty() { tr("client.discover.none") } else { tr("client.discover.pick") } } }
+                                { st.read().discovered.iter().enumerate().map(|(i,s)| { let label = format!("{}{} ({}:{})", if s.encrypted { "🔒 " } else { "" }, s.name, s.ip, s.port); rsx!( option { key: "disc{i}", value: i.to_string(), "{label}" } ) }).collect::<Vec<_>>().into_iter() }
+                            }
+                            button { style: "justify-self:end;", disabled: connected || st.read().scanning, onclick: move |_| {
+                                    st.write().scanning = true;
+                                    // browse() blocks for BROWSE_WINDOW: run it off the UI thread
+                                    let (tx, rx) = tokio::sync::oneshot::channel();
+                                    let _ = std::thread::Builder::new().name("rm-discover".into()).spawn(move || { let _ = tx.send(discovery::browse()); });
+                                    spawn(async move {
+                                        let res = rx.await.unwrap_or_else(|_| Err(anyhow::anyhow!("scan thread exited")));
+                                        let mut w = st.write(); w.scanning = false;
+                                        match res { Ok(list) => w.discovered = list, Err(e) => w.error_message = Some(format!("{}: {e}", tr("client.discover.failed"))) }
+                                    });
+                                }, { if st.read().scanning { tr("client.discover.scanning") } else { tr("client.discover.scan") } } }
                             // Row 1: server_ip
                             span { style: "font-size:12px;color:#bbb;", {tr("client.server_ip")} }
-                            input { style: "width:130px;", value: st.read().client_server_ip.clone(), disabled: connected, maxlength: "15", oninput: move |e| {
-                                    let mut v: String = e.value().chars().filter(|c| c.is_ascii_digit() || *c=='.').collect();
-                                    if v.len() > 15 { v.truncate(15); }
-                                    st.write().client_server_ip = v;
+                            input { style: "width:130px;", value: st.read().client_server_ip.clone(), disabled: connected, title: tr("client.server_ip.hint"), oninput: move |e| {
+                                    // A pasted remotemic:// URL fills host + port and flags whether a PSK is needed
+                                    let mut w = st.write();
+                                    match server_field_input(&e.value()) {
+                                        Ok(ServerField::Url(url)) => {
+                                            w.client_server_ip = url.host;
+                                            if let Some(p) = url.port { w.client_server_port = p.to_string(); }
+                                            w.client_psk_required = url.enc;
+                                        }
+                                        Ok(ServerField::Host(host)) => { w.client_server_ip = host; w.client_psk_required = false; }
+                                        Err(e) => { w.toast = Some((format!("{}: {e}", tr("client.url.invalid")), Instant::now())); return; }
+                                    }
+                                    w.save_settings();
                                 } }
                             // Buttons right side single row
                             div { style: "display:flex;flex-direction:column;gap:8px;justify-self:end;align-self:start;",
-                                if !connected { button { onclick: move |_| {
+                                if !connected && !reconnecting { button { disabled: connecting, onclick: move |_| {
                                         let snapshot = st.read();
                                         let ip = snapshot.client_server_ip.clone();
                                         let port_str = snapshot.client_server_port.clone();
-                                        let sel_out = snapshot.sel_output; drop(snapshot);
+                                        let sel_out = device_choice(&snapshot.sel_output); drop(snapshot);
                                         let ip_trim = ip.trim().to_string(); let port_trim = port_str.trim().to_string();
                                         if ip_trim.is_empty() || port_trim.is_empty() { let mut w = st.write(); w.error_message = Some(tr("error.client.missing_fields")); return; }
-                                        if ip_trim.parse::<std::net::IpAddr>().is_err() { let mut w = st.write(); w.error_message = Some(tr("error.client.invalid_ip")); return; }
+                                        // Syntax only here: the name is resolved on the connect thread (a failed lookup is reported as the connect error)
+                                        if net::parse_host(&ip_trim).is_none() { let mut w = st.write(); w.error_message = Some(tr("error.client.invalid_ip")); return; }
                                         let port: u16 = match port_trim.parse() { Ok(p) if p>0 => p, _ => { let mut w = st.write(); w.error_message = Some(tr("error.client.invalid_port")); return; } };
                                         let (ev_tx, ev_rx) = unbounded_channel();
-                                        let psk_opt = { let p = st.read().client_psk.clone(); if p.trim().is_empty() { None } else { Some(p) } };
-                                        match client::connect_with_output(ip_trim, port, sel_out, psk_opt, Some(ev_tx)) { Ok(cs)=> { let mut w=st.write(); w.client_state=Some(cs); w.event_rx=Some(ev_rx); }, Err(e)=> { let mut w=st.write(); w.error_message=Some(format!("连接服务器失败: {e}")); } }
-                                    }, {tr("client.connect")} } }
-                                if connected { button { onclick: move |_| { if let Some(cs)=&st.read().client_state { client::disconnect(cs); } st.write().client_state=None; }, {tr("client.disconnect")} } }
+                                        // 事件通道先交给事件循环, 连接过程中即可显示进度
+                                        { let mut w = st.write(); w.client_motd = None; w.event_rx = Some(ev_rx); w.client_connecting = Some(None); }
+                                        let psk_src = { let r = st.read(); psk_source(&r.client_psk, &r.client_psk_file, r.client_psk_saved, PSK_ACCOUNT_CLIENT) };
+                                        let (timeout, transport) = { let r = st.read(); (Duration::from_secs(r.client_handshake_timeout), r.client_transport) };
+                                        let (tx, rx) = tokio::sync::oneshot::channel();
+                                        let _ = std::thread::Builder::new().name("rm-connect".into()).spawn(move || { let _ = tx.send(client::connect_with_output(ip_trim, port, sel_out, psk_src, Some(ev_tx), timeout, transport)); });
+                                        spawn(async move {
+                                            let res = rx.await.unwrap_or_else(|_| Err(anyhow::anyhow!("connect thread exited")));
+                                            let mut w = st.write(); w.client_connecting = None;
+                                            match res { Ok(cs)=> { { let w = &mut *w; remember_psk(&mut w.client_psk, w.client_psk_remember, &mut w.client_psk_saved, PSK_ACCOUNT_CLIENT); } w.apply_client_options(&cs); w.client_state=Some(cs); }, Err(e)=> { w.error_message=Some(format!("连接服务器失败: {e}")); } }
+                                        });
+                                    }, { if connecting { tr("client.connecting") } else { tr("client.connect") } } } }
+                                // Step-by-step connect without playback (TCP, handshake, multicast, first frame)
+                                if !connected && !reconnecting { button { title: tr("diag.client.hint"), disabled: connecting || st.read().diagnosing, onclick: move |_| {
+                                        let (ip, port_str) = { let r = st.read(); (r.client_server_ip.trim().to_string(), r.client_server_port.trim().to_string()) };
+                                        if ip.is_empty() || port_str.is_empty() { st.write().error_message = Some(tr("error.client.missing_fields")); return; }
+                                        if net::parse_host(&ip).is_none() { st.write().error_message = Some(tr("error.client.invalid_ip")); return; }
+                                        let port: u16 = match port_str.parse() { Ok(p) if p>0 => p, _ => { st.write().error_message = Some(tr("error.client.invalid_port")); return; } };
+                                        let psk_src = { let r = st.read(); psk_source(&r.client_psk, &r.client_psk_file, r.client_psk_saved, PSK_ACCOUNT_CLIENT) };
+                                        let timeout = Duration::from_secs(st.read().client_handshake_timeout);
+                                        st.write().diagnosing = true;
+                                        let title = lang::tr_args("diag.client.title", &[("server", &net::host_port(&ip, port))]);
+                                        let (tx, rx) = tokio::sync::oneshot::channel();
+                                        let _ = std::thread::Builder::new().name("rm-diagnose".into()).spawn(move || { let _ = tx.send(diagnose::client(ip, port, psk_src, timeout)); });
+                                        spawn(async move {
+                                            let checks = rx.await.unwrap_or_default();
+                                            let mut w = st.write(); w.diagnosing = false; w.diagnostics = Some((title, checks));
+                                        });
+                                    }, { if st.read().diagnosing { tr("diag.running") } else { tr("diag.button") } } } }
+                                if connected || reconnecting { button { onclick: move |_| {
+                                        let mut w = st.write();
+                                        if let Some(cancel) = w.reconnect_cancel.take() { cancel.store(true, Ordering::Relaxed); }
+                                        w.reconnect_attempt = None;
+                                        if let Some(cs) = &w.client_state { client::disconnect(cs); }
+                                        w.client_state = None;
+                                    }, {tr("client.disconnect")} } }
                             }
                             // Row 2: server_port
                             span { style: "font-size:12px;color:#bbb;", {tr("client.server_port")} }
-                            input { style: "width:60px;", value: st.read().client_server_port.clone(), disabled: connected, maxlength: "5", oninput: move |e| { let mut v = e.value().to_string(); if v.len() > 5 { v.truncate(5); } st.write().client_server_port = v; } }
+                            input { style: "width:60px;", value: st.read().client_server_port.clone(), disabled: connected, maxlength: "5", oninput: move |e| { let mut v = e.value().to_string(); if v.len() > 5 { v.truncate(5); } let mut w = st.write(); w.client_server_port = v; w.save_settings(); } }
                             div {} // 占位防止 PSK 挤在同一行
+                            // Row 2b: TCP connect / handshake timeout (seconds, saved)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.handshake_timeout") } }
+                            input { style: "width:60px;", r#type: "number", min: "1", max: "60", value: st.read().client_handshake_timeout.to_string(), disabled: connected || connecting, oninput: move |e| { if let Ok(v) = e.value().trim().parse::<u64>() { let mut w = st.write(); w.client_handshake_timeout = v.clamp(1, 60); w.save_settings(); } } }
+                            span { style: "font-size:11px;color:#888;", { tr("client.handshake_timeout.hint") } }
+                            // Row 2c: transport (TCP = frames over the control connection: no dropouts, more delay)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.transport") } }
+                            select { value: st.read().client_transport.as_str(), disabled: connected || connecting, oninput: move |e| { if let Some(t) = Transport::parse(&e.value()) { let mut w = st.write(); w.client_transport = t; w.save_settings(); } },
+                                option { value: Transport::Udp.as_str(), { tr("client.transport.udp") } }
+                                option { value: Transport::Tcp.as_str(), { tr("client.transport.tcp") } }
+                            }
+                            span { style: "font-size:11px;color:#888;", { tr("client.transport.hint") } }
                             // Row 3: PSK
                             span { style: "font-size:12px;color:#bbb;", { tr("client.psk") } }
-                            input { style: "width:130px;", r#type: "password", placeholder: "(可选)", value: st.read().client_psk.clone(), disabled: connected, oninput: move |e| { st.write().client_psk = e.value().to_string(); } }
+                            { let missing = { let r = st.read(); r.client_psk_required && r.client_psk.is_empty() && r.client_psk_file.is_none() && !r.client_psk_saved };
+                              rsx!(input { style: if missing { "width:130px;border-color:#d9534f;" } else { "width:130px;" }, title: if missing { tr("client.psk.required") } else { String::new() }, r#type: "password", placeholder: psk_placeholder(&st.read().client_psk_file, st.read().client_psk_saved), value: st.read().client_psk.clone(), disabled: connected || st.read().client_psk_file.is_some(), oninput: move |e| { st.write().client_psk = e.value().to_string(); } }) }
+                            div { style: "display:flex;align-items:center;gap:6px;font-size:12px;color:#bbb;",
+                                if st.read().client_psk_file.is_none() {
+                                    button { title: tr("psk.from_file"), disabled: connected, onclick: move |_| { if let Some(p) = rfd::FileDialog::new().pick_file() { st.write().client_psk_file = Some(p); } }, "📄" }
+                                } else {
+                                    button { disabled: connected, onclick: move |_| { st.write().client_psk_file = None; }, "×" }
+                                }
+                                input { r#type: "checkbox", checked: st.read().client_psk_remember, disabled: connected, oninput: move |e| { let mut w = st.write(); w.client_psk_remember = e.checked(); if !e.checked() { forget_psk(&mut w.client_psk_saved, PSK_ACCOUNT_CLIENT); } } }
+                                span { { tr("psk.remember") } }
+                            }
+                            // Row 4: require acknowledged params before showing connected
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.require_ack") } }
+                            input { r#type: "checkbox", checked: st.read().client_require_ack, oninput: move |e| { st.write().client_require_ack = e.checked(); } }
+                            div {}
+                            // Row 5: max session duration (minutes)
+                            span { style: "font-size:12px;color:#bbb;", { tr("session.limit_min") } }
+                            input { style: "width:60px;", placeholder: "0", maxlength: "4", value: st.read().client_session_min.clone(), disabled: connected, oninput: move |e| { st.write().client_session_min = e.value().chars().filter(|c| c.is_ascii_digit()).collect(); } }
+                            div {}
+                            // Row 6: synchronized start (multi-room), fixed once connected
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.sync_start") } }
+                            input { r#type: "checkbox", checked: st.read().client_sync_start, disabled: connected, oninput: move |e| { st.write().client_sync_start = e.checked(); } }
+                            div {}
+                            // Row 7: low-quality unicast substream (switchable while connected)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.low_quality") } }
+                            input { r#type: "checkbox", checked: st.read().client_low_quality, oninput: move |e| {
+                                    let on = e.checked(); let mut w = st.write(); w.client_low_quality = on;
+                                    let res: Result<Vec<()>> = w.all_clients().map(|cs| client::set_low_quality(cs, on)).collect();
+                                    if let Err(err) = res { w.error_message = Some(format!("{err}")); }
+                                } }
+                            div {}
+                            // Row 8: packet-loss concealment (takes effect immediately while connected)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.plc") } }
+                            input { r#type: "checkbox", checked: st.read().client_plc, oninput: move |e| { let mut w = st.write(); w.client_plc = e.checked(); for cs in w.all_clients() { cs.plc_enabled.store(e.checked(), Ordering::Relaxed); } } }
+                            div {}
+                            // Row 9: force mono (takes effect immediately while connected)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.force_mono") } }
+                            input { r#type: "checkbox", checked: st.read().client_force_mono, oninput: move |e| { let mut w = st.write(); w.client_force_mono = e.checked(); for cs in w.all_clients() { cs.force_mono.store(e.checked(), Ordering::Relaxed); } } }
+                            div {}
+                            // Row 9b: stereo width (mid/side, live; 0% = mono, 100% = original)
+                            { let width = st.read().client_width; let max = (client::STEREO_WIDTH_MAX * 100.0) as u32; rsx!(
+                                span { style: "font-size:12px;color:#bbb;", title: tr("client.stereo_width.hint"), { tr("client.stereo_width") } }
+                                input { r#type: "range", min: "0", max: "{max}", step: "5", value: "{width}", oninput: move |e| { if let Ok(v) = e.value().parse::<u32>() {
+                                        let mut w = st.write(); w.client_width = v.min(max);
+                                        for cs in w.all_clients() { cs.stereo_width.store(w.client_width as f64 / 100.0); }
+                                    } } }
+                                span { style: "font-size:11px;color:#ccc;", "{width}%" }
+                            ) }
+                            // Row 10: auto-reconnect after a heartbeat timeout (read when the timeout happens)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.auto_reconnect") } }
+                            input { r#type: "checkbox", checked: st.read().client_auto_reconnect, oninput: move |e| { st.write().client_auto_reconnect = e.checked(); } }
+                            div {}
+                            // Row 11: latency profile (jitter buffer bounds, takes effect immediately while connected)
+                            span { style: "font-size:12px;color:#bbb;", { tr("client.latency") } }
+                            select { style: "width:130px;", value: st.read().client_latency.as_u8().to_string(), oninput: move |e| {
+                                    let profile = LatencyProfile::from_u8(e.value().parse().unwrap_or(LatencyProfile::Balanced.as_u8()));
+                                    let mut w = st.write(); w.client_latency = profile;
+                                    for cs in w.all_clients() { cs.latency_profile.store(profile.as_u8(), Ordering::Relaxed); }
+                                },
+                                { LatencyProfile::ALL.iter().map(|p| rsx!( option { key: "lat{p.as_u8()}", value: p.as_u8().to_string(), { tr(p.lang_key()) } } )) }
+                            }
                             div {}
                         }
+                        // Connect progress (manual connect in flight)
+                        { if let Some(phase) = st.read().client_connecting { rsx!(div { style: "margin-top:8px;padding:6px 10px;border:1px solid #3a5a7a;border-radius:6px;background:#17222d;font-size:12px;color:#cfe3f7;",
+                            { let step = match phase { Some(ConnectPhase::Tcp) => tr("client.connecting.tcp"), Some(ConnectPhase::Handshake) => tr("client.connecting.handshake"), Some(ConnectPhase::WaitingForAudio) => tr("client.connecting.waiting_audio"), None => String::new() }; format!("⏳ {} {step}", tr("client.connecting")) }
+                        }) } else { rsx!(div {}) } }
+                        // Reconnect progress (heartbeat timeout + auto-reconnect)
+                        { if reconnecting { let attempt = st.read().reconnect_attempt.unwrap_or(0); rsx!(div { style: "margin-top:8px;padding:6px 10px;border:1px solid #7a6a3a;border-radius:6px;background:#2a2415;font-size:12px;color:#e8d9a8;",
+                            { format!("{} ({attempt}/{})", tr("client.reconnecting"), client::RECONNECT_MAX_ATTEMPTS) }
+                        }) } else { rsx!(div {}) } }
+                        // Server banner (MOTD), dismissible
+                        { if let Some(motd) = st.read().client_motd.clone() { rsx!(div { style: "margin-top:8px;padding:8px 10px;border:1px solid #3a5a7a;border-radius:6px;background:#17222d;display:flex;gap:8px;align-items:flex-start;",
+                            div { style: "flex:1;font-size:12px;color:#cfe3f7;white-space:pre-wrap;word-break:break-word;",
+                                div { style: "font-size:10px;color:#7fa7cc;margin-bottom:2px;", { tr("client.motd") } }
+                                "{motd}"
+                            }
+                            button { style: "padding:0 6px;", title: tr("client.motd.dismiss"), onclick: move |_| { st.write().client_motd = None; }, "×" }
+                        }) } else { rsx!(div {}) } }
                         // Metrics panel
                         { if let Some(cs)=&st.read().client_state { rsx!(div { style: "margin-top:8px;padding:8px;border:1px solid #2e2e2e;border-radius:6px;display:flex;flex-direction:column;gap:6px;background:#181818;",
                             div { style: "font-size:12px;font-weight:600;color:#bbb;", { tr("client.metrics.title") } }
+                            { // connection stage badge: green only once params + multicast join + first decodable frame are confirmed
+                              let stage = cs.stage.load(Ordering::Relaxed);
+                              let require_ack = st.read().client_require_ack;
+                              let (stage_lbl, color) = if !require_ack { (tr("client.status.connected"), "#216e39") } else { match stage {
+                                  0 => (tr("client.status.handshaking"), "#555"),
+                                  1 => (tr("client.status.joining"), "#8a6d1f"),
+                                  2 => (tr("client.status.waiting_audio"), "#8a6d1f"),
+                                  3 => (tr("client.status.streaming"), "#216e39"),
+                                  _ => (tr("client.status.playing"), "#216e39"),
+                              } };
+                              rsx!(div { style: "font-size:11px;display:flex;align-items:center;gap:8px;",
+                                  span { style: format!("padding:2px 6px;border-radius:4px;background:{};color:#fff;font-size:10px;letter-spacing:.5px;", color), "{stage_lbl}" }
+                                  if cs.dtx_silent.load(Ordering::Relaxed) { span { style: "padding:2px 6px;border-radius:4px;background:#555;color:#fff;font-size:10px;", title: tr("client.status.dtx.hint"), { tr("client.status.dtx") } } }
+                              })
+                            }
+                            { session_remaining_row(&cs.session) }
+                            { // synchronized start schedule (server timeline position + local countdown)
+                              let start_ms = cs.sync_start_ms.load(Ordering::Relaxed);
+                              let at = cs.sync_start_at.lock().ok().and_then(|g| *g);
+                              if let (true, Some(at)) = (start_ms > 0, at) {
+                                  let left = at.saturating_duration_since(Instant::now()).as_millis();
+                                  let when = if left > 0 { format!("{} {:.3}s (-{left}ms)", tr("client.sync_start.at"), start_ms as f64 / 1000.0) } else { format!("{} {:.3}s ✓", tr("client.sync_start.at"), start_ms as f64 / 1000.0) };
+                                  rsx!(div { style: "font-size:11px;color:#aaa;", "{when}" })
+                              } else { rsx!(div {}) }
+                            }
                             { // server audio params row
                               if let Some(p)=&cs.params {
-                                  let fmt_str = match p.sample_format { cpal::SampleFormat::F32 => "f32", cpal::SampleFormat::I16 => "i16", cpal::SampleFormat::U16 => "u16", _=>"f32"};
+                                  let fmt_str = match p.sample_format { cpal::SampleFormat::F32 => "f32", cpal::SampleFormat::I16 => "i16", cpal::SampleFormat::U16 => "u16", cpal::SampleFormat::I32 => "i32", cpal::SampleFormat::F64 => "f64", _=>"f32"};
                                   // 三种状态: 成功(绿色) / 失败(红色: 服务器加密而本地未派生) / 未加密(灰色)
                                   // 优先使用后端共享的整数状态 (避免多线程频繁推送修改)
                                   let status_val = cs.enc_status.load(Ordering::Relaxed);
                                   let (enc_lbl, color) = match status_val {
                                       -1 => (tr("enc.auth_failed"), "#b60205"),
                                       1 => (tr("enc.enabled"), "#216e39"),
-                                      _ => (tr("enc.disabled"), if st.read().server_state.key_bytes.is_some() { "#b60205" } else { "#555" }),
+                                      _ => (tr("enc.disabled"), if st.read().server_state.encrypted() { "#b60205" } else { "#555" }),
                                   };
                                   rsx!(div { style: "font-size:11px;color:#444;display:flex;flex-wrap:wrap;gap:12px;align-items:center;",
                                       span { { format!("SR:{}", p.sample_rate) } }
-                                      span { { format!("CH:{}", p.channels) } }
+                                      span { { let live = cs.frame_channels.load(Ordering::Relaxed); if live != 0 && live != p.channels { format!("CH:{}→{} ({})", p.channels, live, channel_layout_label(live)) } else { format!("CH:{} ({})", p.channels, channel_layout_label(p.channels)) } } }
                                       span { { format!("FMT:{}", fmt_str) } }
                                       span { style: format!("padding:2px 6px;border-radius:4px;background:{};color:#fff;font-size:10px;letter-spacing:.5px;", color), "{enc_lbl}" }
                                   })
@@ -520,14 +1232,129 @@ fn app() -> Element {
                                 }
                                 span { style: "font-size:11px;width:70px;text-align:right;color:#ccc;", { format!("{:.2} RMS", rms) } }
                                 span { style: "font-size:11px;width:60px;text-align:right;color:#ccc;", { format!("{:.1} dB", db) } }
+                                // local playback mute (the stream keeps being received and buffered)
+                                button { style: if st.read().client_muted { "background:#8a2a2a;" } else { "" }, onclick: move |_| {
+                                        let mut w = st.write(); w.client_muted = !w.client_muted;
+                                        if let Some(cs) = &w.client_state { cs.muted.store(w.client_muted, Ordering::Relaxed); }
+                                    }, { if st.read().client_muted { tr("audio.unmute") } else { tr("audio.mute") } } }
+                            }) }
+                            // playback volume (applied in the output callback, independent of the OS volume; all connections)
+                            { let vol = st.read().client_volume; let max = (client::OUTPUT_GAIN_MAX * 100.0) as u32; rsx!(div { style: "display:flex;align-items:center;gap:8px;",
+                                span { style: "font-size:12px;min-width:60px;color:#bbb;", title: tr("client.output_volume.hint"), { tr("client.output_volume") } }
+                                input { r#type: "range", min: "0", max: "{max}", step: "5", value: "{vol}", style: "flex:1;", oninput: move |e| { if let Ok(v)=e.value().parse::<u32>() {
+                                        let mut w = st.write(); w.client_volume = v.min(max);
+                                        for cs in w.all_clients() { cs.output_gain.store(w.client_volume as f64 / 100.0); }
+                                    } } }
+                                span { style: "font-size:11px;width:60px;text-align:right;color:#ccc;", "{vol}%" }
+                                span { style: "font-size:11px;color:#aaa;display:flex;align-items:center;gap:4px;", title: tr("client.limiter.hint"),
+                                    input { r#type: "checkbox", checked: st.read().client_limiter, oninput: move |e| {
+                                            let mut w = st.write(); w.client_limiter = e.checked();
+                                            for cs in w.all_clients() { cs.output_limiter.store(e.checked(), Ordering::Relaxed); }
+                                        } }
+                                    { tr("client.limiter") } }
+                            }) }
+                            // loudness normalization toward a target LUFS (slow gain + limiter, before the volume above)
+                            { let on = st.read().client_loudness; let target = st.read().client_loudness_target;
+                              let (measured, applied) = (cs.loudness.measured.load(), 20.0 * cs.loudness.gain.load().log10());
+                              rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                span { style: "font-size:12px;min-width:60px;color:#bbb;display:flex;align-items:center;gap:4px;", title: tr("client.loudness.hint"),
+                                    input { r#type: "checkbox", checked: on, oninput: move |e| {
+                                            let mut w = st.write(); w.client_loudness = e.checked();
+                                            for cs in w.all_clients() { cs.loudness.enabled.store(e.checked(), Ordering::Relaxed); }
+                                        } }
+                                    { tr("client.loudness") } }
+                                input { r#type: "range", min: "{loudness::TARGET_LUFS_MIN}", max: "{loudness::TARGET_LUFS_MAX}", step: "1", value: "{target}", disabled: !on, style: "flex:1;", oninput: move |e| { if let Ok(v)=e.value().parse::<f64>() {
+                                        let mut w = st.write(); w.client_loudness_target = v.clamp(loudness::TARGET_LUFS_MIN, loudness::TARGET_LUFS_MAX);
+                                        for cs in w.all_clients() { cs.loudness.target_lufs.store(w.client_loudness_target); }
+                                    } } }
+                                span { style: "width:60px;text-align:right;", "{target:.0} LUFS" }
+                                if on && measured.is_finite() { span { style: "width:150px;text-align:right;", { format!("{measured:.1} LUFS, {} {applied:+.1} dB", tr("client.loudness.gain")) } } }
+                            }) }
+                            { channel_meter_rows(cs.channel_rms.levels(), 60) }
+                            { scope_view(cs.scope.snapshot()) }
+                            div { title: tr("client.spectrum.hint"), { spectrum_view(&st.read().client_spectrum) } }
+                            // Connection quality badge (thresholds in quality.rs)
+                            { let q = Quality::from_u8(cs.quality.load(Ordering::Relaxed));
+                              rsx!(div { style: "display:flex;align-items:center;gap:8px;", title: tr("client.quality.hint"),
+                                span { style: "font-size:12px;min-width:60px;color:#bbb;", { tr("client.quality") } }
+                                span { style: format!("padding:2px 8px;border-radius:4px;background:{};color:#fff;font-size:11px;font-weight:600;", q.color()), { tr(q.lang_key()) } }
+                                if q == Quality::Poor { span { style: "font-size:11px;color:#ff9e9e;", { tr("client.quality.poor.tip") } } }
                             }) }
-                            { let lat = cs.avg_latency_ms.load(); let jit = cs.jitter_ms.load(); let loss = cs.packet_loss.load()*100.0; let late = cs.late_drop.load(); rsx!(div { style: "display:grid;grid-template-columns:repeat(2,minmax(0,1fr));gap:4px;font-size:12px;",
+                            // jitter buffer fill vs the adaptive target (marker) on a 0..max scale
+                            { let (buf, target, max) = (cs.buffer_ms.load(), cs.target_buffer_ms.load(), cs.max_buffer_ms.load()); let scale = |v: f64| if max > 0.0 { (v / max).min(1.0) * 100.0 } else { 0.0 };
+                              rsx!(div { style: "display:flex;align-items:center;gap:8px;", title: tr("client.metrics.buffer.hint"),
+                                span { style: "font-size:12px;min-width:60px;color:#bbb;", { tr("client.metrics.buffer") } }
+                                div { style: "flex:1;height:10px;background:#2d2d2d;border-radius:4px;overflow:hidden;position:relative;",
+                                    div { style: format!("position:absolute;left:0;top:0;bottom:0;width:{:.2}%;background:{};", scale(buf), if buf < target { "#f0ad4e" } else { "#3a7bd5" }) }
+                                    div { style: format!("position:absolute;top:0;bottom:0;left:calc({:.2}% - 1px);width:2px;background:#fff;opacity:0.8;", scale(target)) }
+                                }
+                                span { style: "font-size:11px;width:150px;text-align:right;color:#ccc;", { format!("{buf:.0} / {target:.0} / {max:.0} ms") } }
+                            }) }
+                            { let lat = cs.avg_latency_ms.load(); let jit = cs.jitter_ms.load(); let loss = cs.packet_loss.load()*100.0; let late = cs.late_drop.load(); let restarts = cs.output_restarts.load(Ordering::Relaxed); let concealed = cs.plc_concealed.load(Ordering::Relaxed); rsx!(div { style: "display:grid;grid-template-columns:repeat(2,minmax(0,1fr));gap:4px;font-size:12px;",
                                 div { { format!("{}: {:.2}", tr("client.metrics.latency"), lat) } }
                                 div { { format!("{}: {:.2}", tr("client.metrics.jitter"), jit) } }
+                                { let rtt = cs.rtt_ms.load(); if rtt > 0.0 { rsx!(div { title: tr("client.metrics.rtt.hint"), { format!("{}: {:.2}", tr("client.metrics.rtt"), rtt / 2.0) } }) } else { rsx!(div {}) } }
                                 div { { format!("{}: {:.3}%", tr("client.metrics.loss"), loss) } }
+                                { let fails = cs.decrypt_fail.load(Ordering::Relaxed); rsx!(div { title: tr("client.metrics.decrypt_fail.hint"), style: if fails > 0 { "color:#ff7676;" } else { "" }, { format!("{}: {}", tr("client.metrics.decrypt_fail"), fails) } }) }
                                 div { { format!("{}: {}", tr("client.metrics.late"), late as u64) } }
+                                div { { format!("{}: {}", tr("client.metrics.concealed"), concealed) } }
+                                { let rebuilt = cs.fec_recovered.load(Ordering::Relaxed); if cs.fec_group > 0 || rebuilt > 0 { rsx!(div { title: tr("client.metrics.fec.hint"), { format!("{}: {} ({:.0}%)", tr("client.metrics.fec"), rebuilt, cs.fec_recovery.load() * 100.0) } }) } else { rsx!(div {}) } }
+                                if restarts > 0 { div { { format!("{}: {}", tr("client.metrics.output_restarts"), restarts) } } }
+                                if cs.enc_enabled { div { { format!("{}: {}", tr("client.metrics.replay_drop"), cs.replay_drop.load(Ordering::Relaxed)) } } }
                             }) }
+                            { // record the played stream (WAV / MP3)
+                              let recording = client::is_recording(cs);
+                              rsx!(div { style: "display:flex;align-items:center;gap:8px;font-size:12px;",
+                                  button { onclick: move |_| {
+                                          let r = st.read(); let Some(cs) = r.client_state.as_ref() else { return };
+                                          let res = if client::is_recording(cs) { client::stop_recording(cs) } else {
+                                              let format = r.record_format;
+                                              match rfd::FileDialog::new().add_filter(format.label(), &[format.as_str()]).set_file_name(record::default_file_name("client", format).to_string_lossy()).save_file() { Some(path) => client::start_recording(cs, &path, format), None => Ok(()) }
+                                          };
+                                          drop(r);
+                                          if let Err(e) = res { st.write().error_message = Some(format!("{}: {e}", tr("record.failed"))); }
+                                      }, { if recording { tr("record.stop") } else { tr("record.start") } } }
+                                  if recording { span { style: "color:#d9534f;", { format!("● {}", tr("record.active")) } } }
+                              })
+                            }
                         }) } else { rsx!(div { }) } }
+                        // Additional connections: other servers played at the same time (own IP / port / output / PSK each)
+                        div { style: "margin-top:8px;padding:8px;border:1px solid #2e2e2e;border-radius:6px;display:flex;flex-direction:column;gap:6px;background:#181818;",
+                            div { style: "font-size:12px;font-weight:600;color:#bbb;", title: tr("client.extra.hint"), { tr("client.extra.title") } }
+                            div { style: "display:flex;flex-wrap:wrap;align-items:center;gap:6px;",
+                                input { style: "width:120px;", placeholder: tr("client.server_ip"), value: st.read().extra_ip.clone(), oninput: move |e| {
+                                        let mut w = st.write();
+                                        match server_field_input(&e.value()) {
+                                            Ok(ServerField::Url(url)) => { w.extra_ip = url.host; if let Some(p) = url.port { w.extra_port = p.to_string(); } }
+                                            Ok(ServerField::Host(host)) => w.extra_ip = host,
+                                            Err(e) => w.toast = Some((format!("{}: {e}", tr("client.url.invalid")), Instant::now())),
+                                        }
+                                    } }
+                                input { style: "width:56px;", placeholder: tr("client.server_port"), maxlength: "5", value: st.read().extra_port.clone(), oninput: move |e| { st.write().extra_port = e.value().chars().filter(|c| c.is_ascii_digit()).take(5).collect(); } }
+                                select { style: "width:140px;", value: st.read().extra_output.clone(), oninput: move |e| { st.write().extra_output = e.value(); },
+                                    { let def = st.read().default_output.clone(); st.read().output_devices.iter().enumerate().map(|(i, name)| { let label = device_label(name, def.as_deref()); rsx!( option { key: "xout{i}", value: "{name}", "{label}" } ) }).collect::<Vec<_>>().into_iter() }
+                                }
+                                input { style: "width:90px;", r#type: "password", placeholder: "PSK", value: st.read().extra_psk.clone(), oninput: move |e| { st.write().extra_psk = e.value(); } }
+                                button { disabled: st.read().extra_connecting, onclick: move |_| connect_extra(st), { if st.read().extra_connecting { tr("client.connecting") } else { tr("client.extra.add") } } }
+                            }
+                            { st.read().extra_clients.iter().map(|x| {
+                                  let (id, cs) = (x.id, &x.state);
+                                  let norm = cs.current_rms.load().sqrt().min(1.0); let muted = cs.muted.clone(); let on = muted.load(Ordering::Relaxed);
+                                  let stats = format!("{}: {:.1} / {}: {:.1} / {}: {:.2}%", tr("client.metrics.latency"), cs.avg_latency_ms.load(), tr("client.metrics.jitter"), cs.jitter_ms.load(), tr("client.metrics.loss"), cs.packet_loss.load() * 100.0);
+                                  rsx!(div { key: "x{id}", style: "display:flex;align-items:center;gap:8px;font-size:11px;color:#aaa;",
+                                      span { style: "flex:1;min-width:0;overflow:hidden;text-overflow:ellipsis;white-space:nowrap;color:#ccc;", title: "{x.label}", "{x.label}" }
+                                      div { style: "width:80px;height:6px;background:#2d2d2d;border-radius:3px;overflow:hidden;",
+                                          div { style: format!("height:100%;width:{:.2}%;background:linear-gradient(90deg,#2e8b57,#f0ad4e,#d9534f);", norm * 100.0) }
+                                      }
+                                      span { "{stats}" }
+                                      button { style: if on { "background:#8a2a2a;" } else { "" }, onclick: move |_| { muted.fetch_xor(true, Ordering::Relaxed); }, { if on { tr("audio.unmute") } else { tr("audio.mute") } } }
+                                      button { onclick: move |_| {
+                                              let mut w = st.write();
+                                              if let Some(pos) = w.extra_clients.iter().position(|x| x.id == id) { let x = w.extra_clients.remove(pos); client::disconnect(&x.state); }
+                                          }, { tr("client.disconnect") } }
+                                  })
+                              }).collect::<Vec<_>>().into_iter() }
+                        }
                     }
                 }
             }
@@ -535,86 +1362,454 @@ fn app() -> Element {
     };
 }
 
+/// Bind address chosen in the server panel.
+fn server_bind_ip(r: &AppState) -> String { r.server_ip_list.get(r.sel_server_ip).cloned().unwrap_or("0.0.0.0".into()) }
+
+/// Multicast group, TTL, port and egress from the server panel (shared by start and diagnostics).
+fn apply_multicast_settings(r: &AppState, srv_state: &mut server::ServerState) {
+    srv_state.select_multicast_group(r.server_ipv6, Some(&r.server_room));
+    srv_state.multicast_ttl = r.server_ttl;
+    srv_state.multicast_port = r.server_mcast_port.trim().parse().unwrap_or(0);
+    srv_state.multicast_if = r.sel_server_egress.checked_sub(1).and_then(|i| r.server_ip_list.get(i)?.parse().ok());
+}
+
+/// Server dry run: the bind steps of `start_server` with the panel's settings, or the live state
+/// when it is already running.
+fn diagnose_server(r: &AppState) -> Result<Vec<diagnose::Check>> {
+    let ip = server_bind_ip(r);
+    let Some(port) = parse_server_port(&r.server_port_text) else { return Err(anyhow::anyhow!(lang::tr("error.server.invalid_port"))) };
+    if r.server_running { return Ok(diagnose::server(&r.server_state, &ip, port)); }
+    let mut probe = r.server_state.clone();
+    apply_multicast_settings(r, &mut probe);
+    Ok(diagnose::server(&probe, &ip, port))
+}
+
 /// Start server threads + audio input for selected device.
 fn start_server(mut st: Signal<AppState>) -> Result<()> {
-    let ip = st
-        .read()
-        .server_ip_list
-        .get(st.read().sel_server_ip)
-        .cloned()
-        .unwrap_or("0.0.0.0".into());
-    let port = st.read().server_port;
-    println!("[SERVER] start {ip}:{port}");
+    let ip = server_bind_ip(&st.read());
+    let Some(mut port) = parse_server_port(&st.read().server_port_text) else { return Err(anyhow::anyhow!(lang::tr("error.server.invalid_port"))) };
+    let Some(input_channels) = parse_input_channels(&st.read().server_input_channels) else { return Err(anyhow::anyhow!(lang::tr("error.server.invalid_channels"))) };
+    // Taken (another instance, or an old server still in TIME_WAIT): pick a free one instead of failing
+    if !net::tcp_port_free(port) {
+        let free = net::pick_free_port_in(net::DEFAULT_PORT_RANGE)?;
+        let mut w = st.write();
+        w.toast = Some((lang::tr_args("server.port.in_use", &[("port", &port.to_string()), ("free", &free.to_string())]), Instant::now()));
+        (w.server_port, w.server_port_text) = (free, free.to_string());
+        w.save_settings();
+        port = free;
+    }
+    log::info!("[SERVER] start {ip}:{port}");
     let pool = st.read().buffer_pool.clone();
     let (tx, rx_local) = unbounded();
     let mut srv_state = st.read().server_state.clone();
-    // 若用户输入了 PSK, 启用加密
-    let psk_opt = st.read().server_psk.clone();
-    if !psk_opt.trim().is_empty() {
-        srv_state.enable_psk(psk_opt.trim().to_string());
+    // 加密：每客户端密钥优先；否则若用户输入了 PSK 启用 PSK 加密；都没有则明文 (清除上次运行的密钥)
+    let psk_src = { let r = st.read(); psk_source(&r.server_psk, &r.server_psk_file, r.server_psk_saved, PSK_ACCOUNT_SERVER) };
+    if st.read().server_per_client_keys {
+        srv_state.enable_per_client_keys();
+    } else if let Some(src) = psk_src {
+        if !srv_state.enable_psk(&src)? { srv_state.disable_encryption(); }
+        let mut w = st.write(); let w = &mut *w;
+        remember_psk(&mut w.server_psk, w.server_psk_remember, &mut w.server_psk_saved, PSK_ACCOUNT_SERVER);
+    } else {
+        srv_state.disable_encryption();
     }
-    // 将更新后的加密配置写回 GUI 状态，确保界面能读取 key_bytes
+    srv_state.session.limit_secs.store(session_limit_secs(&st.read().server_session_min), Ordering::Relaxed);
+    apply_multicast_settings(&st.read(), &mut srv_state);
+    srv_state.max_clients = st.read().server_max_clients;
+    srv_state.capture_frames = st.read().server_capture_frames;
+    (srv_state.packet_frames, srv_state.mtu) = (st.read().server_packet_frames, st.read().server_mtu);
+    srv_state.output_rate = st.read().server_stream_rate;
+    srv_state.input_channels = input_channels;
+    // 将更新后的加密配置写回 GUI 状态，确保界面能读取会话密钥
     {
         let mut w = st.write();
         w.server_state = srv_state.clone();
+        #[cfg(feature = "control-api")]
+        { *w.api_state.write() = srv_state.clone(); }
     }
     server::start_server(srv_state.clone(), ip.clone(), port, pool.clone(), rx_local)?;
-    st.write().server_running = true;
-    // Capture selected input device immediately to avoid using stale selection inside the thread.
-    let sel = st.read().sel_input;
-    let input_dev = match audio::list_devices() {
-        Ok((inputs, _)) => {
-            inputs
-                .into_iter()
-                .enumerate()
-                .find_map(|(i, d)| if i == sel { Some(d) } else { None })
-        }
-        Err(e) => {
-            eprintln!("list_devices err: {e}");
-            None
+    { let mut w = st.write(); w.server_running = true; w.save_settings(); }
+    // Selected input looked up by name now (the OS list may have been reordered since the UI enumerated it);
+    // gone -> system default with a warning
+    let sel = st.read().sel_input.clone();
+    let input_dev = match audio::input_device_by_name(&sel) {
+        Some(dev) => Some(dev),
+        None => {
+            let fallback = audio::default_input_device();
+            let name = fallback.as_ref().map(audio::capture_name).unwrap_or_default();
+            log::warn!("[SERVER][WARN] input device {sel:?} not found -> default {name:?}");
+            if !sel.is_empty() { st.write().toast = Some((lang::tr_args("audio.input_missing", &[("name", &sel), ("default", &name)]), Instant::now())); }
+            fallback
         }
     };
+    let follow_default = srv_state.follow_default_input.clone();
+    // Follow mode: start on the current OS default (selected device if there is none right now)
+    let input_dev = if follow_default.load(Ordering::Relaxed) { audio::default_input_device().or(input_dev) } else { input_dev };
+    // Channel selection beyond what the device has: fail the start instead of a server without audio
+    if let Some(Ok(params)) = input_dev.as_ref().filter(|_| !srv_state.input_channels.is_empty()).map(audio::default_input_params) {
+        if let Err(e) = audio::channel_selection(&srv_state.input_channels, params.channels) {
+            server::stop_server(&srv_state);
+            st.write().server_running = false;
+            return Err(anyhow::anyhow!(lang::tr_args("error.server.invalid_channels.device", &[("error", &e.to_string())])));
+        }
+    }
     let running_flag = srv_state.input_running.clone();
     running_flag.store(true, Ordering::SeqCst);
-    std::thread::spawn(move || {
+    // Diagnostic sources (test signal, else the chosen WAV) with the params the selected device would negotiate
+    let source = replay::SignalSource::from_u8(srv_state.signal.load(Ordering::Relaxed));
+    let replay_path = srv_state.replay_path.lock().clone();
+    if source != replay::SignalSource::Mic || replay_path.is_some() {
+        let params = input_dev.as_ref().and_then(|d| audio::default_input_params(d).ok()).unwrap_or(audio::AudioParams { sample_rate: 48000, channels: 2, sample_format: cpal::SampleFormat::F32 });
+        let looping = srv_state.replay_loop.load(Ordering::Relaxed);
+        let normalize = srv_state.replay_normalize.load(Ordering::Relaxed);
+        let spawned = match replay_path.filter(|_| source == replay::SignalSource::Mic) {
+            Some(path) => replay::spawn_wav_replay(&path, &params, looping, normalize, pool, tx, running_flag),
+            None => replay::spawn_test_signal(source, &params, pool, tx, running_flag),
+        };
+        if let Err(e) = spawned {
+            server::stop_server(&srv_state);
+            st.write().server_running = false;
+            return Err(e);
+        }
+        *srv_state.audio_params.lock() = Some(params);
+        srv_state.stage.store(2, Ordering::SeqCst);
+        return Ok(());
+    }
+    std::thread::Builder::new().name("rm-input".into()).spawn(move || {
         if let Some(dev) = input_dev {
             let flag = running_flag.clone();
-            let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(1);
+            let (stop_tx, stop_rx) = crossbeam_channel::bounded::<server::InputCommand>(4);
             {
                 let mut guard = srv_state.input_stop_tx.lock();
                 *guard = Some(stop_tx);
             }
-            match audio::build_input_stream(&dev, pool, tx, flag.clone()) {
-                Ok(handle) => {
+            match audio::build_input_stream(&dev, pool.clone(), tx.clone(), flag.clone(), srv_state.gain.clone(), srv_state.muted.clone(), srv_state.agc.clone(), srv_state.gate.clone(), srv_state.capture_frames, &srv_state.input_channels) {
+                Ok(mut handle) => {
                     let params = handle.params.clone();
                     *srv_state.audio_params.lock() = Some(params);
                     srv_state.stage.store(2, Ordering::SeqCst);
-                    // 等待停止信号或标志
+                    let mut current_name = audio::capture_name(&dev);
+                    let mut last_poll = Instant::now();
+                    let mut failed_name: Option<String> = None; // 避免对同一个不可用默认设备反复报错
+                    let mut lost_reported = false;
+                    // 等待停止 / 切换信号或标志
                     while flag.load(Ordering::Relaxed) {
-                        if stop_rx
-                            .recv_timeout(std::time::Duration::from_millis(200))
-                            .is_ok()
-                        {
-                            break;
+                        // 设备被拔出: 停止采集并通知界面, 之后每次轮询尝试重新绑定默认输入 (含重新插入的同一设备)
+                        let lost = handle.lost.load(Ordering::Relaxed);
+                        if lost && !lost_reported {
+                            log::warn!("[SERVER][INPUT] input device {current_name} disconnected -> capture stopped");
+                            let _ = handle.stream.pause();
+                            *srv_state.input_lost.lock() = Some(current_name.clone());
+                            lost_reported = true;
+                        }
+                        let new_dev = match stop_rx.recv_timeout(std::time::Duration::from_millis(200)) {
+                            Ok(server::InputCommand::Stop) => break,
+                            // 手动切换 (运行中选择了其他输入设备)
+                            Ok(server::InputCommand::Switch(name)) => match audio::input_device_by_name(&name) {
+                                Some(d) => d,
+                                None => { log::warn!("[SERVER][INPUT] input device {name} not found; keeping {current_name}"); continue; }
+                            },
+                            Err(_) => {
+                                if !(lost || follow_default.load(Ordering::Relaxed)) || last_poll.elapsed() < FOLLOW_DEFAULT_POLL { continue; }
+                                last_poll = Instant::now();
+                                // 默认设备暂时不存在 (如拔出坞站): 保持当前流, 下次轮询再试
+                                let Some(d) = audio::default_input_device() else { continue };
+                                if !lost && failed_name.as_deref() == Some(audio::capture_name(&d).as_str()) { continue; }
+                                d
+                            }
+                        };
+                        let new_name = audio::capture_name(&new_dev);
+                        if new_name == current_name && !lost { continue; }
+                        log::info!("[SERVER][INPUT] switching input: {current_name} -> {new_name}");
+                        // 先暂停旧流, 避免两个流同时写入缓冲池 (短暂断音可接受)
+                        if let Err(e) = handle.stream.pause() { log::warn!("[SERVER][INPUT] pause err: {e}"); }
+                        match audio::build_input_stream(&new_dev, pool.clone(), tx.clone(), flag.clone(), srv_state.gain.clone(), srv_state.muted.clone(), srv_state.agc.clone(), srv_state.gate.clone(), srv_state.capture_frames, &srv_state.input_channels) {
+                            Ok(new_handle) => {
+                                handle = new_handle;
+                                // 各客户端控制线程发现参数变化后推送 PARAMS
+                                *srv_state.audio_params.lock() = Some(handle.params.clone());
+                                current_name = new_name;
+                                failed_name = None;
+                                lost_reported = false;
+                            }
+                            Err(e) => {
+                                log::warn!("[SERVER][INPUT] switch to {new_name} failed: {e}; keeping {current_name}");
+                                if !lost { if let Err(e) = handle.stream.play() { log::warn!("[SERVER][INPUT] resume err: {e}"); } }
+                                failed_name = Some(new_name);
+                            }
                         }
                     }
                     // 精确停止: pause
                     if let Err(e) = handle.stream.pause() {
-                        eprintln!("[SERVER][INPUT] pause err: {e}");
+                        log::warn!("[SERVER][INPUT] pause err: {e}");
                     }
-                    println!("[SERVER][INPUT] stream paused & thread exit");
+                    log::info!("[SERVER][INPUT] stream paused & thread exit");
                 }
                 Err(e) => {
-                    eprintln!("build input stream failed: {e}");
+                    log::error!("build input stream failed: {e}");
                 }
             }
         } else {
-            eprintln!("No input device found for selected index {sel}");
+            log::error!("[SERVER][INPUT] no input device: {sel:?} not found and no system default input");
         }
-    });
+    })?;
     Ok(())
 }
 
+/// How often the capture thread checks the OS default input in follow mode.
+const FOLLOW_DEFAULT_POLL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Lowest port accepted in the server port field (below needs privileges on most systems).
+const SERVER_PORT_MIN: u16 = 1024;
+
+/// Server port field text -> port, `None` unless it is a number in `SERVER_PORT_MIN..=65535`.
+fn parse_server_port(text: &str) -> Option<u16> { text.trim().parse::<u16>().ok().filter(|&p| p >= SERVER_PORT_MIN) }
+
+/// Input channel field ("3", "1,2"; 1-based, empty = all) -> 0-based device channels; None if malformed.
+fn parse_input_channels(text: &str) -> Option<Vec<u16>> {
+    text.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|s| s.parse::<u16>().ok()?.checked_sub(1)).collect()
+}
+
+/// Self test outcome -> dialog text and level.
+fn self_test_message(res: Result<selftest::Report>) -> (String, rfd::MessageLevel) {
+    match res {
+        Ok(r) => {
+            let level = format!("{:.1}", if r.peak_rms > 0.0 { 20.0 * r.peak_rms.log10() } else { -60.0 });
+            let path = lang::tr(if r.multicast { "selftest.path.multicast" } else { "selftest.path.unicast" });
+            if r.passed() { (lang::tr_args("selftest.passed", &[("level", &level), ("path", &path)]), rfd::MessageLevel::Info) }
+            else { (lang::tr_args("selftest.silent", &[("level", &level), ("path", &path)]), rfd::MessageLevel::Error) }
+        }
+        Err(e) => (lang::tr_args("selftest.failed", &[("error", &format!("{e:#}"))]), rfd::MessageLevel::Error),
+    }
+}
+
+/// Address to share for the running server: the bind address, or the first routable interface
+/// address when bound to 0.0.0.0 / ::.
+fn share_ip(list: &[String], sel: usize) -> Option<std::net::IpAddr> {
+    let ips = || list.iter().filter_map(|s| s.parse::<std::net::IpAddr>().ok());
+    let bound = list.get(sel).and_then(|s| s.parse::<std::net::IpAddr>().ok()).filter(|ip| !ip.is_unspecified());
+    bound.or_else(|| ips().find(|ip| !ip.is_unspecified() && !ip.is_loopback())).or_else(|| ips().find(|ip| !ip.is_unspecified()))
+}
+
+/// QR code for `text` as an inline SVG (black on white).
+fn qr_svg(text: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(text.as_bytes()).map_err(|e| log::warn!("[GUI] QR code: {e}")).ok()?;
+    Some(code.render::<qrcode::render::svg::Color>().min_dimensions(160, 160).quiet_zone(false).build())
+}
+
+/// How long a toast stays up.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+
+/// Capture gain slider range (dB).
+const GAIN_DB_MIN: f64 = -24.0;
+const GAIN_DB_MAX: f64 = 24.0;
+
+/// Keyring accounts for the remembered server / client PSK.
+const PSK_ACCOUNT_SERVER: &str = "server";
+const PSK_ACCOUNT_CLIENT: &str = "client";
+
+/// PSK precedence: typed > file > keyring (if remembered). `None` = no encryption.
+fn psk_source(typed: &str, file: &Option<std::path::PathBuf>, saved: bool, account: &str) -> Option<secret::PskSource> {
+    if !typed.trim().is_empty() { Some(secret::PskSource::Inline(typed.trim().to_string())) }
+    else if let Some(path) = file { Some(secret::PskSource::File(path.clone())) }
+    else if saved { Some(secret::PskSource::Keyring { account: account.into() }) }
+    else { None }
+}
+
+/// After a successful start/connect: move a typed PSK into the keyring (when asked) and drop it from GUI memory.
+fn remember_psk(typed: &mut String, remember: bool, saved: &mut bool, account: &str) {
+    if !remember || typed.trim().is_empty() { return; }
+    match secret::store_keyring(account, typed.trim()) {
+        Ok(()) => { *saved = true; typed.clear(); }
+        Err(e) => log::warn!("[PSK] keyring store failed: {e:#}"),
+    }
+}
+
+/// "Remember" unchecked: remove the stored PSK.
+fn forget_psk(saved: &mut bool, account: &str) {
+    if !*saved { return; }
+    match secret::forget_keyring(account) { Ok(()) => *saved = false, Err(e) => log::warn!("[PSK] keyring delete failed: {e:#}") }
+}
+
+/// PSK input placeholder: chosen file name, "saved in keyring", or optional.
+fn psk_placeholder(file: &Option<std::path::PathBuf>, saved: bool) -> String {
+    if let Some(name) = file.as_ref().and_then(|p| p.file_name()) { name.to_string_lossy().to_string() }
+    else if saved { lang::tr("psk.saved") } else { "(可选)".into() }
+}
+
+/// Client connection ended (`DISCONNECT:` event, or a heartbeat timeout without auto-reconnect).
+fn client_lost(mut st: Signal<AppState>, reason: &str) {
+    let mut w = st.write();
+    if w.error_message.is_none() { w.error_message = Some(lang::tr_args("client.disconnected", &[("reason", reason)])); }
+    w.client_state = None; // 清理状态
+    w.reconnect_cancel = None;
+    w.reconnect_attempt = None;
+}
+
+/// Characters kept while typing a server host: IPv4 / IPv6 literals (brackets allowed) and DNS names.
+fn is_host_char(c: char) -> bool { c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '[' | ']') }
+
+/// What a server address field received: a pasted `remotemic://` URL, or a host as typed.
+enum ServerField { Url(net::ConnectionUrl), Host(String) }
+
+/// Server address field input (main and additional connections): URLs go through the same
+/// `net::parse_host` check as a typed host; typed text keeps only host characters.
+fn server_field_input(raw: &str) -> anyhow::Result<ServerField> {
+    if raw.contains("://") { return raw.parse().map(ServerField::Url); }
+    Ok(ServerField::Host(raw.chars().filter(|c| is_host_char(*c)).take(net::MAX_HOST_LEN).collect()))
+}
+
+/// `OutputLost` event -> message (`new: None` = playback stopped).
+fn output_lost_text(old: &str, new: Option<&str>) -> String {
+    match new { None => lang::tr_args("client.output_lost", &[("name", old)]), Some(new) => lang::tr_args("client.output_switched", &[("old", old), ("new", new)]) }
+}
+
+/// Connect an additional client from the extra-connection form (same handshake timeout and transport
+/// as the main connection). Its events are handled by a task of its own: a disconnect or heartbeat
+/// timeout removes the entry.
+fn connect_extra(mut st: Signal<AppState>) {
+    let (ip, port_str, sel_out, psk, timeout, transport) = { let r = st.read(); (r.extra_ip.trim().to_string(), r.extra_port.trim().to_string(), r.extra_output.clone(), r.extra_psk.trim().to_string(), Duration::from_secs(r.client_handshake_timeout), r.client_transport) };
+    if ip.is_empty() || port_str.is_empty() { st.write().error_message = Some(lang::tr("error.client.missing_fields")); return; }
+    if net::parse_host(&ip).is_none() { st.write().error_message = Some(lang::tr("error.client.invalid_ip")); return; }
+    let port: u16 = match port_str.parse() { Ok(p) if p > 0 => p, _ => { st.write().error_message = Some(lang::tr("error.client.invalid_port")); return; } };
+    let id = { let mut w = st.write(); w.extra_connecting = true; w.next_extra_id += 1; w.next_extra_id };
+    let output = sel_out.clone();
+    let label = format!("{} → {output}", net::host_port(&ip, port));
+    let psk_src = (!psk.is_empty()).then_some(secret::PskSource::Inline(psk));
+    let (ev_tx, mut ev_rx) = unbounded_channel::<ClientEvent>();
+    spawn(async move {
+        while let Some(ev) = ev_rx.recv().await {
+            match ev {
+                ClientEvent::Disconnected(reason) | ClientEvent::TimedOut(reason) => {
+                    let mut w = st.write();
+                    let Some(pos) = w.extra_clients.iter().position(|x| x.id == id) else { continue };
+                    let x = w.extra_clients.remove(pos);
+                    if w.error_message.is_none() { w.error_message = Some(lang::tr_args("client.extra.lost", &[("name", &x.label), ("reason", &reason)])); }
+                }
+                ClientEvent::OutputLost { old, new } => st.write().error_message = Some(output_lost_text(&old, new.as_deref())),
+                _ => {}
+            }
+        }
+    });
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let _ = std::thread::Builder::new().name("rm-connect".into()).spawn(move || { let _ = tx.send(client::connect_with_output(ip, port, device_choice(&sel_out), psk_src, Some(ev_tx), timeout, transport)); });
+    spawn(async move {
+        let res = rx.await.unwrap_or_else(|_| Err(anyhow::anyhow!("connect thread exited")));
+        let mut w = st.write(); w.extra_connecting = false;
+        match res {
+            Ok(cs) => { w.extra_psk.clear(); w.apply_client_options(&cs); w.extra_clients.push(ExtraClient { id, label, state: cs }); }
+            Err(e) => w.error_message = Some(format!("{}: {e}", lang::tr("client.extra.failed"))),
+        }
+    });
+}
+
+/// Retry the timed-out connection in the background (`client::reconnect`, blocking backoff);
+/// the Disconnect button sets the cancel flag.
+fn start_reconnect(mut st: Signal<AppState>, target: client::ConnectTarget, events: Option<UnboundedSender<ClientEvent>>) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    { let mut w = st.write(); w.client_state = None; w.reconnect_attempt = None; w.reconnect_cancel = Some(cancel.clone()); }
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let worker_cancel = cancel.clone();
+    let _ = std::thread::Builder::new().name("rm-reconnect".into()).spawn(move || { let _ = tx.send(client::reconnect(&target, events, &worker_cancel)); });
+    spawn(async move {
+        let res = rx.await.ok().flatten();
+        let mut w = st.write();
+        if cancel.load(Ordering::Relaxed) { if let Some(cs) = res { client::disconnect(&cs); } return; }
+        w.reconnect_cancel = None; w.reconnect_attempt = None;
+        if let Some(cs) = res { log::info!("[GUI] reconnected"); w.apply_client_options(&cs); w.client_state = Some(cs); }
+    });
+}
+
+/// "Mono" / "Stereo" / "N ch" for the metrics CH field.
+fn channel_layout_label(channels: u16) -> String {
+    match channels { 1 => lang::tr("audio.mono"), 2 => lang::tr("audio.stereo"), n => format!("{n} ch") }
+}
+
+/// One thin VU bar per channel (L/R for stereo, 1..N otherwise); nothing for mono sources.
+fn channel_meter_rows(levels: Vec<f64>, label_width: u32) -> Element {
+    if levels.len() < 2 { return rsx!(div {}) }
+    let stereo = levels.len() == 2;
+    rsx!(div { style: "display:flex;flex-direction:column;gap:2px;",
+        { levels.into_iter().enumerate().map(|(i, rms)| {
+            let label = if stereo { ["L", "R"][i].to_string() } else { (i + 1).to_string() };
+            let db = if rms > 0.0 { 20.0 * rms.log10() } else { -60.0 }; let norm = rms.sqrt().min(1.0);
+            rsx!(div { key: "{i}", style: "display:flex;align-items:center;gap:8px;",
+                span { style: format!("font-size:10px;min-width:{label_width}px;color:#888;text-align:right;"), "{label}" }
+                div { style: "flex:1;height:5px;background:#2d2d2d;border-radius:3px;overflow:hidden;",
+                    div { style: format!("height:100%;width:{:.2}%;background:linear-gradient(90deg,#2e8b57,#f0ad4e,#d9534f);", norm * 100.0) }
+                }
+                span { style: "font-size:10px;width:60px;text-align:right;color:#999;", { format!("{:.1} dB", db) } }
+            })
+        }) }
+    })
+}
+
+/// Spectrum bars (heights 0..1, low to high frequency) as SVG rects.
+fn spectrum_view(bars: &[f32]) -> Element {
+    if bars.is_empty() { return rsx!(div {}) }
+    let n = bars.len();
+    rsx!(svg { style: "width:100%;height:48px;background:#1b1b1b;border-radius:4px;", view_box: "0 0 {n} 1", preserve_aspect_ratio: "none",
+        { bars.iter().enumerate().map(|(i, &h)| rsx!( rect { key: "sp{i}", x: "{i as f32 + 0.1}", y: "{1.0 - h}", width: "0.8", height: "{h}", fill: "#2e8b57" } )) }
+    })
+}
+
+/// Points drawn by `scope_view` (each one the largest-magnitude sample of its slice of the ring).
+const SCOPE_POINTS: usize = 256;
+
+/// Live waveform of the latest samples (`meter::Scope` snapshot) as an SVG polyline.
+fn scope_view(samples: Vec<f32>) -> Element {
+    let step = (samples.len() / SCOPE_POINTS).max(1);
+    let points: String = samples.chunks(step).enumerate().map(|(i, c)| {
+        let v = c.iter().copied().fold(0f32, |a, s| if s.abs() > a.abs() { s } else { a });
+        format!("{i},{:.3} ", -v.clamp(-1.0, 1.0))
+    }).collect();
+    let width = samples.len().div_ceil(step);
+    rsx!(svg { style: "width:100%;height:40px;background:#1b1b1b;border-radius:4px;", view_box: "0 -1 {width} 2", preserve_aspect_ratio: "none",
+        line { x1: "0", y1: "0", x2: "{width}", y2: "0", stroke: "#333", stroke_width: "1", vector_effect: "non-scaling-stroke" }
+        polyline { points: "{points}", fill: "none", stroke: "#5bc0de", stroke_width: "1.2", vector_effect: "non-scaling-stroke" }
+    })
+}
+
+/// Minutes text box -> limit in seconds (empty / invalid = 0 = unlimited).
+fn session_limit_secs(minutes: &str) -> u64 { minutes.trim().parse::<u64>().unwrap_or(0).saturating_mul(60) }
+
+/// Remaining session time row (hidden when unlimited); turns red inside the warning window.
+fn session_remaining_row(session: &crate::session::SessionLimit) -> Element {
+    let Some(left) = session.remaining() else { return rsx!(div {}) };
+    let secs = left.as_secs();
+    let color = if left <= crate::session::SESSION_WARN_BEFORE { "#ff7676" } else { "#aaa" };
+    rsx!(div { style: format!("font-size:11px;color:{color};"), { format!("{}: {:02}:{:02}:{:02}", lang::tr("session.remaining"), secs / 3600, secs / 60 % 60, secs % 60) } })
+}
+
+/// Server bind address choices: unspecified first, then interface addresses (IPv4 only unless `ipv6`;
+/// IPv6 link-local fe80::/10 is skipped since binding it needs a scope id), plus the default selection
+/// (first routable address, preferring IPv6 when `ipv6`).
+fn interface_ips(ipv6: bool) -> (Vec<String>, usize) {
+    let mut ips: Vec<std::net::IpAddr> = get_if_addrs::get_if_addrs()
+        .map(|ifs| ifs.into_iter().map(|i| i.ip()).filter(|ip| match ip {
+            std::net::IpAddr::V4(_) => true,
+            std::net::IpAddr::V6(v6) => ipv6 && v6.segments()[0] & 0xffc0 != 0xfe80,
+        }).collect())
+        .unwrap_or_default();
+    ips.sort();
+    ips.dedup();
+    let mut list: Vec<String> = vec!["0.0.0.0".into()];
+    if ipv6 { list.push("::".into()); }
+    list.extend(ips.iter().filter(|ip| !ip.is_unspecified()).map(|ip| ip.to_string()));
+    let routable = |ip: &&std::net::IpAddr| !ip.is_unspecified() && !ip.is_loopback();
+    let pick = ips.iter().filter(routable).find(|ip| ip.is_ipv6() == ipv6).or_else(|| ips.iter().find(routable));
+    let default_sel = pick.and_then(|ip| list.iter().position(|s| *s == ip.to_string())).unwrap_or(0);
+    (list, default_sel)
+}
+
+/// Selected device name -> connect / settings argument (empty = none selected = system default).
+fn device_choice(name: &str) -> Option<String> { (!name.is_empty()).then(|| name.to_string()) }
+
+/// Device dropdown label: append the localized "(default)" marker to the system default device.
+fn device_label(name: &str, default_name: Option<&str>) -> String {
+    if default_name == Some(name) { format!("{name} {}", lang::tr("audio.default_suffix")) } else { name.to_string() }
+}
+
 /// Shared inline style for panel container.
 fn panel_style() -> &'static str {
     "position:relative;border:1px solid var(--color-border);padding:14px 14px 12px 14px;margin:18px 0 10px 0;border-radius:var(--radius-lg);display:flex;flex-direction:column;gap:12px;background:var(--color-panel);"
@@ -0,0 +1,73 @@
+//! LAN server discovery over mDNS / DNS-SD (`_remotemic._udp.local.`).
+//!
+//! The server advertises its control port plus TXT `enc=1|0` (PSK required); clients browse for
+//! `BROWSE_WINDOW` and list every resolved instance. Best effort: networks that drop multicast
+//! simply find nothing and the address is typed in by hand as before.
+use std::{net::Ipv4Addr, time::{Duration, Instant}};
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::server::ServerState;
+
+/// DNS-SD service type.
+pub const SERVICE_TYPE: &str = "_remotemic._udp.local.";
+/// How long `browse` listens for answers.
+pub const BROWSE_WINDOW: Duration = Duration::from_secs(2);
+
+/// One server found by `browse` (one entry per advertised IPv4 address).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredServer { pub name: String, pub ip: Ipv4Addr, pub port: u16, pub encrypted: bool }
+
+/// Running advertisement; dropping it withdraws the service.
+pub struct Advertisement { daemon: ServiceDaemon, fullname: String }
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // Give the daemon a moment to send the goodbye packet before shutting down
+        if let Ok(rx) = self.daemon.unregister(&self.fullname) { let _ = rx.recv_timeout(Duration::from_millis(300)); }
+        let _ = self.daemon.shutdown();
+        log::info!("[DISCOVERY] advertisement withdrawn");
+    }
+}
+
+/// Advertise a started server (`state.control_port`, addresses follow the host's interfaces).
+pub fn start_advertise(state: &ServerState) -> Result<Advertisement> {
+    let host = std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).unwrap_or_else(|_| "remote-mic".into());
+    let host: String = host.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' }).collect();
+    let port = state.control_port;
+    let instance = format!("Remote-Mic {host} {port}");
+    let enc = if state.encrypted() { "1" } else { "0" };
+    let info = ServiceInfo::new(SERVICE_TYPE, &instance, &format!("{host}.local."), "", port, &[("enc", enc)][..])
+        .context("mdns service info")?
+        .enable_addr_auto();
+    let fullname = info.get_fullname().to_string();
+    let daemon = ServiceDaemon::new().context("start mdns daemon")?;
+    daemon.register(info).context("mdns register")?;
+    log::info!("[DISCOVERY] advertising {fullname} (port {port}, enc={enc})");
+    Ok(Advertisement { daemon, fullname })
+}
+
+/// Listen for `BROWSE_WINDOW` and return the servers that resolved, sorted by name (blocking).
+pub fn browse() -> Result<Vec<DiscoveredServer>> {
+    let daemon = ServiceDaemon::new().context("start mdns daemon")?;
+    let rx = daemon.browse(SERVICE_TYPE).context("mdns browse")?;
+    let deadline = Instant::now() + BROWSE_WINDOW;
+    let mut found: Vec<DiscoveredServer> = Vec::new();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match rx.recv_timeout(left) {
+            Ok(ServiceEvent::ServiceResolved(info)) => {
+                let name = info.get_fullname().trim_end_matches(SERVICE_TYPE).trim_end_matches('.').to_string();
+                let encrypted = info.get_property_val_str("enc") == Some("1");
+                for &ip in info.get_addresses_v4() {
+                    let entry = DiscoveredServer { name: name.clone(), ip, port: info.get_port(), encrypted };
+                    if !found.contains(&entry) { found.push(entry); }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    let _ = daemon.shutdown();
+    found.sort_by(|a, b| a.name.cmp(&b.name).then(a.ip.cmp(&b.ip)));
+    Ok(found)
+}
@@ -0,0 +1,100 @@
+//! Silence suppression (DTX) on the wire: once the captured level has stayed under a threshold for
+//! `HANGOVER`, audio frames are no longer sent. A header-only silence marker (docs/protocol.md §2.5)
+//! goes out instead, repeated every `MARKER_INTERVAL`, until a buffer at or above the threshold
+//! resumes full frames.
+//!
+//! Suppressed buffers take no seq number, so clients see no gap in the audio seq and count no loss.
+use std::{sync::{atomic::AtomicBool, Arc}, time::{Duration, Instant}};
+
+use crate::server::AtomicF64;
+
+/// Threshold slider range (dB RMS).
+pub const THRESHOLD_DB_MIN: f64 = -80.0;
+pub const THRESHOLD_DB_MAX: f64 = -30.0;
+/// Quiet time before sending stops (keeps word endings and short pauses on the wire).
+const HANGOVER: Duration = Duration::from_millis(400);
+/// Silence markers while suppressed (keepalive: clients know the stream is alive, just silent).
+const MARKER_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Live DTX settings shared with the multicast loop (read every buffer).
+#[derive(Clone)]
+pub struct DtxSettings {
+    pub enabled: Arc<AtomicBool>,
+    pub threshold_db: Arc<AtomicF64>,
+    /// Frames currently suppressed (for the UI)
+    pub suppressing: Arc<AtomicBool>,
+}
+
+impl DtxSettings {
+    pub fn new() -> Self {
+        Self { enabled: Arc::new(AtomicBool::new(false)), threshold_db: Arc::new(AtomicF64::new(-55.0)), suppressing: Arc::new(AtomicBool::new(false)) }
+    }
+}
+
+impl Default for DtxSettings { fn default() -> Self { Self::new() } }
+
+/// What to put on the wire for one captured buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DtxAction {
+    /// Normal audio frame(s)
+    Send,
+    /// Nothing (suppressed)
+    Skip,
+    /// Silence marker instead of audio
+    Marker,
+}
+
+/// Per-stream DTX state.
+#[derive(Default)]
+pub struct Dtx { quiet_since: Option<Instant>, last_marker: Option<Instant> }
+
+impl Dtx {
+    pub fn new() -> Self { Self::default() }
+
+    /// Decide for one buffer with linear `rms`.
+    pub fn process(&mut self, rms: f64, threshold_db: f64, now: Instant) -> DtxAction {
+        let db = if rms > 0.0 { 20.0 * rms.log10() } else { f64::NEG_INFINITY };
+        if db >= threshold_db { *self = Self::default(); return DtxAction::Send; }
+        let since = *self.quiet_since.get_or_insert(now);
+        if now.duration_since(since) < HANGOVER { return DtxAction::Send; }
+        if self.last_marker.is_some_and(|t| now.duration_since(t) < MARKER_INTERVAL) { return DtxAction::Skip; }
+        self.last_marker = Some(now);
+        DtxAction::Marker
+    }
+
+    /// Nothing to send (push-to-talk released): markers right away, no hangover.
+    pub fn idle(&mut self, now: Instant) -> DtxAction {
+        self.quiet_since.get_or_insert(now);
+        if self.last_marker.is_some_and(|t| now.duration_since(t) < MARKER_INTERVAL) { return DtxAction::Skip; }
+        self.last_marker = Some(now);
+        DtxAction::Marker
+    }
+
+    /// Back to sending (DTX off): the next quiet stretch starts a fresh hangover.
+    pub fn reset(&mut self) { *self = Self::default(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_stream_turns_into_periodic_markers_until_speech() {
+        let db = |v: f64| 10f64.powf(v / 20.0);
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        let mut dtx = Dtx::new();
+        assert_eq!(dtx.process(db(-30.0), -55.0, at(0)), DtxAction::Send);
+        // Hangover: short pauses still go out as audio
+        assert_eq!(dtx.process(db(-70.0), -55.0, at(10)), DtxAction::Send);
+        assert_eq!(dtx.process(db(-70.0), -55.0, at(300)), DtxAction::Send);
+        // Then one marker, nothing until the next interval, another marker
+        assert_eq!(dtx.process(db(-70.0), -55.0, at(410)), DtxAction::Marker);
+        assert_eq!(dtx.process(0.0, -55.0, at(420)), DtxAction::Skip);
+        assert_eq!(dtx.process(db(-70.0), -55.0, at(900)), DtxAction::Skip);
+        assert_eq!(dtx.process(db(-70.0), -55.0, at(910)), DtxAction::Marker);
+        // Speech resumes immediately and restarts the hangover
+        assert_eq!(dtx.process(db(-40.0), -55.0, at(920)), DtxAction::Send);
+        assert_eq!(dtx.process(db(-70.0), -55.0, at(930)), DtxAction::Send);
+    }
+}
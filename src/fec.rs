@@ -0,0 +1,132 @@
+//! XOR parity FEC for multicast frames (docs/protocol.md §2.4).
+//!
+//! After every `k` consecutive sealed frames the server sends one parity frame: the XOR of the `k`
+//! packets, each taken as `len(u16) | packet` and zero-padded to the longest. A client missing
+//! exactly one packet of the group XORs the parity with the others to get it back (length prefix
+//! included) and feeds it through the normal path, so decryption still authenticates it. Works on
+//! the sealed bytes: no key needed, and PCM / Opus frames of any length mix freely.
+use std::collections::VecDeque;
+
+use crate::types;
+
+/// Parity header: magic(2) | first_seq(u32) | k(u8); the XOR body follows.
+pub const FEC_HEADER_LEN: usize = 7;
+/// Largest group size accepted (from the server setting and in parity headers).
+pub const FEC_MAX_GROUP: u8 = 16;
+/// Received packets kept for recovery (two full groups of the largest size).
+const HISTORY: usize = 2 * FEC_MAX_GROUP as usize;
+
+fn frame_seq(packet: &[u8]) -> Option<u32> { packet.get(2..6).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])) }
+
+/// XOR `len(u16) | packet` into `acc`, growing it with zeros as needed.
+fn xor_into(acc: &mut Vec<u8>, packet: &[u8]) {
+    if acc.len() < packet.len() + 2 { acc.resize(packet.len() + 2, 0); }
+    let len = (packet.len() as u16).to_be_bytes();
+    acc[0] ^= len[0]; acc[1] ^= len[1];
+    for (a, b) in acc[2..].iter_mut().zip(packet) { *a ^= b; }
+}
+
+/// Server side: accumulates one group and emits its parity frame.
+#[derive(Default)]
+pub struct FecEncoder { first_seq: u32, count: u8, parity: Vec<u8> }
+
+impl FecEncoder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Add one sent packet; returns the parity frame when it completes a group of `k` (0 = FEC off).
+    /// A seq gap (or a change of `k`) starts a new group.
+    pub fn push(&mut self, k: u8, packet: &[u8]) -> Option<Vec<u8>> {
+        let seq = frame_seq(packet)?;
+        if k == 0 || k > FEC_MAX_GROUP { self.count = 0; return None; }
+        if self.count > 0 && seq != self.first_seq.wrapping_add(self.count as u32) || self.count >= k { self.count = 0; }
+        if self.count == 0 { self.first_seq = seq; self.parity.clear(); }
+        xor_into(&mut self.parity, packet);
+        self.count += 1;
+        if self.count < k { return None; }
+        self.count = 0;
+        let mut frame = Vec::with_capacity(FEC_HEADER_LEN + self.parity.len());
+        frame.extend_from_slice(&types::FRAME_MAGIC_FEC);
+        frame.extend_from_slice(&self.first_seq.to_be_bytes());
+        frame.push(k);
+        frame.extend_from_slice(&self.parity);
+        Some(frame)
+    }
+}
+
+/// Client side: recent packets by seq plus recovery of single losses.
+#[derive(Default)]
+pub struct FecDecoder { packets: VecDeque<(u32, Vec<u8>)>, group: u8 }
+
+impl FecDecoder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Group size of the latest parity frame (0 before the first one).
+    pub fn group(&self) -> u8 { self.group }
+
+    /// Remember a received packet; false if this seq is already known (duplicate, or recovered before
+    /// the original turned up late).
+    pub fn store(&mut self, seq: u32, packet: &[u8]) -> bool {
+        if self.packets.iter().any(|(s, _)| *s == seq) { return false; }
+        let mut copy = if self.packets.len() >= HISTORY { self.packets.pop_front().map(|(_, v)| v).unwrap_or_default() } else { Vec::new() };
+        copy.clear(); copy.extend_from_slice(packet);
+        self.packets.push_back((seq, copy));
+        true
+    }
+
+    /// Rebuild the packet missing from the parity's group; None unless exactly one is missing.
+    pub fn recover(&mut self, parity: &[u8]) -> Option<Vec<u8>> {
+        if parity.len() < FEC_HEADER_LEN + 2 || parity[0..2] != types::FRAME_MAGIC_FEC { return None; }
+        let first = u32::from_be_bytes([parity[2], parity[3], parity[4], parity[5]]);
+        let k = parity[6];
+        if k == 0 || k > FEC_MAX_GROUP { return None; }
+        self.group = k;
+        let mut acc = parity[FEC_HEADER_LEN..].to_vec();
+        let mut missing = None;
+        for i in 0..k as u32 {
+            let seq = first.wrapping_add(i);
+            match self.packets.iter().find(|(s, _)| *s == seq) {
+                Some((_, p)) => xor_into(&mut acc, p),
+                None if missing.is_none() => missing = Some(seq),
+                None => return None,
+            }
+        }
+        missing?;
+        let len = u16::from_be_bytes([acc[0], acc[1]]) as usize;
+        if len + 2 > acc.len() { return None; }
+        acc.drain(..2); acc.truncate(len);
+        // Only trust it if it claims the seq we lost
+        (frame_seq(&acc) == missing).then_some(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seq: u32, len: usize) -> Vec<u8> {
+        let mut p = types::FRAME_MAGIC.to_vec();
+        p.extend_from_slice(&seq.to_be_bytes());
+        p.extend((0..len).map(|i| (i as u32 * 7).wrapping_add(seq) as u8));
+        p
+    }
+
+    #[test]
+    fn single_loss_is_rebuilt_from_parity() {
+        let mut enc = FecEncoder::new();
+        let sent: Vec<Vec<u8>> = [40, 17, 90, 3].iter().enumerate().map(|(i, &len)| packet((u32::MAX - 1).wrapping_add(i as u32), len)).collect();
+        let parity = sent.iter().filter_map(|p| enc.push(4, p)).last().expect("parity after 4 packets");
+        for lost in 0..sent.len() {
+            let mut dec = FecDecoder::new();
+            for (i, p) in sent.iter().enumerate() { if i != lost { assert!(dec.store(frame_seq(p).unwrap(), p)); } }
+            assert_eq!(dec.recover(&parity).as_ref(), Some(&sent[lost]), "lost packet {lost}");
+            assert_eq!(dec.group(), 4);
+        }
+        // Two losses cannot be repaired; nothing lost -> nothing to rebuild
+        let mut dec = FecDecoder::new();
+        for p in &sent[..2] { dec.store(frame_seq(p).unwrap(), p); }
+        assert_eq!(dec.recover(&parity), None);
+        for p in &sent[2..] { dec.store(frame_seq(p).unwrap(), p); }
+        assert_eq!(dec.recover(&parity), None);
+        assert!(!dec.store(frame_seq(&sent[0]).unwrap(), &sent[0]), "duplicate seq");
+    }
+}
@@ -0,0 +1,80 @@
+//! Capture noise gate: chunks (one input callback each) whose RMS stays under a threshold are
+//! replaced by silence before they enter the buffer pool.
+//!
+//! A chunk at or above the threshold opens the gate immediately. Once open, chunks down to
+//! `HYSTERESIS_DB` below the threshold keep it open, and it only closes after `hold` without such
+//! a chunk, so speech pauses and levels hovering at the threshold do not chatter.
+use std::{sync::{atomic::{AtomicBool, AtomicU64}, Arc}, time::{Duration, Instant}};
+
+use crate::server::AtomicF64;
+
+/// Open gate stays open down to this far below the threshold.
+pub const HYSTERESIS_DB: f64 = 4.0;
+/// Threshold slider range (dB).
+pub const THRESHOLD_DB_MIN: f64 = -80.0;
+pub const THRESHOLD_DB_MAX: f64 = -20.0;
+/// Hold slider range (ms).
+pub const HOLD_MS_MIN: u64 = 50;
+pub const HOLD_MS_MAX: u64 = 2000;
+
+/// Live gate settings shared with the capture callback (read every chunk).
+#[derive(Clone)]
+pub struct GateSettings {
+    pub enabled: Arc<AtomicBool>,
+    pub threshold_db: Arc<AtomicF64>,
+    pub hold_ms: Arc<AtomicU64>,
+    /// Last decision of the running gate (for the UI; true while disabled)
+    pub open: Arc<AtomicBool>,
+}
+
+impl GateSettings {
+    pub fn new() -> Self {
+        Self { enabled: Arc::new(AtomicBool::new(false)), threshold_db: Arc::new(AtomicF64::new(-50.0)), hold_ms: Arc::new(AtomicU64::new(300)), open: Arc::new(AtomicBool::new(true)) }
+    }
+}
+
+impl Default for GateSettings { fn default() -> Self { Self::new() } }
+
+/// Per-stream gate state.
+#[derive(Default)]
+pub struct NoiseGate { open: bool, last_loud: Option<Instant> }
+
+impl NoiseGate {
+    pub fn new() -> Self { Self::default() }
+
+    /// Decide for one chunk with linear `rms`; false = replace the chunk with silence.
+    pub fn process(&mut self, rms: f64, threshold_db: f64, hold: Duration, now: Instant) -> bool {
+        let db = if rms > 0.0 { 20.0 * rms.log10() } else { f64::NEG_INFINITY };
+        let floor = if self.open { threshold_db - HYSTERESIS_DB } else { threshold_db };
+        if db >= floor {
+            self.open = true;
+            self.last_loud = Some(now);
+        } else if self.open && self.last_loud.is_none_or(|t| now.duration_since(t) >= hold) {
+            self.open = false;
+        }
+        self.open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate_opens_on_loud_chunks_and_closes_after_hold() {
+        let db = |v: f64| 10f64.powf(v / 20.0);
+        let hold = Duration::from_millis(300);
+        let t0 = Instant::now();
+        let mut gate = NoiseGate::new();
+        assert!(!gate.process(db(-60.0), -50.0, hold, t0), "hiss stays gated");
+        assert!(gate.process(db(-45.0), -50.0, hold, t0), "speech opens immediately");
+        // Inside the hysteresis band: refreshes the hold
+        assert!(gate.process(db(-52.0), -50.0, hold, t0 + Duration::from_millis(500)));
+        // Quiet, but still within hold of the last loud chunk
+        assert!(gate.process(db(-70.0), -50.0, hold, t0 + Duration::from_millis(700)));
+        assert!(!gate.process(db(-70.0), -50.0, hold, t0 + Duration::from_millis(800)));
+        // Closed again: the hysteresis band no longer opens it
+        assert!(!gate.process(db(-52.0), -50.0, hold, t0 + Duration::from_millis(900)));
+        assert!(!gate.process(0.0, -50.0, hold, t0 + Duration::from_millis(900)));
+    }
+}
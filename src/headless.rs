@@ -0,0 +1,138 @@
+//! Headless server (`--server`) and client (`--client`): the same code paths as the GUI, driven
+//! from the command line for machines without a display. Both print a status line periodically
+//! and stop on Ctrl-C.
+use std::{sync::atomic::Ordering, time::Duration};
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError};
+
+use crate::{audio, buffers::AudioBufferPool, client::{self, ClientEvent}, net, secret::PskSource, server::{self, ServerState}, types::Transport};
+
+/// Interval between status lines.
+const STATUS_EVERY: Duration = Duration::from_secs(5);
+
+/// Command-line server options (see `Cli` in main.rs).
+pub struct ServerOptions { pub bind: String, pub egress: Option<std::net::IpAddr>, pub port: Option<u16>, pub mcast_port: Option<u16>, pub room: Option<String>, pub input: Option<String>, pub buffer_frames: Option<u32>, pub packet_frames: Option<u32>, pub mtu: u16, pub sample_rate: Option<u32>, pub channels: Vec<u16>, pub psk: Option<PskSource>, pub per_client_keys: bool, pub fec: u8 }
+
+/// Command-line client options (see `Cli` in main.rs).
+pub struct ClientOptions { pub host: String, pub port: u16, pub output: Option<String>, pub psk: Option<PskSource>, pub handshake_timeout: Duration, pub transport: Transport }
+
+/// Index of the device called `name` in `devices`: exact match first, then case-insensitive substring.
+fn find_device(devices: &[cpal::Device], name: &str, kind: &str) -> Result<usize> {
+    let names: Vec<String> = devices.iter().map(audio::device_name).collect();
+    let lower = name.to_lowercase();
+    names.iter().position(|n| n == name).or_else(|| names.iter().position(|n| n.to_lowercase().contains(&lower)))
+        .ok_or_else(|| anyhow!("{kind} device {name:?} not found; available: {}", names.join(", ")))
+}
+
+/// Input device by name; `None` = system default.
+fn find_input(name: Option<&str>) -> Result<cpal::Device> {
+    let Some(name) = name else { return audio::default_input_device().context("no default input device") };
+    let (inputs, _) = audio::list_devices(false)?;
+    let idx = find_device(&inputs, name, "input")?;
+    Ok(inputs.into_iter().nth(idx).expect("index from the same list"))
+}
+
+/// Fires once on Ctrl-C.
+fn ctrl_c() -> Result<Receiver<()>> {
+    let (stop_tx, stop_rx) = bounded::<()>(1);
+    ctrlc::set_handler(move || { let _ = stop_tx.try_send(()); }).context("install Ctrl-C handler")?;
+    Ok(stop_rx)
+}
+
+/// Wait one status period; true once Ctrl-C was pressed.
+fn interrupted(stop_rx: &Receiver<()>) -> bool {
+    match stop_rx.recv_timeout(STATUS_EVERY) {
+        Ok(()) | Err(RecvTimeoutError::Disconnected) => { println!("[HEADLESS] Ctrl-C -> stopping"); true }
+        Err(RecvTimeoutError::Timeout) => false,
+    }
+}
+
+/// Start the server and capture stream, then block until Ctrl-C (or the session limit) and stop.
+pub fn run_server(opts: ServerOptions) -> Result<()> {
+    let mut state = ServerState::new();
+    if let Some(src) = &opts.psk { if state.enable_psk(src)? { println!("[HEADLESS] encryption enabled"); } }
+    if opts.per_client_keys { state.enable_per_client_keys(); println!("[HEADLESS] encryption enabled (per-client keys)"); }
+    state.select_multicast_group(opts.bind.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_ipv6()), opts.room.as_deref());
+    state.fec_k.store(opts.fec, Ordering::Relaxed);
+    state.multicast_if = opts.egress;
+    state.multicast_port = opts.mcast_port.unwrap_or(0);
+    state.capture_frames = opts.buffer_frames.unwrap_or(0);
+    (state.packet_frames, state.mtu) = (opts.packet_frames.unwrap_or(0), opts.mtu);
+    state.output_rate = opts.sample_rate.unwrap_or(0);
+    // --channels is 1-based like the GUI field
+    state.input_channels = opts.channels.iter().map(|&c| c.checked_sub(1).context("--channels starts at 1")).collect::<Result<_>>()?;
+    let dev = find_input(opts.input.as_deref())?;
+    let port = match opts.port { Some(p) => p, None => net::pick_free_port_in(net::DEFAULT_PORT_RANGE)? };
+    let pool = AudioBufferPool::new(64);
+    let (tx, rx) = unbounded();
+    server::start_server(state.clone(), opts.bind.clone(), port, pool.clone(), rx)?;
+    state.input_running.store(true, Ordering::SeqCst);
+    let handle = match audio::build_input_stream(&dev, pool.clone(), tx, state.input_running.clone(), state.gain.clone(), state.muted.clone(), state.agc.clone(), state.gate.clone(), state.capture_frames, &state.input_channels) {
+        Ok(h) => h,
+        Err(e) => { server::stop_server(&state); return Err(e.context("build input stream")); }
+    };
+    let p = handle.params.clone();
+    *state.audio_params.lock() = Some(p.clone());
+    state.stage.store(2, Ordering::SeqCst);
+    println!("[HEADLESS] serving {}:{port} input={:?} ({} Hz, {} ch, {:?}) — Ctrl-C to stop", opts.bind, audio::device_name(&dev), p.sample_rate, p.channels, p.sample_format);
+
+    let stop_rx = ctrl_c()?;
+    loop {
+        if interrupted(&stop_rx) { break; }
+        // Session limit (or any other stop path) already shut the server down
+        if !state.running.load(Ordering::Relaxed) { break; }
+        // Input unplugged: stop with an error so a supervisor can restart on another device
+        if handle.lost.load(Ordering::Relaxed) {
+            server::stop_server(&state);
+            return Err(anyhow!("input device {} disconnected", audio::device_name(&dev)));
+        }
+        let m = state.metrics_snapshot();
+        let db = if m.rms > 0.0 { 20.0 * m.rms.log10() } else { -60.0 };
+        let err = state.send_error.lock().clone().map(|e| format!(" error={e}")).unwrap_or_default();
+        println!("[HEADLESS] clients={} level={db:.1} dB send={:.1}kbps send_errors={} oversized={} capture_drops={} truncated={}{err}", m.client_count, m.kbps, state.send_errors.load(Ordering::Relaxed), state.oversized_frames.load(Ordering::Relaxed), pool.capture_drops(), pool.truncated.load(Ordering::Relaxed));
+    }
+    if let Err(e) = cpal::traits::StreamTrait::pause(&handle.stream) { eprintln!("[HEADLESS] pause input: {e}"); }
+    server::stop_server(&state);
+    Ok(())
+}
+
+/// Connect with playback, then print metrics until Ctrl-C or the connection ends (the
+/// disconnect reason is returned as the error).
+pub fn run_client(opts: ClientOptions) -> Result<()> {
+    // Resolved to the exact device name up front (the client looks it up again by that name)
+    let output = match &opts.output {
+        Some(name) => { let (_, outputs) = audio::list_devices(false)?; Some(audio::device_name(&outputs[find_device(&outputs, name, "output")?])) }
+        None => None,
+    };
+    let (ev_tx, mut ev_rx) = tokio::sync::mpsc::unbounded_channel();
+    let server = net::host_port(&opts.host, opts.port);
+    let cs = client::connect_with_output(opts.host, opts.port, output, opts.psk, Some(ev_tx), opts.handshake_timeout, opts.transport)
+        .with_context(|| format!("connect {server}"))?;
+    if !cs.connected.load(Ordering::Relaxed) { return Err(anyhow!("handshake rejected by {server}")); }
+    println!("[HEADLESS] connected to {server} — Ctrl-C to stop");
+    let stop_rx = ctrl_c()?;
+    let mut lost: Option<String> = None;
+    loop {
+        let stop = interrupted(&stop_rx);
+        while let Ok(ev) = ev_rx.try_recv() {
+            match ev {
+                ClientEvent::Disconnected(reason) | ClientEvent::TimedOut(reason) => lost = Some(reason),
+                ClientEvent::Motd(text) => println!("[HEADLESS] server message: {text}"),
+                ClientEvent::OutputLost { old, new } => println!("[HEADLESS] output device lost: {old} -> {}", new.unwrap_or_default()),
+                ClientEvent::BufferReady { frames, warmup_ms } => println!("[HEADLESS] playing ({frames} frames buffered in {warmup_ms:.1}ms)"),
+                ClientEvent::ChannelsChanged { old, new } => println!("[HEADLESS] source channels {old} -> {new}"),
+                ClientEvent::OutputRestart(n) => println!("[HEADLESS] output stream restarted (#{n})"),
+                ClientEvent::SessionWarning(secs) => println!("[HEADLESS] session time limit in {secs}s"),
+                _ => {}
+            }
+        }
+        if stop { break; }
+        if !cs.connected.load(Ordering::Relaxed) { return Err(anyhow!("disconnected: {}", lost.unwrap_or_else(|| "connection lost".into()))); }
+        let m = cs.metrics_snapshot();
+        println!("[HEADLESS] quality={} latency={:.2}ms rtt/2={:.2}ms jitter={:.2}ms buffer={:.1}/{:.1}/{:.1}ms loss={:.3}% late={} concealed={} fec_recovered={} decrypt_fail={}",
+            m.quality.as_str(), m.latency_ms, cs.rtt_ms.load() / 2.0, m.jitter_ms, m.buffer_ms, m.target_buffer_ms, m.max_buffer_ms, m.loss * 100.0, m.late_drop,
+            cs.plc_concealed.load(Ordering::Relaxed), cs.fec_recovered.load(Ordering::Relaxed), m.decrypt_fail);
+    }
+    client::disconnect(&cs);
+    Ok(())
+}
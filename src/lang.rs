@@ -1,5 +1,8 @@
 //! Simple JSON-based localization loader.
-use std::collections::HashMap;
+//!
+//! Languages are embedded at build time (`build.rs`); a `lang/<code>.json` next to the executable
+//! or in the config dir overrides the embedded one (or adds a new language) without recompiling.
+use std::{collections::HashMap, path::{Path, PathBuf}};
 use serde::Deserialize;
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
@@ -8,13 +11,24 @@ use parking_lot::RwLock;
 pub struct LangMap(HashMap<String, String>);
 
 impl LangMap {
-    /// Fetch key translation or return the key itself if missing.
-    pub fn get(&self, key: &str) -> String {
-        self.0.get(key).cloned().unwrap_or_else(|| key.to_string())
+    /// Translation of `key` in this language, if present.
+    fn lookup(&self, key: &str) -> Option<&String> { self.0.get(key) }
+}
+
+/// Language used for keys the active language lacks (partial translations).
+pub const DEFAULT_FALLBACK_LANG: &str = "en";
+
+/// Active language plus the fallback consulted for its missing keys.
+struct Langs { active: LangMap, fallback_code: String, fallback: Option<LangMap> }
+
+impl Langs {
+    /// Active -> fallback -> the key itself.
+    fn get(&self, key: &str) -> String {
+        self.active.lookup(key).or_else(|| self.fallback.as_ref()?.lookup(key)).cloned().unwrap_or_else(|| key.to_string())
     }
 }
 
-static LANG: OnceCell<RwLock<LangMap>> = OnceCell::new();
+static LANG: OnceCell<RwLock<Langs>> = OnceCell::new();
 
 // Include the generated embedding table from build.rs
 // Provides: pub static EMBEDDED_LANGS: &[(&str, &str)]
@@ -26,30 +40,116 @@ fn parse_embedded(code: &str) -> Option<LangMap> {
     })
 }
 
+/// Directories searched for `<code>.json` before the embedded table: `lang/` next to the
+/// executable, then `lang/` in the config dir (first match wins).
+fn external_dirs() -> Vec<PathBuf> {
+    let exe_dir = std::env::current_exe().ok().and_then(|p| p.parent().map(Path::to_path_buf));
+    exe_dir.into_iter().chain(crate::config::config_dir()).map(|d| d.join("lang")).collect()
+}
+
+/// Codes are file stems: reject anything that could leave the lang directory.
+fn valid_code(code: &str) -> bool {
+    !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn parse_external(code: &str) -> Option<LangMap> {
+    if !valid_code(code) { return None; }
+    external_dirs().into_iter().map(|d| d.join(format!("{code}.json"))).filter(|p| p.is_file()).find_map(|path| {
+        let parsed = std::fs::read_to_string(&path).map_err(|e| e.to_string())
+            .and_then(|raw| serde_json::from_str::<HashMap<String, String>>(&raw).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(map) => Some(LangMap(map)),
+            Err(e) => { log::warn!("[LANG] ignoring {}: {e}", path.display()); None }
+        }
+    })
+}
+
+/// External file if present, else the embedded table.
+fn load(code: &str) -> Option<LangMap> { parse_external(code).or_else(|| parse_embedded(code)) }
+
 /// Initialize global language map (one-time). Subsequent calls are ignored.
 pub fn init_lang(code: &str) {
-    if let Some(map) = parse_embedded(code) { LANG.set(RwLock::new(map)).ok(); }
+    if let Some(map) = load(code) { LANG.set(RwLock::new(Langs { active: map, fallback_code: DEFAULT_FALLBACK_LANG.into(), fallback: load(DEFAULT_FALLBACK_LANG) })).ok(); }
 }
 
-/// Reload (switch) language from embedded table.
+/// Reload (switch) language (external file, else embedded table).
 pub fn reload_lang(code: &str) {
-    if let Some(cell) = LANG.get() { if let Some(map) = parse_embedded(code) { *cell.write() = map; } }
+    if let Some(cell) = LANG.get() { if let Some(map) = load(code) { cell.write().active = map; } }
+}
+
+/// Choose the fallback language (unknown code = no fallback: missing keys show the key).
+pub fn set_fallback_lang(code: &str) {
+    if let Some(cell) = LANG.get() { let mut l = cell.write(); l.fallback_code = code.to_string(); l.fallback = load(code); }
 }
 
-/// Translate a key using the active language map (fallback to key).
+/// Current fallback language code.
+pub fn fallback_lang() -> String { LANG.get().map(|l| l.read().fallback_code.clone()).unwrap_or_else(|| DEFAULT_FALLBACK_LANG.into()) }
+
+/// Translate a key using the active language map (then the fallback language, then the key).
 pub fn tr(key: &str) -> String { LANG.get().map(|l| l.read().get(key)).unwrap_or_else(|| key.to_string()) }
 
-/// List embedded language codes.
+/// Translate a key and replace `{name}` placeholders with the matching `args` value (unknown
+/// placeholders are left as-is; a missing key still falls back to the key).
+pub fn tr_args(key: &str, args: &[(&str, &str)]) -> String { fill(&tr(key), args) }
+
+/// One left-to-right scan: substituted values are copied verbatim, never searched for placeholders.
+fn fill(template: &str, args: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let tail = &rest[open + 1..];
+        let value = tail.find('}').and_then(|close| args.iter().find(|(name, _)| *name == &tail[..close]).map(|(_, v)| (close, *v)));
+        match value {
+            Some((close, v)) => { out.push_str(v); rest = &tail[close + 1..]; }
+            None => { out.push('{'); rest = tail; }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// List language codes: embedded plus any `<code>.json` found in the external directories
+/// (reads the directories: call once, not per render).
 pub fn available_langs() -> Vec<String> {
-    EMBEDDED_LANGS.iter().map(|(c, _)| (*c).to_string()).collect()
+    let mut codes: Vec<String> = EMBEDDED_LANGS.iter().map(|(c, _)| (*c).to_string()).collect();
+    for dir in external_dirs() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        codes.extend(entries.flatten().map(|e| e.path())
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("json"))
+            .filter_map(|p| p.file_stem().and_then(|s| s.to_str()).map(str::to_string))
+            .filter(|c| valid_code(c)));
+    }
+    codes.sort();
+    codes.dedup();
+    codes
 }
 
-/// Fetch the `this.lang` display value from embedded data.
+/// Fetch the `this.lang` display value (external file, else embedded data).
 pub fn lang_display(code: &str) -> String {
-    if let Some((_, raw)) = EMBEDDED_LANGS.iter().find(|(c, _)| *c == code) {
-        if let Ok(map) = serde_json::from_str::<HashMap<String, String>>(raw) {
-            return map.get("this.lang").cloned().unwrap_or_else(|| code.to_string());
-        }
+    load(code).and_then(|map| map.0.get("this.lang").cloned()).unwrap_or_else(|| code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_filled_by_name() {
+        assert_eq!(fill("Disconnected: {reason}", &[("reason", "timeout")]), "Disconnected: timeout");
+        assert_eq!(fill("{old} -> {new} ({old})", &[("new", "B"), ("old", "A")]), "A -> B (A)");
+        assert_eq!(fill("{unknown} stays", &[("reason", "x")]), "{unknown} stays");
+        assert_eq!(fill("{a} / {b}", &[("a", "{b}"), ("b", "{a}")]), "{b} / {a}");
+        assert_eq!(fill("{{name}} {", &[("name", "x")]), "{x} {");
+        assert_eq!(tr_args("no.such.key", &[("reason", "x")]), "no.such.key");
+    }
+
+    #[test]
+    fn missing_keys_fall_back_before_showing_the_key() {
+        let map = |pairs: &[(&str, &str)]| LangMap(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+        let langs = Langs { active: map(&[("a", "A-xx")]), fallback_code: "en".into(), fallback: Some(map(&[("a", "A-en"), ("b", "B-en")])) };
+        assert_eq!(langs.get("a"), "A-xx");
+        assert_eq!(langs.get("b"), "B-en");
+        assert_eq!(langs.get("c"), "c");
     }
-    code.to_string()
 }
@@ -0,0 +1,71 @@
+//! Output soft limiter: the last stage before samples reach the device, so loud transients (and
+//! volume above 100%) are compressed smoothly instead of hard-clipping.
+//!
+//! A linked peak envelope (fast `ATTACK`, moderate `RELEASE`) drives a soft-knee gain curve around
+//! `THRESHOLD_DB`. Below the knee the gain is exactly 1, so normal levels pass untouched; a final
+//! clamp catches what the attack lets through.
+use std::time::Duration;
+
+/// Output ceiling (dBFS) and knee width (dB, centred on the threshold).
+const THRESHOLD_DB: f32 = -1.0;
+const KNEE_DB: f32 = 6.0;
+/// Envelope time constants.
+const ATTACK: Duration = Duration::from_millis(1);
+const RELEASE: Duration = Duration::from_millis(120);
+
+/// Per-stream limiter state (interleaved f32 at the device rate).
+pub struct Limiter { channels: usize, env: f32, attack: f32, release: f32, knee_start: f32 }
+
+impl Limiter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        let coef = |d: Duration| (-1.0 / (rate * d.as_secs_f64())).exp() as f32;
+        Self { channels: channels.max(1), env: 0.0, attack: coef(ATTACK), release: coef(RELEASE), knee_start: 10f32.powf((THRESHOLD_DB - KNEE_DB / 2.0) / 20.0) }
+    }
+
+    /// Multiply by `gain`, limit and clamp to [-1, 1] in place.
+    pub fn process(&mut self, buf: &mut [f32], gain: f32) {
+        for frame in buf.chunks_exact_mut(self.channels) {
+            let peak = frame.iter().fold(0f32, |m, s| m.max(s.abs())) * gain;
+            let coef = if peak > self.env { self.attack } else { self.release };
+            self.env = peak + (self.env - peak) * coef;
+            let g = if self.env > self.knee_start { gain * knee_gain(20.0 * self.env.log10()) } else { gain };
+            for s in frame.iter_mut() { *s = (*s * g).clamp(-1.0, 1.0); }
+        }
+    }
+}
+
+/// Linear gain for an envelope at `db` dBFS: unity below the knee, quadratic through it, then held
+/// at the threshold (infinite ratio).
+fn knee_gain(db: f32) -> f32 {
+    let over = db - THRESHOLD_DB + KNEE_DB / 2.0;
+    let out = if over <= 0.0 { db } else if over < KNEE_DB { db - over * over / (2.0 * KNEE_DB) } else { THRESHOLD_DB };
+    10f32.powf((out - db) / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amp: f32, frames: usize) -> Vec<f32> { (0..frames).map(|i| amp * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 48_000.0).sin()).collect() }
+
+    #[test]
+    fn passes_normal_levels_and_holds_loud_peaks_near_the_threshold() {
+        let mut lim = Limiter::new(48_000, 1);
+        // -10 dBFS: untouched
+        let quiet = sine(0.316, 4800);
+        let mut out = quiet.clone();
+        lim.process(&mut out, 1.0);
+        assert_eq!(out, quiet);
+        // +6 dB (volume 200%): never past full scale, settles at the -1 dBFS ceiling
+        let mut loud = sine(1.0, 9600);
+        lim.process(&mut loud, 2.0);
+        assert!(loud.iter().all(|s| s.abs() <= 1.0));
+        let settled = loud[4800..].iter().fold(0f32, |m, s| m.max(s.abs()));
+        assert!((20.0 * settled.log10() - THRESHOLD_DB).abs() < 0.5, "settled peak {:.2} dBFS", 20.0 * settled.log10());
+        // Released again once the burst is over
+        let mut after = sine(0.316, 48_000);
+        lim.process(&mut after, 1.0);
+        assert!((after[24_000..].iter().fold(0f32, |m, s| m.max(s.abs())) - 0.316).abs() < 1e-3);
+    }
+}
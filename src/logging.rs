@@ -0,0 +1,152 @@
+//! Log sink for the `log` macros: every record goes to a rotating file in the config dir
+//! (`<config dir>/logs/remote-mic.log`, attach it to bug reports) and, when started from a
+//! terminal, to the console as before (warnings and errors on stderr).
+//!
+//! The level applies to this crate; dependencies only log warnings and errors. It can be changed
+//! at runtime (settings, `--log-level`).
+use std::{fs::{self, File, OpenOptions}, io::{IsTerminal, Write}, path::PathBuf, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+
+/// Rotate when the current file would grow past this.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Files kept: `remote-mic.log` plus `remote-mic.1.log` .. `remote-mic.<KEEP-1>.log` (oldest last).
+const KEEP_FILES: usize = 3;
+const FILE_STEM: &str = "remote-mic";
+/// Default level (also the settings default).
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+/// Levels offered in the settings dropdown / accepted by `--log-level`.
+pub const LEVELS: [LevelFilter; 5] = [LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+
+/// Directory holding the log files.
+pub fn log_dir() -> Option<PathBuf> { crate::config::config_dir().map(|d| d.join("logs")) }
+
+/// Current log file (the one being written).
+pub fn log_path() -> Option<PathBuf> { log_dir().map(|d| rotated_path(&d, 0)) }
+
+fn rotated_path(dir: &std::path::Path, n: usize) -> PathBuf {
+    if n == 0 { dir.join(format!("{FILE_STEM}.log")) } else { dir.join(format!("{FILE_STEM}.{n}.log")) }
+}
+
+/// Parse a level name (`error` .. `trace`, case-insensitive; `off` is not offered).
+pub fn parse_level(name: &str) -> Option<LevelFilter> { LevelFilter::from_str(name).ok().filter(|l| LEVELS.contains(l)) }
+
+struct FileSink { dir: PathBuf, file: Option<File>, size: u64 }
+
+impl FileSink {
+    fn open(dir: PathBuf) -> Self {
+        let mut sink = Self { dir, file: None, size: 0 };
+        sink.reopen();
+        sink
+    }
+
+    fn reopen(&mut self) {
+        let path = rotated_path(&self.dir, 0);
+        let opened = fs::create_dir_all(&self.dir).and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+        match opened {
+            Ok(f) => { self.size = f.metadata().map(|m| m.len()).unwrap_or(0); self.file = Some(f); }
+            Err(e) => { eprintln!("[LOG] open {}: {e} -> file logging off", path.display()); self.file = None; }
+        }
+    }
+
+    /// Shift `remote-mic.log` -> `.1.log` -> ... (the oldest is overwritten) and start a new file.
+    fn rotate(&mut self) {
+        self.file = None;
+        for n in (0..KEEP_FILES - 1).rev() {
+            let _ = fs::rename(rotated_path(&self.dir, n), rotated_path(&self.dir, n + 1));
+        }
+        self.reopen();
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.file.is_some() && self.size > 0 && self.size + line.len() as u64 > MAX_FILE_BYTES { self.rotate(); }
+        let Some(f) = self.file.as_mut() else { return };
+        match f.write_all(line.as_bytes()) {
+            Ok(()) => self.size += line.len() as u64,
+            Err(e) => { eprintln!("[LOG] write failed: {e} -> file logging off"); self.file = None; }
+        }
+    }
+}
+
+struct Logger { file: Option<Mutex<FileSink>>, console: bool }
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let own = metadata.target().split("::").next() == Some(env!("CARGO_CRATE_NAME"));
+        metadata.level() <= if own { log::max_level() } else { LevelFilter::Warn }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) { return; }
+        if self.console {
+            if record.level() <= Level::Warn { eprintln!("{}", record.args()); } else { println!("{}", record.args()); }
+        }
+        if let Some(file) = &self.file {
+            file.lock().write(&format!("{} {:<5} {}: {}\n", timestamp(), record.level(), record.target(), record.args()));
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file { if let Some(f) = file.lock().file.as_mut() { let _ = f.flush(); } }
+    }
+}
+
+/// Install the logger (once, at startup) and log panics before the default hook prints them.
+pub fn init(level: LevelFilter) {
+    let logger = Logger { file: log_dir().map(|d| Mutex::new(FileSink::open(d))), console: std::io::stdout().is_terminal() || std::io::stderr().is_terminal() };
+    if let Err(e) = log::set_boxed_logger(Box::new(logger)) { eprintln!("[LOG] {e}"); return; }
+    log::set_max_level(level);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log::error!("[PANIC] {info}");
+        default_hook(info);
+    }));
+    log::info!("[LOG] remote-mic {} level={level} file={}", env!("CARGO_PKG_VERSION"), log_path().map(|p| p.display().to_string()).unwrap_or_else(|| "-".into()));
+}
+
+/// Change the level at runtime (settings dropdown).
+pub fn set_level(level: LevelFilter) { log::set_max_level(level); }
+
+/// `YYYY-MM-DD hh:mm:ss.mmmZ` (UTC: no time zone database needed).
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs() % 86_400;
+    let (year, month, day) = civil_date((now.as_secs() / 86_400) as i64);
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}.{:03}Z", secs / 3600, secs / 60 % 60, secs % 60, now.subsec_millis())
+}
+
+/// Days since 1970-01-01 to (year, month, day) (Howard Hinnant's `civil_from_days`).
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_into_numbered_files_and_keeps_the_newest() {
+        let dir = std::env::temp_dir().join(format!("rm-log-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut sink = FileSink::open(dir.clone());
+        let line = format!("{}\n", "x".repeat(1023));
+        // Four files' worth: the first one has been rotated out
+        for _ in 0..4 * MAX_FILE_BYTES / 1024 { sink.write(&line); }
+        assert!(rotated_path(&dir, 0).exists() && rotated_path(&dir, 1).exists() && rotated_path(&dir, 2).exists());
+        assert!(!rotated_path(&dir, KEEP_FILES).exists());
+        assert!((0..KEEP_FILES).all(|n| fs::metadata(rotated_path(&dir, n)).map(|m| m.len()).unwrap_or(0) <= MAX_FILE_BYTES));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(parse_level("DEBUG"), Some(LevelFilter::Debug));
+        assert_eq!(parse_level("off"), None);
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+    }
+}
@@ -0,0 +1,208 @@
+//! Playback loudness normalization: the K-weighted, gated integrated loudness (ITU-R BS.1770) of the
+//! last `WINDOW_BLOCKS` is measured, and a slowly moving gain steers it toward a target LUFS.
+//!
+//! The gain follows a long time constant (`SMOOTHING`) so speech dynamics survive and quiet passages
+//! are not pumped up; stretches the gates drop (silence) leave it where it is. A peak limiter after
+//! the gain holds boosted peaks under `CEILING` instead of letting them clip.
+use std::{collections::VecDeque, sync::{atomic::AtomicBool, Arc}, time::Duration};
+
+use crate::client::AtomicF64;
+
+/// Target slider range (LUFS).
+pub const TARGET_LUFS_MIN: f64 = -30.0;
+pub const TARGET_LUFS_MAX: f64 = -10.0;
+/// Gain bounds (dB).
+pub const MIN_GAIN_DB: f64 = -20.0;
+pub const MAX_GAIN_DB: f64 = 20.0;
+/// Measurement step; gating blocks are 4 steps (400 ms, 75% overlap as in BS.1770).
+const STEP: Duration = Duration::from_millis(100);
+const GATE_STEPS: usize = 4;
+/// Measurement window (steps): 10 s.
+const WINDOW_BLOCKS: usize = 100;
+/// Gates: absolute (LUFS) and relative to the ungated mean (LU).
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Gain smoothing time constant.
+const SMOOTHING: Duration = Duration::from_secs(3);
+/// Limiter ceiling (linear, about -0.5 dBFS) and release.
+const CEILING: f32 = 0.944;
+const LIMITER_RELEASE: Duration = Duration::from_millis(150);
+
+/// Live settings shared with the output callback (read every buffer).
+#[derive(Clone)]
+pub struct LoudnessSettings {
+    pub enabled: Arc<AtomicBool>,
+    pub target_lufs: Arc<AtomicF64>,
+    /// Gain currently applied (linear, for the UI)
+    pub gain: Arc<AtomicF64>,
+    /// Last integrated loudness (LUFS; -inf until enough non-silent audio was measured)
+    pub measured: Arc<AtomicF64>,
+}
+
+impl LoudnessSettings {
+    pub fn new() -> Self {
+        Self { enabled: Arc::new(AtomicBool::new(false)), target_lufs: Arc::new(AtomicF64::new(-18.0)), gain: Arc::new(AtomicF64::new(1.0)), measured: Arc::new(AtomicF64::new(f64::NEG_INFINITY)) }
+    }
+}
+
+impl Default for LoudnessSettings { fn default() -> Self { Self::new() } }
+
+/// Biquad, transposed direct form II.
+#[derive(Clone, Copy)]
+struct Biquad { b: [f64; 3], a: [f64; 2], z: [f64; 2] }
+
+impl Biquad {
+    fn run(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// K-weighting for `rate`: high shelf (head) then high pass (RLB), coefficients as in BS.1770 for any rate.
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad { b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0], a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0], z: [0.0; 2] };
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad { b: [1.0, -2.0, 1.0], a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0], z: [0.0; 2] };
+    [shelf, high_pass]
+}
+
+/// Per-stream normalizer state (interleaved f32 at the device rate and channel count).
+pub struct Loudness {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    step_frames: usize,
+    /// K-weighted energy summed over channels, and frames, of the step in progress
+    energy: f64,
+    frames: usize,
+    /// Mean-square energy per finished step (newest last)
+    steps: VecDeque<f64>,
+    measured: Option<f64>,
+    want: f32,
+    gain: f32,
+    smoothing: f32,
+    env: f32,
+    release: f32,
+}
+
+impl Loudness {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        let per_sample = |d: Duration| (-1.0 / (rate * d.as_secs_f64())).exp() as f32;
+        Self {
+            channels: channels.max(1),
+            filters: vec![k_weighting(rate); channels.max(1)],
+            step_frames: ((rate * STEP.as_secs_f64()) as usize).max(1),
+            energy: 0.0,
+            frames: 0,
+            steps: VecDeque::with_capacity(WINDOW_BLOCKS + 1),
+            measured: None,
+            want: 1.0,
+            gain: 1.0,
+            smoothing: 1.0 - per_sample(SMOOTHING),
+            env: 0.0,
+            release: per_sample(LIMITER_RELEASE),
+        }
+    }
+
+    /// Gated integrated loudness over the window (None while everything measured is gated out).
+    pub fn measured(&self) -> Option<f64> { self.measured }
+
+    /// Gain applied at the end of the last buffer (linear, before the limiter).
+    pub fn gain(&self) -> f64 { self.gain as f64 }
+
+    /// Measure one buffer, then apply the smoothed gain and the limiter in place.
+    pub fn process(&mut self, buf: &mut [f32], target_lufs: f64) {
+        for frame in buf.chunks_exact_mut(self.channels) {
+            for (s, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                let y = high_pass.run(shelf.run(*s as f64));
+                self.energy += y * y;
+            }
+            self.frames += 1;
+            if self.frames == self.step_frames { self.finish_step(target_lufs); }
+            self.gain += (self.want - self.gain) * self.smoothing;
+            let peak = frame.iter().fold(0f32, |m, s| m.max(s.abs())) * self.gain;
+            self.env = peak.max(self.env * self.release);
+            let g = if self.env > CEILING { self.gain * CEILING / self.env } else { self.gain };
+            for s in frame.iter_mut() { *s *= g; }
+        }
+    }
+
+    fn finish_step(&mut self, target_lufs: f64) {
+        self.steps.push_back(self.energy / self.frames as f64);
+        if self.steps.len() > WINDOW_BLOCKS { self.steps.pop_front(); }
+        (self.energy, self.frames) = (0.0, 0);
+        self.measured = self.integrated();
+        // Only steer while the newest block passes the gates: silence and fade-out tails freeze the gain
+        let newest = (self.steps.len() >= GATE_STEPS).then(|| lufs(self.block(self.steps.len() - GATE_STEPS)));
+        if let Some(measured) = self.measured.filter(|&m| newest.is_some_and(|n| n > (m + RELATIVE_GATE).max(ABSOLUTE_GATE))) {
+            self.want = 10f64.powf((target_lufs - measured).clamp(MIN_GAIN_DB, MAX_GAIN_DB) / 20.0) as f32;
+        }
+    }
+
+    /// Mean-square energy of the gating block starting at step `i`.
+    fn block(&self, i: usize) -> f64 { self.steps.range(i..i + GATE_STEPS).sum::<f64>() / GATE_STEPS as f64 }
+
+    fn integrated(&self) -> Option<f64> {
+        let blocks = || (0..(self.steps.len() + 1).saturating_sub(GATE_STEPS)).map(|i| self.block(i));
+        let mean_above = |gate: f64| { let (sum, n) = blocks().filter(|&z| lufs(z) > gate).fold((0.0, 0usize), |(s, n), z| (s + z, n + 1)); (n > 0).then(|| sum / n as f64) };
+        let relative = lufs(mean_above(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+        mean_above(relative.max(ABSOLUTE_GATE)).map(lufs)
+    }
+}
+
+/// Loudness (LUFS) of a K-weighted mean-square energy summed over channels.
+fn lufs(z: f64) -> f64 { -0.691 + 10.0 * z.log10() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mono 997 Hz sine at `amp` for `secs`, processed in 10 ms buffers; returns the last buffer.
+    fn run(norm: &mut Loudness, amp: f32, secs: f32, target: f64) -> Vec<f32> {
+        let rate = 48_000usize;
+        let mut last = Vec::new();
+        for chunk in 0..(secs * 100.0) as usize {
+            last = (0..rate / 100).map(|i| amp * (2.0 * std::f32::consts::PI * 997.0 * ((chunk * rate / 100 + i) as f32) / rate as f32).sin()).collect();
+            norm.process(&mut last, target);
+        }
+        last
+    }
+
+    #[test]
+    fn measures_sine_loudness_and_settles_on_the_target() {
+        // BS.1770: a 997 Hz sine at -20 dBFS peak on one channel reads -23.0 LUFS
+        let mut norm = Loudness::new(48_000, 1);
+        run(&mut norm, 0.1, 0.5, -18.0);
+        let lufs = norm.measured().unwrap();
+        assert!((lufs + 23.0).abs() < 0.2, "measured {lufs:.2} LUFS");
+        // Slow: well under the 5 dB needed after one second, there after ~20 s
+        run(&mut norm, 0.1, 1.0, -18.0);
+        assert!(20.0 * norm.gain().log10() < 3.0, "gain {:.2} dB after 1.5s", 20.0 * norm.gain().log10());
+        run(&mut norm, 0.1, 20.0, -18.0);
+        assert!((20.0 * norm.gain().log10() - 5.0).abs() < 0.2, "gain {:.2} dB", 20.0 * norm.gain().log10());
+        // Silence leaves the gain where it was (nothing left to measure once the window has passed)
+        let g = norm.gain();
+        run(&mut norm, 0.0, 12.0, -18.0);
+        assert!(norm.measured().is_none());
+        assert!((20.0 * (norm.gain() / g).log10()).abs() < 0.2, "gain moved to {:.2} dB", 20.0 * norm.gain().log10());
+    }
+
+    #[test]
+    fn limiter_keeps_boosted_peaks_under_the_ceiling() {
+        let mut norm = Loudness::new(48_000, 1);
+        // Quiet passage drives the gain up, then a full-scale burst arrives
+        run(&mut norm, 0.02, 30.0, -10.0);
+        assert!(norm.gain() > 5.0);
+        let out = run(&mut norm, 1.0, 0.2, -10.0);
+        assert!(out.iter().all(|s| s.abs() <= CEILING + 1e-6), "peak {}", out.iter().fold(0f32, |m, s| m.max(s.abs())));
+    }
+}
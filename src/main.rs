@@ -1,9 +1,95 @@
 mod dioxus_gui; // dioxus implementation
-mod lang; mod audio; mod server; mod client; mod buffers; mod net; mod types;
+mod lang; mod audio; mod server; mod client; mod buffers; mod net; mod types; mod session; mod replay; mod convert; mod meter; mod secret; mod degrade; mod control; mod codec; mod discovery; mod config; mod record; mod headless; mod rekey; mod gate; mod dtx; mod agc; mod fec; mod spectrum; mod monitor; mod ptt; mod loudness; mod limiter; mod packetize; mod selftest; mod diagnose; mod logging; mod quality;
+#[cfg(feature = "control-api")] mod api;
 use anyhow::Result;
+use clap::Parser;
+
+/// Command line: no arguments opens the GUI; `--server` / `--client` run headless.
+#[derive(Parser)]
+#[command(name = "remotemic", version, about = "LAN microphone streaming (GUI by default)")]
+struct Cli {
+    /// Run the server without the GUI (blocks until Ctrl-C)
+    #[arg(long, conflicts_with = "client")]
+    server: bool,
+    /// Run a client without the GUI: play the stream from --connect (blocks until Ctrl-C)
+    #[arg(long, requires = "connect")]
+    client: bool,
+    /// Server control address, e.g. 192.168.1.5:50000, [fe80::1]:50000, studio.local:50000 or remotemic://192.168.1.5:50000?enc=1
+    #[arg(long, requires = "client")]
+    connect: Option<net::ConnectionUrl>,
+    /// Output device name (exact or case-insensitive substring; default: system default output)
+    #[arg(long, requires = "client")]
+    output: Option<String>,
+    /// Seconds to wait for the TCP connect and for the server's handshake reply
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..=60), requires = "client")]
+    handshake_timeout: u64,
+    /// Frame transport: udp (multicast, lowest delay) or tcp (over the control connection: no dropouts, more delay)
+    #[arg(long, default_value = "udp", value_parser = ["udp", "tcp"], requires = "client")]
+    transport: String,
+    /// Address to bind (control TCP + multicast send)
+    #[arg(long, default_value = "0.0.0.0", requires = "server")]
+    bind: String,
+    /// Local address of the interface audio is sent from (default: the --bind address)
+    #[arg(long, requires = "server")]
+    egress: Option<std::net::IpAddr>,
+    /// Control port (default: first free port in 50000-50100)
+    #[arg(long, requires = "server")]
+    port: Option<u16>,
+    /// Multicast UDP port clients receive on (default: the control port, or a free UDP port if that is taken)
+    #[arg(long, requires = "server")]
+    mcast_port: Option<u16>,
+    /// Room name: derive the multicast group from it (same room = same group every launch; default: random group)
+    #[arg(long, requires = "server")]
+    room: Option<String>,
+    /// Input device name (exact or case-insensitive substring; default: system default input)
+    #[arg(long, requires = "server")]
+    input: Option<String>,
+    /// Capture frames per callback (smaller = lower latency, more CPU; clamped to what the device supports; default: device default)
+    #[arg(long, requires = "server")]
+    buffer_frames: Option<u32>,
+    /// Input channels to capture, 1-based and comma-separated (e.g. `3` = mono from channel 3; default: all)
+    #[arg(long, requires = "server", value_delimiter = ',')]
+    channels: Vec<u16>,
+    /// Stream sample rate in Hz: capture is resampled to it before sending (default: the capture rate)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(8_000..=192_000), requires = "server")]
+    sample_rate: Option<u32>,
+    /// PCM frames per UDP packet, regrouped from the capture buffers (e.g. 480 = 10 ms at 48 kHz; reduced to fit --mtu; default: one packet per capture buffer)
+    #[arg(long, requires = "server")]
+    packet_frames: Option<u32>,
+    /// Datagram size ceiling (bytes, including IP/UDP headers): --packet-frames payloads are fitted to it, larger frames are counted as oversized
+    #[arg(long, default_value_t = 1500, value_parser = clap::value_parser!(u16).range(1280..), requires = "server")]
+    mtu: u16,
+    /// Pre-shared key (server: enables encryption; client: must match the server)
+    #[arg(long, conflicts_with = "psk_file")]
+    psk: Option<String>,
+    /// Read the pre-shared key from a file
+    #[arg(long)]
+    psk_file: Option<std::path::PathBuf>,
+    /// Send an XOR parity frame after every K multicast frames (recovers one lost frame per group; 0 = off)
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=16), requires = "server")]
+    fec: u8,
+    /// Encrypt with per-client keys (x25519 exchange) instead of a PSK
+    #[arg(long, requires = "server", conflicts_with_all = ["psk", "psk_file"])]
+    per_client_keys: bool,
+    /// Log level for the console and the log file in the config dir (default: the GUI setting, else info)
+    #[arg(long, value_parser = ["error", "warn", "info", "debug", "trace"])]
+    log_level: Option<String>,
+}
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let level = cli.log_level.or(config::load().log_level);
+    logging::init(level.as_deref().and_then(logging::parse_level).unwrap_or(logging::DEFAULT_LEVEL));
     lang::init_lang("zh");
+    let psk = cli.psk.map(secret::PskSource::Inline).or(cli.psk_file.map(secret::PskSource::File));
+    if cli.server {
+        return headless::run_server(headless::ServerOptions { bind: cli.bind, egress: cli.egress, port: cli.port, mcast_port: cli.mcast_port, room: cli.room, input: cli.input, buffer_frames: cli.buffer_frames, packet_frames: cli.packet_frames, mtu: cli.mtu, sample_rate: cli.sample_rate, channels: cli.channels, psk, per_client_keys: cli.per_client_keys, fec: cli.fec });
+    }
+    if let (true, Some(url)) = (cli.client, cli.connect) {
+        let Some(port) = url.port else { anyhow::bail!("--connect {}: port missing", url.host) };
+        if url.enc && psk.is_none() { anyhow::bail!("the server requires a PSK (--psk or --psk-file)"); }
+        return headless::run_client(headless::ClientOptions { host: url.host, port, output: cli.output, psk, handshake_timeout: std::time::Duration::from_secs(cli.handshake_timeout), transport: types::Transport::parse(&cli.transport).unwrap_or_default() });
+    }
     dioxus_gui::run()?;
     Ok(())
 }
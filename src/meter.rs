@@ -0,0 +1,166 @@
+//! Wall-clock based peak meter (shared by server input and client playback metering).
+//!
+//! Peaks are held for `hold` and then fall at `fall_db_per_s`, independent of how often
+//! `update` is called (capture callback rate differs per device).
+use std::{sync::atomic::{AtomicUsize, Ordering}, time::{Duration, Instant}};
+
+use parking_lot::Mutex;
+
+use crate::server::AtomicF64;
+
+/// Default hold time before the peak marker starts falling.
+pub const PEAK_HOLD: Duration = Duration::from_millis(1500);
+/// Default fall rate after the hold expires.
+pub const PEAK_FALL_DB_PER_S: f64 = 20.0;
+/// Channels metered individually (VU per channel); further channels are left out of the bars.
+pub const METER_CHANNELS_MAX: usize = 8;
+
+/// Peak-hold + timed fall on linear RMS values.
+pub struct PeakMeter { peak: f64, held_at: Instant, last: Instant, hold: Duration, fall_db_per_s: f64 }
+
+impl PeakMeter {
+    pub fn new() -> Self { Self::with(PEAK_HOLD, PEAK_FALL_DB_PER_S) }
+
+    pub fn with(hold: Duration, fall_db_per_s: f64) -> Self {
+        let now = Instant::now();
+        Self { peak: 0.0, held_at: now, last: now, hold, fall_db_per_s }
+    }
+
+    /// Feed the latest level; returns the peak to display.
+    pub fn update(&mut self, level: f64, now: Instant) -> f64 {
+        let fall_from = self.held_at + self.hold;
+        if now > fall_from {
+            let dt = now.duration_since(self.last.max(fall_from)).as_secs_f64();
+            self.peak *= 10f64.powf(-self.fall_db_per_s * dt / 20.0);
+        }
+        self.last = now;
+        if level >= self.peak { self.peak = level; self.held_at = now; }
+        self.peak
+    }
+}
+
+impl Default for PeakMeter { fn default() -> Self { Self::new() } }
+
+/// Per-channel RMS of the latest metered buffer, shared with the UI.
+pub struct ChannelLevels { levels: [AtomicF64; METER_CHANNELS_MAX], count: AtomicUsize }
+
+impl ChannelLevels {
+    pub fn new() -> Self { Self { levels: std::array::from_fn(|_| AtomicF64::new(0.0)), count: AtomicUsize::new(0) } }
+
+    /// Meter interleaved `samples` of `channels` channels (the first `METER_CHANNELS_MAX` only).
+    pub fn update(&self, samples: &[f32], channels: usize) {
+        let n = channels.min(METER_CHANNELS_MAX);
+        if n == 0 { return; }
+        let mut acc = [0f64; METER_CHANNELS_MAX];
+        let frames = samples.chunks_exact(channels);
+        let count = frames.len();
+        for frame in frames { for (a, &s) in acc.iter_mut().zip(&frame[..n]) { *a += (s as f64) * (s as f64); } }
+        if count == 0 { return; }
+        for (level, a) in self.levels.iter().zip(&acc[..n]) { level.store((a / count as f64).sqrt()); }
+        self.count.store(n, Ordering::Relaxed);
+    }
+
+    /// Levels of the metered channels (empty until the first update).
+    pub fn levels(&self) -> Vec<f64> { self.levels[..self.count.load(Ordering::Relaxed)].iter().map(AtomicF64::load).collect() }
+}
+
+impl Default for ChannelLevels { fn default() -> Self { Self::new() } }
+
+/// Mono samples kept for the waveform view.
+pub const SCOPE_LEN: usize = 2048;
+
+/// Ring of the most recent mono samples (channels averaged), shared with the UI waveform.
+pub struct Scope { ring: Mutex<(Vec<f32>, usize)> }
+
+impl Scope {
+    pub fn new() -> Self { Self { ring: Mutex::new((vec![0.0; SCOPE_LEN], 0)) } }
+
+    /// Append interleaved `samples` of `channels` channels; skipped while the UI holds the lock
+    /// (audio threads never wait on it).
+    pub fn push(&self, samples: &[f32], channels: usize) {
+        if channels == 0 { return; }
+        let Some(mut ring) = self.ring.try_lock() else { return };
+        let (buf, pos) = &mut *ring;
+        let frames = samples.len() / channels;
+        for frame in samples.chunks_exact(channels).skip(frames.saturating_sub(SCOPE_LEN)) {
+            buf[*pos] = frame.iter().sum::<f32>() / channels as f32;
+            *pos = (*pos + 1) % SCOPE_LEN;
+        }
+    }
+
+    /// The last `SCOPE_LEN` samples, oldest first.
+    pub fn snapshot(&self) -> Vec<f32> {
+        let ring = self.ring.lock();
+        let (buf, pos) = &*ring;
+        [&buf[*pos..], &buf[..*pos]].concat()
+    }
+}
+
+impl Default for Scope { fn default() -> Self { Self::new() } }
+
+/// Send-rate averaging window and EWMA weight of each new window.
+pub const RATE_WINDOW: Duration = Duration::from_secs(1);
+const RATE_SMOOTHING: f64 = 0.5;
+
+/// Bytes / packets accumulated per `RATE_WINDOW`, published as smoothed kbps and packets per second.
+pub struct RateMeter { bytes: u64, packets: u64, window_start: Instant, kbps: Option<f64>, pps: f64 }
+
+impl RateMeter {
+    pub fn new(now: Instant) -> Self { Self { bytes: 0, packets: 0, window_start: now, kbps: None, pps: 0.0 } }
+
+    /// Count one packet of `bytes` written to the network.
+    pub fn add(&mut self, bytes: usize) { self.bytes += bytes as u64; self.packets += 1; }
+
+    /// Close the window once `RATE_WINDOW` has passed; returns the new (kbps, packets/s).
+    pub fn tick(&mut self, now: Instant) -> Option<(f64, f64)> {
+        let secs = now.duration_since(self.window_start).as_secs_f64();
+        if secs < RATE_WINDOW.as_secs_f64() { return None; }
+        let (kbps, pps) = (self.bytes as f64 * 8.0 / 1000.0 / secs, self.packets as f64 / secs);
+        let smoothed = match self.kbps { Some(prev) => (prev + (kbps - prev) * RATE_SMOOTHING, self.pps + (pps - self.pps) * RATE_SMOOTHING), None => (kbps, pps) };
+        (self.bytes, self.packets, self.window_start) = (0, 0, now);
+        self.kbps = Some(smoothed.0); self.pps = smoothed.1;
+        Some(smoothed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_meter_reports_kbps_per_window_and_smooths() {
+        let t0 = Instant::now();
+        let mut rate = RateMeter::new(t0);
+        for _ in 0..100 { rate.add(1000); }
+        assert_eq!(rate.tick(t0 + Duration::from_millis(500)), None, "window still open");
+        assert_eq!(rate.tick(t0 + RATE_WINDOW), Some((800.0, 100.0)));
+        // Silence halves the smoothed value each window instead of dropping to zero at once
+        assert_eq!(rate.tick(t0 + RATE_WINDOW * 2), Some((400.0, 50.0)));
+    }
+
+    #[test]
+    fn channel_levels_deinterleave_and_cap() {
+        let levels = ChannelLevels::new();
+        assert!(levels.levels().is_empty());
+        // L = full scale square, R = silence
+        levels.update(&[1.0, 0.0, -1.0, 0.0, 1.0, 0.0], 2);
+        assert_eq!(levels.levels(), vec![1.0, 0.0]);
+        // 10 channels: only the first METER_CHANNELS_MAX are kept
+        let wide: Vec<f32> = (0..20).map(|i| if i % 10 == 9 { 1.0 } else { 0.5 }).collect();
+        levels.update(&wide, 10);
+        assert_eq!(levels.levels(), vec![0.5; METER_CHANNELS_MAX]);
+    }
+
+    #[test]
+    fn scope_keeps_the_latest_mono_samples_in_order() {
+        let scope = Scope::new();
+        scope.push(&[1.0, 0.0, 0.5, 0.5], 2);
+        let snap = scope.snapshot();
+        assert_eq!(snap.len(), SCOPE_LEN);
+        assert_eq!(&snap[SCOPE_LEN - 2..], &[0.5, 0.5]);
+        // More than fits: only the newest SCOPE_LEN frames stay, across the wrap
+        let ramp: Vec<f32> = (0..SCOPE_LEN + 100).map(|i| i as f32).collect();
+        scope.push(&ramp, 1);
+        assert_eq!(scope.snapshot(), ramp[100..].to_vec());
+    }
+}
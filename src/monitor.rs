@@ -0,0 +1,112 @@
+//! Server-side local monitor: the captured audio (the same buffers the multicast loop sends) played
+//! on the default output device, so the host can check the mic without a second machine.
+//!
+//! There is no echo cancellation: with speakers near the microphone the monitor feeds back into the
+//! capture (and on to every client). Use headphones.
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::JoinHandle, time::Duration};
+use anyhow::{anyhow, Context, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+
+use crate::{audio, client::map_channels, convert::StreamResampler};
+
+/// Queue depth (capture buffers) between the multicast loop and the output callback.
+const QUEUE_BUFFERS: usize = 32;
+/// Playback starts (and restarts after an underrun) once this much audio is queued.
+const PREBUFFER_MS: u32 = 20;
+/// Queued audio beyond this is dropped down to `PREBUFFER_MS` (capture and output clocks drift apart).
+const MAX_BACKLOG_MS: u32 = 150;
+
+/// One capture buffer, interleaved.
+struct Chunk { rate: u32, channels: u16, samples: Vec<f32> }
+
+/// Running monitor: an output thread fed by `push`. `push` never blocks; buffers are dropped when
+/// the output falls behind.
+pub struct LocalMonitor { tx: Sender<Chunk>, stop_tx: Sender<()>, handle: JoinHandle<()>, level: f64 }
+
+impl LocalMonitor {
+    /// Open the default output device; fails if there is none or its stream cannot be built.
+    pub fn start() -> Result<Self> {
+        let dev = audio::default_output_device().context("no output device")?;
+        let (tx, rx) = bounded::<Chunk>(QUEUE_BUFFERS);
+        let (stop_tx, stop_rx) = bounded::<()>(1);
+        let (ready_tx, ready_rx) = bounded::<Result<String>>(1);
+        // cpal streams are not `Send`: built and dropped on the monitor thread
+        let handle = std::thread::Builder::new().name("rm-monitor".into()).spawn(move || {
+            let lost = Arc::new(AtomicBool::new(false));
+            // Plays until this closure returns
+            let _stream = match build_stream(&dev, rx, lost.clone()) {
+                Ok(s) => s,
+                Err(e) => { let _ = ready_tx.send(Err(e)); return; }
+            };
+            let _ = ready_tx.send(Ok(audio::device_name(&dev)));
+            loop {
+                match stop_rx.recv_timeout(Duration::from_millis(200)) {
+                    Err(RecvTimeoutError::Timeout) if !lost.load(Ordering::Relaxed) => continue,
+                    Err(RecvTimeoutError::Timeout) => { log::warn!("[SERVER][MONITOR] output device lost -> monitor off"); break; }
+                    _ => break,
+                }
+            }
+        }).context("spawn monitor thread")?;
+        match ready_rx.recv() {
+            Ok(Ok(name)) => log::info!("[SERVER][MONITOR] playing capture on {name}"),
+            Ok(Err(e)) => { let _ = handle.join(); return Err(e); }
+            Err(_) => return Err(anyhow!("monitor thread exited")),
+        }
+        Ok(Self { tx, stop_tx, handle, level: 0.0 })
+    }
+
+    /// Queue one capture buffer (interleaved `channels` at `rate`); false once the output thread has stopped.
+    pub fn push(&mut self, samples: Vec<f32>, rate: u32, channels: u16) -> bool {
+        self.level = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt() as f64;
+        match self.tx.try_send(Chunk { rate, channels, samples }) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// RMS of the latest monitored buffer (for the local VU).
+    pub fn level(&self) -> f64 { self.level }
+
+    /// Stop playback and wait for the output thread.
+    pub fn stop(self) {
+        let _ = self.stop_tx.send(());
+        let _ = self.handle.join();
+        log::info!("[SERVER][MONITOR] stopped");
+    }
+}
+
+/// Build and start the f32 output stream: capture buffers are mapped to the device channels and
+/// resampled to its rate in the callback.
+fn build_stream(dev: &cpal::Device, rx: Receiver<Chunk>, lost: Arc<AtomicBool>) -> Result<cpal::Stream> {
+    let cfg = dev.default_output_config().context("output config")?;
+    if cfg.sample_format() != cpal::SampleFormat::F32 { return Err(anyhow!("unsupported output sample format {:?}", cfg.sample_format())); }
+    let config: cpal::StreamConfig = cfg.into();
+    let (out_ch, dev_rate) = (config.channels.max(1), config.sample_rate.0);
+    let oc = out_ch as usize;
+    let prebuffer = (dev_rate * PREBUFFER_MS / 1000) as usize * oc;
+    let max_backlog = (dev_rate * MAX_BACKLOG_MS / 1000) as usize * oc;
+    let mut leftover: Vec<f32> = Vec::new(); // device layout, at the device rate
+    let mut mapped: Vec<f32> = Vec::new();
+    let mut resampler: Option<(u32, u16, StreamResampler)> = None;
+    let mut started = false;
+    let stream = dev.build_output_stream(&config, move |out: &mut [f32], _| {
+        for chunk in rx.try_iter() {
+            // Capture format changed (device switch): fresh resampler
+            if !matches!(&resampler, Some((r, c, _)) if *r == chunk.rate && *c == chunk.channels) { resampler = Some((chunk.rate, chunk.channels, StreamResampler::new(chunk.rate, dev_rate, out_ch))); }
+            mapped.clear();
+            map_channels(&chunk.samples, chunk.channels, out_ch, false, &mut mapped);
+            if let Some((_, _, rs)) = resampler.as_mut() { rs.process(&mapped, &mut leftover); }
+        }
+        if leftover.len() > max_backlog { leftover.drain(..leftover.len() - prebuffer); }
+        started = started || leftover.len() >= prebuffer;
+        let avail = if started { leftover.len().min(out.len()) / oc * oc } else { 0 };
+        out[..avail].copy_from_slice(&leftover[..avail]);
+        for s in out[avail..].iter_mut() { *s = 0.0; }
+        leftover.drain(..avail);
+        // Underrun: prebuffer again rather than crackle on every short gap
+        if avail < out.len() { started = false; }
+    }, audio::stream_error_callback("[SERVER][MONITOR][ERR]", lost), None).context("build output stream")?;
+    stream.play().context("start output stream")?;
+    Ok(stream)
+}
@@ -1,5 +1,40 @@
-use std::net::TcpListener;
-use anyhow::Result;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs, UdpSocket};
+use std::ops::RangeInclusive;
+use anyhow::{bail, Context, Result};
+
+/// Default server port range: predictable and easy to open in firewall rules.
+pub const DEFAULT_PORT_RANGE: RangeInclusive<u16> = 50000..=50100;
+
+/// Longest DNS name accepted for a server host.
+pub const MAX_HOST_LEN: usize = 253;
+
+/// Server host as typed: IPv4 / IPv6 literal (IPv6 optionally in brackets) or a DNS name.
+/// Returns it without brackets; None if it can be neither (a dotted name ending in an all-digit
+/// label is a mistyped IPv4 address, not a name).
+pub fn parse_host(s: &str) -> Option<&str> {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix('[').and_then(|h| h.strip_suffix(']')) { return inner.parse::<Ipv6Addr>().is_ok().then_some(inner); }
+    if s.parse::<IpAddr>().is_ok() { return Some(s); }
+    let name = s.strip_suffix('.').unwrap_or(s);
+    let label_ok = |l: &str| (1..=63).contains(&l.len()) && !l.starts_with('-') && !l.ends_with('-') && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let numeric_tld = name.rsplit('.').next().is_some_and(|l| l.chars().all(|c| c.is_ascii_digit()));
+    (s.len() <= MAX_HOST_LEN && name.split('.').all(label_ok) && !numeric_tld).then_some(s)
+}
+
+/// Resolve a server host (see `parse_host`) for `port`. Names with both families prefer IPv4,
+/// which servers bound to 0.0.0.0 (the default) listen on.
+pub fn resolve_host(host: &str, port: u16) -> Result<SocketAddr> {
+    let name = parse_host(host).with_context(|| format!("invalid server address {host:?}"))?;
+    if let Ok(ip) = name.parse::<IpAddr>() { return Ok(SocketAddr::new(ip, port)); }
+    let addrs: Vec<SocketAddr> = (name, port).to_socket_addrs().with_context(|| format!("cannot resolve host {name:?}"))?.collect();
+    addrs.iter().find(|a| a.is_ipv4()).or(addrs.first()).copied().with_context(|| format!("host {name:?} has no addresses"))
+}
+
+/// `host:port` for display and logs, IPv6 literals in brackets.
+pub fn host_port(host: &str, port: u16) -> String {
+    let host = parse_host(host).unwrap_or(host);
+    if host.contains(':') { format!("[{host}]:{port}") } else { format!("{host}:{port}") }
+}
 
 /// Pick a random free TCP port by binding to port 0 and returning the assigned port.
 pub fn pick_free_port() -> Result<u16> {
@@ -8,3 +43,162 @@ pub fn pick_free_port() -> Result<u16> {
     drop(sock);
     Ok(port)
 }
+
+/// Return the first free TCP port in `range`; falls back to an OS-assigned port when the
+/// whole range is taken (or empty / starts at 0).
+pub fn pick_free_port_in(range: RangeInclusive<u16>) -> Result<u16> {
+    range.filter(|&p| p != 0).find(|&p| TcpListener::bind(("0.0.0.0", p)).is_ok()).map_or_else(pick_free_port, Ok)
+}
+
+/// Index of the interface holding `ip` (IPv6 multicast picks its egress by index, not address).
+pub fn interface_index(ip: Ipv6Addr) -> Option<u32> {
+    if_addrs::get_if_addrs().ok()?.into_iter().find(|i| i.ip() == IpAddr::V6(ip)).and_then(|i| i.index)
+}
+
+/// Whether TCP `port` can be bound on all interfaces right now.
+pub fn tcp_port_free(port: u16) -> bool { port != 0 && TcpListener::bind(("0.0.0.0", port)).is_ok() }
+
+/// Pick a random free UDP port (OS-assigned).
+pub fn pick_free_udp_port() -> Result<u16> {
+    let sock = UdpSocket::bind(("0.0.0.0", 0))?;
+    let port = sock.local_addr()?.port();
+    drop(sock);
+    Ok(port)
+}
+
+/// Whether UDP `port` can be bound on all interfaces right now.
+pub fn udp_port_free(port: u16) -> bool { port != 0 && UdpSocket::bind(("0.0.0.0", port)).is_ok() }
+
+/// Return the first free UDP port in `range`; falls back to an OS-assigned port like `pick_free_port_in`.
+pub fn pick_free_udp_port_in(range: RangeInclusive<u16>) -> Result<u16> {
+    range.into_iter().find(|&p| udp_port_free(p)).map_or_else(pick_free_udp_port, Ok)
+}
+
+/// URL scheme for sharing a server address (copy button / QR code in the server panel).
+pub const URL_SCHEME: &str = "remotemic";
+
+/// `remotemic://ip:port` for a server (IPv6 addresses in brackets); `?enc=1` when clients need a PSK.
+pub fn connection_url(ip: IpAddr, port: u16, enc: bool) -> String {
+    format!("{URL_SCHEME}://{}{}", SocketAddr::new(ip, port), if enc { "?enc=1" } else { "" })
+}
+
+/// Server address parsed from `remotemic://host[:port][?enc=1]` (the scheme is optional, so a
+/// plain `host:port` parses too). The host is an IP literal or a DNS name, resolved on connect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionUrl {
+    /// As accepted by `parse_host` (IPv6 without brackets)
+    pub host: String,
+    pub port: Option<u16>,
+    /// Server expects a PSK
+    pub enc: bool,
+}
+
+impl std::str::FromStr for ConnectionUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let rest = match s.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case(URL_SCHEME) => rest,
+            Some((scheme, _)) => bail!("unsupported scheme {scheme:?} (expected {URL_SCHEME}://)"),
+            None => s,
+        };
+        let (authority, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let authority = authority.trim_end_matches('/');
+        // `[v6]:port`, a bare IPv6 literal (no port), or `host:port`
+        let (host, port) = match authority.rsplit_once(':') {
+            _ if authority.parse::<Ipv6Addr>().is_ok() => (authority, None),
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => (host, Some(port)),
+            _ => (authority, None),
+        };
+        let host = parse_host(host).with_context(|| format!("invalid server address {authority:?}"))?.to_string();
+        let port = port.map(|p| p.parse::<u16>().with_context(|| format!("invalid port {p:?}"))).transpose()?;
+        let enc = query.split('&').any(|kv| matches!(kv.split_once('=').unwrap_or((kv, "1")), ("enc", v) if v != "0" && v != "false"));
+        Ok(Self { host, port, enc })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_port_within_range_when_free() {
+        let port = pick_free_port_in(DEFAULT_PORT_RANGE).unwrap();
+        // Either in range, or the whole range was busy on this machine (fallback).
+        if DEFAULT_PORT_RANGE.clone().any(|p| TcpListener::bind(("0.0.0.0", p)).is_ok()) {
+            assert!(DEFAULT_PORT_RANGE.contains(&port), "port {port} outside range");
+        }
+        let _ = TcpListener::bind(("0.0.0.0", port)).expect("picked port should be bindable");
+    }
+
+    #[test]
+    fn falls_back_when_range_is_exhausted() {
+        let held = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let busy = held.local_addr().unwrap().port();
+        let port = pick_free_port_in(busy..=busy).unwrap();
+        assert_ne!(port, busy);
+        assert_ne!(port, 0);
+    }
+
+    #[test]
+    fn udp_picker_skips_a_held_udp_port() {
+        let held = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+        let busy = held.local_addr().unwrap().port();
+        assert!(!udp_port_free(busy));
+        let port = pick_free_udp_port_in(busy..=busy).unwrap();
+        assert_ne!(port, busy);
+        let _ = UdpSocket::bind(("0.0.0.0", port)).expect("picked port should be bindable");
+    }
+
+    #[test]
+    fn connection_url_brackets_ipv6() {
+        assert_eq!(connection_url("192.168.1.20".parse().unwrap(), 50000, false), "remotemic://192.168.1.20:50000");
+        assert_eq!(connection_url("fd00::5".parse().unwrap(), 50001, true), "remotemic://[fd00::5]:50001?enc=1");
+    }
+
+    #[test]
+    fn parses_connection_urls() {
+        let url = |s: &str| s.parse::<ConnectionUrl>();
+        let at = |host: &str, port: Option<u16>, enc: bool| ConnectionUrl { host: host.into(), port, enc };
+        assert_eq!(url("remotemic://192.168.1.20:50000").unwrap(), at("192.168.1.20", Some(50000), false));
+        // Round trip with the sharing side, IPv6 in brackets
+        let shared = connection_url("fd00::5".parse().unwrap(), 50001, true);
+        assert_eq!(url(&shared).unwrap(), at("fd00::5", Some(50001), true));
+        // Host names, as in the client IP field
+        assert_eq!(url("remotemic://studio.local:50000").unwrap(), at("studio.local", Some(50000), false));
+        assert_eq!(url("remotemic://studio-pc/?enc=1").unwrap(), at("studio-pc", None, true));
+        // Missing port: only the address is known
+        assert_eq!(url("remotemic://10.0.0.7/").unwrap().port, None);
+        assert_eq!(url("remotemic://[fe80::1]").unwrap(), at("fe80::1", None, false));
+        assert_eq!(url("fe80::1").unwrap(), at("fe80::1", None, false));
+        // enc flag: optional, `enc=0` means off, other parameters are ignored
+        assert!(url("REMOTEMIC://10.0.0.7:50000?x=1&enc").unwrap().enc);
+        assert!(!url("remotemic://10.0.0.7:50000?enc=0").unwrap().enc);
+        // Scheme optional; other schemes and bad hosts rejected
+        assert_eq!(url(" 10.0.0.7:50002 ").unwrap().port, Some(50002));
+        assert!(url("http://10.0.0.7:50000").is_err());
+        assert!(url("remotemic://pc_1.lan:50000").is_err());
+        assert!(url("remotemic://192.168.1.300:50000").is_err());
+        assert!(url("remotemic://10.0.0.7:99999").is_err());
+        assert!(url("remotemic://[10.0.0.7]:50000").is_err());
+    }
+
+    #[test]
+    fn parses_and_resolves_server_hosts() {
+        assert_eq!(parse_host(" 192.168.1.20 "), Some("192.168.1.20"));
+        assert_eq!(parse_host("[fe80::1]"), Some("fe80::1"));
+        assert_eq!(parse_host("fe80::1"), Some("fe80::1"));
+        assert_eq!(parse_host("studio-pc.local."), Some("studio-pc.local."));
+        assert_eq!(parse_host("localhost"), Some("localhost"));
+        for bad in ["", "[10.0.0.1]", "192.168.1.300", "a..b", "-pc.lan", "pc_1.lan", "host:50000"] { assert_eq!(parse_host(bad), None, "{bad:?}"); }
+        assert_eq!(resolve_host("[::1]", 50000).unwrap(), "[::1]:50000".parse().unwrap());
+        assert!(resolve_host("localhost", 50000).unwrap().ip().is_loopback());
+        assert!(resolve_host("192.168.1.300", 50000).unwrap_err().to_string().contains("invalid server address"));
+    }
+
+    #[test]
+    fn no_interface_index_for_an_address_nobody_holds() {
+        assert_eq!(interface_index("2001:db8::dead".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,95 @@
+//! Send-side repacketizing: captured PCM is regrouped into payloads of a fixed number of frames
+//! before framing, so the datagram size no longer follows the device callback size (a large
+//! callback otherwise becomes one datagram past the MTU, fragmented by IP, and lost whole when any
+//! fragment is). Leftover frames wait in a small buffer for the next capture buffer.
+use crate::types;
+
+/// Target frames per packet offered in the server panel (0 = one packet per capture buffer).
+pub const PACKET_FRAME_CHOICES: [u32; 5] = [0, 120, 240, 480, 960];
+/// Default datagram ceiling (Ethernet MTU).
+pub const DEFAULT_MTU: u16 = 1500;
+/// Smallest accepted MTU (IPv6 minimum link MTU).
+pub const MIN_MTU: u16 = 1280;
+/// IPv6 + UDP headers (the larger case).
+pub const IP_UDP_OVERHEAD: usize = 40 + 8;
+/// Bytes of a datagram that are not audio: IP/UDP headers, frame header, AEAD tag.
+pub const DATAGRAM_OVERHEAD: usize = IP_UDP_OVERHEAD + types::FRAME_HEADER_LEN + types::FRAME_TAG_LEN;
+/// Largest UDP payload over IPv4 (65535 - 20 IP - 8 UDP); `send_to` fails with EMSGSIZE past it.
+pub const MAX_DATAGRAM: usize = 65_507;
+/// Largest payload a frame can carry once sealed: header + payload + AEAD tag within one datagram.
+pub const MAX_PAYLOAD: usize = MAX_DATAGRAM - types::FRAME_HEADER_LEN - types::FRAME_TAG_LEN;
+
+/// Frames per payload: `target`, reduced so a datagram stays within `mtu` (at least 1 frame).
+pub fn packet_frames(target: u32, mtu: u16, bytes_per_frame: usize) -> usize {
+    let fit = (mtu as usize).saturating_sub(DATAGRAM_OVERHEAD) / bytes_per_frame.max(1);
+    (target as usize).min(fit).max(1)
+}
+
+/// Accumulation buffer for one capture stream.
+pub struct Packetizer { frames: u32, mtu: u16, format: Option<(u32, u16, u8)>, pending: Vec<u8> }
+
+impl Packetizer {
+    /// `frames` 0 = off (each capture buffer is passed through as one payload, split only past
+    /// `MAX_PAYLOAD`).
+    pub fn new(frames: u32, mtu: u16) -> Self { Self { frames, mtu, format: None, pending: Vec::new() } }
+
+    /// Drop buffered frames (stream interrupted: silence suppression, codec switch).
+    pub fn clear(&mut self) { self.pending.clear(); }
+
+    /// Append one capture buffer; returns each full payload with how long before the end of `data`
+    /// it ends (ns), so the caller can timestamp it on the capture timeline. A format change drops
+    /// the frames still buffered in the old one.
+    pub fn push(&mut self, data: &[u8], sr: u32, ch: u16, fmt_code: u8) -> Vec<(Vec<u8>, u64)> {
+        let bytes_per_frame = types::bytes_per_sample(fmt_code) * ch.max(1) as usize;
+        let ns_per_byte = 1e9 / (bytes_per_frame as f64 * sr.max(1) as f64);
+        if self.frames == 0 {
+            let size = (MAX_PAYLOAD / bytes_per_frame).max(1) * bytes_per_frame;
+            return data.chunks(size).scan(data.len(), |left, c| { *left -= c.len(); Some((c.to_vec(), (*left as f64 * ns_per_byte) as u64)) }).collect();
+        }
+        if self.format != Some((sr, ch, fmt_code)) { self.format = Some((sr, ch, fmt_code)); self.pending.clear(); }
+        let size = packet_frames(self.frames, self.mtu, bytes_per_frame) * bytes_per_frame;
+        self.pending.extend_from_slice(data);
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= size {
+            offset += size;
+            packets.push((self.pending[offset - size..offset].to_vec(), ((self.pending.len() - offset) as f64 * ns_per_byte) as u64));
+        }
+        self.pending.drain(..offset);
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regroups_capture_buffers_into_fixed_payloads_under_the_mtu() {
+        // i16 mono, 480 frames = 960 bytes per payload; 1000-frame buffers leave a remainder
+        let mut p = Packetizer::new(480, DEFAULT_MTU);
+        let buf: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+        let first = p.push(&buf, 48_000, 1, types::FMT_I16);
+        assert_eq!(first.len(), 2);
+        assert!(first.iter().all(|(b, _)| b.len() == 960));
+        assert_eq!(first[0].0[..], buf[..960]);
+        // 40 frames still after the second payload at 48 kHz
+        assert_eq!(first[1].1, 833_333);
+        assert_eq!(p.push(&buf, 48_000, 1, types::FMT_I16).len(), 2);
+        // 80 leftover + 1000 frames: two more payloads (the remainders joined up)
+        assert_eq!(p.push(&buf, 48_000, 1, types::FMT_I16).len(), 2);
+        // f32 stereo: 480 frames would be 3840 bytes, capped to what fits in 1500
+        assert_eq!(packet_frames(480, DEFAULT_MTU, 8), (1500 - DATAGRAM_OVERHEAD) / 8);
+        let out = p.push(&vec![0u8; 8 * 1000], 48_000, 2, types::FMT_F32);
+        assert!(out.iter().all(|(b, _)| b.len() + DATAGRAM_OVERHEAD <= 1500));
+        // Off: one payload per buffer, as captured, unless it could not be sealed in one frame
+        let mut off = Packetizer::new(0, DEFAULT_MTU);
+        assert_eq!(off.push(&buf, 48_000, 1, types::FMT_I16), vec![(buf.clone(), 0)]);
+        let huge = off.push(&vec![0u8; 8 * 10_000], 48_000, 2, types::FMT_F32);
+        assert_eq!(huge.len(), 2);
+        // 65507 - 23 - 16 = 65468 bytes, rounded down to whole 8-byte frames: the sealed datagram still fits
+        assert_eq!(huge[0].0.len(), 65_464);
+        assert!(huge[0].0.len() + types::FRAME_HEADER_LEN + types::FRAME_TAG_LEN <= MAX_DATAGRAM);
+        assert_eq!(huge[1].1, 0);
+    }
+}
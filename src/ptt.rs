@@ -0,0 +1,58 @@
+//! Push-to-talk: while enabled, the server only sends audio while a global hotkey is held.
+//!
+//! The multicast loop checks `held` per buffer, so buffers still queued when the key is released are
+//! dropped rather than sent late; idle time goes out as silence markers right away (`dtx::Dtx::idle`,
+//! no hangover). Meters, recording and the local monitor keep following the capture.
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use anyhow::{Context, Result};
+use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use parking_lot::Mutex;
+
+/// Hotkey used until the user picks another one.
+pub const DEFAULT_KEY: &str = "F8";
+
+/// Push-to-talk settings and live key state, shared with the multicast loop.
+#[derive(Clone)]
+pub struct PttSettings {
+    pub enabled: Arc<AtomicBool>,
+    /// Hotkey held right now (= transmitting while enabled)
+    pub held: Arc<AtomicBool>,
+    /// Hotkey text, e.g. `F8` or `Ctrl+Shift+Space` (`global_hotkey` syntax)
+    pub key: Arc<Mutex<String>>,
+}
+
+impl PttSettings {
+    pub fn new() -> Self {
+        Self { enabled: Arc::new(AtomicBool::new(false)), held: Arc::new(AtomicBool::new(false)), key: Arc::new(Mutex::new(DEFAULT_KEY.into())) }
+    }
+
+    /// Push-to-talk on and the key not held: nothing may be sent.
+    pub fn idle(&self) -> bool { self.enabled.load(Ordering::Relaxed) && !self.held.load(Ordering::Relaxed) }
+}
+
+impl Default for PttSettings { fn default() -> Self { Self::new() } }
+
+/// Registered global hotkey feeding `PttSettings::held`; unregistered on drop.
+/// Create it on the GUI (event loop) thread: the OS delivers hotkey events there.
+pub struct PttHotkey { manager: GlobalHotKeyManager, hotkey: HotKey, held: Arc<AtomicBool> }
+
+impl PttHotkey {
+    pub fn register(ptt: &PttSettings) -> Result<Self> {
+        let text = ptt.key.lock().clone();
+        let hotkey: HotKey = text.parse().with_context(|| format!("hotkey {text:?}"))?;
+        let manager = GlobalHotKeyManager::new().context("global hotkeys unavailable")?;
+        manager.register(hotkey).with_context(|| format!("register hotkey {text:?}"))?;
+        let (id, held) = (hotkey.id(), ptt.held.clone());
+        GlobalHotKeyEvent::set_event_handler(Some(move |e: GlobalHotKeyEvent| if e.id == id { held.store(e.state == HotKeyState::Pressed, Ordering::Relaxed); }));
+        log::info!("[SERVER][PTT] hold {text} to transmit");
+        Ok(Self { manager, hotkey, held: ptt.held.clone() })
+    }
+}
+
+impl Drop for PttHotkey {
+    fn drop(&mut self) {
+        GlobalHotKeyEvent::set_event_handler(None::<fn(GlobalHotKeyEvent)>);
+        if let Err(e) = self.manager.unregister(self.hotkey) { log::warn!("[SERVER][PTT] unregister: {e}"); }
+        self.held.store(false, Ordering::Relaxed);
+    }
+}
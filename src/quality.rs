@@ -0,0 +1,58 @@
+//! Connection quality badge (good / fair / poor) derived from the client metrics, so the raw
+//! latency / jitter / loss numbers read at a glance. All thresholds live here.
+
+/// Upper bounds one grade allows (every one must hold).
+pub struct Limits {
+    /// Packet loss ratio (0..1)
+    pub loss: f64,
+    pub jitter_ms: f64,
+    /// Average delay over the fastest frame seen (`ClientState::avg_latency_ms`)
+    pub latency_ms: f64,
+    /// Late-dropped frames / frames received + lost (0..1)
+    pub late: f64,
+}
+
+pub const GOOD: Limits = Limits { loss: 0.005, jitter_ms: 5.0, latency_ms: 20.0, late: 0.005 };
+pub const FAIR: Limits = Limits { loss: 0.03, jitter_ms: 20.0, latency_ms: 60.0, late: 0.02 };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Quality { #[default] Unknown, Good, Fair, Poor }
+
+impl Quality {
+    pub const ALL: [Quality; 4] = [Quality::Unknown, Quality::Good, Quality::Fair, Quality::Poor];
+
+    /// Compact form for atomics (`ClientState::quality`).
+    pub fn as_u8(self) -> u8 { self as u8 }
+    pub fn from_u8(v: u8) -> Self { Self::ALL.get(v as usize).copied().unwrap_or_default() }
+
+    /// Grade the current metrics: the best grade whose limits all hold.
+    pub fn assess(latency_ms: f64, jitter_ms: f64, loss: f64, late: f64) -> Self {
+        let within = |l: &Limits| loss < l.loss && jitter_ms < l.jitter_ms && latency_ms < l.latency_ms && late < l.late;
+        if within(&GOOD) { Quality::Good } else if within(&FAIR) { Quality::Fair } else { Quality::Poor }
+    }
+
+    pub fn as_str(self) -> &'static str { match self { Quality::Unknown => "unknown", Quality::Good => "good", Quality::Fair => "fair", Quality::Poor => "poor" } }
+
+    /// Language key of the badge label.
+    pub fn lang_key(self) -> &'static str { match self { Quality::Unknown => "client.quality.unknown", Quality::Good => "client.quality.good", Quality::Fair => "client.quality.fair", Quality::Poor => "client.quality.poor" } }
+
+    /// Badge background.
+    pub fn color(self) -> &'static str { match self { Quality::Unknown => "#555", Quality::Good => "#2e7d32", Quality::Fair => "#b8860b", Quality::Poor => "#c62828" } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grades_by_the_worst_metric() {
+        assert_eq!(Quality::assess(3.0, 1.0, 0.0, 0.0), Quality::Good);
+        // one metric past the good limit is enough to drop a grade
+        assert_eq!(Quality::assess(3.0, 8.0, 0.0, 0.0), Quality::Fair);
+        assert_eq!(Quality::assess(3.0, 1.0, 0.01, 0.0), Quality::Fair);
+        assert_eq!(Quality::assess(3.0, 1.0, 0.0, 0.05), Quality::Poor);
+        assert_eq!(Quality::assess(80.0, 1.0, 0.0, 0.0), Quality::Poor);
+        assert!(Quality::ALL.iter().all(|&q| Quality::from_u8(q.as_u8()) == q));
+        assert_eq!(Quality::from_u8(9), Quality::Unknown);
+    }
+}
@@ -0,0 +1,182 @@
+//! Recording helpers shared by client and server: WAV (32-bit float, interleaved; the lossless
+//! default) or MP3 (feature `mp3`, LAME via `mp3lame-encoder`) for long archives.
+//!
+//! Writers are finalized explicitly on stop; `hound` also patches the WAV header on drop, so a
+//! recording cut short by a disconnect or thread exit is still a playable file. MP3 needs no
+//! header patch: finalizing flushes the encoder's last frames.
+use std::{fs::File, io::BufWriter, path::{Path, PathBuf}, thread::JoinHandle};
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{bounded, Sender, TrySendError};
+
+pub type WavWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Recording file format (record settings).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RecordFormat { #[default] Wav, Mp3 }
+
+impl RecordFormat {
+    pub const ALL: [RecordFormat; 2] = [RecordFormat::Wav, RecordFormat::Mp3];
+
+    /// Config name, also the file extension.
+    pub fn as_str(self) -> &'static str { match self { RecordFormat::Wav => "wav", RecordFormat::Mp3 => "mp3" } }
+
+    pub fn parse(s: &str) -> Option<Self> { Self::ALL.into_iter().find(|f| f.as_str().eq_ignore_ascii_case(s.trim())) }
+
+    /// Save dialog filter name.
+    pub fn label(self) -> &'static str { match self { RecordFormat::Wav => "WAV", RecordFormat::Mp3 => "MP3" } }
+
+    /// Whether this build can write the format (MP3 needs the `mp3` feature).
+    pub fn available(self) -> bool { match self { RecordFormat::Wav => true, RecordFormat::Mp3 => cfg!(feature = "mp3") } }
+}
+
+/// MP3 bitrate (CBR): transparent for speech and music at a fraction of the f32 WAV size.
+#[cfg(feature = "mp3")]
+const MP3_BITRATE: mp3lame_encoder::Bitrate = mp3lame_encoder::Bitrate::Kbps192;
+
+/// LAME encoder writing straight to the file.
+#[cfg(feature = "mp3")]
+pub struct Mp3Writer { enc: mp3lame_encoder::Encoder, file: BufWriter<File>, channels: u16, buf: Vec<u8> }
+
+#[cfg(feature = "mp3")]
+impl Mp3Writer {
+    fn create(path: &Path, sample_rate: u32, channels: u16) -> Result<Self> {
+        use mp3lame_encoder::{Builder, Quality};
+        // LAME encodes mono or stereo only; the input rate is resampled to the nearest MPEG rate
+        anyhow::ensure!(channels <= 2, "MP3 records mono or stereo only ({channels} channels): choose WAV");
+        let lame = |e| anyhow!("mp3 encoder: {e:?}");
+        let mut b = Builder::new().context("create mp3 encoder")?;
+        b.set_num_channels(channels as u8).map_err(lame)?;
+        b.set_sample_rate(sample_rate).map_err(lame)?;
+        b.set_brate(MP3_BITRATE).map_err(lame)?;
+        b.set_quality(Quality::Good).map_err(lame)?;
+        let enc = b.build().map_err(lame)?;
+        let file = File::create(path).with_context(|| format!("create {}", path.display()))?;
+        Ok(Self { enc, file: BufWriter::new(file), channels, buf: Vec::new() })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        use mp3lame_encoder::{InterleavedPcm, MonoPcm};
+        use std::io::Write;
+        self.buf.clear();
+        let encoded = if self.channels == 2 { self.enc.encode_to_vec(InterleavedPcm(samples), &mut self.buf) } else { self.enc.encode_to_vec(MonoPcm(samples), &mut self.buf) };
+        encoded.map_err(|e| anyhow!("mp3 encode: {e:?}"))?;
+        self.file.write_all(&self.buf).context("write mp3")
+    }
+
+    /// Flush the encoder's buffered frames and the file.
+    fn finalize(mut self) -> Result<()> {
+        use std::io::Write;
+        self.buf.clear();
+        self.enc.flush_to_vec::<mp3lame_encoder::FlushNoGap>(&mut self.buf).map_err(|e| anyhow!("mp3 flush: {e:?}"))?;
+        self.file.write_all(&self.buf).context("write mp3")?;
+        self.file.flush().context("write mp3")
+    }
+}
+
+/// An open recording file.
+pub enum Recording { Wav(WavWriter), #[cfg(feature = "mp3")] Mp3(Mp3Writer) }
+
+impl Recording {
+    /// Append interleaved samples.
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        match self {
+            Recording::Wav(w) => samples.iter().try_for_each(|&s| w.write_sample(s)).context("write wav"),
+            #[cfg(feature = "mp3")]
+            Recording::Mp3(w) => w.write(samples),
+        }
+    }
+
+    /// Finish the file (WAV header / MP3 encoder flush).
+    pub fn finalize(self) -> Result<()> {
+        match self {
+            Recording::Wav(w) => w.finalize().context("finalize wav"),
+            #[cfg(feature = "mp3")]
+            Recording::Mp3(w) => w.finalize(),
+        }
+    }
+}
+
+/// Create `path` for `channels` x f32 samples at `sample_rate` Hz in `format`.
+pub fn create(path: &Path, format: RecordFormat, sample_rate: u32, channels: u16) -> Result<Recording> {
+    let channels = channels.max(1);
+    let rec = match format {
+        RecordFormat::Wav => {
+            let spec = hound::WavSpec { channels, sample_rate, bits_per_sample: 32, sample_format: hound::SampleFormat::Float };
+            Recording::Wav(hound::WavWriter::create(path, spec).with_context(|| format!("create {}", path.display()))?)
+        }
+        #[cfg(feature = "mp3")]
+        RecordFormat::Mp3 => Recording::Mp3(Mp3Writer::create(path, sample_rate, channels)?),
+        #[cfg(not(feature = "mp3"))]
+        RecordFormat::Mp3 => anyhow::bail!("MP3 recording is not in this build (feature `mp3`)"),
+    };
+    log::info!("[RECORD] {} ({} {sample_rate} Hz, {channels} ch)", path.display(), format.label());
+    Ok(rec)
+}
+
+/// Queue depth (buffers) between the audio thread and the writer thread.
+const QUEUE_BUFFERS: usize = 256;
+
+/// WAV writer running on its own thread, so disk stalls never block the caller (audio send path).
+/// `push` never blocks: buffers are dropped (and counted) if the writer falls behind.
+pub struct ThreadedRecorder { tx: Sender<Vec<f32>>, handle: JoinHandle<Result<()>>, sample_rate: u32, channels: u16, dropped: u64 }
+
+impl ThreadedRecorder {
+    pub fn start(path: &Path, format: RecordFormat, sample_rate: u32, channels: u16) -> Result<Self> {
+        let mut writer = create(path, format, sample_rate, channels)?;
+        let (tx, rx) = bounded::<Vec<f32>>(QUEUE_BUFFERS);
+        // Encoding (MP3) runs here too, off the send path
+        let handle = std::thread::Builder::new().name("rm-record".into()).spawn(move || {
+            for samples in rx { writer.write(&samples)?; }
+            writer.finalize()
+        }).context("spawn record thread")?;
+        Ok(Self { tx, handle, sample_rate, channels: channels.max(1), dropped: 0 })
+    }
+
+    /// Whether incoming audio still matches the file header (a device switch changes it).
+    pub fn matches(&self, sample_rate: u32, channels: u16) -> bool { self.sample_rate == sample_rate && self.channels == channels.max(1) }
+
+    /// Queue interleaved samples; false once the writer thread has stopped (write error).
+    pub fn push(&mut self, samples: Vec<f32>) -> bool {
+        match self.tx.try_send(samples) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => { self.dropped += 1; true }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Flush queued audio, finalize the header and wait for the writer thread.
+    pub fn finish(self) -> Result<()> {
+        drop(self.tx);
+        if self.dropped > 0 { log::warn!("[RECORD] writer fell behind: {} buffers dropped", self.dropped); }
+        self.handle.join().map_err(|_| anyhow!("record thread panicked"))?
+    }
+}
+
+/// Suggested file name for the save dialog, e.g. `remote-mic-client-1760000000.wav`.
+pub fn default_file_name(role: &str, format: RecordFormat) -> PathBuf {
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    PathBuf::from(format!("remote-mic-{role}-{secs}.{}", format.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_chosen_format_and_names_files_after_it() {
+        assert_eq!(RecordFormat::parse(" MP3 "), Some(RecordFormat::Mp3));
+        assert!(default_file_name("client", RecordFormat::Mp3).to_string_lossy().ends_with(".mp3"));
+        let path = std::env::temp_dir().join(format!("rm-record-test-{}.wav", std::process::id()));
+        let mut rec = create(&path, RecordFormat::Wav, 48_000, 2).unwrap();
+        rec.write(&[0.5, -0.5, 0.25, -0.25]).unwrap();
+        rec.finalize().unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.samples::<f32>().map(Result::unwrap).collect::<Vec<_>>(), vec![0.5, -0.5, 0.25, -0.25]);
+        let _ = std::fs::remove_file(&path);
+        // Without the `mp3` feature the format is refused up front (no empty file left behind)
+        let mp3 = path.with_extension("mp3");
+        assert_eq!(create(&mp3, RecordFormat::Mp3, 48_000, 2).is_ok(), RecordFormat::Mp3.available());
+        if !RecordFormat::Mp3.available() { assert!(!mp3.exists()); }
+        let _ = std::fs::remove_file(&mp3);
+    }
+}
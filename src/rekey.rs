@@ -0,0 +1,200 @@
+//! PSK session keys and their periodic rotation.
+//!
+//! key = SHA256(psk || salt). The server draws a fresh salt every `REKEY_INTERVAL`, announces it on
+//! every control connection (`REKEY <epoch> <salthex>`) and only seals frames with it `REKEY_LEAD`
+//! later, so clients (which read the control channel once per heartbeat) hold the new key before
+//! the first frame needs it. Each frame carries its key epoch; clients keep the previous key for
+//! frames still in flight across the switch.
+//!
+//! Per-client key mode (no PSK): the session keys come from a random secret instead, and every
+//! client runs an x25519 exchange on its control connection (`KX`). Each epoch key is sent wrapped
+//! under that client's exchange key (`KEY`), so the multicast payload is still encrypted once.
+//! Kicking a client forces an immediate rotation it never receives, which revokes it.
+use std::time::{Duration, Instant};
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Time a key stays in use before the next one is announced.
+pub const REKEY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Announce -> switch delay (well above the 1 s client heartbeat read cadence).
+pub const REKEY_LEAD: Duration = Duration::from_secs(3);
+
+/// One key generation; `epoch` wraps and is written into every sealed frame header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EpochKey { pub epoch: u8, pub salt: [u8;8], pub key: [u8;32] }
+
+impl EpochKey {
+    pub fn derive(psk: &str, epoch: u8, salt: [u8;8]) -> Self {
+        let mut hasher: Sha256 = Default::default();
+        hasher.update(psk.as_bytes());
+        hasher.update(salt);
+        let mut key = [0u8;32]; key.copy_from_slice(&hasher.finalize()[..32]);
+        Self { epoch, salt, key }
+    }
+
+    pub fn salt_hex(&self) -> String { to_hex(&self.salt) }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() }
+
+/// Even-length hex string -> bytes.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() & 1 == 1 || !hex.is_ascii() { return None; }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// 16 hex chars -> 8-byte salt.
+pub fn parse_salt(hex: &str) -> Option<[u8;8]> { from_hex(hex)?.try_into().ok() }
+
+/// 64 hex chars -> x25519 public key.
+pub fn parse_public(hex: &str) -> Option<[u8;32]> { from_hex(hex)?.try_into().ok() }
+
+/// Fresh x25519 key pair for one control connection.
+pub fn kx_keypair() -> (EphemeralSecret, [u8;32]) {
+    let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+    let public = PublicKey::from(&secret).to_bytes();
+    (secret, public)
+}
+
+/// Key-encryption key shared with the peer: SHA256(label || x25519(secret, peer)).
+pub fn kx_finish(secret: EphemeralSecret, peer: [u8;32]) -> [u8;32] {
+    let shared = secret.diffie_hellman(&PublicKey::from(peer));
+    let mut hasher: Sha256 = Default::default();
+    hasher.update(b"remote-mic/kx");
+    hasher.update(shared.as_bytes());
+    let mut kek = [0u8;32]; kek.copy_from_slice(&hasher.finalize()[..32]);
+    kek
+}
+
+/// Seal `k.key` under `kek`: nonce(24) | ciphertext(32 + tag 16); epoch and salt are the AAD.
+pub fn wrap(kek: &[u8;32], k: &EpochKey) -> Vec<u8> {
+    let nonce: [u8;24] = rand::thread_rng().gen();
+    let aad = [&[k.epoch][..], &k.salt].concat();
+    let ct = XChaCha20Poly1305::new(kek.into()).encrypt(&nonce.into(), Payload { msg: &k.key, aad: &aad }).expect("wrap 32-byte key");
+    [&nonce[..], &ct].concat()
+}
+
+/// Inverse of `wrap`; None if the blob was not sealed under `kek` for this epoch / salt.
+pub fn unwrap(kek: &[u8;32], epoch: u8, salt: [u8;8], wrapped: &[u8]) -> Option<EpochKey> {
+    if wrapped.len() < 24 { return None; }
+    let (nonce, ct) = wrapped.split_at(24);
+    let aad = [&[epoch][..], &salt].concat();
+    let key = XChaCha20Poly1305::new(kek.into()).decrypt(nonce.into(), Payload { msg: ct, aad: &aad }).ok()?;
+    Some(EpochKey { epoch, salt, key: key.try_into().ok()? })
+}
+
+fn random_salt() -> [u8;8] { let mut salt = [0u8;8]; rand::thread_rng().fill(&mut salt); salt }
+
+/// Server side: the key frames are sealed with plus the announced successor.
+pub struct KeyRotation { psk: String, active: EpochKey, next: Option<EpochKey>, due: Instant }
+
+impl KeyRotation {
+    pub fn new(psk: String) -> Self {
+        let active = EpochKey::derive(&psk, 0, random_salt());
+        Self { psk, active, next: None, due: Instant::now() + REKEY_INTERVAL }
+    }
+
+    /// Keys from a random secret nobody else knows (per-client key mode: clients only get wrapped keys).
+    pub fn random() -> Self { Self::new(to_hex(&rand::thread_rng().gen::<[u8;32]>())) }
+
+    /// Start a rotation now (revocation): a pending announcement is dropped and replaced, since the
+    /// revoked client may already hold it.
+    pub fn rotate_now(&mut self) { self.next = None; self.due = Instant::now(); }
+
+    /// Key sent in the handshake and used for sealing.
+    pub fn active(&self) -> EpochKey { self.active }
+
+    /// Announced key not yet in use (to be sent as `REKEY` / `KEY` on every control connection).
+    pub fn next(&self) -> Option<EpochKey> { self.next }
+
+    /// Advance the schedule (announce, then switch `REKEY_LEAD` later); returns the key to seal with.
+    pub fn tick(&mut self, now: Instant) -> EpochKey {
+        if now >= self.due {
+            match self.next.take() {
+                Some(next) => { log::info!("[REKEY] sealing with epoch {}", next.epoch); self.active = next; self.due = now + REKEY_INTERVAL.saturating_sub(REKEY_LEAD); }
+                None => {
+                    let next = EpochKey::derive(&self.psk, self.active.epoch.wrapping_add(1), random_salt());
+                    log::info!("[REKEY] announcing epoch {}", next.epoch);
+                    self.next = Some(next); self.due = now + REKEY_LEAD;
+                }
+            }
+        }
+        self.active
+    }
+}
+
+/// Client side: keys by epoch, current and previous (slot = epoch parity, so a switch never evicts
+/// the key still needed by in-flight frames).
+#[derive(Default)]
+pub struct KeyRing { psk: Option<String>, kek: Option<[u8;32]>, keys: [Option<EpochKey>; 2] }
+
+impl KeyRing {
+    pub fn new(psk: Option<String>) -> Self { Self { psk, kek: None, keys: [None, None] } }
+
+    /// Per-client key mode: keys arrive wrapped under `kek` (see `kx_finish`).
+    pub fn with_kek(kek: [u8;32]) -> Self { Self { psk: None, kek: Some(kek), keys: [None, None] } }
+
+    /// Whether keys can be obtained at all (a PSK was provided or the key exchange ran).
+    pub fn has_secret(&self) -> bool { self.psk.is_some() || self.kek.is_some() }
+
+    /// Whether any epoch key is installed.
+    pub fn ready(&self) -> bool { self.keys.iter().any(Option::is_some) }
+
+    /// Derive and keep the key for `epoch` (no-op without a PSK).
+    pub fn install(&mut self, epoch: u8, salt: [u8;8]) {
+        let Some(psk) = self.psk.as_deref() else { return };
+        self.keys[(epoch & 1) as usize] = Some(EpochKey::derive(psk, epoch, salt));
+    }
+
+    /// Unwrap and keep a `KEY` announcement; false if it does not open under our exchange key.
+    pub fn install_wrapped(&mut self, epoch: u8, salt: [u8;8], wrapped: &[u8]) -> bool {
+        let Some(k) = self.kek.as_ref().and_then(|kek| unwrap(kek, epoch, salt, wrapped)) else { return false };
+        self.keys[(epoch & 1) as usize] = Some(k);
+        true
+    }
+
+    pub fn get(&self, epoch: u8) -> Option<EpochKey> { self.keys[(epoch & 1) as usize].filter(|k| k.epoch == epoch) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_announces_before_switching_and_ring_keeps_previous_key() {
+        let mut rot = KeyRotation::new("secret".into());
+        let first = rot.active();
+        let mut ring = KeyRing::new(Some("secret".into()));
+        ring.install(first.epoch, first.salt);
+        let start = Instant::now();
+        // Announced but not yet used for sealing
+        assert!(rot.tick(start + REKEY_INTERVAL) == first);
+        let next = rot.next().expect("announced");
+        assert_eq!(next.epoch, 1);
+        ring.install(next.epoch, next.salt);
+        // After the lead time the new key seals; both epochs still open on the client
+        assert!(rot.tick(start + REKEY_INTERVAL + REKEY_LEAD) == next);
+        assert!(rot.next().is_none());
+        assert!(ring.get(0) == Some(first) && ring.get(1) == Some(next));
+        assert!(ring.get(2).is_none());
+        assert_eq!(parse_salt(&next.salt_hex()), Some(next.salt));
+    }
+
+    #[test]
+    fn wrapped_keys_open_only_for_the_exchanging_client() {
+        let (server_secret, server_public) = kx_keypair();
+        let (client_secret, client_public) = kx_keypair();
+        let server_kek = kx_finish(server_secret, client_public);
+        let mut ring = KeyRing::with_kek(kx_finish(client_secret, server_public));
+        let k = KeyRotation::random().active();
+        let wrapped = wrap(&server_kek, &k);
+        assert!(!ring.install_wrapped(k.epoch.wrapping_add(1), k.salt, &wrapped), "epoch is authenticated");
+        assert!(ring.install_wrapped(k.epoch, k.salt, &wrapped));
+        assert!(ring.get(k.epoch) == Some(k));
+        let (other_secret, _) = kx_keypair();
+        let mut outsider = KeyRing::with_kek(kx_finish(other_secret, server_public));
+        assert!(!outsider.install_wrapped(k.epoch, k.salt, &wrapped));
+    }
+}
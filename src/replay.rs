@@ -0,0 +1,304 @@
+//! Diagnostic replay: stream a WAV file (or a generated test signal) through the normal
+//! pool -> frame -> multicast path.
+//!
+//! The file is decoded once, converted to the negotiated `AudioParams` (resample, channel
+//! map, sample format) and then pushed into the buffer pool in ~10ms chunks at real-time pace,
+//! exactly like the capture callback in `audio::build_input_stream`. Test signals (440 Hz sine,
+//! pink noise) are synthesized chunk by chunk at the same pace.
+use std::{fs, path::Path, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+use anyhow::{anyhow, bail, Context, Result};
+use cpal::SampleFormat;
+use crossbeam_channel::Sender;
+use rand::Rng;
+
+use crate::{audio::AudioParams, buffers::AudioBufferPool, convert, types};
+
+/// Replay normalization: peak target and gain cap (+36 dB) applied before integer down-conversion.
+const NORMALIZE_TARGET: f32 = 0.98;
+const NORMALIZE_MAX_GAIN: f32 = 64.0;
+
+/// Test signal peak level (-12 dBFS) and sine frequency.
+const TEST_LEVEL: f32 = 0.25;
+const TONE_HZ: f64 = 440.0;
+
+/// Server audio source: the input device, or a generated test signal (`ServerState::signal`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignalSource { #[default] Mic, Tone, Noise }
+
+impl SignalSource {
+    pub const ALL: [SignalSource; 3] = [SignalSource::Mic, SignalSource::Tone, SignalSource::Noise];
+
+    /// Compact form for atomics (`ServerState::signal`).
+    pub fn as_u8(self) -> u8 { self as u8 }
+    pub fn from_u8(v: u8) -> Self { Self::ALL.into_iter().find(|s| s.as_u8() == v).unwrap_or_default() }
+
+    /// Lang key of the selector label.
+    pub fn label_key(self) -> &'static str { match self { SignalSource::Mic => "server.signal.mic", SignalSource::Tone => "server.signal.tone", SignalSource::Noise => "server.signal.noise" } }
+}
+
+/// Test signal state carried across chunks (sine phase / pink noise filter).
+struct TestSignal { source: SignalSource, phase: f64, step: f64, pink: [f32; 7] }
+
+impl TestSignal {
+    fn new(source: SignalSource, sample_rate: u32) -> Self { Self { source, phase: 0.0, step: TONE_HZ / sample_rate.max(1) as f64, pink: [0.0; 7] } }
+
+    /// Fill interleaved `out` (same value on every channel).
+    fn fill(&mut self, out: &mut [f32], channels: usize) {
+        let mut rng = rand::thread_rng();
+        for frame in out.chunks_mut(channels.max(1)) {
+            let v = match self.source {
+                SignalSource::Mic => 0.0,
+                SignalSource::Tone => {
+                    let v = (self.phase * std::f64::consts::TAU).sin() as f32;
+                    self.phase = (self.phase + self.step).fract();
+                    v
+                }
+                SignalSource::Noise => {
+                    // Paul Kellet's pink filter (white in -1..1 -> peaks around +-1 after scaling)
+                    let w: f32 = rng.gen_range(-1.0..1.0);
+                    let b = &mut self.pink;
+                    b[0] = 0.99886 * b[0] + w * 0.0555179; b[1] = 0.99332 * b[1] + w * 0.0750759;
+                    b[2] = 0.96900 * b[2] + w * 0.153852; b[3] = 0.86650 * b[3] + w * 0.3104856;
+                    b[4] = 0.55000 * b[4] + w * 0.5329522; b[5] = -0.7616 * b[5] - w * 0.0168980;
+                    let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + w * 0.5362;
+                    b[6] = w * 0.115926;
+                    (pink * 0.11).clamp(-1.0, 1.0)
+                }
+            };
+            frame.fill(v * TEST_LEVEL);
+        }
+    }
+}
+
+/// Decoded WAV contents (interleaved f32 in -1..1).
+pub struct WavData { pub sample_rate: u32, pub channels: u16, pub samples: Vec<f32> }
+
+/// Parse a RIFF/WAVE file: PCM 8/16/24/32-bit and IEEE float 32-bit (incl. WAVE_FORMAT_EXTENSIBLE).
+pub fn read_wav(path: &Path) -> Result<WavData> {
+    parse_wav(&fs::read(path).with_context(|| format!("read {}", path.display()))?)
+}
+
+fn parse_wav(bytes: &[u8]) -> Result<WavData> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" { bail!("not a RIFF/WAVE file"); }
+    let mut fmt: Option<(u16, u16, u32, u16)> = None; // (format tag, channels, rate, bits)
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos+4];
+        let len = u32::from_le_bytes([bytes[pos+4], bytes[pos+5], bytes[pos+6], bytes[pos+7]]) as usize;
+        let body = &bytes[pos+8..(pos+8+len).min(bytes.len())];
+        if id == b"fmt " && body.len() >= 16 {
+            let mut tag = u16::from_le_bytes([body[0], body[1]]);
+            if tag == 0xFFFE && body.len() >= 26 { tag = u16::from_le_bytes([body[24], body[25]]); } // extensible: sub-format GUID prefix
+            fmt = Some((tag, u16::from_le_bytes([body[2], body[3]]), u32::from_le_bytes([body[4], body[5], body[6], body[7]]), u16::from_le_bytes([body[14], body[15]])));
+        } else if id == b"data" {
+            let (tag, channels, sample_rate, bits) = fmt.ok_or_else(|| anyhow!("data chunk before fmt chunk"))?;
+            if channels == 0 || sample_rate == 0 { bail!("invalid fmt chunk"); }
+            let samples: Vec<f32> = match (tag, bits) {
+                (1, 8) => body.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+                (1, 16) => body.chunks_exact(2).map(|c| convert::i16_to_f32(i16::from_le_bytes([c[0], c[1]]))).collect(),
+                (1, 24) => body.chunks_exact(3).map(|c| (i32::from_le_bytes([0, c[0], c[1], c[2]]) >> 8) as f32 / 8_388_608.0).collect(),
+                (1, 32) => body.chunks_exact(4).map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32 / 2_147_483_648.0).collect(),
+                (3, 32) => body.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+                _ => bail!("unsupported WAV encoding (format tag {tag}, {bits} bits)"),
+            };
+            return Ok(WavData { sample_rate, channels, samples });
+        }
+        pos += 8 + len + (len & 1); // chunks are word aligned
+    }
+    bail!("no data chunk")
+}
+
+/// Convert interleaved samples to the target rate / channel count (linear interpolation, simple channel map).
+fn conform(wav: &WavData, sample_rate: u32, channels: u16) -> Vec<f32> {
+    let in_ch = wav.channels.max(1) as usize; let out_ch = channels.max(1) as usize;
+    let in_frames = wav.samples.len() / in_ch;
+    if in_frames == 0 { return Vec::new(); }
+    let out_frames = ((in_frames as u64 * sample_rate as u64) / wav.sample_rate as u64).max(1) as usize;
+    let step = wav.sample_rate as f64 / sample_rate as f64;
+    let frame_at = |i: usize, c: usize| -> f32 {
+        let f = &wav.samples[i*in_ch..(i+1)*in_ch];
+        if out_ch == 1 { f.iter().sum::<f32>() / in_ch as f32 } else { f[c % in_ch] }
+    };
+    let mut out = Vec::with_capacity(out_frames * out_ch);
+    for n in 0..out_frames {
+        let t = n as f64 * step; let i = (t as usize).min(in_frames - 1); let j = (i + 1).min(in_frames - 1); let frac = (t - i as f64) as f32;
+        for c in 0..out_ch { let a = frame_at(i, c); out.push(a + (frame_at(j, c) - a) * frac); }
+    }
+    out
+}
+
+/// Encode f32 samples into the raw byte layout the capture callback would produce.
+fn encode(samples: &[f32], fmt: SampleFormat) -> Vec<u8> {
+    match fmt {
+        SampleFormat::I16 => samples.iter().flat_map(|&s| convert::f32_to_i16(s).to_le_bytes()).collect(),
+        SampleFormat::U16 => samples.iter().flat_map(|&s| convert::f32_to_u16(s).to_le_bytes()).collect(),
+        SampleFormat::I32 => samples.iter().flat_map(|&s| convert::f32_to_i32(s).to_le_bytes()).collect(),
+        SampleFormat::F64 => samples.iter().flat_map(|&s| (s as f64).to_ne_bytes()).collect(),
+        _ => samples.iter().flat_map(|&s| s.to_ne_bytes()).collect(),
+    }
+}
+
+/// Load `path`, conform it to `params` and spawn a thread feeding the pool until `running` clears
+/// (or the file ends when `looping` is false). `normalize` peak-normalizes before integer conversion.
+pub fn spawn_wav_replay(path: &Path, params: &AudioParams, looping: bool, normalize: bool, pool: Arc<AudioBufferPool>, send_ready: Sender<usize>, running: Arc<AtomicBool>) -> Result<thread::JoinHandle<()>> {
+    let wav = read_wav(path)?;
+    log::info!("[REPLAY] {}: {}Hz {}ch -> {}Hz {}ch {:?}", path.display(), wav.sample_rate, wav.channels, params.sample_rate, params.channels, params.sample_format);
+    let bytes_per_sample = types::bytes_per_sample(types::sample_format_code(params.sample_format));
+    let ch = params.channels.max(1) as usize;
+    let mut samples = conform(&wav, params.sample_rate, params.channels);
+    if normalize && matches!(params.sample_format, SampleFormat::I16 | SampleFormat::U16 | SampleFormat::I32) {
+        let gain = convert::normalize_peak(&mut samples, NORMALIZE_TARGET, NORMALIZE_MAX_GAIN);
+        log::info!("[REPLAY] normalized: gain {:.1} dB", 20.0 * gain.log10());
+    }
+    let data = encode(&samples, params.sample_format);
+    if data.is_empty() { bail!("WAV file contains no audio"); }
+    let frames = chunk_frames(params, &pool);
+    let chunk_bytes = frames * ch * bytes_per_sample;
+    let chunk_dur = Duration::from_secs_f64(frames as f64 / params.sample_rate as f64);
+    Ok(thread::Builder::new().name("rm-replay".into()).spawn(move || {
+        let mut offset = 0usize; let mut next = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            if offset >= data.len() {
+                if !looping { log::info!("[REPLAY] end of file"); break; }
+                offset = 0;
+            }
+            let end = (offset + chunk_bytes).min(data.len());
+            push_chunk(&pool, &send_ready, &data[offset..end]);
+            offset = end;
+            pace(&mut next, chunk_dur);
+        }
+        log::info!("[REPLAY] thread exit");
+    })?)
+}
+
+/// Spawn a thread feeding a generated test signal (`source` must not be `Mic`) until `running` clears.
+pub fn spawn_test_signal(source: SignalSource, params: &AudioParams, pool: Arc<AudioBufferPool>, send_ready: Sender<usize>, running: Arc<AtomicBool>) -> Result<thread::JoinHandle<()>> {
+    if source == SignalSource::Mic { bail!("no test signal selected"); }
+    log::info!("[REPLAY] test signal {source:?} -> {}Hz {}ch {:?}", params.sample_rate, params.channels, params.sample_format);
+    let ch = params.channels.max(1) as usize;
+    let frames = chunk_frames(params, &pool);
+    let chunk_dur = Duration::from_secs_f64(frames as f64 / params.sample_rate as f64);
+    let (sample_rate, fmt) = (params.sample_rate, params.sample_format);
+    Ok(thread::Builder::new().name("rm-tone".into()).spawn(move || {
+        let mut signal = TestSignal::new(source, sample_rate);
+        let mut samples = vec![0f32; frames * ch];
+        let mut next = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            signal.fill(&mut samples, ch);
+            push_chunk(&pool, &send_ready, &encode(&samples, fmt));
+            pace(&mut next, chunk_dur);
+        }
+        log::info!("[REPLAY] test signal thread exit");
+    })?)
+}
+
+/// ~10ms of frames per chunk, bounded by the pool buffer size (first 4 bytes hold the length).
+fn chunk_frames(params: &AudioParams, pool: &AudioBufferPool) -> usize {
+    let frame_bytes = params.channels.max(1) as usize * types::bytes_per_sample(types::sample_format_code(params.sample_format));
+    let capacity = pool.data.first().map(|b| b.lock().len().saturating_sub(4)).unwrap_or(0);
+    ((params.sample_rate as usize / 100).min(capacity / frame_bytes)).max(1)
+}
+
+/// Copy one chunk into a free pool buffer and hand it to the multicast loop (dropped if the pool is exhausted).
+fn push_chunk(pool: &AudioBufferPool, send_ready: &Sender<usize>, chunk: &[u8]) {
+    let Some(idx) = pool.pop() else { return };
+    let mut guard = pool.data[idx].lock();
+    guard[0..4].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+    guard[4..4+chunk.len()].copy_from_slice(chunk);
+    drop(guard);
+    let _ = send_ready.send(idx);
+}
+
+/// Sleep until the next chunk is due (real-time pace; resync after falling behind).
+fn pace(next: &mut Instant, chunk_dur: Duration) {
+    *next += chunk_dur;
+    if let Some(wait) = next.checked_duration_since(Instant::now()) { thread::sleep(wait); } else { *next = Instant::now(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signals_stay_continuous_and_below_level() {
+        let mut tone = TestSignal::new(SignalSource::Tone, 48_000);
+        let (mut a, mut b) = (vec![0f32; 960], vec![0f32; 960]);
+        tone.fill(&mut a, 2); tone.fill(&mut b, 2);
+        assert!(a.chunks(2).all(|f| f[0] == f[1]), "same value on every channel");
+        // Phase carries over: the first frame of the next chunk follows the last one of the previous
+        let max_step = (std::f64::consts::TAU * TONE_HZ / 48_000.0) as f32 * TEST_LEVEL;
+        assert!((b[0] - a[958]).abs() <= max_step * 1.01);
+        let peak = a.iter().chain(&b).fold(0f32, |m, v| m.max(v.abs()));
+        assert!(peak <= TEST_LEVEL && peak > TEST_LEVEL * 0.99, "peak {peak}");
+        let mut noise = TestSignal::new(SignalSource::Noise, 48_000);
+        let mut n = vec![0f32; 4800];
+        noise.fill(&mut n, 1);
+        assert!(n.iter().all(|v| v.abs() <= TEST_LEVEL));
+        let rms = (n.iter().map(|v| v * v).sum::<f32>() / n.len() as f32).sqrt();
+        assert!(rms > 0.01, "rms {rms}");
+    }
+
+    /// RIFF/WAVE file from `(id, body)` chunks (odd bodies padded like real files).
+    fn riff(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, data) in chunks {
+            body.extend_from_slice(*id); body.extend((data.len() as u32).to_le_bytes()); body.extend_from_slice(data);
+            if data.len() & 1 == 1 { body.push(0); }
+        }
+        [b"RIFF".to_vec(), (body.len() as u32).to_le_bytes().to_vec(), body].concat()
+    }
+
+    /// Plain 16-byte `fmt ` body.
+    fn fmt(tag: u16, channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+        let align = channels * bits / 8;
+        [tag.to_le_bytes().as_slice(), &channels.to_le_bytes(), &rate.to_le_bytes(), &(rate * align as u32).to_le_bytes(), &align.to_le_bytes(), &bits.to_le_bytes()].concat()
+    }
+
+    #[test]
+    fn parses_16_bit_stereo_pcm() {
+        let data: Vec<u8> = [16384i16, -16384, 0, -32768].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let wav = parse_wav(&riff(&[(b"fmt ", fmt(1, 2, 44_100, 16)), (b"data", data)])).unwrap();
+        assert_eq!((wav.sample_rate, wav.channels), (44_100, 2));
+        assert_eq!(wav.samples, vec![0.5, -0.5, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn parses_24_bit_pcm_with_sign_extension() {
+        let data: Vec<u8> = [0x40_0000i32, -0x40_0000, -0x80_0000, 0].iter().flat_map(|s| s.to_le_bytes()[..3].to_vec()).collect();
+        let wav = parse_wav(&riff(&[(b"fmt ", fmt(1, 1, 48_000, 24)), (b"data", data)])).unwrap();
+        assert_eq!(wav.samples, vec![0.5, -0.5, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn parses_extensible_float_after_an_odd_sized_chunk() {
+        // WAVE_FORMAT_EXTENSIBLE: cbSize 22, valid bits, channel mask, then the sub-format GUID (IEEE float = 3)
+        let mut ext = fmt(0xFFFE, 2, 96_000, 32);
+        ext.extend(22u16.to_le_bytes()); ext.extend(32u16.to_le_bytes()); ext.extend(3u32.to_le_bytes());
+        ext.extend(3u16.to_le_bytes()); ext.extend([0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71]);
+        let data: Vec<u8> = [0.25f32, -0.75].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let wav = parse_wav(&riff(&[(b"fmt ", ext), (b"LIST", b"odd".to_vec()), (b"data", data)])).unwrap();
+        assert_eq!((wav.sample_rate, wav.channels, wav.samples), (96_000, 2, vec![0.25, -0.75]));
+    }
+
+    #[test]
+    fn rejects_data_before_fmt_and_unsupported_encodings() {
+        let err = parse_wav(&riff(&[(b"data", vec![0; 4]), (b"fmt ", fmt(1, 1, 8_000, 16))])).err().expect("data before fmt must fail");
+        assert!(err.to_string().contains("data chunk before fmt"), "{err}");
+        assert!(parse_wav(&riff(&[(b"fmt ", fmt(3, 1, 8_000, 64)), (b"data", vec![0; 8])])).is_err());
+        assert!(parse_wav(&riff(&[(b"fmt ", fmt(1, 1, 8_000, 16))])).is_err(), "no data chunk");
+        assert!(parse_wav(b"RIFX\0\0\0\0WAVE").is_err());
+    }
+
+    #[test]
+    fn conform_resamples_and_maps_channels() {
+        let wav = WavData { sample_rate: 48_000, channels: 2, samples: vec![1.0, 0.0, 0.5, 0.5, 0.0, 1.0, -1.0, -1.0] };
+        // Same format: untouched
+        assert_eq!(conform(&wav, 48_000, 2), wav.samples);
+        // Mono at half the rate: every other frame, L/R averaged
+        assert_eq!(conform(&wav, 24_000, 1), vec![0.5, 0.5]);
+        // Mono source onto stereo: copied to both sides
+        let mono = WavData { sample_rate: 8_000, channels: 1, samples: vec![0.2, 0.4] };
+        assert_eq!(conform(&mono, 8_000, 2), vec![0.2, 0.2, 0.4, 0.4]);
+        assert!(conform(&WavData { sample_rate: 8_000, channels: 2, samples: vec![0.1] }, 8_000, 2).is_empty(), "partial frame only");
+    }
+}
@@ -0,0 +1,52 @@
+//! PSK sources: typed inline, read from a file, or stored in the OS keyring.
+//!
+//! The keyring is the only place a remembered PSK is persisted; nothing is written to a
+//! plaintext config.
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+
+/// Keyring service name (entries are per role: `server` / `client`).
+const KEYRING_SERVICE: &str = "remote-mic";
+
+/// Where a PSK comes from.
+#[derive(Clone, Debug)]
+pub enum PskSource {
+    Inline(String),
+    File(PathBuf),
+    Keyring { account: String },
+}
+
+impl PskSource {
+    /// Load the PSK; `Ok(None)` when the source is empty (no encryption).
+    pub fn resolve(&self) -> Result<Option<String>> {
+        let psk = match self {
+            PskSource::Inline(s) => s.clone(),
+            PskSource::File(path) => std::fs::read_to_string(path).with_context(|| format!("read PSK file {}", path.display()))?,
+            PskSource::Keyring { account } => entry(account)?.get_password().with_context(|| format!("read PSK from keyring ({account})"))?,
+        };
+        let psk = psk.trim().to_string();
+        Ok(if psk.is_empty() { None } else { Some(psk) })
+    }
+}
+
+fn entry(account: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, account).context("open keyring")
+}
+
+/// Remember `psk` in the OS keyring under `account`.
+pub fn store_keyring(account: &str, psk: &str) -> Result<()> {
+    entry(account)?.set_password(psk).context("write PSK to keyring")
+}
+
+/// Forget the stored PSK (missing entry is not an error).
+pub fn forget_keyring(account: &str) -> Result<()> {
+    match entry(account)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e).context("delete PSK from keyring"),
+    }
+}
+
+/// Whether a PSK is stored for `account` (keyring unavailable counts as no).
+pub fn has_keyring(account: &str) -> bool {
+    entry(account).and_then(|e| e.get_password().map_err(Into::into)).is_ok()
+}
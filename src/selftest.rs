@@ -0,0 +1,64 @@
+//! One-click loopback self test: an in-process server on 127.0.0.1 streams the test tone to an
+//! in-process client for `DURATION`, and passes if the client's level meter saw the tone. Covers the
+//! control handshake, multicast on loopback (or the unicast fallback), decoding, playback and the
+//! metrics path before a real session.
+use std::{sync::{atomic::Ordering, Arc}, time::{Duration, Instant}};
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::{unbounded, Sender};
+
+use crate::{audio::AudioParams, buffers::AudioBufferPool, client, net, replay::{self, SignalSource}, server::{self, ServerState}, types::Transport};
+
+/// How long the tone is streamed.
+const DURATION: Duration = Duration::from_secs(2);
+/// Level check interval (the client meter updates per frame).
+const POLL: Duration = Duration::from_millis(50);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pass threshold on the client level (linear RMS, -40 dBFS; the tone arrives around -15 dBFS).
+pub const MIN_RMS: f64 = 0.01;
+const LOOPBACK: &str = "127.0.0.1";
+
+/// Outcome of a finished run (setup failures are errors instead).
+pub struct Report {
+    /// Highest client level seen (linear RMS)
+    pub peak_rms: f64,
+    /// Frames came over multicast (false: the client fell back to unicast)
+    pub multicast: bool,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool { self.peak_rms >= MIN_RMS }
+}
+
+/// Run the test, playing the tone on `output` (None = system default). Both sides are stopped
+/// before it returns, whatever the outcome.
+pub fn run(output: Option<String>) -> Result<Report> {
+    let mut state = ServerState::new();
+    state.select_multicast_group(false, None);
+    let port = net::pick_free_port_in(net::DEFAULT_PORT_RANGE)?;
+    let pool = AudioBufferPool::new(64);
+    let (tx, rx) = unbounded();
+    server::start_server(state.clone(), LOOPBACK.into(), port, pool.clone(), rx).context("start loopback server")?;
+    let res = stream(&state, port, pool, tx, output);
+    server::stop_server(&state);
+    res
+}
+
+fn stream(state: &ServerState, port: u16, pool: Arc<AudioBufferPool>, tx: Sender<usize>, output: Option<String>) -> Result<Report> {
+    let params = AudioParams { sample_rate: 48_000, channels: 1, sample_format: cpal::SampleFormat::F32 };
+    state.input_running.store(true, Ordering::SeqCst);
+    replay::spawn_test_signal(SignalSource::Tone, &params, pool, tx, state.input_running.clone())?;
+    *state.audio_params.lock() = Some(params);
+    state.stage.store(2, Ordering::SeqCst);
+    let cs = client::connect_with_output(LOOPBACK.into(), port, output, None, None, HANDSHAKE_TIMEOUT, Transport::Udp).context("connect to loopback server")?;
+    if !cs.connected.load(Ordering::Relaxed) { bail!("handshake rejected"); }
+    if cs.audio_tx.is_none() { client::disconnect(&cs); bail!("no output device for playback"); }
+    let start = Instant::now();
+    let mut peak_rms: f64 = 0.0;
+    while start.elapsed() < DURATION {
+        std::thread::sleep(POLL);
+        peak_rms = peak_rms.max(cs.current_rms.load());
+    }
+    let report = Report { peak_rms, multicast: !cs.unicast_fallback };
+    client::disconnect(&cs);
+    Ok(report)
+}
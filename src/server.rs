@@ -1,20 +1,46 @@
 //! UDP audio multicast + TCP control server implementation.
-use std::{net::{TcpListener, TcpStream, UdpSocket, SocketAddr, Shutdown, Ipv4Addr}, thread, time::{Duration, Instant}, sync::{Arc, atomic::{AtomicBool, AtomicU8, Ordering, AtomicU64}}};
+use std::{net::{TcpListener, TcpStream, UdpSocket, SocketAddr, Shutdown, IpAddr, Ipv4Addr, Ipv6Addr}, thread, time::{Duration, Instant}, sync::{Arc, atomic::{AtomicBool, AtomicU8, Ordering, AtomicU64}}};
 use std::io::Write;
 use anyhow::{Result, Context};
 use dashmap::DashMap;
 use rand::{Rng, distributions::Alphanumeric};
-use sha2::{Sha256, Digest};
-use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305};
 use crossbeam_channel::{Receiver};
 use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
 
-use crate::{audio::{AudioParams}, buffers::AudioBufferPool, types};
+use crate::{agc::AgcSettings, audio::{AudioParams}, buffers::AudioBufferPool, codec::{Codec, OpusStreamEncoder}, control::{ControlMessage, LineBuffer}, convert, degrade::{self, Degrader, Optional}, discovery::{self, Advertisement}, dtx::{Dtx, DtxAction, DtxSettings}, fec::FecEncoder, gate::GateSettings, meter::{ChannelLevels, PeakMeter, RateMeter, Scope}, monitor::LocalMonitor, net, packetize::{self, Packetizer}, ptt::PttSettings, record::{RecordFormat, ThreadedRecorder}, rekey::{self, EpochKey, KeyRotation}, replay::SignalSource, secret::PskSource, session::{SessionCheck, SessionLimit}, types};
 use crossbeam_channel::Sender as CbSender;
 
 #[derive(Clone, Debug)]
 /// Lightweight client entry (updated by control loop and used by multicast loop).
-pub struct ClientInfo { pub addr: SocketAddr, pub key: String, pub last_seen: Instant, pub udp_port: Option<u16> }
+/// `control` is a second handle on the control connection, so `stop_server` can notify the client
+/// without waiting for its control thread.
+pub struct ClientInfo { pub addr: SocketAddr, pub key: String, pub last_seen: Instant, pub udp_port: Option<u16>, pub substream: Substream, pub opus: bool, pub want: Option<(u32, u16)>, pub tcp_frames: Option<CbSender<Vec<u8>>>, pub kicked: bool, pub control: Option<Arc<TcpStream>> }
+
+/// Which audio stream a client receives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Substream {
+    #[default] Multicast,
+    /// Unicast low-quality substream (`SUBSTREAM LQ`)
+    LowQuality,
+    /// Multicast frames copied to the client by unicast (`WANT_UNICAST`, group join failed)
+    Unicast,
+    /// Multicast frames streamed over the control connection (`TRANSPORT TCP`)
+    Tcp,
+}
+
+/// Frames queued per TCP-transport client (~5 s of 10 ms chunks); a client further behind loses frames.
+const TCP_FRAME_QUEUE: usize = 500;
+/// A TCP-transport client that cannot take a frame for this long is dropped.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Commands for the capture thread (cpal streams are not `Send`, so the stream is rebuilt on that thread).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputCommand {
+    Stop,
+    /// Pause capture and rebuild the stream on the input device with this name
+    Switch(String),
+}
 
 // Minimal atomic f64 wrapper (reuse pattern from client)
 #[derive(Debug)]
@@ -29,56 +55,199 @@ pub struct ServerState {
     pub audio_params: Arc<Mutex<Option<AudioParams>>>,
     pub stage: Arc<AtomicU8>, // 0=stopped,1=listening,2=audio_ready
     pub input_running: Arc<AtomicBool>, // controls input capture thread/stream
-    pub input_stop_tx: Arc<Mutex<Option<CbSender<()>>>>, // signal precise stop
+    pub input_stop_tx: Arc<Mutex<Option<CbSender<InputCommand>>>>, // signal precise stop / device switch
+    pub input_lost: Arc<Mutex<Option<String>>>, // name of an input device unplugged while capturing (taken by the GUI for its dialog)
     pub current_rms: Arc<AtomicF64>, // latest audio RMS
+    pub send_kbps: Arc<AtomicF64>, // smoothed network send rate (bytes actually written, all destinations)
+    pub send_pps: Arc<AtomicF64>, // smoothed packets (frames incl. parity / unicast copies) per second
     pub peak_rms: Arc<AtomicF64>,    // decaying peak RMS
-    pub multicast_addr: Ipv4Addr,     // multicast address
-    pub multicast_port: u16,          // multicast port (can be same or separate from control port)
+    pub channel_rms: Arc<ChannelLevels>, // per-channel RMS (first `METER_CHANNELS_MAX` channels)
+    pub scope: Arc<Scope>,               // latest sent samples, mono (waveform view)
+    pub multicast_addr: IpAddr,       // multicast group (IPv4 239/8, or IPv6 ff02::/16 via `select_multicast_group`)
+    pub multicast_room: Option<String>, // room name the group was derived from (None = random group)
+    pub multicast_port: u16,          // multicast port (0 = auto: the control port if its UDP side is free, else a free UDP port)
+    pub control_port: u16,            // TCP control port of the running server (set by `start_server`)
+    pub multicast_ttl: u32,           // IPv4 multicast TTL (1 = local subnet; routers decrement per hop)
+    pub multicast_if: Option<IpAddr>, // Egress interface address for the UDP send socket (None = the control bind address)
+    pub max_clients: usize,           // connected client limit (0 = unlimited); extra connections get `FULL`
     pub psk: Option<String>,          // optional pre-shared key (enables encryption)
-    pub salt: [u8;8],                 // session salt (key derivation + nonce prefix)
-    pub key_bytes: Option<[u8;32]>,   // derived symmetric key (XChaCha20-Poly1305)
+    pub keys: Arc<Mutex<Option<KeyRotation>>>, // session keys derived from the PSK, rotated periodically (None = no encryption)
+    pub per_client_keys: bool,        // session keys from a random secret, handed to each client wrapped via x25519 (`KX` / `KEY`)
+    pub revoked: Arc<Mutex<Vec<IpAddr>>>, // hosts refused with `REVOKED` (see `revoke_client`)
+    pub send_errors: Arc<AtomicU64>,  // total multicast send failures
+    pub oversized_frames: Arc<AtomicU64>, // sealed frames whose datagram exceeded `mtu` (IP-fragmented on the wire)
+    pub send_error: Arc<Mutex<Option<String>>>, // sustained send failure message (None = healthy)
+    pub session: SessionLimit,        // optional max run time (auto stop)
+    pub replay_path: Arc<Mutex<Option<std::path::PathBuf>>>, // diagnostic replay: stream this WAV instead of the input device
+    pub replay_loop: Arc<AtomicBool>, // replay: restart at end of file
+    pub replay_normalize: Arc<AtomicBool>, // replay: peak-normalize before i16/u16 conversion
+    pub signal: Arc<AtomicU8>, // audio source: `replay::SignalSource` (Mic = input device / replay file, Tone / Noise = test signal)
+    pub follow_default_input: Arc<AtomicBool>, // capture: follow the OS default input device (rebuild on change)
+    pub motd: Arc<Mutex<String>>,     // banner sent to clients after the handshake (empty = none)
+    pub codec: Arc<AtomicU8>,         // multicast codec (`Codec::as_u8`); Opus only while every client can decode it
+    pub fec_k: Arc<AtomicU8>,         // XOR parity frame after every k multicast frames (0 = off, max `fec::FEC_MAX_GROUP`)
+    pub gain: Arc<AtomicF64>,         // capture gain (linear, 1.0 = unity), applied in the input callback
+    pub muted: Arc<AtomicBool>,       // capture mute: frames keep flowing with a silent payload
+    pub capture_frames: u32,          // frames per input callback (0 = device default), applied when the capture stream is built
+    pub packet_frames: u32,           // PCM frames per datagram (0 = one per capture buffer), see packetize.rs
+    pub mtu: u16,                     // datagram ceiling for `packet_frames` payloads
+    pub output_rate: u32,             // multicast sample rate (0 = capture rate); capture is resampled to it before framing
+    pub input_channels: Vec<u16>,     // device channels to capture (0-based, in order; empty = all), applied when the capture stream is built
+    pub agc: AgcSettings,             // capture automatic gain control (replaces `gain` while enabled), applied in the input callback
+    pub gate: GateSettings,           // capture noise gate (threshold / hold), applied in the input callback
+    pub dtx: DtxSettings,             // silence suppression: silence markers instead of frames while quiet (multicast loop)
+    pub ptt: PttSettings,             // push-to-talk: frames only while the hotkey is held (multicast loop)
+    pub advert: Arc<Mutex<Option<Advertisement>>>, // mDNS advertisement while running (dropped on stop)
+    pub recording: Arc<Mutex<Option<ThreadedRecorder>>>, // captured audio -> WAV / MP3 (fed from the multicast loop)
+    pub monitor: Arc<Mutex<Option<LocalMonitor>>>, // captured audio -> local default output (fed from the multicast loop)
 }
 
 impl ServerState { pub fn new() -> Self {
-    // Multicast address: choose inside 239.0.0.0/8 (administratively scoped)
-    let maddr = Ipv4Addr::new(239,rand::thread_rng().gen(),rand::thread_rng().gen(), rand::thread_rng().gen());
-    let mut salt=[0u8;8]; rand::thread_rng().fill(&mut salt);
-    Self { running: Arc::new(AtomicBool::new(false)), clients: Arc::new(DashMap::new()), audio_params: Arc::new(Mutex::new(None)), stage: Arc::new(AtomicU8::new(0)), input_running: Arc::new(AtomicBool::new(false)), input_stop_tx: Arc::new(Mutex::new(None)), current_rms: Arc::new(AtomicF64::new(0.0)), peak_rms: Arc::new(AtomicF64::new(0.0)), multicast_addr: maddr, multicast_port: 0, psk: None, salt, key_bytes: None }
+    let maddr = random_group(false);
+    Self { running: Arc::new(AtomicBool::new(false)), clients: Arc::new(DashMap::new()), audio_params: Arc::new(Mutex::new(None)), stage: Arc::new(AtomicU8::new(0)), input_running: Arc::new(AtomicBool::new(false)), input_stop_tx: Arc::new(Mutex::new(None)), input_lost: Arc::new(Mutex::new(None)), current_rms: Arc::new(AtomicF64::new(0.0)), send_kbps: Arc::new(AtomicF64::new(0.0)), send_pps: Arc::new(AtomicF64::new(0.0)), peak_rms: Arc::new(AtomicF64::new(0.0)), channel_rms: Arc::new(ChannelLevels::new()), scope: Arc::new(Scope::new()), multicast_addr: maddr, multicast_room: None, multicast_port: 0, control_port: 0, multicast_ttl: 1, multicast_if: None, max_clients: 0, psk: None, keys: Arc::new(Mutex::new(None)), per_client_keys: false, revoked: Arc::new(Mutex::new(Vec::new())), send_errors: Arc::new(AtomicU64::new(0)), oversized_frames: Arc::new(AtomicU64::new(0)), send_error: Arc::new(Mutex::new(None)), session: SessionLimit::new(), replay_path: Arc::new(Mutex::new(None)), replay_loop: Arc::new(AtomicBool::new(true)), replay_normalize: Arc::new(AtomicBool::new(false)), signal: Arc::new(AtomicU8::new(SignalSource::Mic.as_u8())), follow_default_input: Arc::new(AtomicBool::new(false)), motd: Arc::new(Mutex::new(String::new())), codec: Arc::new(AtomicU8::new(Codec::Pcm.as_u8())), fec_k: Arc::new(AtomicU8::new(0)), gain: Arc::new(AtomicF64::new(1.0)), muted: Arc::new(AtomicBool::new(false)), capture_frames: 0, packet_frames: 0, mtu: packetize::DEFAULT_MTU, output_rate: 0, input_channels: Vec::new(), agc: AgcSettings::new(), gate: GateSettings::new(), dtx: DtxSettings::new(), ptt: PttSettings::new(), advert: Arc::new(Mutex::new(None)), recording: Arc::new(Mutex::new(None)), monitor: Arc::new(Mutex::new(None)) }
 } 
-    /// Enable PSK encryption (call before start_server)
-    pub fn enable_psk(&mut self, psk: String) {
+    /// Choose the multicast group (call before start_server). With a non-empty `room` the group is derived
+    /// from its name, so the same room gets the same group on every launch; otherwise a random group is
+    /// kept and only redrawn when the family changes (or after leaving a room).
+    pub fn select_multicast_group(&mut self, ipv6: bool, room: Option<&str>) {
+        let room = room.map(str::trim).filter(|r| !r.is_empty()).map(str::to_string);
+        if let Some(r) = &room { self.multicast_addr = room_group(r, ipv6); }
+        else if self.multicast_room.is_some() || self.multicast_addr.is_ipv6() != ipv6 { self.multicast_addr = random_group(ipv6); }
+        self.multicast_room = room;
+    }
+    /// Enable PSK encryption from `source` (call before start_server). Returns false when the source is empty.
+    pub fn enable_psk(&mut self, source: &PskSource) -> Result<bool> {
+        let Some(psk) = source.resolve()? else { return Ok(false) };
         self.psk = Some(psk.clone());
-    // Derive key = SHA256(psk || salt)
-    let mut hasher: Sha256 = Default::default();
-        hasher.update(psk.as_bytes());
-        hasher.update(&self.salt);
-        let digest = hasher.finalize();
-        let mut key = [0u8;32]; key.copy_from_slice(&digest[..32]);
-        self.key_bytes = Some(key);
+        // Fresh salt (epoch 0) per enable; key = SHA256(psk || salt), see rekey.rs
+        self.keys = Arc::new(Mutex::new(Some(KeyRotation::new(psk))));
+        self.per_client_keys = false;
+        Ok(true)
+    }
+    /// Per-client key mode (call before start_server): no shared secret, each client gets the
+    /// session keys wrapped under its own x25519 exchange, so one client can be revoked (`kick_client`).
+    pub fn enable_per_client_keys(&mut self) {
+        self.psk = None;
+        self.keys = Arc::new(Mutex::new(Some(KeyRotation::random())));
+        self.per_client_keys = true;
+    }
+    /// Plaintext frames (call before start_server).
+    pub fn disable_encryption(&mut self) {
+        self.psk = None;
+        self.keys = Arc::new(Mutex::new(None));
+        self.per_client_keys = false;
+    }
+    /// Whether frames are encrypted (a PSK was enabled).
+    pub fn encrypted(&self) -> bool { self.keys.lock().is_some() }
+    /// Format clients receive: the capture params with `output_rate` applied (handshake, `PARAMS`, recording).
+    pub fn stream_params(&self) -> Option<AudioParams> {
+        self.audio_params.lock().clone().map(|p| AudioParams { sample_rate: if self.output_rate > 0 { self.output_rate } else { p.sample_rate }, ..p })
+    }
+    /// Read the live metrics once (headless status line, control API `/status`).
+    pub fn metrics_snapshot(&self) -> ServerMetrics {
+        ServerMetrics { rms: self.current_rms.load(), peak: self.peak_rms.load(), client_count: self.clients.len(), kbps: self.send_kbps.load() }
     }
 }
-impl Clone for ServerState { fn clone(&self)->Self { Self { running: self.running.clone(), clients: self.clients.clone(), audio_params: self.audio_params.clone(), stage: self.stage.clone(), input_running: self.input_running.clone(), input_stop_tx: self.input_stop_tx.clone(), current_rms: self.current_rms.clone(), peak_rms: self.peak_rms.clone(), multicast_addr: self.multicast_addr, multicast_port: self.multicast_port, psk: self.psk.clone(), salt: self.salt, key_bytes: self.key_bytes } } }
+
+/// Plain copy of the server metrics (`ServerState::metrics_snapshot`).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ServerMetrics { pub rms: f64, pub peak: f64, pub client_count: usize, pub kbps: f64 }
+impl Clone for ServerState { fn clone(&self)->Self { Self { running: self.running.clone(), clients: self.clients.clone(), audio_params: self.audio_params.clone(), stage: self.stage.clone(), input_running: self.input_running.clone(), input_stop_tx: self.input_stop_tx.clone(), input_lost: self.input_lost.clone(), current_rms: self.current_rms.clone(), send_kbps: self.send_kbps.clone(), send_pps: self.send_pps.clone(), peak_rms: self.peak_rms.clone(), channel_rms: self.channel_rms.clone(), scope: self.scope.clone(), multicast_addr: self.multicast_addr, multicast_room: self.multicast_room.clone(), multicast_port: self.multicast_port, control_port: self.control_port, multicast_ttl: self.multicast_ttl, multicast_if: self.multicast_if, max_clients: self.max_clients, psk: self.psk.clone(), keys: self.keys.clone(), per_client_keys: self.per_client_keys, revoked: self.revoked.clone(), send_errors: self.send_errors.clone(), oversized_frames: self.oversized_frames.clone(), send_error: self.send_error.clone(), session: self.session.clone(), replay_path: self.replay_path.clone(), replay_loop: self.replay_loop.clone(), replay_normalize: self.replay_normalize.clone(), signal: self.signal.clone(), follow_default_input: self.follow_default_input.clone(), motd: self.motd.clone(), codec: self.codec.clone(), fec_k: self.fec_k.clone(), gain: self.gain.clone(), muted: self.muted.clone(), capture_frames: self.capture_frames, packet_frames: self.packet_frames, mtu: self.mtu, output_rate: self.output_rate, input_channels: self.input_channels.clone(), agc: self.agc.clone(), gate: self.gate.clone(), dtx: self.dtx.clone(), ptt: self.ptt.clone(), advert: self.advert.clone(), recording: self.recording.clone(), monitor: self.monitor.clone() } } }
 
 /// Launch server threads (control + audio multicast). Non-blocking.
 pub fn start_server(mut state: ServerState, bind_ip: String, port: u16, pool: Arc<AudioBufferPool>, filled_rx: Receiver<usize>) -> Result<()> {
     state.running.store(true, Ordering::SeqCst);
     state.stage.store(0, Ordering::SeqCst);
-    let tcp_listener = TcpListener::bind((bind_ip.as_str(), port)).with_context(|| "bind tcp")?;
-    tcp_listener.set_nonblocking(true).ok();
-    // Multicast: bind ephemeral local port for sending
-    let udp = UdpSocket::bind((bind_ip.as_str(), 0)).with_context(|| "bind udp multicast send socket")?;
-    udp.set_nonblocking(true).ok();
-    state.multicast_port = port; // use provided port for multicast receive side
-    println!("[SERVER] multicast group selected: {}:{} (enc={})", state.multicast_addr, state.multicast_port, if state.key_bytes.is_some() {"on"} else {"off"});
+    state.send_errors.store(0, Ordering::SeqCst);
+    state.oversized_frames.store(0, Ordering::SeqCst);
+    state.send_kbps.store(0.0); state.send_pps.store(0.0);
+    *state.send_error.lock() = None;
+    state.session.start();
+    let tcp_listener = bind_control(&bind_ip, port)?;
+    let (udp, send_ip) = bind_multicast_sender(&state, &bind_ip)?;
+    state.control_port = port;
+    state.multicast_port = multicast_port_for(&state, port)?;
+    log::info!("[SERVER] multicast group selected: {}:{} ttl={} egress={} room={} (enc={})", state.multicast_addr, state.multicast_port, state.multicast_ttl, send_ip, state.multicast_room.as_deref().unwrap_or("-"), if state.encrypted() {"on"} else {"off"});
     state.stage.store(1, Ordering::SeqCst); // listening
+    // LAN discovery (best effort: the server works without it)
+    match discovery::start_advertise(&state) { Ok(ad) => *state.advert.lock() = Some(ad), Err(e) => log::warn!("[DISCOVERY] advertise failed: {e:#}") }
     let s_clone = state.clone();
     // Control thread
-    thread::spawn(move || { control_loop(tcp_listener, s_clone); });
+    thread::Builder::new().name("rm-ctrl".into()).spawn(move || { control_loop(tcp_listener, s_clone); }).context("spawn control thread")?;
     let s_clone2 = state.clone();
-    thread::spawn(move || { audio_multicast_loop(s_clone2, udp, pool, filled_rx); });
+    thread::Builder::new().name("rm-mcast".into()).spawn(move || { audio_multicast_loop(s_clone2, udp, pool, filled_rx); }).context("spawn multicast thread")?;
     Ok(())
 }
 
+/// Control listener (non-blocking: the accept loop polls `running`).
+pub fn bind_control(bind_ip: &str, port: u16) -> Result<TcpListener> {
+    let listener = TcpListener::bind((bind_ip, port)).with_context(|| format!("bind tcp {bind_ip}:{port}"))?;
+    listener.set_nonblocking(true).ok();
+    Ok(listener)
+}
+
+/// Multicast send socket: ephemeral port on the egress address (default: `bind_ip`; same family as the
+/// group, any address if it is the other family), TTL / interface applied. Returns it with that address.
+pub fn bind_multicast_sender(state: &ServerState, bind_ip: &str) -> Result<(UdpSocket, IpAddr)> {
+    let egress = state.multicast_if.map(Ok).unwrap_or_else(|| bind_ip.parse::<IpAddr>());
+    let send_ip = match (egress, state.multicast_addr) {
+        (Ok(ip), group) if ip.is_ipv6() == group.is_ipv6() => ip,
+        (_, IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        (_, IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    let udp = UdpSocket::bind((send_ip, 0)).with_context(|| format!("bind udp multicast send socket on {send_ip}"))?;
+    udp.set_nonblocking(true).ok();
+    set_multicast_opts(&udp, state);
+    Ok((udp, send_ip))
+}
+
+/// Multicast receive port (clients bind it): explicit, else the control port unless something here holds its UDP side.
+pub fn multicast_port_for(state: &ServerState, control_port: u16) -> Result<u16> {
+    if state.multicast_port != 0 { return Ok(state.multicast_port); }
+    if net::udp_port_free(control_port) { Ok(control_port) } else { net::pick_free_udp_port_in(net::DEFAULT_PORT_RANGE) }
+}
+
+/// Random multicast group: IPv4 inside 239.0.0.0/8 (administratively scoped) or IPv6 link-local
+/// scope `ff02::524d:x:x:x` (clear of the well-known ff02::1/2/fb and solicited-node ff02::1:ffxx:xxxx groups).
+fn random_group(ipv6: bool) -> IpAddr {
+    let mut rng = rand::thread_rng();
+    if ipv6 { IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0x524d, rng.gen(), rng.gen(), rng.gen())) }
+    else { IpAddr::V4(Ipv4Addr::new(239, rng.gen(), rng.gen(), rng.gen())) }
+}
+
+/// Group for a room name (trimmed, case-insensitive), from SHA-256 of the name. IPv4 stays inside 239/8
+/// but avoids second octets 0 / 128: their MAC addresses alias the 224.0.0.x control block, which
+/// switches flood to every port. IPv6 uses the same `ff02::524d:x:x:x` range as `random_group`.
+fn room_group(room: &str, ipv6: bool) -> IpAddr {
+    let h = Sha256::digest(format!("remotemic-room:{}", room.trim().to_lowercase()).as_bytes());
+    let word = |i: usize| u16::from_be_bytes([h[i], h[i + 1]]);
+    if ipv6 { IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0x524d, word(0), word(2), word(4))) }
+    else { IpAddr::V4(Ipv4Addr::new(239, if h[0] & 0x7f == 0 { h[0] | 1 } else { h[0] }, h[1], h[2])) }
+}
+
+/// Apply `multicast_ttl` (IPv4) and the egress interface of the socket's bound address. IPv6 groups
+/// are link-local (`ff02::`), so no hop limit; their outgoing interface comes from
+/// `IPV6_MULTICAST_IF` (an interface index), not the bound address, so it is looked up and set too.
+fn set_multicast_opts(udp: &UdpSocket, state: &ServerState) {
+    match udp.local_addr() {
+        Ok(SocketAddr::V4(local)) if state.multicast_addr.is_ipv4() => {
+            if let Err(e) = udp.set_multicast_ttl_v4(state.multicast_ttl.clamp(1, 255)) { log::warn!("[SERVER] set multicast ttl {} failed: {e}", state.multicast_ttl); }
+            if !local.ip().is_unspecified() {
+                if let Err(e) = socket2::SockRef::from(udp).set_multicast_if_v4(local.ip()) { log::warn!("[SERVER] set multicast interface {} failed: {e}", local.ip()); }
+            }
+        }
+        Ok(SocketAddr::V6(local)) if !local.ip().is_unspecified() => {
+            // A scoped link-local bind already names its interface
+            let index = if local.scope_id() != 0 { Some(local.scope_id()) } else { net::interface_index(*local.ip()) };
+            match index {
+                Some(i) => if let Err(e) = socket2::SockRef::from(udp).set_multicast_if_v6(i) { log::warn!("[SERVER] set multicast interface {} (index {i}) failed: {e}", local.ip()); },
+                None => log::warn!("[SERVER] no interface holds {} -> IPv6 multicast leaves through the system default", local.ip()),
+            }
+        }
+        _ => {}
+    }
+}
+
 fn random_key() -> String { rand::thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect() }
 
 /// Accept & service control TCP connections (handshake + heartbeats + UDP port announce).
@@ -86,33 +255,64 @@ fn control_loop(listener: TcpListener, state: ServerState) {
     let _buf = [0u8; 1024];
     loop {
         if !state.running.load(Ordering::Relaxed) { break; }
+        match state.session.check() {
+            SessionCheck::Warn(left) => log::warn!("[SERVER][SESSION] time limit reached in {}s", left.as_secs()),
+            SessionCheck::Expired => { log::info!("[SERVER][SESSION] time limit reached -> stopping"); stop_server(&state); break; }
+            SessionCheck::Ok => {}
+        }
         match listener.accept() {
+            Ok((mut stream, addr)) if state.revoked.lock().contains(&addr.ip()) => {
+                log::info!("[SERVER] {addr} rejected: revoked");
+                let _ = stream.write_all(b"REVOKED\n");
+                let _ = stream.shutdown(Shutdown::Both);
+            },
+            Ok((mut stream, addr)) if state.max_clients > 0 && state.clients.len() >= state.max_clients => {
+                log::info!("[SERVER] {addr} rejected: client limit {} reached", state.max_clients);
+                let _ = stream.write_all(b"FULL\n");
+                let _ = stream.shutdown(Shutdown::Both);
+            },
             Ok((mut stream, addr)) => {
                 // Make per-client stream non-blocking so we can poll running flag
                 let _ = stream.set_nonblocking(true);
                 let key = random_key();
-                let params = state.audio_params.lock().clone();
+                let params = state.stream_params();
+                let enc_key = state.keys.lock().as_ref().map(|r| r.active());
+                let kx = state.per_client_keys.then(rekey::kx_keypair);
                 let header = if let Some(p)=params { 
                     let fmt_code = crate::types::sample_format_code(p.sample_format);
-                    let mut base = format!("OK {} {} {} {} {} {}", key, p.sample_rate, p.channels, fmt_code, state.multicast_addr, state.multicast_port);
-                    if let Some(_kb) = state.key_bytes { 
-                        // Append ENC + salt hex
-                        let salt_hex: String = state.salt.iter().map(|b| format!("{:02x}", b)).collect();
-                        base.push_str(&format!(" ENC {}", salt_hex));
+                    let mut base = format!("OK V{} {} {} {} {} {} {}", types::PROTOCOL_VERSION, key, p.sample_rate, p.channels, fmt_code, state.multicast_addr, state.multicast_port);
+                    if let Some((_, public)) = &kx {
+                        // Per-client keys: x25519 public key, the session key follows as `KEY` once the client answers `KX`
+                        base.push_str(&format!(" KX {}", rekey::to_hex(public)));
+                    } else if let Some(k) = enc_key { 
+                        // Append ENC + salt hex + key epoch
+                        base.push_str(&format!(" ENC {} {}", k.salt_hex(), k.epoch));
                     } else {
                         base.push_str(" NOENC");
                     }
+                    // Parity group size (old clients ignore it and skip parity frames by their magic)
+                    let fec_k = state.fec_k.load(Ordering::Relaxed);
+                    if fec_k > 0 { base.push_str(&format!(" FEC {fec_k}")); }
+                    // Heartbeat stamps are echoed (old clients ignore it and send plain HEART)
+                    base.push_str(" RTT");
+                    // Transports on offer (old clients ignore it and stay on UDP)
+                    base.push_str(" TRANSPORT");
+                    for t in types::Transport::ALL { base.push(' '); base.push_str(t.as_str()); }
+                    // Codec capability (old clients ignore it and never answer CODECS -> stream stays PCM)
+                    base.push_str(" CODECS");
+                    for c in Codec::ALL { base.push(' '); base.push_str(c.as_str()); }
                     base.push('\n');
                     base
                 } else { format!("NO_PARAMS {key}\n") };
                 let _ = stream.write_all(header.as_bytes());
-                let ci = ClientInfo { addr, key: key.clone(), last_seen: Instant::now(), udp_port: None };
+                if let Some(motd) = ControlMessage::motd(&state.motd.lock()) { let _ = stream.write_all(motd.to_line().as_bytes()); }
+                let ci = ClientInfo { addr, key: key.clone(), last_seen: Instant::now(), udp_port: None, substream: Substream::Multicast, opus: false, want: None, tcp_frames: None, kicked: false, control: stream.try_clone().ok().map(Arc::new) };
                 state.clients.insert(addr, ci);
                 let st_clone = state.clone();
-                thread::spawn(move || { per_client_control(stream, addr, st_clone); });
+                if let Err(e) = thread::Builder::new().name(format!("rm-client-{addr}")).spawn(move || { let announced = if kx.is_some() { None } else { enc_key }; per_client_control(stream, addr, st_clone, announced, kx.map(|(secret, _)| secret)); }) { log::error!("[SERVER] spawn client thread for {addr}: {e}"); }
             },
             Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => { thread::sleep(Duration::from_millis(50)); },
-            Err(e) => { eprintln!("accept err: {e}"); thread::sleep(Duration::from_millis(200)); }
+            Err(e) => { log::error!("accept err: {e}"); thread::sleep(Duration::from_millis(200)); }
         }
         // Heartbeat cleanup
         let now = Instant::now();
@@ -122,136 +322,481 @@ fn control_loop(listener: TcpListener, state: ServerState) {
     }
 }
 
-/// Handle a single client's control connection until disconnect.
-fn per_client_control(mut stream: TcpStream, addr: SocketAddr, state: ServerState) {
+/// Handle a single client's control connection until disconnect. `announced` = newest session key
+/// this client holds (handshake); later keys are pushed as `REKEY`, or as `KEY` wrapped under the
+/// exchange key once the client answered `KX` (per-client key mode).
+fn per_client_control(mut stream: TcpStream, addr: SocketAddr, state: ServerState, mut announced: Option<EpochKey>, mut kx: Option<x25519_dalek::EphemeralSecret>) {
     use std::io::Read; use std::io::Write;
     let mut buf = [0u8; 256];
+    let mut kek: Option<[u8;32]> = None;
+    let mut lines = LineBuffer::new();
+    // Format this client was told about (handshake); an input device switch is pushed as `PARAMS`
+    let mut sent_params = state.stream_params();
+    // TCP transport: frames queued by the multicast loop, written between control reads
+    let mut tcp_frames: Option<Receiver<Vec<u8>>> = None;
     loop {
         if !state.running.load(Ordering::Relaxed) {
-            let _ = stream.write_all(b"SERVER_STOP\n");
+            let _ = stream.write_all(ControlMessage::ServerStop.to_line().as_bytes());
+            break;
+        }
+        // Kicked by the host: say so, then drop the entry
+        if state.clients.get(&addr).is_some_and(|c| c.kicked) {
+            log::info!("[SERVER] {addr} kicked");
+            let _ = stream.write_all(ControlMessage::Kicked.to_line().as_bytes());
+            state.clients.remove(&addr);
             break;
         }
+        // Entry removed (expired / transport stalled) -> tell this client to go away
+        if !state.clients.contains_key(&addr) {
+            let _ = stream.write_all(ControlMessage::ServerStop.to_line().as_bytes());
+            break;
+        }
+        // Key rotation: hand out the announced key before frames switch to it
+        if let Some(next) = state.keys.lock().as_ref().and_then(|r| r.next()) {
+            let msg = match kek {
+                Some(kek) => Some(ControlMessage::Key { epoch: next.epoch, salt: next.salt, wrapped: rekey::wrap(&kek, &next) }),
+                None if !state.per_client_keys => Some(ControlMessage::Rekey { epoch: next.epoch, salt: next.salt }),
+                None => None, // key exchange not done yet: gets the keys with its `KEY` replies
+            };
+            if let Some(msg) = msg.filter(|_| announced != Some(next)) { let _ = stream.write_all(msg.to_line().as_bytes()); announced = Some(next); }
+        }
+        let params = state.stream_params();
+        if let Some(p) = params.as_ref().filter(|_| params != sent_params) {
+            let msg = ControlMessage::Params { sample_rate: p.sample_rate, channels: p.channels, fmt: types::sample_format_code(p.sample_format) };
+            let _ = stream.write_all(msg.to_line().as_bytes());
+            sent_params = params;
+        }
         match stream.read(&mut buf) {
             Ok(0) => break,
             Ok(n) => {
-                let raw = String::from_utf8_lossy(&buf[..n]).to_string();
-                for line in raw.lines() {
-                    let line = line.trim(); if line.is_empty() { continue; }
-                    if line.starts_with("HEART ") {
-                        let parts: Vec<_> = line.split_whitespace().collect();
-                        if parts.len()==2 { if let Some(mut ci) = state.clients.get_mut(&addr) { if ci.key == parts[1] { ci.last_seen = std::time::Instant::now(); let _ = stream.write_all(b"OK\n"); } } }
-                    } else if line == "DISCONNECT" { state.clients.remove(&addr); let _ = stream.write_all(b"BYE\n"); return; }
+                for msg in lines.push(&buf[..n]) {
+                    match msg {
+                        ControlMessage::Heart { key, stamp } => if let Some(mut ci) = state.clients.get_mut(&addr) { if ci.key == key { ci.last_seen = std::time::Instant::now(); let reply = stamp.map_or(ControlMessage::Ok, |stamp| ControlMessage::Echo { stamp }); let _ = stream.write_all(reply.to_line().as_bytes()); } },
+                        ControlMessage::Substream { low_quality_port } => if let Some(mut ci) = state.clients.get_mut(&addr).filter(|ci| ci.substream != Substream::Tcp) {
+                            match low_quality_port {
+                                Some(p) => { ci.udp_port = Some(p); ci.substream = Substream::LowQuality; log::info!("[SERVER] {addr} -> low-quality substream (udp {p})"); },
+                                None => { ci.substream = Substream::Multicast; log::info!("[SERVER] {addr} -> multicast"); },
+                            }
+                        },
+                        ControlMessage::Udp { port } => if let Some(mut ci) = state.clients.get_mut(&addr) { ci.udp_port = Some(port); },
+                        ControlMessage::WantUnicast { port } => if let Some(mut ci) = state.clients.get_mut(&addr).filter(|ci| ci.substream != Substream::Tcp) { ci.udp_port = Some(port); ci.substream = Substream::Unicast; log::info!("[SERVER] {addr} -> unicast fallback (udp {port})"); },
+                        ControlMessage::Kx { public } => if let Some(secret) = kx.take() {
+                            let k = rekey::kx_finish(secret, public); kek = Some(k);
+                            let (active, next) = state.keys.lock().as_ref().map(|r| (Some(r.active()), r.next())).unwrap_or((None, None));
+                            for key in active.into_iter().chain(next) { let _ = stream.write_all(ControlMessage::Key { epoch: key.epoch, salt: key.salt, wrapped: rekey::wrap(&k, &key) }.to_line().as_bytes()); announced = Some(key); }
+                            log::info!("[SERVER] {addr} key exchange done");
+                        },
+                        ControlMessage::Want { sample_rate, channels } => if let Some(mut ci) = state.clients.get_mut(&addr) {
+                            ci.want = Some((sample_rate, channels));
+                            // Informational for now: the stream keeps one format, the client resamples / maps channels on playback
+                            let stream = state.stream_params().map(|p| (p.sample_rate, p.channels));
+                            log::info!("[SERVER] {addr} plays {sample_rate} Hz x{channels}{}", match stream { Some(s) if s != (sample_rate, channels) => format!(" (stream {} Hz x{}: converted by the client)", s.0, s.1), _ => String::new() });
+                        },
+                        ControlMessage::Codecs { list } => if let Some(mut ci) = state.clients.get_mut(&addr) { ci.opus = list.contains(&Codec::Opus); log::info!("[SERVER] {addr} codecs: {list:?}"); },
+                        ControlMessage::Transport { kind: types::Transport::Tcp } => if let Some(mut ci) = state.clients.get_mut(&addr) {
+                            // Blocking writes from here on: a frame must never be cut by WouldBlock (short read timeout keeps the loop polling)
+                            if stream.set_nonblocking(false).and_then(|_| stream.set_read_timeout(Some(Duration::from_millis(1)))).and_then(|_| stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT))).is_err() { continue; }
+                            let (tx, rx) = crossbeam_channel::bounded(TCP_FRAME_QUEUE);
+                            ci.tcp_frames = Some(tx); ci.substream = Substream::Tcp; tcp_frames = Some(rx);
+                            log::info!("[SERVER] {addr} -> tcp transport");
+                        },
+                        ControlMessage::Transport { kind: types::Transport::Udp } => {}
+                        ControlMessage::Disconnect => { state.clients.remove(&addr); let _ = stream.write_all(ControlMessage::Bye.to_line().as_bytes()); return; },
+                        ControlMessage::Ok | ControlMessage::Echo { .. } | ControlMessage::Bye | ControlMessage::ServerStop | ControlMessage::Kicked | ControlMessage::Motd { .. } | ControlMessage::Rekey { .. } | ControlMessage::Key { .. } | ControlMessage::Params { .. } | ControlMessage::Frame { .. } => {} // server -> client only
+                    }
                 }
             },
-            Err(e) if e.kind()==std::io::ErrorKind::WouldBlock => { std::thread::sleep(std::time::Duration::from_millis(50)); },
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => match &tcp_frames {
+                Some(rx) => if !push_tcp_frames(&mut stream, rx) { log::info!("[SERVER] {addr} tcp transport stalled -> dropping client"); state.clients.remove(&addr); break; },
+                None => std::thread::sleep(std::time::Duration::from_millis(50)),
+            },
             Err(_) => { break; },
         }
     }
     let _ = stream.shutdown(Shutdown::Both);
 }
 
+/// TCP transport: wait briefly for queued frames and write them all as `FRAME` messages; false if the
+/// client stopped reading (write timed out or failed).
+fn push_tcp_frames(stream: &mut TcpStream, rx: &Receiver<Vec<u8>>) -> bool {
+    let Ok(first) = rx.recv_timeout(Duration::from_millis(20)) else { return true };
+    std::iter::once(first).chain(rx.try_iter()).all(|frame| stream.write_all(&ControlMessage::frame_bytes(&frame)).is_ok())
+}
+
+/// Multicast send socket wrapper: counts failures, flags sustained outages and rebinds on fatal errors;
+/// meters the bytes it gets onto the network.
+struct MulticastSender { udp: UdpSocket, bind_ip: IpAddr, consecutive_fail: u32, failing_since: Option<Instant>, rate: RateMeter }
+
+/// Sustained failure threshold before the server surfaces an error state.
+const SEND_FAIL_SURFACE_AFTER: Duration = Duration::from_secs(2);
+
+impl MulticastSender {
+    fn new(udp: UdpSocket) -> Self {
+        let bind_ip = udp.local_addr().map(|a| a.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        Self { udp, bind_ip, consecutive_fail: 0, failing_since: None, rate: RateMeter::new(Instant::now()) }
+    }
+
+    /// Fatal = the socket itself is unusable (closed / invalid handle); everything else (ENOBUFS, network down, would block) is transient.
+    fn is_fatal(e: &std::io::Error) -> bool {
+        matches!(e.kind(), std::io::ErrorKind::NotConnected | std::io::ErrorKind::InvalidInput)
+            || matches!(e.raw_os_error(), Some(9) | Some(10009) | Some(10038)) // EBADF / WSAEBADF / WSAENOTSOCK
+    }
+
+    fn send(&mut self, buf: &[u8], dest: SocketAddr, state: &ServerState) {
+        // Unicast targets follow the control connection's family, which may differ from the group's
+        let dest = match (dest, self.bind_ip) {
+            (SocketAddr::V4(a), IpAddr::V6(_)) => SocketAddr::new(IpAddr::V6(a.ip().to_ipv6_mapped()), a.port()),
+            (SocketAddr::V6(a), IpAddr::V4(_)) => a.ip().to_ipv4_mapped().map_or(dest, |v4| SocketAddr::new(IpAddr::V4(v4), a.port())),
+            _ => dest,
+        };
+        match self.udp.send_to(buf, dest) {
+            Ok(n) => {
+                self.rate.add(n);
+                if self.consecutive_fail > 0 {
+                    log::info!("[SERVER][SEND] recovered after {} failed sends", self.consecutive_fail);
+                    self.consecutive_fail = 0; self.failing_since = None;
+                    *state.send_error.lock() = None;
+                }
+            }
+            Err(e) => {
+                state.send_errors.fetch_add(1, Ordering::Relaxed);
+                self.consecutive_fail += 1;
+                let since = *self.failing_since.get_or_insert_with(Instant::now);
+                if self.consecutive_fail == 1 { log::warn!("[SERVER][SEND] multicast send failed: {e}"); }
+                if Self::is_fatal(&e) {
+                    log::warn!("[SERVER][SEND] socket invalid ({e}) -> rebinding on {}", self.bind_ip);
+                    match UdpSocket::bind((self.bind_ip, 0)) {
+                        Ok(sock) => { sock.set_nonblocking(true).ok(); set_multicast_opts(&sock, state); self.udp = sock; }
+                        Err(be) => log::warn!("[SERVER][SEND] rebind failed: {be}"),
+                    }
+                }
+                if since.elapsed() >= SEND_FAIL_SURFACE_AFTER {
+                    let mut guard = state.send_error.lock();
+                    if guard.is_none() { *guard = Some(format!("multicast send failing — network down? ({e})")); }
+                }
+            }
+        }
+    }
+}
+
 /// Pop captured buffers, build framed packets with timestamp, and send to all clients.
 fn audio_multicast_loop(state: ServerState, udp: UdpSocket, pool: Arc<AudioBufferPool>, filled_rx: Receiver<usize>) {
+    let mut sender = MulticastSender::new(udp);
     let mut seq: u32 = 0;
+    let mut lq_seq: u32 = 0; // own counter: Opus emits 0..n multicast frames per buffer
+    let mut silence_seq: u32 = 0; // silence markers (DTX)
+    let mut dtx = Dtx::new();
+    let mut opus_enc: Option<OpusStreamEncoder> = None;
+    let mut fec = FecEncoder::new();
+    let mut packetizer = Packetizer::new(state.packet_frames, state.mtu);
+    let mut rate_conv: Option<(u32, u16, convert::StreamResampler)> = None; // (capture rate, channels, resampler) for `output_rate`
+    let mut last_codec = Codec::Pcm;
     let mut rms_counter: u32 = 0;
+    let mut peak_meter = PeakMeter::new();
+    let mut degrader = Degrader::from_env("SERVER", &degrade::SERVER_ORDER);
         // Base monotonic time reference for timestamps (nanoseconds since first frame loop start)
         let start_instant = Instant::now();
     while state.running.load(Ordering::Relaxed) {
+        // Send rate (checked every pass, so it decays while nothing is captured)
+        if let Some((kbps, pps)) = sender.rate.tick(Instant::now()) { state.send_kbps.store(kbps); state.send_pps.store(pps); }
+        // Capture chunks larger than the buffers were truncated: grow the pool (no buffer lock held here)
+        if pool.grow_to_wanted() { log::warn!("[SERVER] capture chunks truncated ({} so far) -> pool buffers grown to {} bytes", pool.truncated.load(Ordering::Relaxed), pool.buffer_size()); }
         if let Ok(idx) = filled_rx.recv_timeout(Duration::from_millis(200)) {
+            let busy_start = Instant::now();
             let data_guard = pool.data[idx].lock();
             let raw: &[u8] = &data_guard;
             if raw.len() < 4 { pool.push(idx); continue; }
             let payload_len = u32::from_le_bytes([raw[0],raw[1],raw[2],raw[3]]) as usize;
             if payload_len == 0 || payload_len+4 > raw.len() { pool.push(idx); continue; }
             let data = &raw[4..4+payload_len];
-            // Compute simple RMS (assume f32 frames if divisible by 4) for debug
-            let rms = if data.len() % 4 == 0 { let mut acc=0f64; let mut cnt=0usize; for chunk in data.chunks_exact(4) { let mut a=[0u8;4]; a.copy_from_slice(chunk); let v=f32::from_ne_bytes(a) as f64; acc+=v*v; cnt+=1; } if cnt>0 { (acc/(cnt as f64)).sqrt() } else { 0.0 } } else { 0.0 };
-            rms_counter += 1; if rms_counter % 50 == 0 { println!("[SERVER] RMS ~ {:.5}", rms); }
-            // Update shared RMS & peak (decay ~1% per frame batch ~depends on capture rate) ; GUI decays similarly
-            state.current_rms.store(rms as f64);
-            let prev_peak = state.peak_rms.load();
-            let new_peak = if rms > prev_peak { rms } else { prev_peak * 0.99 }; // simple exponential decay
-            if (new_peak - prev_peak).abs() > 1e-12 { state.peak_rms.store(new_peak); }
-            // println!("[SERVER] multicast buffer {} ({} bytes payload) to {} clients", idx, data.len(), state.clients.len());
-            let to_remove = vec![]; // currently unused removal list placeholder
+            rms_counter += 1;
             let params_opt = state.audio_params.lock().clone();
             let (sr, ch, fmt_code) = if let Some(p)=params_opt { (p.sample_rate, p.channels, types::sample_format_code(p.sample_format)) } else { (48000u32, 2u16, types::FMT_F32) };
-            // Header: magic(2) + seq(u32) + fmt(u8) + ch(u8) + rate(u32) + payload_len(u16) = 2+4+1+1+4+2 =14 bytes
-            // New header with timestamp (nanoseconds since start):
-            // magic(2) | seq(u32) | fmt(u8) | ch(u8) | rate(u32) | payload_len(u16) | ts_us(u64)
-            // = 2+4+1+1+4+2+8 = 22 bytes header
-            let payload_len = data.len().min(u16::MAX as usize) as u16;
-            let ts_ns: u64 = start_instant.elapsed().as_nanos() as u64;
-            let mut frame = Vec::with_capacity(22 + payload_len as usize);
-            frame.extend_from_slice(&types::FRAME_MAGIC);          // 0..2
-            frame.extend_from_slice(&seq.to_be_bytes());            // 2..6
-            frame.push(fmt_code);                                   // 6
-            frame.push(ch as u8);                                   // 7
-            frame.extend_from_slice(&sr.to_be_bytes());             // 8..12
-            frame.extend_from_slice(&payload_len.to_be_bytes());    // 12..14
-            frame.extend_from_slice(&ts_ns.to_be_bytes());          // 14..22
-            frame.extend_from_slice(&data[..payload_len as usize]); // 22..
-            seq = seq.wrapping_add(1);
-            // Optional encryption (payload only, header as AAD)
-            let mcast_sock = SocketAddr::new(std::net::IpAddr::V4(state.multicast_addr), state.multicast_port);
-            if let Some(key_bytes) = state.key_bytes {
-                // Rebuild header so payload_len reflects ciphertext length; use final header as AAD
-                if frame.len() >= 22 {
-                    let plaintext_payload_len = frame.len() - 22; // existing payload length (u16 already capped)
-                    let ciphertext_len = plaintext_payload_len + 16; // AEAD tag 16 bytes
-                    if ciphertext_len <= u16::MAX as usize {
-                        // Extract fields
-                        let seq_header = seq.wrapping_sub(1); // seq value in header
-                        let fmt_code = frame[6];
-                        let ch_byte = frame[7];
-                        let sr_bytes = &frame[8..12];
-                        let ts_bytes = &frame[14..22];
-                        let payload_plain = &frame[22..];
-                        let mut nonce = [0u8;24];
-                        nonce[..8].copy_from_slice(&state.salt);
-                        nonce[8..12].copy_from_slice(&seq_header.to_be_bytes());
-                        nonce[12..20].copy_from_slice(&u64::from_be_bytes(ts_bytes.try_into().unwrap()).to_be_bytes());
-                        let cipher = XChaCha20Poly1305::new(&key_bytes.into());
-                        // Build final header (AAD)
-                        let mut final_header = [0u8;22];
-                        final_header[0..2].copy_from_slice(&types::FRAME_MAGIC);
-                        final_header[2..6].copy_from_slice(&seq_header.to_be_bytes());
-                        final_header[6] = fmt_code;
-                        final_header[7] = ch_byte;
-                        final_header[8..12].copy_from_slice(sr_bytes);
-                        final_header[12..14].copy_from_slice(&(ciphertext_len as u16).to_be_bytes());
-                        final_header[14..22].copy_from_slice(ts_bytes);
-                        match cipher.encrypt(&nonce.into(), Payload { msg: payload_plain, aad: &final_header }) {
-                            Ok(ct) => {
-                                let mut out = Vec::with_capacity(22 + ct.len());
-                                out.extend_from_slice(&final_header);
-                                out.extend_from_slice(&ct);
-                                let _ = udp.send_to(&out, mcast_sock);
-                            }
-                            Err(e) => {
-                                eprintln!("[SERVER][ENC] encrypt fail seq={seq_header}: {e} -> send plaintext");
-                                let _ = udp.send_to(&frame, mcast_sock);
-                            }
-                        }
-                    } else {
-                        // Fallback: plaintext (too large)
-                        let _ = udp.send_to(&frame, mcast_sock);
-                    }
-                } else {
-                    let _ = udp.send_to(&frame, mcast_sock);
+            // Fixed stream rate: resample (phase kept across buffers) and re-encode in the capture format, so everything below sees the stream format
+            let resampled;
+            let (data, sr) = match state.output_rate {
+                out if out > 0 && out != sr => {
+                    if !matches!(&rate_conv, Some((from, c, _)) if *from == sr && *c == ch) { rate_conv = Some((sr, ch, convert::StreamResampler::new(sr, out, ch))); }
+                    let mut pcm = Vec::with_capacity(data.len() / types::bytes_per_sample(fmt_code) * out as usize / sr.max(1) as usize + 8);
+                    if let Some((_, _, conv)) = rate_conv.as_mut() { conv.process(&convert::decode_pcm(data, fmt_code), &mut pcm); }
+                    resampled = convert::encode_pcm(&pcm, fmt_code);
+                    (&resampled[..], out)
                 }
-            } else { let _ = udp.send_to(&frame, mcast_sock); }
+                _ => (data, sr),
+            };
+            if data.is_empty() { pool.push(idx); continue; }
+            // Metering is optional work: under CPU pressure only every 4th buffer is metered
+            if !degrader.is_shed(Optional::Metering) || rms_counter & 3 == 0 {
+                // Same per-buffer RMS the capture-side noise gate uses
+                let rms = convert::rms(data, fmt_code);
+                if rms_counter % 50 == 0 { log::debug!("[SERVER] RMS ~ {:.5}", rms); }
+                // Update shared RMS & peak (wall-clock hold/fall, independent of the capture callback rate)
+                state.current_rms.store(rms);
+                state.peak_rms.store(peak_meter.update(rms, Instant::now()));
+                let pcm = convert::decode_pcm(data, fmt_code);
+                state.channel_rms.update(&pcm, ch as usize);
+                state.scope.push(&pcm, ch as usize);
+            }
+            // log::trace!("[SERVER] multicast buffer {} ({} bytes payload) to {} clients", idx, data.len(), state.clients.len());
+            let to_remove = vec![]; // currently unused removal list placeholder
+            // 23-byte header with timestamp (nanoseconds since start), see build_frame
+            let ts_ns: u64 = start_instant.elapsed().as_nanos() as u64;
+            let mcast_sock = SocketAddr::new(state.multicast_addr, state.multicast_port);
+            // Opus only when selected and every connected client announced it (old clients keep PCM)
+            let codec = if Codec::from_u8(state.codec.load(Ordering::Relaxed)) == Codec::Opus && state.clients.iter().all(|c| c.opus) { Codec::Opus } else { Codec::Pcm };
+            if codec != last_codec { log::info!("[SERVER] multicast codec -> {}", codec.as_str()); last_codec = codec; opus_enc = None; packetizer.clear(); }
+            if codec == Codec::Opus && !matches!(&opus_enc, Some(e) if e.matches(sr, ch)) {
+                opus_enc = OpusStreamEncoder::new(sr, ch).map_err(|e| log::warn!("[SERVER][OPUS] {e:#} -> PCM")).ok();
+            }
+            // Unicast fallback: same sealed frames, also sent to clients that could not join the group
+            let uc_targets: Vec<SocketAddr> = state.clients.iter().filter(|c| c.substream == Substream::Unicast).filter_map(|c| c.udp_port.map(|p| SocketAddr::new(c.addr.ip(), p))).collect();
+            // TCP transport: queued for each client's control thread (full queue = client too far behind, frame dropped)
+            let tcp_targets: Vec<CbSender<Vec<u8>>> = state.clients.iter().filter_map(|c| c.tcp_frames.clone()).collect();
+            let fec_k = state.fec_k.load(Ordering::Relaxed).min(crate::fec::FEC_MAX_GROUP);
+            // Silence suppression: quiet buffers send nothing (no seq taken) or an occasional silence marker.
+            // Push-to-talk released: same on the wire, without hangover (buffers queued before the release are dropped here too)
+            let now = Instant::now();
+            let ptt_idle = state.ptt.idle();
+            let dtx_action = if ptt_idle { dtx.idle(now) } else if state.dtx.enabled.load(Ordering::Relaxed) { dtx.process(convert::rms(data, fmt_code), state.dtx.threshold_db.load(), now) } else { dtx.reset(); DtxAction::Send };
+            state.dtx.suppressing.store(!ptt_idle && dtx_action != DtxAction::Send, Ordering::Relaxed);
+            let opus_packets = opus_enc.as_mut().filter(|_| codec == Codec::Opus && dtx_action == DtxAction::Send).map(|enc| (enc.rate(), enc.channels(), enc.push(&convert::decode_pcm(data, fmt_code))));
+            match opus_packets {
+                Some((rate, enc_ch, Ok(packets))) => for (packet, lag_ns) in packets {
+                    // Packet start on the capture timeline: this buffer arrived ~when its last sample did
+                    let frame = build_frame(types::FRAME_MAGIC, seq, types::FMT_OPUS, enc_ch as u8, rate, ts_ns.saturating_sub(lag_ns), &packet);
+                    seq = seq.wrapping_add(1);
+                    let Some(packet) = frame.and_then(|f| seal_frame(&state, f, types::SUBSTREAM_MULTICAST)) else { continue };
+                    sender.send(&packet, mcast_sock, &state);
+                    for &dest in &uc_targets { sender.send(&packet, dest, &state); }
+                    for tx in &tcp_targets { if tx.try_send(packet.clone()).is_ok() { sender.rate.add(packet.len()); } }
+                    // FEC parity completing a group goes wherever UDP frames go (TCP clients do not lose frames)
+                    if let Some(parity) = fec.push(fec_k, &packet) { sender.send(&parity, mcast_sock, &state); for &dest in &uc_targets { sender.send(&parity, dest, &state); } }
+                },
+                Some((_, _, Err(e))) => { log::warn!("[SERVER][OPUS] {e:#}"); opus_enc = None; }
+                None if dtx_action != DtxAction::Send => packetizer.clear(),
+                // Fixed-size payloads when set (each stamped with when its last frame was captured), else the buffer as captured
+                None => for (payload, lag_ns) in packetizer.push(data, sr, ch, fmt_code) {
+                    let frame = build_frame(types::FRAME_MAGIC, seq, fmt_code, ch as u8, sr, ts_ns.saturating_sub(lag_ns), &payload);
+                    seq = seq.wrapping_add(1);
+                    // Optional encryption (payload only, header as AAD)
+                    let Some(packet) = frame.and_then(|f| seal_frame(&state, f, types::SUBSTREAM_MULTICAST)) else { continue };
+                    sender.send(&packet, mcast_sock, &state);
+                    for &dest in &uc_targets { sender.send(&packet, dest, &state); }
+                    for tx in &tcp_targets { if tx.try_send(packet.clone()).is_ok() { sender.rate.add(packet.len()); } }
+                    if let Some(parity) = fec.push(fec_k, &packet) { sender.send(&parity, mcast_sock, &state); for &dest in &uc_targets { sender.send(&parity, dest, &state); } }
+                },
+            }
+            // Silence marker: header only, same destinations as audio frames plus the low-quality substream
+            let marker = (dtx_action == DtxAction::Marker).then(|| {
+                let frame = build_frame(types::FRAME_MAGIC_SILENCE, silence_seq, fmt_code, ch as u8, sr, ts_ns, &[]);
+                silence_seq = silence_seq.wrapping_add(1);
+                let packet = seal_frame(&state, frame?, types::SUBSTREAM_SILENCE)?;
+                sender.send(&packet, mcast_sock, &state);
+                for &dest in &uc_targets { sender.send(&packet, dest, &state); }
+                for tx in &tcp_targets { if tx.try_send(packet.clone()).is_ok() { sender.rate.add(packet.len()); } }
+                Some(packet)
+            }).flatten();
+            // Recording: decoded copy handed to the writer thread (never blocks this loop)
+            { let mut rec = state.recording.lock();
+              if let Some(r) = rec.as_mut() {
+                  if !r.matches(sr, ch) || !r.push(convert::decode_pcm(data, fmt_code)) {
+                      log::info!("[SERVER][RECORD] capture format changed or writer stopped -> recording ended");
+                      if let Some(r) = rec.take() { if let Err(e) = r.finish() { log::warn!("[SERVER][RECORD] {e:#}"); } }
+                  }
+              } }
+            // Local monitor: same decoded buffer, queued for the output thread (never blocks this loop)
+            { let mut mon = state.monitor.lock();
+              if mon.as_mut().is_some_and(|m| !m.push(convert::decode_pcm(data, fmt_code), sr, ch)) {
+                  log::info!("[SERVER][MONITOR] output thread stopped -> monitor off");
+                  if let Some(m) = mon.take() { m.stop(); }
+              } }
+            // Low-quality unicast substream: convert once, send to every opted-in client
+            let lq_targets: Vec<SocketAddr> = state.clients.iter().filter(|c| c.substream == Substream::LowQuality).filter_map(|c| c.udp_port.map(|p| SocketAddr::new(c.addr.ip(), p))).collect();
+            if let Some(packet) = &marker { for &dest in &lq_targets { sender.send(packet, dest, &state); } }
+            if !lq_targets.is_empty() && dtx_action == DtxAction::Send {
+                let lq_payload = low_quality_payload(data, sr, ch, fmt_code);
+                let lq_frame = build_frame(types::FRAME_MAGIC_LQ, lq_seq, types::FMT_I16, 1, types::LQ_SAMPLE_RATE, ts_ns, &lq_payload);
+                if let Some(packet) = lq_frame.and_then(|f| seal_frame(&state, f, types::SUBSTREAM_LOW_QUALITY)) { for dest in lq_targets { sender.send(&packet, dest, &state); } }
+                lq_seq = lq_seq.wrapping_add(1);
+            }
             for r in to_remove { state.clients.remove(&r); }
+            let bytes_per_frame = types::bytes_per_sample(fmt_code) * ch.max(1) as usize;
+            degrader.observe(busy_start.elapsed(), Duration::from_secs_f64(data.len() as f64 / bytes_per_frame as f64 / sr.max(1) as f64));
             pool.push(idx);
         }
     }
 }
 
+/// Plaintext frame: `types::build_frame_header` + payload (see docs/protocol.md §2.1).
+/// `key_epoch` is filled in by `seal_frame` (0 for plaintext frames). Payloads past
+/// `packetize::MAX_PAYLOAD` would not fit one datagram once sealed: dropped (None), never truncated.
+fn build_frame(magic: [u8;2], seq: u32, fmt_code: u8, ch: u8, sr: u32, ts_ns: u64, payload: &[u8]) -> Option<Vec<u8>> {
+    if payload.len() > packetize::MAX_PAYLOAD { log::warn!("[SERVER] frame seq={seq} payload {} bytes > {} -> dropped", payload.len(), packetize::MAX_PAYLOAD); return None; }
+    let header = types::FrameHeader { magic, seq, fmt: fmt_code, ch, rate: sr, payload_len: payload.len() as u16, ts_ns, key_epoch: 0 };
+    let mut frame = Vec::with_capacity(types::FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&types::build_frame_header(&header));
+    frame.extend_from_slice(payload);
+    Some(frame)
+}
+
+/// Encrypt the payload of a plaintext frame when a key is configured (header = AAD, payload_len
+/// rewritten to ciphertext length, key epoch stamped). `substream` goes into the nonce so the
+/// multicast and the low-quality copy of the same seq/ts never share a nonce. A frame that cannot
+/// be sealed (cipher error, or a ciphertext too long for `payload_len`) is dropped (None), never
+/// sent in plaintext.
+fn seal_frame(state: &ServerState, frame: Vec<u8>, substream: u8) -> Option<Vec<u8>> {
+    const HDR: usize = types::FRAME_HEADER_LEN;
+    let Some(key) = state.keys.lock().as_mut().map(|r| r.tick(Instant::now())) else { check_frame_size(state, &frame); return Some(frame); };
+    let plain = types::parse_frame_header(&frame)?;
+    let ciphertext_len = frame.len() - HDR + types::FRAME_TAG_LEN;
+    if ciphertext_len > u16::MAX as usize { log::warn!("[SERVER][ENC] frame seq={} too large to seal ({ciphertext_len} bytes) -> dropped", plain.seq); return None; }
+    let header = types::FrameHeader { payload_len: ciphertext_len as u16, key_epoch: key.epoch, ..plain };
+    match types::seal_payload(&key.key, &key.salt, &header, substream, &frame[HDR..]) {
+        Ok(ct) => { let mut out = Vec::with_capacity(HDR + ct.len()); out.extend_from_slice(&types::build_frame_header(&header)); out.extend_from_slice(&ct); check_frame_size(state, &out); Some(out) }
+        Err(e) => { log::error!("[SERVER][ENC] encrypt fail seq={}: {e} -> dropped", header.seq); None }
+    }
+}
+
+/// Count (and log, at 1, 2, 4, 8... occurrences) frames whose datagram is larger than `state.mtu`:
+/// sent anyway, but IP-fragmented, and lost whole when any fragment is.
+fn check_frame_size(state: &ServerState, frame: &[u8]) {
+    let datagram = frame.len() + packetize::IP_UDP_OVERHEAD;
+    if datagram <= state.mtu as usize { return; }
+    let n = state.oversized_frames.fetch_add(1, Ordering::Relaxed) + 1;
+    if n.is_power_of_two() { log::warn!("[SERVER] oversized frame: {datagram} byte datagram > MTU {} ({n} so far) -> set frames per packet to split them", state.mtu); }
+}
+
+/// Down-convert one captured buffer to the low-quality substream format (mono i16 LE at `LQ_SAMPLE_RATE`).
+fn low_quality_payload(data: &[u8], sr: u32, ch: u16, fmt_code: u8) -> Vec<u8> {
+    let ch = ch.max(1) as usize;
+    let samples = convert::decode_pcm(data, fmt_code);
+    let mono: Vec<f32> = samples.chunks_exact(ch).map(|f| f.iter().sum::<f32>() / ch as f32).collect();
+    let mut out = Vec::new();
+    convert::resample_linear(&mono, sr, types::LQ_SAMPLE_RATE, &mut out);
+    out.iter().flat_map(|&s| convert::f32_to_i16(s).to_le_bytes()).collect()
+}
+
+/// Drop a single client: its control thread sends `KICKED`, removes the entry and closes the connection.
+pub fn kick_client(state: &ServerState, addr: &SocketAddr) -> bool {
+    let removed = state.clients.get_mut(addr).map(|mut c| c.kicked = true).is_some();
+    // Per-client keys: rotate right away; the kicked client never receives the key frames switch to
+    if removed && state.per_client_keys { if let Some(r) = state.keys.lock().as_mut() { r.rotate_now(); } }
+    removed
+}
+
+/// Kick a client and refuse further connections from its host; with per-client keys it also loses
+/// the session key (rotated past it by `kick_client`).
+pub fn revoke_client(state: &ServerState, addr: &SocketAddr) -> bool {
+    { let mut revoked = state.revoked.lock(); if !revoked.contains(&addr.ip()) { revoked.push(addr.ip()); } }
+    log::info!("[SERVER] {addr} revoked");
+    kick_client(state, addr)
+}
+
+/// Start recording captured audio (current stream params) to `path` in `format`.
+pub fn start_recording(state: &ServerState, path: &std::path::Path, format: RecordFormat) -> Result<()> {
+    let params = state.stream_params().context("audio not ready")?;
+    let rec = ThreadedRecorder::start(path, format, params.sample_rate, params.channels)?;
+    if let Some(old) = state.recording.lock().replace(rec) { old.finish()?; }
+    Ok(())
+}
+
+/// Stop recording and finalize the file (no-op when not recording).
+pub fn stop_recording(state: &ServerState) -> Result<()> {
+    let rec = state.recording.lock().take();
+    if let Some(r) = rec { r.finish()?; log::info!("[SERVER][RECORD] finalized"); }
+    Ok(())
+}
+
+/// Play the captured audio on the local default output (no-op when already monitoring).
+pub fn start_monitor(state: &ServerState) -> Result<()> {
+    let mut mon = state.monitor.lock();
+    if mon.is_none() { *mon = Some(LocalMonitor::start()?); }
+    Ok(())
+}
+
+/// Stop local playback (no-op when not monitoring).
+pub fn stop_monitor(state: &ServerState) {
+    let mon = state.monitor.lock().take();
+    if let Some(m) = mon { m.stop(); }
+}
+
+/// Move capture to the input device named `device` without restarting the server: the capture
+/// thread pauses the current stream, rebuilds on the new device and updates `audio_params`;
+/// connected clients are then sent `PARAMS` if the format changed.
+pub fn switch_input_device(state: &ServerState, device: String) -> Result<()> {
+    let tx = state.input_stop_tx.lock().clone().context("no capture thread running")?;
+    tx.try_send(InputCommand::Switch(device)).map_err(|e| anyhow::anyhow!("capture thread busy or gone: {e}"))
+}
+
 /// Signal server shutdown (threads exit naturally when flags flip).
 pub fn stop_server(state: &ServerState) {
     state.running.store(false, Ordering::SeqCst);
     state.input_running.store(false, Ordering::SeqCst);
-    if let Some(tx) = state.input_stop_tx.lock().take() { let _ = tx.send(()); }
+    if let Some(tx) = state.input_stop_tx.lock().take() { let _ = tx.send(InputCommand::Stop); }
     state.stage.store(0, Ordering::SeqCst);
-    // Clients will naturally time out / be removed; optionally we could clear now.
+    state.advert.lock().take();
+    if let Err(e) = stop_recording(state) { log::warn!("[SERVER][RECORD] {e:#}"); }
+    stop_monitor(state);
+    notify_stopped(state);
+}
+
+/// Send `SERVER_STOP` to every client right away (their control threads only notice `running` on
+/// their next pass) and drop the entries. TCP-transport clients are left to their thread: it writes
+/// frames on the same socket, so a line from here could land inside one, and it checks `running`
+/// between frame batches anyway.
+fn notify_stopped(state: &ServerState) {
+    let line = ControlMessage::ServerStop.to_line();
+    for c in state.clients.iter().filter(|c| c.substream != Substream::Tcp) {
+        if let Some(stream) = &c.control { let _ = (&**stream).write_all(line.as_bytes()); }
+    }
+    state.clients.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_past_the_datagram_limit_are_dropped_not_truncated() {
+        let fits = build_frame(types::FRAME_MAGIC, 1, types::FMT_F32, 2, 48_000, 0, &vec![0u8; packetize::MAX_PAYLOAD]).unwrap();
+        assert_eq!(fits.len() + types::FRAME_TAG_LEN, packetize::MAX_DATAGRAM);
+        assert_eq!(types::parse_frame_header(&fits).unwrap().payload_len as usize, packetize::MAX_PAYLOAD);
+        assert!(build_frame(types::FRAME_MAGIC, 2, types::FMT_F32, 2, 48_000, 0, &vec![0u8; packetize::MAX_PAYLOAD + 1]).is_none());
+    }
+
+    #[test]
+    fn room_names_map_to_stable_groups() {
+        let group = room_group("Studio A", false);
+        assert_eq!(group, room_group("  studio a ", false));
+        assert_ne!(group, room_group("Studio B", false));
+        let IpAddr::V4(v4) = group else { panic!("expected IPv4, got {group}") };
+        assert_eq!(v4.octets()[0], 239);
+        assert_ne!(v4.octets()[1] & 0x7f, 0);
+        assert!(room_group("Studio A", true).to_string().starts_with("ff02::524d:"));
+        // Leaving the room falls back to a random group
+        let mut state = ServerState::new();
+        state.select_multicast_group(false, Some("Studio A"));
+        assert_eq!((state.multicast_addr, state.multicast_room.as_deref()), (group, Some("Studio A")));
+        state.select_multicast_group(false, Some(" "));
+        assert_eq!(state.multicast_room, None);
+        assert!(state.multicast_addr.is_ipv4());
+    }
+
+    #[test]
+    fn stop_server_notifies_clients_immediately() {
+        use std::io::{BufRead, BufReader};
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = BufReader::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap());
+        let (stream, addr) = listener.accept().unwrap();
+        let state = ServerState::new();
+        state.running.store(true, Ordering::SeqCst);
+        state.clients.insert(addr, ClientInfo { addr, key: "k".into(), last_seen: Instant::now(), udp_port: None, substream: Substream::Multicast, opus: false, want: None, tcp_frames: None, kicked: false, control: Some(Arc::new(stream)) });
+        // No control thread runs here: the notice can only come from stop_server itself
+        stop_server(&state);
+        assert!(state.clients.is_empty());
+        client.get_ref().set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert_eq!(line, ControlMessage::ServerStop.to_line());
+    }
 }
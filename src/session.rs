@@ -0,0 +1,41 @@
+//! Optional maximum session duration (kiosk / demo deployments), shared by server and client.
+use std::{sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, time::{Duration, Instant}};
+use parking_lot::Mutex;
+
+/// Warning lead time before the session is cut off.
+pub const SESSION_WARN_BEFORE: Duration = Duration::from_secs(60);
+
+/// Result of a periodic limit check.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionCheck { Ok, Warn(Duration), Expired }
+
+/// Session time limit: `limit_secs == 0` means unlimited. Cheap to clone (Arc fields).
+#[derive(Clone, Default)]
+pub struct SessionLimit {
+    pub limit_secs: Arc<AtomicU64>,
+    started: Arc<Mutex<Option<Instant>>>,
+    warned: Arc<AtomicBool>,
+}
+
+impl SessionLimit {
+    pub fn new() -> Self { Self::default() }
+
+    /// Mark the session as started now (resets the warning).
+    pub fn start(&self) { *self.started.lock() = Some(Instant::now()); self.warned.store(false, Ordering::Relaxed); }
+
+    /// Remaining time, `None` when unlimited or not started.
+    pub fn remaining(&self) -> Option<Duration> {
+        let limit = self.limit_secs.load(Ordering::Relaxed);
+        if limit == 0 { return None; }
+        self.started.lock().map(|t| Duration::from_secs(limit).saturating_sub(t.elapsed()))
+    }
+
+    /// Called from the owning loop: reports the T-minus warning once, then expiry.
+    pub fn check(&self) -> SessionCheck {
+        match self.remaining() {
+            Some(left) if left.is_zero() => SessionCheck::Expired,
+            Some(left) if left <= SESSION_WARN_BEFORE && !self.warned.swap(true, Ordering::Relaxed) => SessionCheck::Warn(left),
+            _ => SessionCheck::Ok,
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Spectrum view: FFT of the latest `meter::Scope` samples, grouped into log-spaced bars.
+//!
+//! Runs on the UI refresh tick, never on an audio thread. Bars jump up to a new level at once
+//! and fall back smoothly (`FALL`), so short syllables stay readable at a 100 ms refresh.
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use crate::meter::SCOPE_LEN;
+
+/// FFT size (the whole scope ring: ~43 ms at 48 kHz, ~23 Hz per bin).
+pub const FFT_LEN: usize = SCOPE_LEN;
+/// Bars drawn, log-spaced between `FREQ_MIN` and `FREQ_MAX` (capped at Nyquist).
+pub const BARS: usize = 32;
+pub const FREQ_MIN: f32 = 50.0;
+pub const FREQ_MAX: f32 = 16_000.0;
+/// Level mapped to an empty bar (dBFS); 0 dBFS fills it.
+pub const DB_FLOOR: f32 = -80.0;
+/// Share of the previous bar height kept per update while the level drops.
+const FALL: f32 = 0.6;
+
+pub struct Spectrum { fft: Arc<dyn Fft<f32>>, window: Vec<f32>, buf: Vec<Complex<f32>>, scratch: Vec<Complex<f32>>, bars: [f32; BARS] }
+
+impl Spectrum {
+    pub fn new() -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_LEN);
+        // Hann window against leakage from the ring's hard edges
+        let window = (0..FFT_LEN).map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_LEN as f32).cos()).collect();
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        Self { fft, window, buf: Vec::with_capacity(FFT_LEN), scratch, bars: [0.0; BARS] }
+    }
+
+    /// Analyse the newest `FFT_LEN` of `samples` (mono, oldest first, at `sample_rate`); returns the
+    /// bar heights 0..1, lowest frequency first.
+    pub fn update(&mut self, samples: &[f32], sample_rate: u32) -> &[f32; BARS] {
+        let tail = &samples[samples.len().saturating_sub(FFT_LEN)..];
+        self.buf.clear();
+        self.buf.extend(tail.iter().zip(&self.window).map(|(&s, &w)| Complex::new(s * w, 0.0)));
+        self.buf.resize(FFT_LEN, Complex::default());
+        self.fft.process_with_scratch(&mut self.buf, &mut self.scratch);
+        // Full-scale sine -> 1.0 (Hann coherent gain 0.5, one-sided spectrum)
+        let scale = 4.0 / FFT_LEN as f32;
+        let hz_per_bin = sample_rate.max(1) as f32 / FFT_LEN as f32;
+        let top = FREQ_MAX.min(sample_rate as f32 / 2.0).max(FREQ_MIN * 2.0);
+        let ratio = (top / FREQ_MIN).powf(1.0 / BARS as f32);
+        for (i, bar) in self.bars.iter_mut().enumerate() {
+            let (lo, hi) = (FREQ_MIN * ratio.powi(i as i32), FREQ_MIN * ratio.powi(i as i32 + 1));
+            // At least one bin per bar (low bars are narrower than a bin)
+            let first = ((lo / hz_per_bin) as usize).clamp(1, FFT_LEN / 2 - 1);
+            let last = ((hi / hz_per_bin) as usize).clamp(first + 1, FFT_LEN / 2);
+            let mag = self.buf[first..last].iter().map(|c| c.norm()).fold(0f32, f32::max) * scale;
+            let db = if mag > 0.0 { 20.0 * mag.log10() } else { DB_FLOOR };
+            let level = ((db - DB_FLOOR) / -DB_FLOOR).clamp(0.0, 1.0);
+            *bar = level.max(*bar * FALL);
+        }
+        &self.bars
+    }
+
+    /// Drop the held bar heights (e.g. after disconnecting).
+    pub fn reset(&mut self) { self.bars = [0.0; BARS]; }
+}
+
+impl Default for Spectrum { fn default() -> Self { Self::new() } }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_lights_its_own_bar_and_bars_fall_back() {
+        let rate = 48_000;
+        let tone: Vec<f32> = (0..FFT_LEN).map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin()).collect();
+        let mut spectrum = Spectrum::new();
+        let bars = *spectrum.update(&tone, rate);
+        let loudest = (0..BARS).max_by(|&a, &b| bars[a].total_cmp(&bars[b])).unwrap();
+        let ratio = (FREQ_MAX / FREQ_MIN).powf(1.0 / BARS as f32);
+        let (lo, hi) = (FREQ_MIN * ratio.powi(loudest as i32), FREQ_MIN * ratio.powi(loudest as i32 + 1));
+        assert!(lo <= 1000.0 && 1000.0 < hi * 1.05, "peak bar {loudest}: {lo:.0}-{hi:.0} Hz");
+        // Half scale = -6 dBFS
+        assert!((bars[loudest] - (1.0 - 6.0 / -DB_FLOOR)).abs() < 0.03, "level {}", bars[loudest]);
+        assert!(bars[0] < 0.3 && bars[BARS - 1] < 0.3);
+        // Silence: the bar decays instead of vanishing
+        let after = spectrum.update(&[0.0; FFT_LEN], rate)[loudest];
+        assert!((after - bars[loudest] * FALL).abs() < 1e-6);
+    }
+}